@@ -19,6 +19,12 @@ pub struct SaveDocumentResponse {
     pub message: String,
 }
 
+/// Response from checking whether a document exists
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckDocumentResponse {
+    pub exists: bool,
+}
+
 /// Enhanced API routes for document persistence
 pub struct DocumentPersistenceApi {
     persistence_service: Arc<DocumentPersistenceService>,
@@ -45,14 +51,20 @@ impl DocumentPersistenceApi {
             .and(with_persistence_service(persistence_service.clone()))
             .and_then(Self::handle_save_document);
 
-        // Route for checking if document exists
+        // Route for checking if document exists, without creating it
         let check_document = warp::path!("api" / "documents" / String / "check")
             .and(warp::get())
             .and(with_persistence_service(persistence_service.clone()))
             .and_then(Self::handle_check_document);
 
+        // Route for creating a document if it doesn't exist
+        let ensure_document = warp::path!("api" / "documents" / String / "ensure")
+            .and(warp::post())
+            .and(with_persistence_service(persistence_service.clone()))
+            .and_then(Self::handle_ensure_document);
+
         // Combine routes
-        save_document.or(check_document)
+        save_document.or(check_document).or(ensure_document)
             .with(warp::cors()
                 .allow_any_origin()
                 .allow_headers(vec!["content-type", "x-user-id", "authorization"])
@@ -105,13 +117,37 @@ impl DocumentPersistenceApi {
         }
     }
 
-    /// Check if a document exists and can be accessed
+    /// Check if a document exists, leaving it missing if it doesn't
     async fn handle_check_document(
         id: String,
         persistence_service: Arc<DocumentPersistenceService>,
     ) -> Result<impl Reply, Rejection> {
         tracing::info!("Checking document existence: {}", id);
 
+        let exists = match Uuid::parse_str(&id) {
+            Ok(document_id) => persistence_service.document_exists(&document_id).await,
+            Err(_) => return Ok(warp::reply::with_status(
+                warp::reply::json(&SaveDocumentResponse {
+                    success: false,
+                    message: "Invalid document ID format".to_string(),
+                }),
+                warp::http::StatusCode::BAD_REQUEST,
+            )),
+        };
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CheckDocumentResponse { exists }),
+            warp::http::StatusCode::OK,
+        ))
+    }
+
+    /// Make sure a document exists, creating it if it doesn't and auto-creation is enabled
+    async fn handle_ensure_document(
+        id: String,
+        persistence_service: Arc<DocumentPersistenceService>,
+    ) -> Result<impl Reply, Rejection> {
+        tracing::info!("Ensuring document exists: {}", id);
+
         let document_id = match Uuid::parse_str(&id) {
             Ok(id) => id,
             Err(_) => return Ok(warp::reply::json(&SaveDocumentResponse {
@@ -120,7 +156,7 @@ impl DocumentPersistenceApi {
             })),
         };
 
-        // Use the branch manager to check if the document exists
+        // Use the branch manager to create the document if it's missing
         let branch_manager = persistence_service.branch_manager();
         match branch_manager.ensure_document_exists(&document_id, &format!("Document {}", id)).await {
             Ok(existed) => {
@@ -136,10 +172,10 @@ impl DocumentPersistenceApi {
                 }))
             }
             Err(e) => {
-                tracing::error!("Error checking document {}: {:?}", id, e);
+                tracing::error!("Error ensuring document {}: {:?}", id, e);
                 Ok(warp::reply::json(&SaveDocumentResponse {
                     success: false,
-                    message: format!("Error checking document: {}", e),
+                    message: format!("Error ensuring document: {}", e),
                 }))
             }
         }
@@ -1,24 +1,48 @@
 use anyhow::Result;
 // Remove unused import: futures::future
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::convert::Infallible;
+use std::ops::Range;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 use warp::{Filter, Rejection, Reply};
 
+use crate::crdt::activity::{ActivityEvent, ACTIVITY_FEED_CAPACITY};
+use crate::crdt::coalesce::COALESCE_WINDOW;
+use crate::crdt::comments::Comment;
+use crate::crdt::document::{Document, DocumentEncoding, DocumentVisibility, Role};
 use crate::crdt::engine::CrdtEngine;
+use crate::crdt::history::{self, OperationRecord, Patch};
 use crate::crdt::operations::DocumentOperation;
+use crate::crdt::stats::DocumentStats;
 use crate::git::manager::GitManager;
+use crate::network::directory::{ActiveSession, DiscoveredDocument, ANNOUNCE_INTERVAL};
 use crate::network::engine::NetworkEngine;
+use crate::network::service::TopicMetrics;
 use crate::utils::config::Config;
 use crate::utils::errors::AppError;
+use crate::utils::latex::lint::{self, LintWarning};
+use crate::utils::latex::{self, RefIssue};
+use crate::utils::timing::OperationHistogram;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateDocumentRequest {
     pub title: String,
     #[serde(alias = "owner_id")]
     pub owner: String,
+    #[serde(default)]
+    pub encoding: DocumentEncoding,
+    #[serde(default)]
+    pub visibility: DocumentVisibility,
+    /// Human-readable name to find the document by in place of its ID
+    #[serde(default)]
+    pub slug: Option<String>,
+    /// Shared by everyone creating this document, e.g. taken from an invitation link, so
+    /// copies peers create independently are merged into one
+    #[serde(default)]
+    pub dedup_nonce: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,22 +50,168 @@ pub struct CreateDocumentResponse {
     pub document_id: Uuid,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForkDocumentRequest {
+    pub title: String,
+    #[serde(alias = "owner_id")]
+    pub owner: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferOwnerRequest {
+    /// User making the request, who must be the current owner
+    pub user_id: String,
+    pub new_owner: String,
+}
+
+/// Query of a document deletion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteDocumentQuery {
+    /// User making the request, who must be the owner
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetRoleRequest {
+    /// User making the request, who must be the owner
+    pub user_id: String,
+    /// User whose role is set
+    pub member: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetCustomMetadataRequest {
+    /// User making the request, who must be at least an editor
+    pub user_id: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomMetadataEntry {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishDocumentRequest {
+    /// User making the request, who must be the owner
+    pub user_id: String,
+    /// Repository to create, as `owner/name`
+    pub github_repo: String,
+    #[serde(default)]
+    pub private: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishDocumentResponse {
+    pub html_url: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocumentListResponse {
     pub documents: Vec<DocumentInfo>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedDocumentInfo {
+    #[serde(flatten)]
+    pub document: DocumentInfo,
+    pub deleted_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashListResponse {
+    /// Oldest deletion first
+    pub documents: Vec<TrashedDocumentInfo>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocumentInfo {
     pub id: Uuid,
+    #[serde(default)]
+    pub slug: Option<String>,
     pub title: String,
     pub owner: String,
     pub collaborators: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
     pub repository_url: Option<String>,
+    #[serde(default)]
+    pub forked_from: Option<Uuid>,
+    #[serde(default)]
+    pub encoding: DocumentEncoding,
+    #[serde(default)]
+    pub visibility: DocumentVisibility,
+    #[serde(default)]
+    pub roles: BTreeMap<String, Role>,
+    #[serde(default)]
+    pub custom_metadata: HashMap<String, String>,
     pub created_at: String,
     pub updated_at: String,
 }
 
+impl From<&Document> for DocumentInfo {
+    fn from(doc: &Document) -> Self {
+        Self {
+            id: doc.id,
+            slug: doc.slug.clone(),
+            title: doc.title.clone(),
+            owner: doc.owner.clone(),
+            collaborators: doc.collaborators.iter().cloned().collect(),
+            tags: doc.tags.iter().cloned().collect(),
+            repository_url: doc.repository_url.clone(),
+            forked_from: doc.forked_from,
+            encoding: doc.encoding,
+            visibility: doc.visibility,
+            roles: doc.roles.clone(),
+            custom_metadata: doc.custom_metadata.clone(),
+            created_at: doc.created_at.to_rfc3339(),
+            updated_at: doc.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+/// Most documents a single batch fetch may ask for
+pub const MAX_BATCH_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchDocumentsRequest {
+    pub ids: Vec<String>,
+}
+
+/// Outcome of fetching one document of a batch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BatchDocumentEntry {
+    Document(Box<DocumentInfo>),
+    Error(ErrorResponse),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchDocumentsResponse {
+    /// Each requested ID, as given, with its document or why it couldn't be fetched
+    pub documents: BTreeMap<String, BatchDocumentEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateDocumentRequest {
+    /// User making the request, who must be at least an editor
+    pub user_id: String,
+    pub title: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub repository_url: Option<String>,
+    pub visibility: Option<DocumentVisibility>,
+    /// New slug, or an empty one to take the document's slug away
+    pub slug: Option<String>,
+}
+
+/// Room an upload form may take beyond its file, for the other fields and part headers
+pub const UPLOAD_FORM_OVERHEAD: u64 = 64 * 1024;
+
+/// Bytes of content, before encoding, sent per chunk of a streamed download
+pub const CONTENT_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InsertOperationRequest {
     pub user_id: String,
@@ -49,6 +219,75 @@ pub struct InsertOperationRequest {
     pub content: String,
 }
 
+/// Most operations a single batch may hold
+pub const MAX_OPERATION_BATCH_SIZE: usize = 1000;
+
+/// One operation of a batch pushed by another tool, with positions in characters as of after
+/// the operations before it in the batch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum BatchOperation {
+    Insert { user_id: String, position: usize, content: String },
+    Delete { user_id: String, range: Range<usize> },
+    Replace { user_id: String, range: Range<usize>, content: String },
+    Move { user_id: String, source: Range<usize>, dest: usize },
+}
+
+impl BatchOperation {
+    pub fn user_id(&self) -> &str {
+        match self {
+            BatchOperation::Insert { user_id, .. }
+            | BatchOperation::Delete { user_id, .. }
+            | BatchOperation::Replace { user_id, .. }
+            | BatchOperation::Move { user_id, .. } => user_id,
+        }
+    }
+
+    fn into_operation(self, document_id: Uuid) -> DocumentOperation {
+        match self {
+            BatchOperation::Insert { user_id, position, content } => DocumentOperation::Insert { document_id, user_id, position, content },
+            BatchOperation::Delete { user_id, range } => DocumentOperation::Delete { document_id, user_id, range },
+            BatchOperation::Replace { user_id, range, content } => DocumentOperation::Replace { document_id, user_id, range, content },
+            BatchOperation::Move { user_id, source, dest } => DocumentOperation::Move { document_id, user_id, source, dest },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationBatchResponse {
+    /// Number of operations applied
+    pub applied: usize,
+    /// Version the document is at after the batch
+    pub version: usize,
+}
+
+/// Query parameters of a raw insert, whose body is the bytes to insert
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawInsertQuery {
+    pub user_id: String,
+    pub position: usize,
+}
+
+/// Query of a partial content fetch, which may give either a line or a byte range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentRangeQuery {
+    /// Inclusive range of lines counted from 1, such as `100-200`
+    pub lines: Option<String>,
+    /// Inclusive range of bytes counted from 0, such as `0-1023`, as in an HTTP `Range` header
+    pub bytes: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoRequest {
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoResponse {
+    /// Whether there was anything to undo
+    pub undone: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeleteOperationRequest {
     pub user_id: String,
@@ -56,6 +295,145 @@ pub struct DeleteOperationRequest {
     pub end: usize,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddCommentRequest {
+    pub user_id: String,
+    pub start: usize,
+    pub end: usize,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveCommentRequest {
+    /// User making the request, who must be at least an editor
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LintResponse {
+    pub warnings: Vec<LintWarning>,
+    /// References to undefined labels, and labels nothing refers to
+    #[serde(default)]
+    pub references: Vec<RefIssue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityQuery {
+    /// Number of most recent entries to return, defaulting to the whole feed
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityResponse {
+    /// Entries oldest first
+    pub activity: Vec<ActivityEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceResponse {
+    /// How much smaller the document's exported oplog is after the checkpoint
+    pub oplog_bytes_reclaimed: u64,
+    /// How much smaller the document's Git object store is, or `None` without a repository
+    pub repository_bytes_reclaimed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitStatusResponse {
+    pub document_id: Uuid,
+    pub repository_url: Option<String>,
+    /// Whether the remote answered its latest reachability check; unset until it is checked
+    pub reachable: Option<bool>,
+    pub last_checked: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the remote last answered a check
+    pub last_success: Option<chrono::DateTime<chrono::Utc>>,
+    /// Why the latest check failed
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceCountResponse {
+    pub active_collaborators: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommentListResponse {
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationLogResponse {
+    pub operations: Vec<OperationRecord>,
+}
+
+/// Query of a document's patches
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PatchesQuery {
+    /// Frontier to start after, as comma-separated `agent:seq` IDs such as a patch's `id`,
+    /// defaulting to the beginning of the history
+    pub since: Option<String>,
+    /// Frontier to stop at, defaulting to the document's current version
+    pub until: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchesResponse {
+    /// Patches in the order they were applied
+    pub patches: Vec<Patch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayResponse {
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConvergenceResponse {
+    pub converged: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationStreamQuery {
+    /// Version to resume from
+    pub since: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveredDocumentListResponse {
+    pub documents: Vec<DiscoveredDocument>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkInfoResponse {
+    pub peer_id: String,
+    pub listen_addresses: Vec<String>,
+    pub connected_peers: Vec<String>,
+    pub subscribed_documents: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GossipsubMetricsResponse {
+    pub topics: Vec<TopicMetrics>,
+}
+
+/// Whether operations are held back, and how many were held (after pausing) or released
+/// (after resuming) in each direction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PropagationResponse {
+    pub paused: bool,
+    pub inbound: usize,
+    pub outbound: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationMetricsResponse {
+    pub operations: Vec<OperationHistogram>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveSessionListResponse {
+    pub sessions: Vec<ActiveSession>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OperationResponse {
     pub success: bool,
@@ -88,6 +466,8 @@ pub struct HttpApi {
     crdt_engine: Arc<RwLock<CrdtEngine>>,
     network_engine: Arc<RwLock<NetworkEngine>>,
     git_manager: Arc<RwLock<GitManager>>,
+    // Task broadcasting runs of typing once the user pauses
+    flush_task: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
 }
 
 impl HttpApi {
@@ -100,6 +480,7 @@ impl HttpApi {
             crdt_engine,
             network_engine,
             git_manager,
+            flush_task: Arc::new(RwLock::new(None)),
         }
     }
 
@@ -108,20 +489,102 @@ impl HttpApi {
         let crdt_engine = self.crdt_engine.clone();
         let network_engine = self.network_engine.clone();
         let git_manager = self.git_manager.clone();
+        let admin_token = config.server.admin_token.clone();
+
+        let addrs = config.server.api_socket_addrs()?;
+
+        // Broadcast runs of typing once the user pauses
+        let flush_crdt_engine = crdt_engine.clone();
+        let flush_network_engine = network_engine.clone();
+        let flush_task = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(COALESCE_WINDOW).await;
+                if let Err(e) = Self::broadcast_coalesced_operations(&flush_crdt_engine, &flush_network_engine).await {
+                    tracing::error!("Failed to broadcast coalesced operations: {}", e);
+                }
+            }
+        });
+        if let Some(previous) = self.flush_task.write().await.replace(flush_task) {
+            previous.abort();
+        }
 
-        let addr = format!("{}:{}", config.server.api_host, config.server.api_port)
-            .parse::<std::net::SocketAddr>()
-            .map_err(|e| anyhow::anyhow!("Failed to parse API address: {}", e))?;
-
-        tracing::info!("HTTP API binding to socket address: {}", addr);
-
-        // Move all dependencies into the tokio::spawn
+        // Announce public documents again for peers that joined the network since
+        let announce_crdt_engine = crdt_engine.clone();
+        let announce_network_engine = network_engine.clone();
         tokio::spawn(async move {
-            // Create routes directly inside the async block to avoid lifetime issues
-            let routes = Self::create_routes(crdt_engine, network_engine, git_manager);
-            warp::serve(routes).run(addr).await;
+            loop {
+                tokio::time::sleep(ANNOUNCE_INTERVAL).await;
+                if let Err(e) = Self::announce_public_documents(&announce_crdt_engine, &announce_network_engine).await {
+                    tracing::warn!("Failed to announce public documents: {}", e);
+                }
+            }
         });
 
+        // Serve the same routes on every address, binding them all before serving any so a
+        // taken address fails startup
+        let max_upload_bytes = config.storage.max_document_size_mb.saturating_mul(1024 * 1024);
+        let routes = Self::create_routes(crdt_engine, network_engine, git_manager, admin_token, max_upload_bytes);
+        let mut servers = Vec::new();
+        for addr in addrs {
+            tracing::info!("HTTP API binding to socket address: {}", addr);
+            let (_, server) = warp::serve(routes.clone())
+                .try_bind_ephemeral(addr)
+                .map_err(|e| AppError::ApiError(format!("Failed to bind HTTP API to {}: {}", addr, e)))?;
+            servers.push(server);
+        }
+        for server in servers {
+            tokio::spawn(server);
+        }
+
+        Ok(())
+    }
+
+    /// Stop broadcasting runs of typing, sending the ones that have ended first
+    pub async fn stop(&self) -> Result<()> {
+        if let Some(handle) = self.flush_task.write().await.take() {
+            handle.abort();
+        }
+        Self::broadcast_coalesced_operations(&self.crdt_engine, &self.network_engine).await
+    }
+
+    async fn broadcast_coalesced_operations(
+        crdt_engine: &Arc<RwLock<CrdtEngine>>,
+        network_engine: &Arc<RwLock<NetworkEngine>>,
+    ) -> Result<()> {
+        let ready = crdt_engine.read().await.flush_coalesced_operations()?;
+        if ready.is_empty() {
+            return Ok(());
+        }
+
+        let mut network = network_engine.write().await;
+        for (doc_id, encoded) in ready {
+            network.broadcast_operation(&doc_id, encoded).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn announce_public_documents(
+        crdt_engine: &Arc<RwLock<CrdtEngine>>,
+        network_engine: &Arc<RwLock<NetworkEngine>>,
+    ) -> Result<()> {
+        let public = {
+            let engine = crdt_engine.read().await;
+            let mut public = Vec::new();
+            for document in engine.list_documents().await? {
+                let doc = document.read().await;
+                if doc.visibility == DocumentVisibility::Public {
+                    public.push(doc.clone());
+                }
+            }
+            public
+        };
+
+        let mut network = network_engine.write().await;
+        for doc in &public {
+            network.announce_document(doc).await?;
+        }
+
         Ok(())
     }
 
@@ -130,6 +593,8 @@ impl HttpApi {
         crdt_engine: Arc<RwLock<CrdtEngine>>,
         network_engine: Arc<RwLock<NetworkEngine>>,
         git_manager: Arc<RwLock<GitManager>>,
+        admin_token: Option<String>,
+        max_upload_bytes: u64,
     ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
         let ping = warp::path("api")
             .and(warp::path("ping"))
@@ -148,23 +613,50 @@ impl HttpApi {
             .and(warp::body::json())
             .and_then(Self::handle_user_registration);
 
-        let create_document = warp::path("api")
-            .and(warp::path("documents"))
-            .and(warp::post())
-            .and(warp::body::json())
-            .and(with_crdt_engine(crdt_engine.clone()))
-            .and_then(Self::handle_create_document);
+        let create_document = Self::create_document_route(crdt_engine.clone(), network_engine.clone());
 
         let list_documents = warp::path("api")
             .and(warp::path("documents"))
+            .and(warp::path::end())
             .and(warp::get())
             .and(with_crdt_engine(crdt_engine.clone()))
             .and_then(Self::handle_list_documents);
 
-        let get_document = warp::path!("api" / "documents" / String)
-            .and(warp::get())
+        let batch_documents = Self::batch_documents_route(crdt_engine.clone());
+
+        let upload_document = Self::upload_route(crdt_engine.clone(), max_upload_bytes);
+
+        let get_document = Self::get_document_route(crdt_engine.clone());
+
+        let update_document = Self::update_document_route(crdt_engine.clone(), network_engine.clone());
+
+        let delete_document = warp::path!("api" / "documents" / String)
+            .and(warp::delete())
+            .and(warp::query::<DeleteDocumentQuery>())
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and(with_network_engine(network_engine.clone()))
+            .and_then(Self::handle_delete_document);
+
+        let list_trash = Self::trash_route(crdt_engine.clone());
+        let restore_trashed = Self::restore_trashed_route(crdt_engine.clone(), network_engine.clone());
+
+        let fork_document = warp::path!("api" / "documents" / String / "fork")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and_then(Self::handle_fork_document);
+
+        let transfer_owner = warp::path!("api" / "documents" / String / "transfer-owner")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and_then(Self::handle_transfer_owner);
+
+        let set_role = warp::path!("api" / "documents" / String / "roles")
+            .and(warp::post())
+            .and(warp::body::json())
             .and(with_crdt_engine(crdt_engine.clone()))
-            .and_then(Self::handle_get_document);
+            .and_then(Self::handle_set_role);
 
         let insert_operation = warp::path!("api" / "documents" / String / "insert")
             .and(warp::post())
@@ -173,6 +665,22 @@ impl HttpApi {
             .and(with_network_engine(network_engine.clone()))
             .and_then(Self::handle_insert_operation);
 
+        let insert_raw = warp::path!("api" / "documents" / String / "insert" / "raw")
+            .and(warp::post())
+            .and(warp::query::<RawInsertQuery>())
+            .and(warp::body::bytes())
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and(with_network_engine(network_engine.clone()))
+            .and_then(Self::handle_insert_raw);
+
+        let get_content = Self::content_route(crdt_engine.clone());
+        let stream_content = Self::content_stream_route(crdt_engine.clone());
+
+        let get_raw_content = warp::path!("api" / "documents" / String / "content" / "raw")
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and_then(Self::handle_get_raw_content);
+
         let delete_operation = warp::path!("api" / "documents" / String / "delete")
             .and(warp::post())
             .and(warp::body::json())
@@ -180,14 +688,78 @@ impl HttpApi {
             .and(with_network_engine(network_engine.clone()))
             .and_then(Self::handle_delete_operation);
 
-        let git_sync = warp::path!("api" / "documents" / String / "sync")
+        let lint_document = Self::lint_route(crdt_engine.clone());
+
+        let undo = warp::path!("api" / "documents" / String / "undo")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and(with_network_engine(network_engine.clone()))
+            .and_then(Self::handle_undo);
+
+        let operation_stream = Self::operation_stream_route(crdt_engine.clone());
+
+        let comments = Self::comments_route(crdt_engine.clone());
+
+        let operation_batch = Self::operation_batch_route(crdt_engine.clone(), network_engine.clone());
+        let activity = Self::activity_route(crdt_engine.clone());
+        let patches = Self::patches_route(crdt_engine.clone());
+        let stats = Self::stats_route(crdt_engine.clone());
+        let presence_count = Self::presence_count_route(crdt_engine.clone());
+
+        // Admin-only debugging routes
+        let get_oplog = warp::path!("api" / "documents" / String / "oplog")
+            .and(warp::get())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_admin_token(admin_token.clone()))
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and_then(Self::handle_get_oplog);
+
+        let replay_oplog = warp::path!("api" / "documents" / String / "replay")
             .and(warp::post())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_admin_token(admin_token.clone()))
+            .and(warp::body::bytes())
             .and(with_crdt_engine(crdt_engine.clone()))
+            .and_then(Self::handle_replay_oplog);
+
+        let check_convergence = warp::path!("api" / "documents" / String / "convergence")
+            .and(warp::get())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_admin_token(admin_token.clone()))
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and_then(Self::handle_check_convergence);
+
+        let propagation = Self::propagation_route(network_engine.clone(), admin_token.clone());
+
+        let maintenance = Self::maintenance_route(crdt_engine.clone(), network_engine.clone(), git_manager.clone(), admin_token.clone());
+
+        let git_status = Self::git_status_route(crdt_engine.clone(), git_manager.clone());
+
+        let publish_document = Self::publish_route(crdt_engine.clone(), git_manager.clone());
+
+        let discovered_documents = warp::path!("api" / "network" / "documents")
+            .and(warp::get())
+            .and(with_network_engine(network_engine.clone()))
+            .and_then(Self::handle_discovered_documents);
+
+        let active_sessions = warp::path!("api" / "network" / "sessions")
+            .and(warp::get())
+            .and(with_network_engine(network_engine.clone()))
+            .and_then(Self::handle_active_sessions);
+
+        let custom_metadata = Self::custom_metadata_route(crdt_engine.clone(), network_engine.clone());
+
+        let network_info = Self::network_info_route(network_engine.clone());
+        let gossipsub_metrics = Self::gossipsub_metrics_route(network_engine.clone());
+        let operation_metrics = Self::operation_metrics_route(crdt_engine.clone());
+
+        let git_sync = warp::path!("api" / "documents" / String / "sync")
+            .and(warp::post())
             .and(with_git_manager(git_manager.clone()))
-            .map(|id: String, crdt: Arc<RwLock<CrdtEngine>>, git: Arc<RwLock<GitManager>>| {
+            .map(|id: String, git: Arc<RwLock<GitManager>>| {
                 // Clone the ID for use in the spawned blocking task
                 let id_clone = id.clone();
-                let crdt_clone = crdt.clone();
                 let git_clone = git.clone();
 
                 // Return a response immediately
@@ -198,7 +770,7 @@ impl HttpApi {
                 tokio::task::spawn_blocking(move || {
                     // Convert to a blocking sync operation
                     tokio::runtime::Handle::current().block_on(async {
-                        if let Err(e) = Self::process_git_sync(id_clone, crdt_clone, git_clone).await {
+                        if let Err(e) = Self::process_git_sync(id_clone, git_clone).await {
                             eprintln!("Error in git sync: {}", e);
                         }
                     });
@@ -207,13 +779,72 @@ impl HttpApi {
                 resp
             });
 
-        // Combine all routes
-        let api = create_document
+        // Combine all routes, boxing each group so the combined filter's type stays shallow
+        let documents = create_document
             .or(list_documents)
+            .or(batch_documents)
+            .or(upload_document)
+            .or(list_trash)
+            .or(restore_trashed)
             .or(get_document)
-            .or(insert_operation)
+            .or(update_document)
+            .or(delete_document)
+            .or(fork_document)
+            .or(transfer_owner)
+            .or(set_role)
+            .or(custom_metadata)
+            .map(Reply::into_response)
+            .boxed();
+
+        let editing = insert_operation
+            .or(operation_batch)
+            .or(insert_raw)
+            .or(get_content)
+            .or(stream_content)
+            .or(get_raw_content)
             .or(delete_operation)
+            .or(undo)
+            .or(lint_document)
+            .or(operation_stream)
+            .map(Reply::into_response)
+            .boxed();
+
+        let history = comments
+            .or(activity)
+            .or(patches)
+            .or(stats)
+            .or(presence_count)
+            .map(Reply::into_response)
+            .boxed();
+
+        let admin = get_oplog
+            .or(replay_oplog)
+            .or(check_convergence)
+            .or(maintenance)
+            .map(Reply::into_response)
+            .boxed();
+
+        let git = git_status
+            .or(publish_document)
             .or(git_sync)
+            .map(Reply::into_response)
+            .boxed();
+
+        let network = discovered_documents
+            .or(active_sessions)
+            .or(network_info)
+            .or(gossipsub_metrics)
+            .or(propagation)
+            .or(operation_metrics)
+            .map(Reply::into_response)
+            .boxed();
+
+        let api = documents
+            .or(editing)
+            .or(history)
+            .or(admin)
+            .or(git)
+            .or(network)
             .or(user_registration)
             .or(ping);
 
@@ -221,17 +852,337 @@ impl HttpApi {
         api.with(warp::cors()
            .allow_any_origin()
            .allow_headers(vec!["content-type", "x-user-id", "authorization"])
-           .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"]))
+           .allow_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]))
     }
 
-    /// Creates routes for this HTTP API instance - kept for backwards compatibility
-    #[allow(dead_code)]
-    fn routes(&self) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-        Self::create_routes(
-            Arc::clone(&self.crdt_engine),
-            Arc::clone(&self.network_engine),
-            Arc::clone(&self.git_manager)
-        )
+    /// Metadata of several documents at once
+    pub(crate) fn batch_documents_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / "batch")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_batch_documents)
+    }
+
+    /// Create a document
+    pub(crate) fn create_document_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path("api")
+            .and(warp::path("documents"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine))
+            .and(with_network_engine(network_engine))
+            .and_then(Self::handle_create_document)
+    }
+
+    /// Change a document's metadata
+    pub(crate) fn update_document_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String)
+            .and(warp::patch())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine))
+            .and(with_network_engine(network_engine))
+            .and_then(Self::handle_update_document)
+    }
+
+    /// Add, list and resolve a document's comments
+    pub(crate) fn comments_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        let add = warp::path!("api" / "documents" / String / "comments")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and_then(Self::handle_add_comment);
+
+        let list = warp::path!("api" / "documents" / String / "comments")
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and_then(Self::handle_list_comments);
+
+        let resolve = warp::path!("api" / "documents" / String / "comments" / String / "resolve")
+            .and(warp::patch())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_resolve_comment);
+
+        add.or(list).unify().or(resolve).unify()
+    }
+
+    /// Set or read one of a document's custom metadata entries
+    pub(crate) fn custom_metadata_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        let set = warp::path!("api" / "documents" / String / "metadata" / String)
+            .and(warp::put())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine.clone()))
+            .and(with_network_engine(network_engine))
+            .and_then(Self::handle_set_custom_metadata);
+
+        let get = warp::path!("api" / "documents" / String / "metadata" / String)
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_get_custom_metadata);
+
+        set.or(get).unify()
+    }
+
+    /// Lint warnings for a document's current content
+    pub(crate) fn lint_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "lint")
+            .and(warp::post())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_lint)
+    }
+
+    /// Recent activity in a document
+    pub(crate) fn activity_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "activity")
+            .and(warp::get())
+            .and(warp::query::<ActivityQuery>())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_activity)
+    }
+
+    /// Apply a batch of operations from another tool, all or none
+    pub(crate) fn operation_batch_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "operations")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine))
+            .and(with_network_engine(network_engine))
+            .and_then(Self::handle_operation_batch)
+    }
+
+    /// A document's edit history as patches
+    pub(crate) fn patches_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "patches")
+            .and(warp::get())
+            .and(warp::query::<PatchesQuery>())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_patches)
+    }
+
+    /// Who contributed what to a document
+    pub(crate) fn stats_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "stats")
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_stats)
+    }
+
+    /// Number of active collaborators in a document
+    pub(crate) fn presence_count_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "presence-count")
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_presence_count)
+    }
+
+    /// Compact a document's history and repack its Git repository, for admins
+    pub(crate) fn maintenance_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+        git_manager: Arc<RwLock<GitManager>>,
+        admin_token: Option<String>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "maintenance")
+            .and(warp::post())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_admin_token(admin_token))
+            .and(with_crdt_engine(crdt_engine))
+            .and(with_network_engine(network_engine))
+            .and(with_git_manager(git_manager))
+            .and_then(Self::handle_maintenance)
+    }
+
+    /// A document's metadata, by its ID or slug
+    pub(crate) fn get_document_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String)
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_get_document)
+    }
+
+    /// Create a document from a `.tex` file uploaded as `multipart/form-data`, with the file in
+    /// the `file` field, its owner in `owner` and, optionally, its title in `title`
+    pub(crate) fn upload_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        max_bytes: u64,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / "upload")
+            .and(warp::post())
+            .and(warp::multipart::form().max_length(max_bytes.saturating_add(UPLOAD_FORM_OVERHEAD)))
+            .and(warp::any().map(move || max_bytes))
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_upload_document)
+    }
+
+    /// Whether a document's remote could be reached when last checked
+    pub(crate) fn git_status_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        git_manager: Arc<RwLock<GitManager>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "git-status")
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine))
+            .and(with_git_manager(git_manager))
+            .and_then(Self::handle_git_status)
+    }
+
+    /// Push a document to a new GitHub repository, for its owner
+    pub(crate) fn publish_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        git_manager: Arc<RwLock<GitManager>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "publish")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_crdt_engine(crdt_engine))
+            .and(with_git_manager(git_manager))
+            .and_then(Self::handle_publish_document)
+    }
+
+    /// Documents in the trash
+    pub(crate) fn trash_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / "trash")
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_list_trash)
+    }
+
+    /// Bring a document back out of the trash
+    pub(crate) fn restore_trashed_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "restore-trashed")
+            .and(warp::post())
+            .and(warp::query::<DeleteDocumentQuery>())
+            .and(with_crdt_engine(crdt_engine))
+            .and(with_network_engine(network_engine))
+            .and_then(Self::handle_restore_trashed)
+    }
+
+    /// This node's peer ID and network status
+    pub(crate) fn network_info_route(
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "network" / "info")
+            .and(warp::get())
+            .and(with_network_engine(network_engine))
+            .and_then(Self::handle_network_info)
+    }
+
+    /// Mesh health and message counts of each gossipsub topic this node subscribes to
+    pub(crate) fn gossipsub_metrics_route(
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "network" / "gossipsub")
+            .and(warp::get())
+            .and(with_network_engine(network_engine))
+            .and_then(Self::handle_gossipsub_metrics)
+    }
+
+    /// Admin-only switch pausing and resuming the propagation of operations, to reproduce
+    /// network partitions
+    pub(crate) fn propagation_route(
+        network_engine: Arc<RwLock<NetworkEngine>>,
+        admin_token: Option<String>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        let pause = warp::path!("api" / "network" / "pause").map(|| true);
+        let resume = warp::path!("api" / "network" / "resume").map(|| false);
+
+        pause
+            .or(resume)
+            .unify()
+            .and(warp::post())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_admin_token(admin_token))
+            .and(with_network_engine(network_engine))
+            .and_then(Self::handle_propagation)
+    }
+
+    /// How long CRDT operations, merges and Git syncs have taken on this node
+    pub(crate) fn operation_metrics_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Json,), Error = Rejection> + Clone {
+        warp::path!("api" / "metrics" / "operations")
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_operation_metrics)
+    }
+
+    /// A document's content, or a range of its lines or bytes
+    pub(crate) fn content_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "content")
+            .and(warp::get())
+            .and(warp::query::<ContentRangeQuery>())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_get_content)
+    }
+
+    /// A document's content in its declared encoding, sent in chunks as it is encoded
+    pub(crate) fn content_stream_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "content" / "stream")
+            .and(warp::get())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_stream_content)
+    }
+
+    /// Server-sent event stream of a document's operations, one JSON record per event
+    pub(crate) fn operation_stream_route(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+        warp::path!("api" / "documents" / String / "operations" / "stream")
+            .and(warp::get())
+            .and(warp::query::<OperationStreamQuery>())
+            .and(with_crdt_engine(crdt_engine))
+            .and_then(Self::handle_operation_stream)
+    }
+
+    /// Creates routes for this HTTP API instance - kept for backwards compatibility
+    #[allow(dead_code)]
+    fn routes(&self) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        Self::create_routes(
+            Arc::clone(&self.crdt_engine),
+            Arc::clone(&self.network_engine),
+            Arc::clone(&self.git_manager),
+            None,
+            Config::default().storage.max_document_size_mb.saturating_mul(1024 * 1024),
+        )
     }
 
     async fn handle_user_registration(
@@ -239,31 +1190,1087 @@ impl HttpApi {
     ) -> Result<impl Reply, Infallible> {
         tracing::info!("User registration request for: {}", req.name);
 
-        // Generate a user ID (in a real system this would be stored)
-        let user_id = Uuid::new_v4().to_string();
+        // Generate a user ID (in a real system this would be stored)
+        let user_id = Uuid::new_v4().to_string();
+
+        // Create the response
+        let response = UserResponse {
+            id: user_id,
+            name: req.name,
+        };
+
+        tracing::info!("User registration successful: {:?}", response);
+
+        // Return the user info
+        Ok(warp::reply::json(&response))
+    }
+
+    async fn handle_create_document(
+        req: CreateDocumentRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        tracing::info!("Creating document: title={:?}, owner={:?}", req.title, req.owner);
+
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let document_id = {
+                let engine = crdt_engine.read().await;
+                let document_id = match &req.dedup_nonce {
+                    Some(nonce) => engine.create_document_with_dedup(req.title, req.owner, nonce).await?,
+                    None => engine.create_document(req.title, req.owner).await?,
+                };
+                if req.encoding != DocumentEncoding::default() {
+                    engine.set_document_encoding(&document_id, req.encoding).await?;
+                }
+                engine.set_document_visibility(&document_id, req.visibility).await?;
+                if let Some(slug) = req.slug {
+                    // Don't leave a document behind when its slug is refused
+                    if let Err(e) = engine.set_document_slug(&document_id, Some(slug)).await {
+                        engine.purge_document(&document_id).await?;
+                        return Err(e);
+                    }
+                }
+                document_id
+            };
+            if req.visibility == DocumentVisibility::Public {
+                Self::announce(&crdt_engine, &network_engine, &document_id).await;
+            }
+            tracing::info!("Document created successfully with ID: {}", document_id);
+            Ok(warp::reply::json(&CreateDocumentResponse { document_id }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_list_documents(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let engine = crdt_engine.read().await;
+            let documents = engine.list_documents().await?;
+
+            // Create a vector of futures for each document
+            let doc_info_futures = documents
+                .into_iter()
+                .map(|doc| async move {
+                    let doc = doc.read().await;
+                    DocumentInfo::from(&*doc)
+                })
+                .collect::<Vec<_>>();
+
+            // Await all futures concurrently
+            let doc_infos = futures::future::join_all(doc_info_futures).await;
+
+            Ok(warp::reply::json(&DocumentListResponse {
+                documents: doc_infos,
+            }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_batch_documents(
+        req: BatchDocumentsRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        if req.ids.len() > MAX_BATCH_SIZE {
+            return Ok(warp::reply::json(&ErrorResponse {
+                error: format!("At most {} documents can be fetched at once, got {}", MAX_BATCH_SIZE, req.ids.len()),
+            }));
+        }
+
+        let engine = crdt_engine.read().await;
+
+        // Fetch every document concurrently, each failing on its own
+        let entry_futures = req.ids
+            .into_iter()
+            .map(|id| {
+                let engine = &engine;
+                async move {
+                    let result: Result<DocumentInfo> = async {
+                        let doc_id = engine.resolve_document_id(&id)?;
+                        let document = engine.get_document(&doc_id).await?;
+                        let doc = document.read().await;
+                        Ok(DocumentInfo::from(&*doc))
+                    }
+                    .await;
+
+                    let entry = match result {
+                        Ok(info) => BatchDocumentEntry::Document(Box::new(info)),
+                        Err(e) => BatchDocumentEntry::Error(ErrorResponse { error: e.to_string() }),
+                    };
+                    (id, entry)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let documents = futures::future::join_all(entry_futures).await.into_iter().collect();
+
+        Ok(warp::reply::json(&BatchDocumentsResponse { documents }))
+    }
+
+    async fn handle_get_document(
+        id: String,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let document = engine.get_document(&doc_id).await?;
+            let doc = document.read().await;
+
+            Ok(warp::reply::json(&DocumentInfo::from(&*doc)))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_update_document(
+        id: String,
+        req: UpdateDocumentRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let info = {
+                let engine = crdt_engine.read().await;
+                engine.authorize(&doc_id, &req.user_id, Role::Editor).await?;
+                if let Some(title) = req.title {
+                    engine.rename_document(&doc_id, title, Some(&req.user_id)).await?;
+                }
+                if let Some(tags) = req.tags {
+                    engine.set_document_tags(&doc_id, tags, Some(&req.user_id)).await?;
+                }
+                if let Some(url) = req.repository_url {
+                    engine.set_repository_url(&doc_id, url, Some(&req.user_id)).await?;
+                }
+                if let Some(visibility) = req.visibility {
+                    engine.set_document_visibility(&doc_id, visibility).await?;
+                }
+                if let Some(slug) = req.slug {
+                    engine.set_document_slug(&doc_id, Some(slug).filter(|slug| !slug.is_empty())).await?;
+                }
+
+                let document = engine.get_document(&doc_id).await?;
+                let doc = document.read().await;
+                DocumentInfo::from(&*doc)
+            };
+
+            if req.visibility == Some(DocumentVisibility::Public) {
+                Self::announce(&crdt_engine, &network_engine, &doc_id).await;
+            }
+
+            Ok(warp::reply::json(&info))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    /// Announce a document that was just made public
+    ///
+    /// Failing to announce doesn't fail the request; the document is announced again later.
+    async fn announce(
+        crdt_engine: &Arc<RwLock<CrdtEngine>>,
+        network_engine: &Arc<RwLock<NetworkEngine>>,
+        doc_id: &Uuid,
+    ) {
+        let result: Result<()> = async {
+            let doc = {
+                let engine = crdt_engine.read().await;
+                let document = engine.get_document(doc_id).await?;
+                document.read().await.clone()
+            };
+            network_engine.write().await.announce_document(&doc).await
+        }
+        .await;
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to announce document {}: {}", doc_id, e);
+        }
+    }
+
+    async fn handle_discovered_documents(
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let network = network_engine.read().await;
+            let documents = network.get_discovered_documents().await?;
+
+            Ok(warp::reply::json(&DiscoveredDocumentListResponse { documents }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_propagation(
+        pause: bool,
+        authorization: Option<String>,
+        admin_token: Option<String>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            check_admin(admin_token.as_deref(), authorization.as_deref())?;
+
+            let mut network = network_engine.write().await;
+            let (inbound, outbound) = if pause {
+                network.pause();
+                network.held_operations().await
+            } else {
+                network.resume().await?
+            };
+
+            Ok(warp::reply::json(&PropagationResponse {
+                paused: network.is_paused(),
+                inbound,
+                outbound,
+            }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_operation_metrics(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let operations = crdt_engine.read().await.operation_timings().histograms();
+        Ok(warp::reply::json(&OperationMetricsResponse { operations }))
+    }
+
+    async fn handle_gossipsub_metrics(
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        Ok(match network_engine.read().await.get_gossipsub_metrics().await {
+            Ok(topics) => warp::reply::json(&GossipsubMetricsResponse { topics }),
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_network_info(
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let network = network_engine.read().await;
+
+            Ok(warp::reply::json(&NetworkInfoResponse {
+                peer_id: network.get_local_peer_id().await?,
+                listen_addresses: network.get_listen_addresses(),
+                connected_peers: network.get_connected_peers().await?,
+                subscribed_documents: network.get_subscribed_documents().await?,
+            }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_active_sessions(
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let network = network_engine.read().await;
+            let sessions = network.get_active_sessions().await?;
+
+            Ok(warp::reply::json(&ActiveSessionListResponse { sessions }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_delete_document(
+        id: String,
+        query: DeleteDocumentQuery,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            {
+                let engine = crdt_engine.read().await;
+                engine.authorize(&doc_id, &query.user_id, Role::Owner).await?;
+                engine.delete_document(&doc_id).await?;
+            }
+            network_engine.write().await.leave_document(doc_id).await?;
+
+            Ok(warp::reply::json(&OperationResponse { success: true }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_list_trash(
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let engine = crdt_engine.read().await;
+
+            let mut documents = Vec::new();
+            for (doc, deleted_at) in engine.list_trashed_documents().await? {
+                documents.push(TrashedDocumentInfo {
+                    document: DocumentInfo::from(&*doc.read().await),
+                    deleted_at: deleted_at.to_rfc3339(),
+                });
+            }
+
+            Ok(warp::reply::json(&TrashListResponse { documents }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_restore_trashed(
+        id: String,
+        query: DeleteDocumentQuery,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let public = {
+                let engine = crdt_engine.read().await;
+                engine.authorize_trashed(&doc_id, &query.user_id, Role::Owner).await?;
+                engine.restore_document(&doc_id).await?;
+                engine.get_document(&doc_id).await?.read().await.visibility == DocumentVisibility::Public
+            };
+
+            // Deleting left the document's topics; follow it again, and catch up on what
+            // peers did while it was gone
+            network_engine.write().await.subscribe_to_document(doc_id).await?;
+            if public {
+                Self::announce(&crdt_engine, &network_engine, &doc_id).await;
+            }
+
+            Ok(warp::reply::json(&OperationResponse { success: true }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_fork_document(
+        id: String,
+        req: ForkDocumentRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let document_id = engine.fork_document(&doc_id, req.title, req.owner).await?;
+
+            Ok(warp::reply::json(&CreateDocumentResponse { document_id }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_publish_document(
+        id: String,
+        req: PublishDocumentRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        git_manager: Arc<RwLock<GitManager>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            // Publishing pushes with the server's GitHub token, so only the owner may do it
+            crdt_engine.read().await.authorize(&doc_id, &req.user_id, Role::Owner).await?;
+
+            let mut manager = git_manager.write().await;
+            let html_url = manager.publish_document(&doc_id, &req.github_repo, req.private).await?;
+
+            Ok(warp::reply::json(&PublishDocumentResponse { html_url }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_transfer_owner(
+        id: String,
+        req: TransferOwnerRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let document = engine.get_document(&doc_id).await?;
+            if document.read().await.owner != req.user_id {
+                return Err(anyhow::anyhow!(AppError::Unauthorized(
+                    "only the document owner can transfer ownership".to_string()
+                )));
+            }
+
+            engine.transfer_ownership(&doc_id, req.new_owner, Some(&req.user_id)).await?;
+
+            let doc = document.read().await;
+            Ok(warp::reply::json(&DocumentInfo::from(&*doc)))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_set_role(
+        id: String,
+        req: SetRoleRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            engine.authorize(&doc_id, &req.user_id, Role::Owner).await?;
+            engine.set_role(&doc_id, req.member, req.role, Some(&req.user_id)).await?;
+
+            let document = engine.get_document(&doc_id).await?;
+            let doc = document.read().await;
+            Ok(warp::reply::json(&DocumentInfo::from(&*doc)))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_set_custom_metadata(
+        id: String,
+        key: String,
+        req: SetCustomMetadataRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let doc = {
+                let engine = crdt_engine.read().await;
+                engine.authorize(&doc_id, &req.user_id, Role::Editor).await?;
+                engine.set_custom_metadata(&doc_id, key, req.value, Some(&req.user_id)).await?;
+                let document = engine.get_document(&doc_id).await?;
+                document.read().await.clone()
+            };
+
+            // Peers that miss this update catch up with the next one, which carries the whole map
+            if let Err(e) = network_engine.write().await.broadcast_metadata(&doc).await {
+                tracing::warn!("Failed to broadcast metadata of document {}: {}", doc_id, e);
+            }
+
+            Ok(warp::reply::json(&DocumentInfo::from(&doc)))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_get_custom_metadata(
+        id: String,
+        key: String,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let value = engine.get_custom_metadata(&doc_id, &key).await?.ok_or_else(|| {
+                anyhow::anyhow!(AppError::OperationRejected(format!("No metadata entry named {}", key)))
+            })?;
+
+            Ok(warp::reply::json(&CustomMetadataEntry { key, value }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_insert_operation(
+        id: String,
+        req: InsertOperationRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            engine.authorize(&doc_id, &req.user_id, Role::Editor).await?;
+
+            // Create the operation
+            let operation = DocumentOperation::Insert {
+                document_id: doc_id,
+                user_id: req.user_id,
+                position: req.position,
+                content: req.content,
+            };
+
+            // Apply locally; keystrokes are held back until the user's run of typing ends
+            let ready = engine.apply_typed_operation(&doc_id, operation).await?;
+
+            // Broadcast to network
+            let mut network = network_engine.write().await;
+            for encoded in ready {
+                network.broadcast_operation(&doc_id, encoded).await?;
+            }
+
+            Ok(warp::reply::json(&OperationResponse { success: true }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_operation_batch(
+        id: String,
+        batch: Vec<BatchOperation>,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+            if batch.len() > MAX_OPERATION_BATCH_SIZE {
+                return Err(anyhow::anyhow!(AppError::OperationRejected(format!(
+                    "a batch may hold at most {} operations, got {}",
+                    MAX_OPERATION_BATCH_SIZE,
+                    batch.len()
+                ))));
+            }
+
+            let engine = crdt_engine.read().await;
+            let mut users: Vec<&str> = batch.iter().map(BatchOperation::user_id).collect();
+            users.sort_unstable();
+            users.dedup();
+            for user_id in users {
+                engine.authorize(&doc_id, user_id, Role::Editor).await?;
+            }
+
+            let operations = batch.into_iter().map(|operation| operation.into_operation(doc_id)).collect();
+            let (ready, version) = engine.apply_operation_batch(&doc_id, operations).await?;
+
+            let applied = ready.len();
+            let mut network = network_engine.write().await;
+            for encoded in ready {
+                network.broadcast_operation(&doc_id, encoded).await?;
+            }
+
+            Ok(warp::reply::json(&OperationBatchResponse { applied, version }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_insert_raw(
+        id: String,
+        query: RawInsertQuery,
+        body: warp::hyper::body::Bytes,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            engine.authorize(&doc_id, &query.user_id, Role::Editor).await?;
+            let encoded = engine.insert_bytes(&doc_id, query.user_id, query.position, &body).await?;
+
+            let mut network = network_engine.write().await;
+            network.broadcast_operation(&doc_id, encoded).await?;
+
+            Ok(warp::reply::json(&OperationResponse { success: true }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    /// Serve a document's content as bytes in its declared encoding
+    async fn handle_get_raw_content(
+        id: String,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let result: Result<warp::reply::Response, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let encoding = engine.get_document(&doc_id).await?.read().await.encoding;
+            let bytes = engine.get_document_bytes(&doc_id).await?;
+
+            Ok(warp::reply::with_header(
+                bytes,
+                "content-type",
+                format!("text/plain; charset={}", encoding.charset()),
+            )
+            .into_response())
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            })
+            .into_response(),
+        })
+    }
+
+    /// Serve a document's content in its declared encoding, or just the requested range of it
+    async fn handle_get_content(
+        id: String,
+        query: ContentRangeQuery,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let result: Result<warp::reply::Response, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let encoding = engine.get_document(&doc_id).await?.read().await.encoding;
+
+            let selected = match (&query.lines, &query.bytes) {
+                (Some(_), Some(_)) => Err((
+                    warp::http::StatusCode::BAD_REQUEST,
+                    "Only one of lines and bytes can be given".to_string(),
+                )),
+                (Some(spec), None) => {
+                    let content = engine.get_document_snapshot(&doc_id).await?;
+                    match select_lines(&content, spec) {
+                        Ok((lines, range)) => Ok((encoding.encode(&lines)?, Some(range))),
+                        Err(e) => Err(e),
+                    }
+                }
+                (None, Some(spec)) => {
+                    let bytes = engine.get_document_bytes(&doc_id).await?;
+                    select_bytes(&bytes, spec).map(|(bytes, range)| (bytes, Some(range)))
+                }
+                (None, None) => Ok((engine.get_document_bytes(&doc_id).await?, None)),
+            };
+
+            let (body, content_range) = match selected {
+                Ok(selected) => selected,
+                Err((status, error)) => {
+                    return Ok(warp::reply::with_status(warp::reply::json(&ErrorResponse { error }), status).into_response());
+                }
+            };
+
+            let mut response = warp::reply::with_header(
+                body,
+                "content-type",
+                format!("text/plain; charset={}", encoding.charset()),
+            )
+            .into_response();
+            if let Some(content_range) = content_range {
+                *response.status_mut() = warp::http::StatusCode::PARTIAL_CONTENT;
+                response.headers_mut().insert(
+                    "content-range",
+                    warp::http::HeaderValue::from_str(&content_range)?,
+                );
+            }
+
+            Ok(response)
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            })
+            .into_response(),
+        })
+    }
+
+    /// Stream a document's content, encoding it a chunk at a time
+    ///
+    /// Chunks are cut from the engine's cached snapshot, so large documents are sent without
+    /// another copy of their content, encoded or not, being made for the response.
+    async fn handle_stream_content(
+        id: String,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let result: Result<warp::reply::Response, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let encoding = engine.get_document(&doc_id).await?.read().await.encoding;
+            let content = engine.get_document_snapshot(&doc_id).await?;
+
+            // Every character of a Latin-1 document encodes to one byte
+            let content_length = match encoding {
+                DocumentEncoding::Utf8 => content.len(),
+                DocumentEncoding::Latin1 => content.chars().count(),
+            };
+
+            let chunks = futures::stream::unfold(0, move |start| {
+                let content = Arc::clone(&content);
+                async move {
+                    if start >= content.len() {
+                        return None;
+                    }
+                    let mut end = (start + CONTENT_STREAM_CHUNK_SIZE).min(content.len());
+                    while !content.is_char_boundary(end) {
+                        end += 1;
+                    }
+                    Some((encoding.encode(&content[start..end]), end))
+                }
+            });
+
+            let mut response = warp::reply::Response::new(warp::hyper::Body::wrap_stream(chunks));
+            let headers = response.headers_mut();
+            headers.insert(
+                "content-type",
+                warp::http::HeaderValue::from_str(&format!("text/plain; charset={}", encoding.charset()))?,
+            );
+            headers.insert("content-length", warp::http::HeaderValue::from(content_length));
+
+            Ok(response)
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            })
+            .into_response(),
+        })
+    }
+
+    async fn handle_lint(
+        id: String,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let content = engine.get_document_snapshot(&doc_id).await?;
+
+            Ok(warp::reply::json(&LintResponse {
+                warnings: lint::lint(&content),
+                references: latex::check_references(&content),
+            }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_activity(
+        id: String,
+        query: ActivityQuery,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let limit = query.limit.unwrap_or(ACTIVITY_FEED_CAPACITY);
+            let activity = crdt_engine.read().await.get_activity(&doc_id, limit).await?;
+
+            Ok(warp::reply::json(&ActivityResponse { activity }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_patches(
+        id: String,
+        query: PatchesQuery,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let parse = |frontier: &String| {
+                history::parse_frontier(frontier)
+                    .ok_or_else(|| anyhow::anyhow!(AppError::ProtocolError(format!("Malformed frontier: {}", frontier))))
+            };
+            let since = query.since.as_ref().map(parse).transpose()?;
+            let until = query.until.as_ref().map(parse).transpose()?;
+
+            let patches = crdt_engine.read().await.get_patches(&doc_id, since.as_deref(), until.as_deref()).await?;
+
+            Ok(warp::reply::json(&PatchesResponse { patches }))
+        }
+        .await;
 
-        // Create the response
-        let response = UserResponse {
-            id: user_id,
-            name: req.name,
-        };
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
 
-        tracing::info!("User registration successful: {:?}", response);
+    async fn handle_stats(
+        id: String,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<DocumentStats, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
 
-        // Return the user info
-        Ok(warp::reply::json(&response))
+            crdt_engine.read().await.document_stats(&doc_id).await
+        }
+        .await;
+
+        Ok(match result {
+            Ok(stats) => warp::reply::json(&stats),
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
     }
 
-    async fn handle_create_document(
-        req: CreateDocumentRequest,
+    async fn handle_presence_count(
+        id: String,
         crdt_engine: Arc<RwLock<CrdtEngine>>,
-    ) -> Result<impl Reply, Infallible> {
-        tracing::info!("Creating document: title={:?}, owner={:?}", req.title, req.owner);
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            engine.get_document(&doc_id).await?;
+
+            Ok(warp::reply::json(&PresenceCountResponse {
+                active_collaborators: engine.active_collaborator_count(&doc_id),
+            }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_maintenance(
+        id: String,
+        authorization: Option<String>,
+        admin_token: Option<String>,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
+        git_manager: Arc<RwLock<GitManager>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            check_admin(admin_token.as_deref(), authorization.as_deref())?;
+
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let oplog_bytes_reclaimed = {
+                // A peer holding the old history would merge operations whose IDs now mean
+                // something else, so only unshared documents are checkpointed; the engine also
+                // refuses ones synced in the past. Holding the network keeps the document from
+                // being joined until the checkpoint is done.
+                let network = network_engine.read().await;
+                if network.is_document_shared(&doc_id) {
+                    return Err(anyhow::anyhow!(AppError::OperationRejected(format!(
+                        "document {} is shared with peers; leave it before checkpointing its history",
+                        doc_id
+                    ))));
+                }
+
+                let engine = crdt_engine.read().await;
+                engine.get_document(&doc_id).await?;
+                engine.checkpoint_document(&doc_id).await?
+            };
+
+            // Repack off the async runtime, without holding the manager's lock meanwhile
+            let manager = git_manager.read().await.clone();
+            let repository_bytes_reclaimed =
+                tokio::task::spawn_blocking(move || manager.compact_repository(&doc_id)).await??;
+
+            Ok(warp::reply::json(&MaintenanceResponse {
+                oplog_bytes_reclaimed,
+                repository_bytes_reclaimed,
+            }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_upload_document(
+        form: warp::multipart::FormData,
+        max_bytes: u64,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        use futures::TryStreamExt;
 
         let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let mut form = form;
+            let mut file = None;
+            let mut title = None;
+            let mut owner = None;
+            while let Some(part) = form
+                .try_next()
+                .await
+                .map_err(|e| AppError::ApiError(format!("Invalid upload: {}", e)))?
+            {
+                let name = part.name().to_string();
+                let filename = part.filename().map(str::to_string);
+                let data = read_upload_part(part, max_bytes).await?;
+                match name.as_str() {
+                    "file" => file = Some((filename, data)),
+                    "title" => title = Some(uploaded_text(data)?),
+                    "owner" | "owner_id" => owner = Some(uploaded_text(data)?),
+                    _ => {}
+                }
+            }
+
+            let (filename, data) = file.ok_or_else(|| AppError::ApiError("No file was uploaded".to_string()))?;
+            let owner = owner
+                .filter(|owner| !owner.trim().is_empty())
+                .ok_or_else(|| AppError::ApiError("An owner is required".to_string()))?;
+            let content = uploaded_text(data)?;
+
+            // Without a title, the file's name stands in for one
+            let title = title.filter(|title| !title.trim().is_empty()).unwrap_or_else(|| {
+                let filename = filename.unwrap_or_default();
+                let stem = filename.strip_suffix(".tex").unwrap_or(&filename);
+                if stem.trim().is_empty() { "Untitled".to_string() } else { stem.to_string() }
+            });
+
             let engine = crdt_engine.read().await;
-            let document_id = engine.create_document(req.title, req.owner).await?;
-            tracing::info!("Document created successfully with ID: {}", document_id);
+            let document_id = engine.create_document(title, owner.clone()).await?;
+            // Don't leave an empty document behind when the content is refused
+            if let Err(e) = engine.update_document_content_from(&document_id, content, &owner).await {
+                engine.purge_document(&document_id).await?;
+                return Err(e);
+            }
+            tracing::info!("Document {} created from an upload", document_id);
+
             Ok(warp::reply::json(&CreateDocumentResponse { document_id }))
         }
         .await;
@@ -276,35 +2283,31 @@ impl HttpApi {
         })
     }
 
-    async fn handle_list_documents(
+    async fn handle_git_status(
+        id: String,
         crdt_engine: Arc<RwLock<CrdtEngine>>,
-    ) -> Result<impl Reply, Infallible> {
+        git_manager: Arc<RwLock<GitManager>>,
+    ) -> Result<warp::reply::Json, Infallible> {
         let result: Result<warp::reply::Json, anyhow::Error> = async {
-            let engine = crdt_engine.read().await;
-            let documents = engine.list_documents().await?;
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
 
-            // Create a vector of futures for each document
-            let doc_info_futures = documents
-                .into_iter()
-                .map(|doc| async move {
-                    let doc = doc.read().await;
-                    DocumentInfo {
-                        id: doc.id,
-                        title: doc.title.clone(),
-                        owner: doc.owner.clone(),
-                        collaborators: doc.collaborators.iter().cloned().collect(),
-                        repository_url: doc.repository_url.clone(),
-                        created_at: doc.created_at.to_rfc3339(),
-                        updated_at: doc.updated_at.to_rfc3339(),
-                    }
-                })
-                .collect::<Vec<_>>();
+            let document = crdt_engine.read().await.get_document(&doc_id).await?;
+            let repository_url = document.read().await.repository_url.clone();
 
-            // Await all futures concurrently
-            let doc_infos = futures::future::join_all(doc_info_futures).await;
+            // A check of a remote the document has since been unlinked from doesn't count
+            let status = git_manager
+                .read()
+                .await
+                .remote_status(&doc_id)
+                .filter(|status| repository_url.as_deref() == Some(status.repository_url.as_str()));
 
-            Ok(warp::reply::json(&DocumentListResponse {
-                documents: doc_infos,
+            Ok(warp::reply::json(&GitStatusResponse {
+                document_id: doc_id,
+                repository_url,
+                reachable: status.as_ref().map(|status| status.is_reachable()),
+                last_checked: status.as_ref().map(|status| status.last_checked),
+                last_success: status.as_ref().and_then(|status| status.last_success),
+                last_error: status.and_then(|status| status.last_error),
             }))
         }
         .await;
@@ -317,29 +2320,26 @@ impl HttpApi {
         })
     }
 
-    async fn handle_get_document(
+    async fn handle_undo(
         id: String,
+        req: UndoRequest,
         crdt_engine: Arc<RwLock<CrdtEngine>>,
+        network_engine: Arc<RwLock<NetworkEngine>>,
     ) -> Result<impl Reply, Infallible> {
         let result: Result<warp::reply::Json, anyhow::Error> = async {
-            let doc_id = Uuid::parse_str(&id)
-                .map_err(|_| anyhow::anyhow!(AppError::InvalidUuid(id.clone())))?;
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
 
             let engine = crdt_engine.read().await;
-            let document = engine.get_document(&doc_id).await?;
-            let doc = document.read().await;
+            engine.authorize(&doc_id, &req.user_id, Role::Editor).await?;
+            let ready = engine.undo(&doc_id, &req.user_id).await?;
+            let undone = !ready.is_empty();
 
-            let doc_info = DocumentInfo {
-                id: doc.id,
-                title: doc.title.clone(),
-                owner: doc.owner.clone(),
-                collaborators: doc.collaborators.iter().cloned().collect(),
-                repository_url: doc.repository_url.clone(),
-                created_at: doc.created_at.to_rfc3339(),
-                updated_at: doc.updated_at.to_rfc3339(),
-            };
+            let mut network = network_engine.write().await;
+            for encoded in ready {
+                network.broadcast_operation(&doc_id, encoded).await?;
+            }
 
-            Ok(warp::reply::json(&doc_info))
+            Ok(warp::reply::json(&UndoResponse { undone }))
         }
         .await;
 
@@ -351,24 +2351,23 @@ impl HttpApi {
         })
     }
 
-    async fn handle_insert_operation(
+    async fn handle_delete_operation(
         id: String,
-        req: InsertOperationRequest,
+        req: DeleteOperationRequest,
         crdt_engine: Arc<RwLock<CrdtEngine>>,
         network_engine: Arc<RwLock<NetworkEngine>>,
     ) -> Result<impl Reply, Infallible> {
         let result: Result<warp::reply::Json, anyhow::Error> = async {
-            let doc_id = Uuid::parse_str(&id)
-                .map_err(|_| anyhow::anyhow!(AppError::InvalidUuid(id.clone())))?;
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
 
             let engine = crdt_engine.read().await;
+            engine.authorize(&doc_id, &req.user_id, Role::Editor).await?;
 
             // Create the operation
-            let operation = DocumentOperation::Insert {
+            let operation = DocumentOperation::Delete {
                 document_id: doc_id,
                 user_id: req.user_id,
-                position: req.position,
-                content: req.content,
+                range: req.start..req.end,
             };
 
             // Apply locally
@@ -390,33 +2389,154 @@ impl HttpApi {
         })
     }
 
-    async fn handle_delete_operation(
+    async fn handle_operation_stream(
         id: String,
-        req: DeleteOperationRequest,
+        query: OperationStreamQuery,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Response, Infallible> {
+        use futures::StreamExt;
+
+        let result = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            engine.stream_operations(&doc_id, query.since.unwrap_or(0)).await
+        }
+        .await;
+
+        Ok(match result {
+            Ok(records) => {
+                let events = records.map(|record| {
+                    let event = warp::sse::Event::default()
+                        .id(record.version.to_string())
+                        .json_data(&record)
+                        .unwrap_or_else(|e| warp::sse::Event::default().event("error").data(e.to_string()));
+                    Ok::<_, Infallible>(event)
+                });
+                warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response()
+            }
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            })
+            .into_response(),
+        })
+    }
+
+    async fn handle_add_comment(
+        id: String,
+        req: AddCommentRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            engine.authorize(&doc_id, &req.user_id, Role::Commenter).await?;
+            let comment = engine.add_comment(&doc_id, req.user_id, req.start..req.end, req.body).await?;
+
+            Ok(warp::reply::json(&comment))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_list_comments(
+        id: String,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let comments = engine.get_comments(&doc_id).await?;
+
+            Ok(warp::reply::json(&CommentListResponse { comments }))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_resolve_comment(
+        id: String,
+        comment_id: String,
+        req: ResolveCommentRequest,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<warp::reply::Json, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+            let comment_id = Uuid::parse_str(&comment_id)
+                .map_err(|_| anyhow::anyhow!(AppError::InvalidUuid(comment_id.clone())))?;
+
+            let engine = crdt_engine.read().await;
+            engine.authorize(&doc_id, &req.user_id, Role::Editor).await?;
+            let comment = engine.resolve_comment(&doc_id, &comment_id).await?;
+
+            Ok(warp::reply::json(&comment))
+        }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
+
+    async fn handle_get_oplog(
+        id: String,
+        authorization: Option<String>,
+        admin_token: Option<String>,
         crdt_engine: Arc<RwLock<CrdtEngine>>,
-        network_engine: Arc<RwLock<NetworkEngine>>,
     ) -> Result<impl Reply, Infallible> {
         let result: Result<warp::reply::Json, anyhow::Error> = async {
-            let doc_id = Uuid::parse_str(&id)
-                .map_err(|_| anyhow::anyhow!(AppError::InvalidUuid(id.clone())))?;
+            check_admin(admin_token.as_deref(), authorization.as_deref())?;
+
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
 
             let engine = crdt_engine.read().await;
+            let operations = engine.get_operation_log(&doc_id).await?;
 
-            // Create the operation
-            let operation = DocumentOperation::Delete {
-                document_id: doc_id,
-                user_id: req.user_id,
-                range: req.start..req.end,
-            };
+            Ok(warp::reply::json(&OperationLogResponse { operations }))
+        }
+        .await;
 
-            // Apply locally
-            let encoded = engine.apply_local_operation(&doc_id, operation).await?;
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
 
-            // Broadcast to network
-            let mut network = network_engine.write().await;
-            network.broadcast_operation(&doc_id, encoded).await?;
+    async fn handle_replay_oplog(
+        id: String,
+        authorization: Option<String>,
+        admin_token: Option<String>,
+        body: warp::hyper::body::Bytes,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            check_admin(admin_token.as_deref(), authorization.as_deref())?;
 
-            Ok(warp::reply::json(&OperationResponse { success: true }))
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
+
+            let engine = crdt_engine.read().await;
+            let content = engine.replay_oplog(&doc_id, &body).await?;
+
+            Ok(warp::reply::json(&ReplayResponse { content }))
         }
         .await;
 
@@ -428,29 +2548,41 @@ impl HttpApi {
         })
     }
 
-    async fn process_git_sync(
+    async fn handle_check_convergence(
         id: String,
+        authorization: Option<String>,
+        admin_token: Option<String>,
         crdt_engine: Arc<RwLock<CrdtEngine>>,
-        git_manager: Arc<RwLock<GitManager>>,
-    ) -> Result<(), anyhow::Error> {
-        let doc_id = Uuid::parse_str(&id)
-            .map_err(|_| anyhow::anyhow!(AppError::InvalidUuid(id.clone())))?;
+    ) -> Result<impl Reply, Infallible> {
+        let result: Result<warp::reply::Json, anyhow::Error> = async {
+            check_admin(admin_token.as_deref(), authorization.as_deref())?;
+
+            let doc_id = crdt_engine.read().await.resolve_document_id(&id)?;
 
-        // Get document data first
-        let doc_content = {
             let engine = crdt_engine.read().await;
-            engine.get_document_content(&doc_id).await?
-        };
+            let converged = engine.verify_convergence(&doc_id).await?;
 
-        // The key point here is to avoid holding a lock on git_manager while performing Git operations
-        // that could prevent the Send trait from being implemented
-        {
-            let mut manager = git_manager.write().await;
-            // This is a blocking operation that should be safe to use with tokio::task::spawn_blocking
-            // Instead of using async code with git2, which isn't Send/Sync
-            manager.sync_document_blocking(&doc_id, doc_content)?;
+            Ok(warp::reply::json(&ConvergenceResponse { converged }))
         }
+        .await;
+
+        Ok(match result {
+            Ok(response) => response,
+            Err(e) => warp::reply::json(&ErrorResponse {
+                error: e.to_string(),
+            }),
+        })
+    }
 
+    async fn process_git_sync(
+        id: String,
+        git_manager: Arc<RwLock<GitManager>>,
+    ) -> Result<(), anyhow::Error> {
+        let doc_id = git_manager.read().await.resolve_document_id(&id).await?;
+
+        // A shared lock lets other documents sync alongside this one; the manager serializes
+        // operations on the same document and caps how many run at once
+        git_manager.read().await.sync_to_repository(&doc_id).await?;
         Ok(())
     }
 
@@ -458,6 +2590,39 @@ impl HttpApi {
 }
 
 // Helper functions to extract dependencies
+/// Read a part of an upload form, failing once it is larger than `max_bytes`
+async fn read_upload_part(part: warp::multipart::Part, max_bytes: u64) -> Result<Vec<u8>> {
+    use futures::TryStreamExt;
+    use warp::hyper::body::Buf;
+
+    let mut stream = part.stream();
+    let mut data = Vec::new();
+    while let Some(chunk) = stream
+        .try_next()
+        .await
+        .map_err(|e| AppError::ApiError(format!("Invalid upload: {}", e)))?
+    {
+        data.extend_from_slice(chunk.chunk());
+        if data.len() as u64 > max_bytes {
+            return Err(AppError::ApiError(format!("Upload is larger than the {} byte limit", max_bytes)).into());
+        }
+    }
+
+    Ok(data)
+}
+
+/// Uploaded bytes as text, rejecting anything that isn't UTF-8 or holds control characters
+/// no LaTeX source would
+fn uploaded_text(data: Vec<u8>) -> Result<String> {
+    let text = String::from_utf8(data)
+        .map_err(|_| AppError::ApiError("Upload is not UTF-8 text".to_string()))?;
+    if text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c')) {
+        return Err(AppError::ApiError("Upload is not text".to_string()).into());
+    }
+
+    Ok(text.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(text))
+}
+
 fn with_crdt_engine(
     crdt_engine: Arc<RwLock<CrdtEngine>>,
 ) -> impl Filter<Extract = (Arc<RwLock<CrdtEngine>>,), Error = std::convert::Infallible> + Clone {
@@ -470,8 +2635,65 @@ fn with_network_engine(
     warp::any().map(move || network_engine.clone())
 }
 
+fn with_admin_token(
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (Option<String>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || admin_token.clone())
+}
+
+/// Check an `Authorization: Bearer <token>` header against the configured admin token
+fn check_admin(admin_token: Option<&str>, authorization: Option<&str>) -> Result<()> {
+    let admin_token = admin_token
+        .ok_or_else(|| anyhow::anyhow!(AppError::Unauthorized("admin endpoints are disabled".to_string())))?;
+
+    match authorization.and_then(|header| header.strip_prefix("Bearer ")) {
+        Some(token) if token == admin_token => Ok(()),
+        _ => Err(anyhow::anyhow!(AppError::Unauthorized("invalid admin token".to_string()))),
+    }
+}
+
 fn with_git_manager(
     git_manager: Arc<RwLock<GitManager>>,
 ) -> impl Filter<Extract = (Arc<RwLock<GitManager>>,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || git_manager.clone())
 }
+
+/// Parse an inclusive `start-end` range, where a single number selects just that unit
+fn parse_range(spec: &str) -> Result<(usize, usize), (warp::http::StatusCode, String)> {
+    let (start, end) = spec.split_once('-').unwrap_or((spec, spec));
+    match (start.trim().parse(), end.trim().parse()) {
+        (Ok(start), Ok(end)) => Ok((start, end)),
+        _ => Err((warp::http::StatusCode::BAD_REQUEST, format!("Invalid range {:?}", spec))),
+    }
+}
+
+/// Select an inclusive range of lines, counted from 1, along with its `Content-Range` value
+///
+/// Lines keep their terminating newline.
+fn select_lines(content: &str, spec: &str) -> Result<(String, String), (warp::http::StatusCode, String)> {
+    let (first, last) = parse_range(spec)?;
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+
+    if first == 0 || first > last || last > lines.len() {
+        return Err((
+            warp::http::StatusCode::RANGE_NOT_SATISFIABLE,
+            format!("Lines {} are out of bounds for a document of {} lines", spec, lines.len()),
+        ));
+    }
+
+    Ok((lines[first - 1..last].concat(), format!("lines {}-{}/{}", first, last, lines.len())))
+}
+
+/// Select an inclusive range of bytes, counted from 0, along with its `Content-Range` value
+fn select_bytes(bytes: &[u8], spec: &str) -> Result<(Vec<u8>, String), (warp::http::StatusCode, String)> {
+    let (first, last) = parse_range(spec)?;
+
+    if first > last || last >= bytes.len() {
+        return Err((
+            warp::http::StatusCode::RANGE_NOT_SATISFIABLE,
+            format!("Bytes {} are out of bounds for a document of {} bytes", spec, bytes.len()),
+        ));
+    }
+
+    Ok((bytes[first..=last].to_vec(), format!("bytes {}-{}/{}", first, last, bytes.len())))
+}
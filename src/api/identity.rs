@@ -0,0 +1,176 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::sync::Arc;
+
+use crate::utils::config::AuthConfig;
+use crate::utils::errors::AppError;
+
+/// Who the bearer of a token is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserIdentity {
+    pub user_id: String,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+}
+
+impl UserIdentity {
+    fn user(user_id: &str) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            display_name: None,
+            email: None,
+        }
+    }
+}
+
+/// Maps the token a client authenticates with to the user it belongs to
+#[async_trait]
+pub trait IdentityProvider: Send + Sync {
+    /// Resolve a token, failing with `AppError::Unauthorized` if it isn't valid
+    async fn resolve(&self, token: &str) -> Result<UserIdentity>;
+}
+
+/// The provider the configuration asks for
+pub fn provider_from_config(config: &AuthConfig) -> Result<Arc<dyn IdentityProvider>> {
+    Ok(match config {
+        AuthConfig::SharedSecret { secret } => Arc::new(SharedSecretProvider::new(secret.as_bytes())),
+        AuthConfig::Jwt { algorithm, key, issuer, audience } => {
+            let mut provider = JwtProvider::new(algorithm, key)?;
+            if let Some(issuer) = issuer {
+                provider = provider.with_issuer(issuer);
+            }
+            if let Some(audience) = audience {
+                provider = provider.with_audience(audience);
+            }
+            Arc::new(provider)
+        }
+    })
+}
+
+fn unauthorized(message: impl Into<String>) -> anyhow::Error {
+    AppError::Unauthorized(message.into()).into()
+}
+
+/// Tokens of the form `<user_id>.<signature>`, the signature being the hex HMAC-SHA256 of
+/// the user ID under a secret shared with whoever issues them
+pub struct SharedSecretProvider {
+    secret: Vec<u8>,
+}
+
+impl SharedSecretProvider {
+    pub fn new(secret: &[u8]) -> Self {
+        Self { secret: secret.to_vec() }
+    }
+
+    /// Issue a token for a user
+    pub fn sign(&self, user_id: &str) -> String {
+        let signature = self.mac(user_id).finalize().into_bytes();
+        let hex: String = signature.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}.{}", user_id, hex)
+    }
+
+    fn mac(&self, user_id: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes keys of any length");
+        mac.update(user_id.as_bytes());
+        mac
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for SharedSecretProvider {
+    async fn resolve(&self, token: &str) -> Result<UserIdentity> {
+        let (user_id, hex) = token.rsplit_once('.').ok_or_else(|| unauthorized("malformed token"))?;
+        let signature = decode_hex(hex).ok_or_else(|| unauthorized("malformed token signature"))?;
+
+        // Compared in constant time
+        self.mac(user_id)
+            .verify_slice(&signature)
+            .map_err(|_| unauthorized("invalid token signature"))?;
+
+        Ok(UserIdentity::user(user_id))
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// JWTs issued by an external identity provider, e.g. an OIDC one
+///
+/// The signature and expiry are checked, and the `sub` claim becomes the user ID.
+pub struct JwtProvider {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    email: Option<String>,
+}
+
+impl JwtProvider {
+    /// Verify tokens signed with `algorithm`, e.g. `"RS256"`: `key` is the shared secret for
+    /// the `HS*` algorithms, and the PEM-encoded public key for the others
+    pub fn new(algorithm: &str, key: &str) -> Result<Self> {
+        let algorithm: Algorithm = algorithm
+            .parse()
+            .map_err(|_| AppError::ConfigError(format!("Unsupported JWT algorithm: {}", algorithm)))?;
+        let pem = key.as_bytes();
+        let key = match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Ok(DecodingKey::from_secret(pem)),
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512 => {
+                DecodingKey::from_rsa_pem(pem)
+            }
+            Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(pem),
+            Algorithm::EdDSA => DecodingKey::from_ed_pem(pem),
+        }
+        .map_err(|e| AppError::ConfigError(format!("Invalid JWT key: {}", e)))?;
+
+        let mut validation = Validation::new(algorithm);
+        validation.validate_aud = false;
+
+        Ok(Self { key, validation })
+    }
+
+    /// Only accept tokens issued by `issuer`
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.validation.set_issuer(&[issuer]);
+        self
+    }
+
+    /// Only accept tokens meant for `audience`
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.validation.set_audience(&[audience]);
+        self.validation.validate_aud = true;
+        self
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for JwtProvider {
+    async fn resolve(&self, token: &str) -> Result<UserIdentity> {
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation)
+            .map_err(|e| unauthorized(format!("invalid token: {}", e)))?
+            .claims;
+
+        Ok(UserIdentity {
+            user_id: claims.sub,
+            display_name: claims.name,
+            email: claims.email,
+        })
+    }
+}
@@ -1,5 +1,7 @@
 pub mod http;
 pub mod websocket;
 pub mod protocol;
+pub mod receipts;
 pub mod server;
+pub mod identity;
 pub mod document_persistence_api;
@@ -1,7 +1,14 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Range;
 
+use crate::crdt::activity::ActivityEvent;
+use crate::crdt::chat::ChatEntry;
+use crate::crdt::comments::Comment;
+use crate::crdt::document::{Document, Role};
+use crate::crdt::history::OperationRecord;
+
 /// API protocol messages for communication with clients
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "payload")]
@@ -16,6 +23,16 @@ pub enum ApiMessage {
     DocumentOperation {
         /// Operation details
         operation: Operation,
+        /// ID echoed back in the operation's result; the server assigns one when unset. An
+        /// operation sent again with the same ID, say after a reconnect, is applied only once
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        operation_id: Option<String>,
+    },
+
+    /// Outcome of a document operation, sent in reply to every one
+    OperationResult {
+        /// Result, carrying the operation's ID
+        result: OperationResponse,
     },
 
     /// Document state update
@@ -42,6 +59,45 @@ pub enum ApiMessage {
         document_id: Uuid,
     },
 
+    /// Watch a document update live without collaborating: the session can't edit it and
+    /// isn't shown in its presence
+    Spectate {
+        /// Document ID
+        document_id: Uuid,
+    },
+
+    /// Reopen a document after reconnecting, catching up from the version the client last saw
+    Resume {
+        /// Document ID
+        document_id: Uuid,
+        /// Version of the last `DocumentUpdate` or `DocumentPatch` the client received
+        version: String,
+    },
+
+    /// The operations a resuming client missed, to apply in order to the content it has
+    DocumentPatch {
+        /// Document ID
+        document_id: Uuid,
+        /// Version the operations apply on top of
+        base_version: String,
+        /// Version after applying them
+        version: String,
+        /// The missed operations
+        operations: Vec<OperationRecord>,
+    },
+
+    /// Fetch document metadata without opening the document
+    GetDocumentInfo {
+        /// Document ID
+        document_id: Uuid,
+    },
+
+    /// Document metadata response
+    DocumentInfo {
+        /// Document metadata
+        document: DocumentInfoMessage,
+    },
+
     /// User presence information
     PresenceUpdate {
         /// Document ID
@@ -50,21 +106,158 @@ pub enum ApiMessage {
         presence: UserPresence,
     },
 
+    /// A document was deleted; clients should close it
+    DocumentDeleted {
+        /// Document ID
+        document_id: Uuid,
+    },
+
+    /// A document was merged into a peer's copy of it under another ID; clients keep it open
+    /// under the new one
+    DocumentIdChanged {
+        /// ID the document had
+        old_id: Uuid,
+        /// ID the document has now
+        new_id: Uuid,
+    },
+
+    /// A user's presence was removed from a document after they went silent
+    PresenceRemoved {
+        /// Document ID
+        document_id: Uuid,
+        /// User whose presence was removed
+        user_id: String,
+    },
+
+    /// An edit landed on other users' cursors or selections; advisory, as the edits still merge
+    ConflictHint {
+        /// Document ID
+        document_id: Uuid,
+        /// User who made the edit
+        user_id: String,
+        /// Users whose cursor or selection the edit overlapped, sorted
+        user_ids: Vec<String>,
+    },
+
+    /// Something happened in a document
+    ActivityUpdate {
+        /// Document ID
+        document_id: Uuid,
+        /// The new activity feed entry
+        activity: ActivityEvent,
+    },
+
+    /// A chat message about a document; clients send only the body, and every session on the
+    /// document gets it back with its sender and when it was sent
+    ChatMessage {
+        /// Document ID
+        document_id: Uuid,
+        /// The message
+        body: String,
+        /// Who sent the message
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user_id: Option<String>,
+        /// When the message was sent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    },
+
+    /// Ask for a document's recent chat, answered with its messages, oldest first
+    ChatHistory {
+        /// Document ID
+        document_id: Uuid,
+        /// The messages; left out when asking
+        #[serde(default)]
+        messages: Vec<ChatEntry>,
+    },
+
+    /// A comment on a document was added or changed
+    CommentUpdate {
+        /// Document ID
+        document_id: Uuid,
+        /// Current state of the comment
+        comment: Comment,
+    },
+
+    /// Document metadata changed; only the changed fields are present
+    MetadataChanged {
+        /// Document ID
+        document_id: Uuid,
+        /// New title
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        /// New owner
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        owner: Option<String>,
+        /// New set of tags
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tags: Option<Vec<String>>,
+        /// New set of collaborators
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        collaborators: Option<Vec<String>>,
+        /// New repository URL
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        repository_url: Option<String>,
+        /// New roles of users with an assigned role
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        roles: Option<BTreeMap<String, Role>>,
+        /// New custom metadata, in full
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        custom_metadata: Option<HashMap<String, String>>,
+    },
+
     /// List available documents
     ListDocuments,
 
+    /// List the documents the session's user owns, collaborates on or has a role in
+    ListMyDocuments,
+
     /// Document list response
     DocumentList {
         /// List of document summaries
         documents: Vec<DocumentSummary>,
     },
 
+    /// Notification addressed to a user, such as being mentioned in a comment
+    Notification {
+        /// User the notification comes from
+        from: String,
+        /// Notification text
+        body: String,
+    },
+
     /// Server heartbeat to check connection status
     Heartbeat {
         /// Current server timestamp
         timestamp: String,
     },
 
+    /// Client acknowledgement of a server heartbeat
+    HeartbeatAck,
+
+    /// The session is sending operations faster than it may, and should pause
+    Throttle {
+        /// How long until the next operation will be accepted
+        retry_after_ms: u64,
+    },
+
+    /// Sync a document with peers and commit and push it to its repository right away,
+    /// rather than waiting for the next round
+    SyncNow {
+        /// Document ID
+        document_id: Uuid,
+    },
+
+    /// The outcome of a `SyncNow`
+    SyncComplete {
+        /// Document ID
+        document_id: Uuid,
+        /// Version of the document once synced
+        version: String,
+        /// Whether anything was committed to the document's repository
+        git_committed: bool,
+    },
+
     /// Error message
     Error {
         /// Error code
@@ -74,6 +267,51 @@ pub enum ApiMessage {
     },
 }
 
+impl ApiMessage {
+    /// Parse a message received from a client
+    ///
+    /// Unknown fields are ignored, unless `strict` is set, in which case the first one is
+    /// reported by its path, e.g. `payload.titel`.
+    pub fn parse(text: &str, strict: bool) -> Result<Self, String> {
+        let received: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let message: ApiMessage = serde_json::from_value(received.clone()).map_err(|e| e.to_string())?;
+
+        if strict {
+            // Whatever doesn't survive a round trip through the message types is unknown
+            let known = serde_json::to_value(&message).map_err(|e| e.to_string())?;
+            if let Some(field) = unknown_field(&received, &known, "") {
+                return Err(format!("unknown field `{}`", field));
+            }
+        }
+
+        Ok(message)
+    }
+}
+
+/// Find the path of the first field in `received` that is missing from `known`
+///
+/// Null fields are skipped, as optional fields that are unset aren't always serialized.
+fn unknown_field(received: &serde_json::Value, known: &serde_json::Value, path: &str) -> Option<String> {
+    let join = |key: &str| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+
+    match (received, known) {
+        (serde_json::Value::Object(received), serde_json::Value::Object(known)) => {
+            received.iter().filter(|(_, value)| !value.is_null()).find_map(|(key, value)| {
+                match known.get(key) {
+                    Some(known) => unknown_field(value, known, &join(key)),
+                    None => Some(join(key)),
+                }
+            })
+        }
+        (serde_json::Value::Array(received), serde_json::Value::Array(known)) => received
+            .iter()
+            .zip(known)
+            .enumerate()
+            .find_map(|(i, (received, known))| unknown_field(received, known, &join(&i.to_string()))),
+        _ => None,
+    }
+}
+
 /// Operation types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Operation {
@@ -104,6 +342,16 @@ pub enum Operation {
         /// New content
         content: String,
     },
+
+    /// Move text in a range to another position
+    Move {
+        /// Document ID
+        document_id: Uuid,
+        /// Range to move (start..end)
+        source: Range<usize>,
+        /// Position to move the text to, as of before the move
+        dest: usize,
+    },
 }
 
 /// Document summary information
@@ -111,6 +359,9 @@ pub enum Operation {
 pub struct DocumentSummary {
     /// Document ID
     pub id: Uuid,
+    /// Human-readable name the document can be found by in place of its ID
+    #[serde(default)]
+    pub slug: Option<String>,
     /// Document title
     pub title: String,
     /// Document owner
@@ -121,6 +372,59 @@ pub struct DocumentSummary {
     pub active_collaborators: usize,
 }
 
+/// Document metadata, mirroring the HTTP `DocumentInfo`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentInfoMessage {
+    /// Document ID
+    pub id: Uuid,
+    /// Human-readable name the document can be found by in place of its ID
+    #[serde(default)]
+    pub slug: Option<String>,
+    /// Document title
+    pub title: String,
+    /// Document owner
+    pub owner: String,
+    /// Document collaborators
+    pub collaborators: Vec<String>,
+    /// Document tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Repository URL
+    pub repository_url: Option<String>,
+    /// Document this one was forked from
+    #[serde(default)]
+    pub forked_from: Option<Uuid>,
+    /// Roles assigned to individual users
+    #[serde(default)]
+    pub roles: BTreeMap<String, Role>,
+    /// Custom metadata values
+    #[serde(default)]
+    pub custom_metadata: HashMap<String, String>,
+    /// Creation time
+    pub created_at: String,
+    /// Last modified time
+    pub updated_at: String,
+}
+
+impl From<&Document> for DocumentInfoMessage {
+    fn from(doc: &Document) -> Self {
+        Self {
+            id: doc.id,
+            slug: doc.slug.clone(),
+            title: doc.title.clone(),
+            owner: doc.owner.clone(),
+            collaborators: doc.collaborators.iter().cloned().collect(),
+            tags: doc.tags.iter().cloned().collect(),
+            repository_url: doc.repository_url.clone(),
+            forked_from: doc.forked_from,
+            roles: doc.roles.clone(),
+            custom_metadata: doc.custom_metadata.clone(),
+            created_at: doc.created_at.to_rfc3339(),
+            updated_at: doc.updated_at.to_rfc3339(),
+        }
+    }
+}
+
 /// User presence information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserPresence {
@@ -136,6 +440,9 @@ pub struct UserPresence {
     pub is_active: bool,
     /// Timestamp of the last activity
     pub last_activity: String,
+    /// Display color, assigned from the user ID when left empty
+    #[serde(default)]
+    pub color: String,
 }
 
 /// Response to document operations
@@ -147,4 +454,7 @@ pub struct OperationResponse {
     pub success: bool,
     /// Error message if unsuccessful
     pub error: Option<String>,
+    /// Machine-readable kind of error if unsuccessful, as in `ApiMessage::Error`, e.g. `forbidden`
+    #[serde(default)]
+    pub code: Option<String>,
 }
@@ -0,0 +1,81 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// What is known about an operation a client sent, by the ID it gave it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Receipt {
+    /// Not seen before; it is now claimed and has to be completed or released
+    New,
+    /// Still being applied, from an earlier send
+    InFlight,
+    /// Already applied
+    Applied,
+}
+
+/// The operations each user's clients sent recently, so ones re-sent after a reconnect are
+/// recognised instead of applied twice
+///
+/// Operations are keyed by user and the ID the client gave them, as a reconnecting client gets
+/// a new session but stays the same user. Only the most recent `capacity` are remembered.
+#[derive(Debug)]
+pub struct OperationReceipts {
+    capacity: usize,
+    inner: Mutex<ReceiptsInner>,
+}
+
+#[derive(Debug, Default)]
+struct ReceiptsInner {
+    /// Whether each operation was applied, or is still in flight
+    applied: HashMap<(String, String), bool>,
+    /// Keys in the order they were claimed, oldest first
+    order: VecDeque<(String, String)>,
+}
+
+impl OperationReceipts {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(ReceiptsInner::default()),
+        }
+    }
+
+    /// Look up an operation, claiming it if it is new
+    pub fn claim(&self, user_id: &str, operation_id: &str) -> Receipt {
+        let mut inner = self.lock();
+        let key = (user_id.to_string(), operation_id.to_string());
+        match inner.applied.get(&key) {
+            Some(true) => return Receipt::Applied,
+            Some(false) => return Receipt::InFlight,
+            None => {}
+        }
+
+        inner.applied.insert(key.clone(), false);
+        inner.order.push_back(key);
+        while inner.order.len() > self.capacity {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.applied.remove(&oldest);
+            }
+        }
+        Receipt::New
+    }
+
+    /// Record that a claimed operation was applied
+    pub fn complete(&self, user_id: &str, operation_id: &str) {
+        if let Some(applied) = self.lock().applied.get_mut(&(user_id.to_string(), operation_id.to_string())) {
+            *applied = true;
+        }
+    }
+
+    /// Forget a claimed operation that wasn't applied, so sending it again retries it
+    pub fn release(&self, user_id: &str, operation_id: &str) {
+        let mut inner = self.lock();
+        let key = (user_id.to_string(), operation_id.to_string());
+        if inner.applied.remove(&key).is_some() {
+            inner.order.retain(|claimed| *claimed != key);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ReceiptsInner> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -4,11 +4,14 @@ use tokio::sync::RwLock;
 use tracing::info;
 
 use crate::api::http::HttpApi;
+use crate::api::identity;
 use crate::api::websocket::WebSocketServer;
 use crate::api::document_persistence_api::DocumentPersistenceApi;
 use crate::crdt::engine::CrdtEngine;
+use crate::crdt::presence::PRESENCE_SWEEP_INTERVAL;
 use crate::git::manager::GitManager;
 use crate::network::engine::NetworkEngine;
+use crate::storage::audit_log::AuditLog;
 use crate::storage::document_persistence_service::DocumentPersistenceService;
 use crate::utils::config::Config;
 
@@ -19,6 +22,9 @@ pub struct ApiServer {
     config: Config,
     // Optional heartbeat task handle
     heartbeat_task: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
+    // Task decaying the presence of collaborators who went silent
+    presence_sweep_task: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
+    crdt_engine: Arc<RwLock<CrdtEngine>>,
 }
 
 impl ApiServer {
@@ -34,9 +40,20 @@ impl ApiServer {
             Arc::clone(&git_manager),
         );
 
-        let websocket_server = WebSocketServer::new(
+        let mut websocket_server = WebSocketServer::new(
             Arc::clone(&crdt_engine),
-        );
+        )
+        .with_strict_protocol(config.server.strict_protocol)
+        .with_session_queue_depth(config.server.session_queue_depth)
+        .with_operation_rate_limit(config.server.operation_rate_limit)
+        .with_presence_broadcast_interval(config.server.presence_broadcast_interval())
+        .with_auto_create_missing_documents(config.storage.auto_create_missing_documents)
+        .with_lock_wait_timeout(config.debug.lock_wait_timeout())
+        .with_network_engine(Arc::clone(&network_engine))
+        .with_git_manager(Arc::clone(&git_manager));
+        if let Some(auth) = &config.server.auth {
+            websocket_server = websocket_server.with_identity_provider(identity::provider_from_config(auth)?);
+        }
 
         // Document persistence API is initialized later when the persistence service is available
         let document_persistence_api = None;
@@ -47,6 +64,8 @@ impl ApiServer {
             document_persistence_api,
             config: config.clone(),
             heartbeat_task: Arc::new(RwLock::new(None)),
+            presence_sweep_task: Arc::new(RwLock::new(None)),
+            crdt_engine,
         })
     }
 
@@ -55,16 +74,19 @@ impl ApiServer {
         self.document_persistence_api = Some(DocumentPersistenceApi::new(persistence_service));
     }
 
+    /// Record WebSocket sign-ins in the audit log
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.websocket_server.set_audit_log(audit_log);
+    }
+
     pub async fn start(&self) -> Result<()> {
         info!("Starting HTTP API server...");
-        info!("Binding HTTP API to {}:{}", self.config.server.api_host, self.config.server.api_port);
         self.http_api.start(&self.config).await?;
-        info!("HTTP API server started successfully on {}:{}", self.config.server.api_host, self.config.server.api_port);
+        info!("HTTP API server started successfully on {:?}", self.config.server.api_socket_addrs()?);
 
         info!("Starting WebSocket server...");
-        info!("Binding WebSocket to {}:{}", self.config.server.ws_host, self.config.server.ws_port);
         self.websocket_server.start(&self.config).await?;
-        info!("WebSocket server started successfully on {}:{}", self.config.server.ws_host, self.config.server.ws_port);
+        info!("WebSocket server started successfully on {:?}", self.config.server.ws_socket_addrs()?);
 
         // Start document persistence API if available
         if let Some(ref persistence_api) = self.document_persistence_api {
@@ -92,26 +114,24 @@ impl ApiServer {
             info!("Document Persistence API started successfully on {}", addr);
         }
 
-        // Start the heartbeat task
-        let websocket_server = self.websocket_server.clone();
-        let heartbeat_task = tokio::spawn(async move {
-            let heartbeat_interval = tokio::time::Duration::from_secs(30); // 30 seconds
+        // Start the heartbeat task, unless heartbeats are disabled
+        let heartbeat_task = self.websocket_server.spawn_heartbeat(self.config.server.heartbeat_interval());
+        match self.config.server.heartbeat_interval() {
+            Some(interval) => info!("WebSocket heartbeat task started, every {:?}", interval),
+            None => info!("WebSocket heartbeat disabled"),
+        }
+        *self.heartbeat_task.write().await = heartbeat_task;
 
+        // Start the presence sweep; the WebSocket server broadcasts the transitions
+        let crdt_engine = Arc::clone(&self.crdt_engine);
+        let decay = self.config.server.presence_decay();
+        let presence_sweep_task = tokio::spawn(async move {
             loop {
-                tokio::time::sleep(heartbeat_interval).await;
-
-                if let Err(e) = websocket_server.send_heartbeat().await {
-                    tracing::error!("Error sending heartbeat: {:?}", e);
-                }
+                tokio::time::sleep(PRESENCE_SWEEP_INTERVAL).await;
+                crdt_engine.read().await.sweep_presences(std::time::Instant::now(), decay);
             }
         });
-
-        {
-            let mut task_handle = self.heartbeat_task.write().await;
-            *task_handle = Some(heartbeat_task);
-        }
-
-        info!("WebSocket heartbeat task started");
+        *self.presence_sweep_task.write().await = Some(presence_sweep_task);
 
         Ok(())
     }
@@ -128,6 +148,12 @@ impl ApiServer {
             }
         }
 
+        if let Some(handle) = self.presence_sweep_task.write().await.take() {
+            handle.abort();
+        }
+
+        self.http_api.stop().await?;
+
         // Currently, we don't have explicit stop methods for our servers as they
         // run in Tokio tasks. In a more complex application, we might use shutdown
         // signals or channels to gracefully terminate these services.
@@ -1,20 +1,34 @@
 use anyhow::Result;
+use dashmap::DashMap;
 use futures::{StreamExt, SinkExt};
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{mpsc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use uuid::Uuid;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 use warp::Filter;
 use warp::ws::Message as WarpMessage;
 
 // We'll use Warp's WebSocket message type throughout the application
 // and provide conversions when needed
 
-use crate::api::protocol::ApiMessage;
+use crate::api::identity::IdentityProvider;
+use crate::api::protocol::{ApiMessage, DocumentInfoMessage, DocumentSummary, OperationResponse};
+use crate::api::receipts::{OperationReceipts, Receipt};
+use crate::crdt::chat::{ChatEntry, CHAT_HISTORY_CAPACITY};
+use crate::crdt::comments::Comment;
+use crate::crdt::document::{Document, Role};
 use crate::crdt::engine::CrdtEngine;
+use crate::storage::audit_log::{AuditLog, AuditRecord};
+use crate::crdt::events::DocumentEvent;
 use crate::crdt::operations::DocumentOperation;
 use crate::crdt::document_branch_manager::DocumentBranchManager;
+use crate::git::manager::GitManager;
+use crate::network::engine::NetworkEngine;
+use crate::utils::config::RateLimitConfig;
 use crate::utils::errors::AppError;
+use crate::utils::lock_wait::LockWait;
+use crate::utils::rate_limit::TokenBucket;
 
 /// User client session information
 #[derive(Debug, Clone)]
@@ -23,10 +37,34 @@ pub struct ClientSession {
     pub user_id: String,
     /// Active document ID
     pub document_id: Option<Uuid>,
-    /// Channel to send messages to the client
-    pub sender: mpsc::Sender<WarpMessage>,
+    /// Channel to send messages to the client, once a connection is attached
+    pub sender: Option<mpsc::Sender<WarpMessage>>,
+    /// Whether the client has sent an `Authentication` message
+    pub authenticated: bool,
+    /// Whether the session only watches its document, see `ApiMessage::Spectate`
+    pub spectator: bool,
 }
 
+impl ClientSession {
+    /// Queue a message for the client, waiting while its queue is full
+    async fn send(&self, text: String) -> Result<(), mpsc::error::SendError<WarpMessage>> {
+        match &self.sender {
+            Some(sender) => sender.send(WarpMessage::text(text)).await,
+            // Without a connection there is nowhere to deliver to
+            None => Err(mpsc::error::SendError(WarpMessage::text(text))),
+        }
+    }
+}
+
+/// How many outgoing messages a session queues before senders have to wait
+pub const DEFAULT_SESSION_QUEUE_DEPTH: usize = 32;
+
+/// How many recently sent operations are remembered to recognise ones re-sent after a reconnect
+pub const DEFAULT_OPERATION_RECEIPTS: usize = 4096;
+
+/// Most operations a resuming client is sent as a patch rather than the full content
+pub const MAX_RESUME_PATCH_OPERATIONS: usize = 500;
+
 /// WebSocket server for real-time communication with clients
 #[derive(Clone)]
 pub struct WebSocketServer {
@@ -36,6 +74,31 @@ pub struct WebSocketServer {
     sessions: Arc<RwLock<HashMap<String, ClientSession>>>,
     /// Document branch manager for handling missing document branches
     document_branch_manager: Arc<DocumentBranchManager>,
+    /// Whether messages with unknown fields are rejected
+    strict_protocol: bool,
+    /// How many outgoing messages each session queues
+    session_queue_depth: usize,
+    /// Where sign-ins are recorded, if auditing is enabled
+    audit_log: Option<AuditLog>,
+    /// Checks authentication tokens; without one, clients are taken at their word
+    identity_provider: Option<Arc<dyn IdentityProvider>>,
+    /// How fast each user may send operations, if limited
+    operation_rate_limit: Option<RateLimitConfig>,
+    /// Tokens for the operations each user may send, kept across re-authentication and
+    /// reconnects so neither refills them
+    operation_buckets: Arc<DashMap<String, TokenBucket>>,
+    /// How often each user's presence is broadcast at most, if updates are coalesced
+    presence_broadcast_interval: Option<Duration>,
+    /// Users, by document, whose presence changed and is due to be broadcast
+    pending_presence: Arc<std::sync::Mutex<HashSet<(Uuid, String)>>>,
+    /// Operations clients sent recently, by user and the ID the client gave them
+    operation_receipts: Arc<OperationReceipts>,
+    /// Network engine, for syncing documents with peers on request
+    network_engine: Option<Arc<RwLock<NetworkEngine>>>,
+    /// Git manager, for committing documents to their repositories on request
+    git_manager: Option<Arc<RwLock<GitManager>>>,
+    /// How long to wait for the engine or the sessions before giving up
+    lock_wait: LockWait,
 }
 
 impl WebSocketServer {
@@ -49,59 +112,301 @@ impl WebSocketServer {
             crdt_engine,
             sessions: Arc::new(RwLock::new(HashMap::new())),
             document_branch_manager,
+            strict_protocol: false,
+            session_queue_depth: DEFAULT_SESSION_QUEUE_DEPTH,
+            audit_log: None,
+            identity_provider: None,
+            operation_rate_limit: None,
+            operation_buckets: Arc::new(DashMap::new()),
+            presence_broadcast_interval: None,
+            pending_presence: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            operation_receipts: Arc::new(OperationReceipts::new(DEFAULT_OPERATION_RECEIPTS)),
+            network_engine: None,
+            git_manager: None,
+            lock_wait: LockWait::default(),
         }
     }
 
+    /// Reject messages with unknown fields instead of ignoring them
+    pub fn with_strict_protocol(mut self, strict: bool) -> Self {
+        self.strict_protocol = strict;
+        self
+    }
+
+    /// Set how many outgoing messages each session queues before senders have to wait
+    pub fn with_session_queue_depth(mut self, depth: usize) -> Self {
+        self.session_queue_depth = depth;
+        self
+    }
+
+    /// Limit how fast each user may send operations, telling sessions that go faster when
+    /// to try again
+    pub fn with_operation_rate_limit(mut self, limit: Option<RateLimitConfig>) -> Self {
+        self.operation_rate_limit = limit;
+        self
+    }
+
+    /// Broadcast each user's presence at most once per `interval`, with the latest of the
+    /// updates they sent meanwhile, rather than on every update
+    pub fn with_presence_broadcast_interval(mut self, interval: Option<Duration>) -> Self {
+        self.presence_broadcast_interval = interval;
+        self
+    }
+
+    /// Authenticate clients as the user their token resolves to, rejecting those without a
+    /// valid one
+    pub fn with_identity_provider(mut self, provider: Arc<dyn IdentityProvider>) -> Self {
+        self.identity_provider = Some(provider);
+        self
+    }
+
+    /// Create documents operations arrive for but this node doesn't hold, instead of
+    /// rejecting the operations
+    pub fn with_auto_create_missing_documents(mut self, enabled: bool) -> Self {
+        self.document_branch_manager =
+            Arc::new(DocumentBranchManager::new(Arc::clone(&self.crdt_engine)).with_auto_create(enabled));
+        self
+    }
+
+    /// Fail requests and broadcasts that wait longer than `timeout` for the engine or the
+    /// sessions, rather than stalling behind whoever holds them
+    pub fn with_lock_wait_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.lock_wait = LockWait::new(timeout);
+        self
+    }
+
+    /// Sync documents with peers when clients ask for it with `SyncNow`
+    pub fn with_network_engine(mut self, network_engine: Arc<RwLock<NetworkEngine>>) -> Self {
+        self.network_engine = Some(network_engine);
+        self
+    }
+
+    /// Commit and push documents to their repositories when clients ask for it with `SyncNow`
+    pub fn with_git_manager(mut self, git_manager: Arc<RwLock<GitManager>>) -> Self {
+        self.git_manager = Some(git_manager);
+        self
+    }
+
+    /// Record sign-ins in the audit log
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.audit_log = Some(audit_log);
+    }
+
+    /// Create the channel a session's outgoing messages are queued on
+    pub fn session_channel(&self) -> (mpsc::Sender<WarpMessage>, mpsc::Receiver<WarpMessage>) {
+        mpsc::channel(self.session_queue_depth)
+    }
+
     /// Start the WebSocket server
     pub async fn start(&self, config: &crate::utils::config::Config) -> Result<()> {
-        // Get config values
-        let addr = format!("{}:{}", config.server.ws_host, config.server.ws_port);
-        let socket_addr: std::net::SocketAddr = addr.parse()
-            .map_err(|e| AppError::ApiError(format!("Invalid WebSocket address: {}", e)))?;
-
-        tracing::info!("WebSocket binding to socket address: {}", socket_addr);
+        let addrs = config.server.ws_socket_addrs()?;
 
         // Create a clone of relevant resources for the handler closure
         let server_ref = self.clone();
 
         // Create the WebSocket upgrader with CORS support
-        let make_service = warp::serve(
-            warp::path("ws")
-                .and(warp::ws())
-                .and(warp::any().map(move || server_ref.clone()))
-                .map(|ws: warp::ws::Ws, server: WebSocketServer| {
-                    ws.on_upgrade(move |websocket| handle_websocket_connection(websocket, server))
-                })
-                // Add CORS support for WebSocket handshake
-                .with(warp::cors()
-                    .allow_any_origin()
-                    .allow_headers(vec!["content-type", "authorization"])
-                    .allow_methods(vec!["GET", "POST", "OPTIONS"]))
-        );
-
-        // Start the server in a background task
-        tokio::spawn(async move {
-            make_service.run(socket_addr).await;
-        });
+        let routes = warp::path("ws")
+            .and(warp::ws())
+            .and(warp::any().map(move || server_ref.clone()))
+            .map(|ws: warp::ws::Ws, server: WebSocketServer| {
+                ws.on_upgrade(move |websocket| handle_websocket_connection(websocket, server))
+            })
+            // Add CORS support for WebSocket handshake
+            .with(warp::cors()
+                .allow_any_origin()
+                .allow_headers(vec!["content-type", "authorization"])
+                .allow_methods(vec!["GET", "POST", "OPTIONS"]));
+
+        // Serve on every address, binding them all before serving any so a taken address
+        // fails startup
+        let mut servers = Vec::new();
+        for addr in addrs {
+            tracing::info!("WebSocket binding to socket address: {}", addr);
+            let (_, server) = warp::serve(routes.clone())
+                .try_bind_ephemeral(addr)
+                .map_err(|e| AppError::ApiError(format!("Failed to bind WebSocket server to {}: {}", addr, e)))?;
+            servers.push(server);
+        }
+        for server in servers {
+            tokio::spawn(server);
+        }
+
+        // Forward engine events (comments, etc.) to connected clients
+        self.start_event_forwarding().await;
 
         Ok(())
     }
 
+    /// Forward document events from the CRDT engine to the sessions editing each document
+    pub async fn start_event_forwarding(&self) -> tokio::task::JoinHandle<()> {
+        let mut events = self.crdt_engine.read().await.subscribe_events();
+        let server = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(DocumentEvent::CommentUpdated { document_id, comment }) => {
+                        // Comments are only ever resolved after being added, so an unresolved
+                        // one is new and its mentions haven't been notified yet
+                        if !comment.resolved {
+                            server.notify_mentions(&comment).await;
+                        }
+
+                        let message = ApiMessage::CommentUpdate { document_id, comment };
+                        if let Err(e) = server.broadcast_to_document(document_id, &message).await {
+                            tracing::warn!("Error broadcasting comment update: {:?}", e);
+                        }
+                    }
+                    Ok(DocumentEvent::MetadataChanged { document_id, change }) => {
+                        let message = ApiMessage::MetadataChanged {
+                            document_id,
+                            title: change.title,
+                            owner: change.owner,
+                            tags: change.tags,
+                            collaborators: change.collaborators,
+                            repository_url: change.repository_url,
+                            roles: change.roles,
+                            custom_metadata: change.custom_metadata,
+                        };
+                        if let Err(e) = server.broadcast_to_document(document_id, &message).await {
+                            tracing::warn!("Error broadcasting metadata change: {:?}", e);
+                        }
+                    }
+                    Ok(DocumentEvent::PresenceChanged { document_id, presence }) => {
+                        let message = ApiMessage::PresenceUpdate { document_id, presence };
+                        if let Err(e) = server.broadcast_to_document(document_id, &message).await {
+                            tracing::warn!("Error broadcasting presence change: {:?}", e);
+                        }
+                    }
+                    Ok(DocumentEvent::PresenceRemoved { document_id, user_id }) => {
+                        let message = ApiMessage::PresenceRemoved { document_id, user_id };
+                        if let Err(e) = server.broadcast_to_document(document_id, &message).await {
+                            tracing::warn!("Error broadcasting presence removal: {:?}", e);
+                        }
+                    }
+                    Ok(DocumentEvent::ActivityRecorded { event }) => {
+                        let document_id = event.document_id;
+                        let message = ApiMessage::ActivityUpdate { document_id, activity: event };
+                        if let Err(e) = server.broadcast_to_document(document_id, &message).await {
+                            tracing::warn!("Error broadcasting activity: {:?}", e);
+                        }
+                    }
+                    Ok(DocumentEvent::ChatReceived { document_id, entry }) => {
+                        let message = ApiMessage::ChatMessage {
+                            document_id,
+                            body: entry.body,
+                            user_id: Some(entry.user_id),
+                            timestamp: Some(entry.timestamp),
+                        };
+                        if let Err(e) = server.broadcast_to_document(document_id, &message).await {
+                            tracing::warn!("Error broadcasting chat message: {:?}", e);
+                        }
+                    }
+                    Ok(DocumentEvent::DocumentDeleted { document_id }) => {
+                        if let Err(e) = server.close_document(document_id).await {
+                            tracing::warn!("Error broadcasting document deletion: {:?}", e);
+                        }
+                    }
+                    Ok(DocumentEvent::DocumentIdChanged { old_id, new_id }) => {
+                        if let Err(e) = server.move_document(old_id, new_id).await {
+                            tracing::warn!("Error broadcasting document ID change: {:?}", e);
+                        }
+                    }
+                    // Clients get operations through their own edit flow, but are told when one
+                    // ran into someone else's spot
+                    Ok(DocumentEvent::OperationApplied { document_id, records }) => {
+                        let Some((user_id, hint)) = records
+                            .into_iter()
+                            .find_map(|record| Some((record.agent, record.conflict_hint?)))
+                        else {
+                            continue;
+                        };
+                        let message = ApiMessage::ConflictHint {
+                            document_id,
+                            user_id,
+                            user_ids: hint.user_ids,
+                        };
+                        if let Err(e) = server.broadcast_to_document(document_id, &message).await {
+                            tracing::warn!("Error broadcasting conflict hint: {:?}", e);
+                        }
+                    }
+                    // Resyncs are the network's business
+                    Ok(DocumentEvent::ResyncNeeded { .. }) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("WebSocket event forwarding skipped {} events", skipped);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
     /// Clone the WebSocketServer
     pub fn clone(&self) -> Self {
         Self {
             crdt_engine: Arc::clone(&self.crdt_engine),
             sessions: Arc::clone(&self.sessions),
             document_branch_manager: Arc::clone(&self.document_branch_manager),
+            strict_protocol: self.strict_protocol,
+            session_queue_depth: self.session_queue_depth,
+            audit_log: self.audit_log.clone(),
+            identity_provider: self.identity_provider.clone(),
+            operation_rate_limit: self.operation_rate_limit,
+            operation_buckets: Arc::clone(&self.operation_buckets),
+            presence_broadcast_interval: self.presence_broadcast_interval,
+            pending_presence: Arc::clone(&self.pending_presence),
+            operation_receipts: Arc::clone(&self.operation_receipts),
+            network_engine: self.network_engine.clone(),
+            git_manager: self.git_manager.clone(),
+            lock_wait: self.lock_wait,
         }
     }
 
     /// Handle an incoming API message
     pub async fn handle_message(&self, session_id: &str, message: ApiMessage) -> Result<Option<ApiMessage>> {
+        // Everything except authentication requires an authenticated session
+        if !matches!(message, ApiMessage::Authentication { .. }) && !self.is_authenticated(session_id).await? {
+            return Ok(Some(ApiMessage::Error {
+                code: "unauthenticated".to_string(),
+                message: "Authenticate before sending other messages".to_string(),
+            }));
+        }
+
+        // Any message is a sign of life for the user's presence in their open document
+        if let Ok(ClientSession { user_id, document_id: Some(document_id), spectator: false, .. }) = self.get_session(session_id).await {
+            self.engine().await?.touch_presence(&document_id, &user_id);
+        }
+
         match message {
-            ApiMessage::Authentication { user_id, token: _ } => {
-                // In a real system, we would validate the token
-                self.register_session(session_id, user_id.clone()).await?;
+            ApiMessage::Authentication { user_id, token } => {
+                // With an identity provider, the token decides who the user is
+                let user_id = match (&self.identity_provider, token) {
+                    (None, _) => user_id,
+                    (Some(provider), Some(token)) => match provider.resolve(&token).await {
+                        Ok(identity) => identity.user_id,
+                        Err(e) => {
+                            return Ok(Some(ApiMessage::Error {
+                                code: "auth_failed".to_string(),
+                                message: e.to_string(),
+                            }));
+                        }
+                    },
+                    (Some(_), None) => {
+                        return Ok(Some(ApiMessage::Error {
+                            code: "auth_failed".to_string(),
+                            message: "A token is required to authenticate".to_string(),
+                        }));
+                    }
+                };
+                self.register_session(session_id, user_id.clone(), true).await?;
+                if let Some(audit_log) = &self.audit_log {
+                    audit_log.record(AuditRecord::new(
+                        Some(user_id.clone()),
+                        None,
+                        "authenticate",
+                        serde_json::json!({ "session_id": session_id }),
+                    )).await;
+                }
 
                 // Return a positive authentication response
                 Ok(Some(ApiMessage::Error {
@@ -110,56 +415,150 @@ impl WebSocketServer {
                 }))
             },
 
-            ApiMessage::DocumentOperation { operation } => {
-                // Get the session
-                let session = self.get_session(session_id).await?;
-
-                // Convert API operation to CRDT operation
-                let crdt_op = match operation {
-                    crate::api::protocol::Operation::Insert { document_id, position, content } => {
-                        DocumentOperation::Insert {
-                            document_id,
-                            user_id: session.user_id.clone(),
-                            position,
-                            content,
-                        }
-                    },
-                    crate::api::protocol::Operation::Delete { document_id, range } => {
-                        DocumentOperation::Delete {
-                            document_id,
-                            user_id: session.user_id.clone(),
-                            range,
-                        }
-                    },
-                    crate::api::protocol::Operation::Replace { document_id, range, content } => {
-                        DocumentOperation::Replace {
-                            document_id,
-                            user_id: session.user_id.clone(),
-                            range,
-                            content,
-                        }
-                    },
+            ApiMessage::DocumentOperation { operation, operation_id } => {
+                // An operation the client gave an ID may be sent again after a reconnect, so
+                // remember it by user, which outlives the session, to apply it only once
+                let receipt = match &operation_id {
+                    Some(operation_id) => Some((self.get_session(session_id).await?.user_id, operation_id.clone())),
+                    None => None,
                 };
 
-                // Apply the operation
-                self.apply_operation(crdt_op).await?;
+                // Clients match the result to their operation by ID, so make one up if unset
+                let operation_id = operation_id.unwrap_or_else(|| Uuid::new_v4().to_string());
 
-                Ok(None)
+                match receipt.as_ref().map(|(user_id, id)| self.operation_receipts.claim(user_id, id)) {
+                    Some(Receipt::Applied) => {
+                        return Ok(Some(ApiMessage::OperationResult {
+                            result: OperationResponse { operation_id, success: true, error: None, code: None },
+                        }));
+                    }
+                    Some(Receipt::InFlight) => {
+                        return Ok(Some(ApiMessage::OperationResult {
+                            result: OperationResponse {
+                                operation_id,
+                                success: false,
+                                error: Some("Operation is already being applied".to_string()),
+                                code: Some("in_flight".to_string()),
+                            },
+                        }));
+                    }
+                    Some(Receipt::New) | None => {}
+                }
+
+                // Tell a session that is going too fast when to try again, so it can pace
+                // itself instead of retrying blindly
+                if let Some(retry_after) = self.take_operation_token(session_id).await? {
+                    let retry_after_ms = u64::try_from(retry_after.as_micros().div_ceil(1000)).unwrap_or(u64::MAX);
+                    let throttle = serde_json::to_string(&ApiMessage::Throttle { retry_after_ms })?;
+                    if let Ok(session) = self.get_session(session_id).await
+                        && let Err(e) = session.send(throttle).await
+                    {
+                        tracing::warn!("Error sending throttle message to session: {:?}", e);
+                    }
+                    if let Some((user_id, id)) = &receipt {
+                        self.operation_receipts.release(user_id, id);
+                    }
+
+                    return Ok(Some(ApiMessage::OperationResult {
+                        result: OperationResponse {
+                            operation_id,
+                            success: false,
+                            error: Some(format!("Rate limited: retry after {} ms", retry_after_ms)),
+                            code: Some("rate_limited".to_string()),
+                        },
+                    }));
+                }
+                let error = self.handle_operation(session_id, operation).await.err();
+                let code = error.as_ref().map(|e| match e.downcast_ref::<AppError>() {
+                    Some(AppError::Forbidden(_)) => "forbidden".to_string(),
+                    _ => "error".to_string(),
+                });
+                let error = error.map(|e| e.to_string());
+                if let Some((user_id, id)) = &receipt {
+                    match error {
+                        None => self.operation_receipts.complete(user_id, id),
+                        Some(_) => self.operation_receipts.release(user_id, id),
+                    }
+                }
+
+                Ok(Some(ApiMessage::OperationResult {
+                    result: OperationResponse {
+                        operation_id,
+                        success: error.is_none(),
+                        error,
+                        code,
+                    },
+                }))
             },
 
             ApiMessage::OpenDocument { document_id } => {
                 // Set the active document for this session
-                self.set_active_document(session_id, document_id).await?;
+                self.set_active_document(session_id, document_id, false).await?;
 
-                // Get the document content
-                let engine = self.crdt_engine.read().await;
-                let content = engine.get_document_content(&document_id).await?;
+                // Return the document content, with the version to resume from later
+                let engine = self.engine().await?;
+                let (content, version) = engine.get_versioned_snapshot(&document_id).await?;
 
-                // Return the document content
                 Ok(Some(ApiMessage::DocumentUpdate {
                     document_id,
-                    content,
-                    version: "latest".to_string(), // Simplified version handling
+                    content: content.to_string(),
+                    version: version.to_string(),
+                }))
+            },
+
+            ApiMessage::Spectate { document_id } => {
+                // Watch the document like an editor would, without being able to edit it
+                self.set_active_document(session_id, document_id, true).await?;
+
+                let engine = self.engine().await?;
+                let (content, version) = engine.get_versioned_snapshot(&document_id).await?;
+
+                Ok(Some(ApiMessage::DocumentUpdate {
+                    document_id,
+                    content: content.to_string(),
+                    version: version.to_string(),
+                }))
+            },
+
+            ApiMessage::Resume { document_id, version } => {
+                self.set_active_document(session_id, document_id, false).await?;
+
+                // Send only what the client missed, unless that can't be worked out or is
+                // more than the content itself
+                let engine = self.engine().await?;
+                let (content, current) = engine.get_versioned_snapshot(&document_id).await?;
+                let missed = match version.parse::<usize>() {
+                    Ok(since) => engine
+                        .operations_since(&document_id, since)
+                        .await?
+                        .filter(|operations| operations.len() <= MAX_RESUME_PATCH_OPERATIONS)
+                        .map(|operations| (since, operations)),
+                    Err(_) => None,
+                };
+
+                Ok(Some(match missed {
+                    Some((since, operations)) => ApiMessage::DocumentPatch {
+                        document_id,
+                        base_version: since.to_string(),
+                        version: (since + operations.iter().map(|op| op.len).sum::<usize>()).to_string(),
+                        operations,
+                    },
+                    None => ApiMessage::DocumentUpdate {
+                        document_id,
+                        content: content.to_string(),
+                        version: current.to_string(),
+                    },
+                }))
+            },
+
+            ApiMessage::GetDocumentInfo { document_id } => {
+                // Read the metadata only, leaving the session's active document untouched
+                let engine = self.engine().await?;
+                let document = engine.get_document(&document_id).await?;
+                let doc = document.read().await;
+
+                Ok(Some(ApiMessage::DocumentInfo {
+                    document: DocumentInfoMessage::from(&*doc),
                 }))
             },
 
@@ -168,11 +567,12 @@ impl WebSocketServer {
                 let session = self.get_session(session_id).await?;
 
                 // Create the document
-                let engine = self.crdt_engine.read().await;
+                let engine = self.engine().await?;
                 let document_id = engine.create_document(title, session.user_id.clone()).await?;
+                drop(engine);
 
                 // Set as active document
-                self.set_active_document(session_id, document_id).await?;
+                self.set_active_document(session_id, document_id, false).await?;
 
                 // Return the document ID
                 Ok(Some(ApiMessage::DocumentUpdate {
@@ -183,42 +583,109 @@ impl WebSocketServer {
             },
 
             ApiMessage::ListDocuments => {
-                // Get the session
-                let session = self.get_session(session_id).await?;
+                // Make sure the session exists
+                self.get_session(session_id).await?;
 
-                // Get the list of documents
-                let engine = self.crdt_engine.read().await;
-                let documents = engine.list_documents().await?;
-
-                // Here we would typically filter documents by user or create DocumentSummary objects
-                // For now we'll just return mock document summaries
-                let doc_summaries = documents.iter().map(|_doc| {
-                    crate::api::protocol::DocumentSummary {
-                        id: Uuid::new_v4(), // Would actually extract from doc
-                        title: "Document".to_string(), // Would extract from doc
-                        owner: session.user_id.clone(),
-                        updated_at: chrono::Utc::now().to_rfc3339(),
-                        active_collaborators: 1,
-                    }
-                }).collect();
+                // Here we would typically filter documents by user
+                Ok(Some(ApiMessage::DocumentList {
+                    documents: self.document_summaries(|_| true).await?,
+                }))
+            },
+
+            ApiMessage::ListMyDocuments => {
+                let session = self.get_session(session_id).await?;
 
-                // Return the document list
                 Ok(Some(ApiMessage::DocumentList {
-                    documents: doc_summaries,
+                    documents: self
+                        .document_summaries(|doc| doc.is_collaborator(&session.user_id) || doc.roles.contains_key(&session.user_id))
+                        .await?,
                 }))
             },
 
             ApiMessage::PresenceUpdate { document_id, presence } => {
+                // Spectators stay out of the presence list
+                if self.get_session(session_id).await?.spectator {
+                    return Ok(Some(ApiMessage::Error {
+                        code: "forbidden".to_string(),
+                        message: "Spectators can't share their presence".to_string(),
+                    }));
+                }
+
                 // Update the user's presence
-                let engine = self.crdt_engine.read().await;
-                engine.update_user_presence(document_id, presence).await?;
+                let presence = self.engine().await?.update_user_presence(document_id, presence).await?;
+
+                // Broadcast to other users, right away or once the user's window closes
+                match self.presence_broadcast_interval {
+                    Some(interval) => self.schedule_presence_broadcast(document_id, presence.user_id, interval),
+                    None => self.broadcast_presence(document_id).await?,
+                }
+
+                Ok(None)
+            },
+
+            // Only the sign of life above matters
+            ApiMessage::HeartbeatAck => Ok(None),
 
-                // Broadcast to other users
-                self.broadcast_presence(document_id).await?;
+            ApiMessage::ChatMessage { document_id, body, .. } => {
+                // Anyone who can read the document can discuss it
+                let session = self.get_session(session_id).await?;
+                let engine = self.engine().await?;
+                engine.authorize(&document_id, &session.user_id, Role::Viewer).await?;
+
+                // Sessions here hear about it from the engine, like messages from peers
+                let entry = ChatEntry {
+                    user_id: session.user_id,
+                    body,
+                    timestamp: chrono::Utc::now(),
+                };
+                engine.record_chat(&document_id, entry.clone()).await?;
+                drop(engine);
+
+                if let Some(network_engine) = &self.network_engine
+                    && let Err(e) = network_engine.write().await.broadcast_chat(&document_id, &entry).await
+                {
+                    tracing::warn!("Could not send chat message about document {} to peers: {}", document_id, e);
+                }
 
                 Ok(None)
             },
 
+            ApiMessage::ChatHistory { document_id, .. } => {
+                let session = self.get_session(session_id).await?;
+                let engine = self.engine().await?;
+                engine.authorize(&document_id, &session.user_id, Role::Viewer).await?;
+
+                Ok(Some(ApiMessage::ChatHistory {
+                    document_id,
+                    messages: engine.get_chat_history(&document_id, CHAT_HISTORY_CAPACITY).await?,
+                }))
+            },
+
+            ApiMessage::SyncNow { document_id } => {
+                let session = self.get_session(session_id).await?;
+                self.engine().await?.authorize(&document_id, &session.user_id, Role::Editor).await?;
+
+                // Peers answer in their own time; their operations are merged as they arrive
+                if let Some(network_engine) = &self.network_engine {
+                    let mut network = network_engine.write().await;
+                    if let Err(e) = network.request_sync_from_peers(document_id).await {
+                        tracing::warn!("Could not ask peers to sync document {}: {}", document_id, e);
+                    }
+                }
+
+                let git_committed = match &self.git_manager {
+                    Some(git_manager) => GitManager::sync_now(Arc::clone(git_manager), document_id).await?,
+                    None => false,
+                };
+
+                let (_, version) = self.engine().await?.get_versioned_snapshot(&document_id).await?;
+                Ok(Some(ApiMessage::SyncComplete {
+                    document_id,
+                    version: version.to_string(),
+                    git_committed,
+                }))
+            },
+
             _ => {
                 // Unhandled message type
                 Err(AppError::ApiError(format!("Unhandled message type")).into())
@@ -226,6 +693,111 @@ impl WebSocketServer {
         }
     }
 
+    /// Summaries of the documents `include` picks
+    async fn document_summaries(&self, include: impl Fn(&Document) -> bool) -> Result<Vec<DocumentSummary>> {
+        let engine = self.engine().await?;
+        let documents = engine.list_documents().await?;
+
+        let mut summaries = Vec::new();
+        for doc in documents {
+            let doc = doc.read().await;
+            if !include(&doc) {
+                continue;
+            }
+            summaries.push(DocumentSummary {
+                id: doc.id,
+                slug: doc.slug.clone(),
+                title: doc.title.clone(),
+                owner: doc.owner.clone(),
+                updated_at: doc.updated_at.to_rfc3339(),
+                active_collaborators: engine.active_collaborator_count(&doc.id),
+            });
+        }
+
+        Ok(summaries)
+    }
+
+    /// Take a token for an operation from the bucket of the session's user, or say how long
+    /// until one will be available
+    async fn take_operation_token(&self, session_id: &str) -> Result<Option<Duration>> {
+        let Some(limit) = self.operation_rate_limit else {
+            return Ok(None);
+        };
+        let user_id = self.get_session(session_id).await?.user_id;
+        let mut bucket = self.operation_buckets.entry(user_id).or_insert_with(|| limit.bucket());
+        Ok(bucket.try_take(std::time::Instant::now()).err())
+    }
+
+    /// Apply an operation a session sent, converted to a CRDT operation by the session's user
+    async fn handle_operation(&self, session_id: &str, operation: crate::api::protocol::Operation) -> Result<()> {
+        // Get the session
+        let session = self.get_session(session_id).await?;
+        if session.spectator {
+            return Err(AppError::Forbidden("spectators can't edit documents".to_string()).into());
+        }
+
+        // Convert API operation to CRDT operation
+        let crdt_op = match operation {
+            crate::api::protocol::Operation::Insert { document_id, position, content } => {
+                DocumentOperation::Insert {
+                    document_id,
+                    user_id: session.user_id.clone(),
+                    position,
+                    content,
+                }
+            },
+            crate::api::protocol::Operation::Delete { document_id, range } => {
+                DocumentOperation::Delete {
+                    document_id,
+                    user_id: session.user_id.clone(),
+                    range,
+                }
+            },
+            crate::api::protocol::Operation::Replace { document_id, range, content } => {
+                DocumentOperation::Replace {
+                    document_id,
+                    user_id: session.user_id.clone(),
+                    range,
+                    content,
+                }
+            },
+            crate::api::protocol::Operation::Move { document_id, source, dest } => {
+                DocumentOperation::Move {
+                    document_id,
+                    user_id: session.user_id.clone(),
+                    source,
+                    dest,
+                }
+            },
+        };
+
+        // Only editors may change the text of a document that exists already
+        let denied = self.authorize_operation(&crdt_op).await.err().and_then(|e| match e.downcast::<AppError>() {
+            Ok(AppError::Forbidden(message)) => Some(message),
+            _ => None,
+        });
+        if let Some(message) = denied {
+            return Err(AppError::Forbidden(message).into());
+        }
+
+        // Apply the operation
+        self.apply_operation(crdt_op).await
+    }
+
+    /// Check that the author of an operation may edit its document
+    async fn authorize_operation(&self, operation: &DocumentOperation) -> Result<()> {
+        let (document_id, user_id) = match operation {
+            DocumentOperation::Insert { document_id, user_id, .. }
+            | DocumentOperation::Delete { document_id, user_id, .. }
+            | DocumentOperation::Replace { document_id, user_id, .. }
+            | DocumentOperation::Move { document_id, user_id, .. } => (document_id, user_id),
+        };
+
+        let engine = self.engine().await?;
+        engine.authorize(document_id, user_id, Role::Editor).await?;
+        Ok(())
+    }
+
     /// Apply a document operation to the CRDT engine
     pub async fn apply_operation(&self, operation: DocumentOperation) -> Result<()> {
         // Clone the operation upfront to avoid borrow issues
@@ -236,15 +808,20 @@ impl WebSocketServer {
             DocumentOperation::Insert { document_id, .. } => *document_id,
             DocumentOperation::Delete { document_id, .. } => *document_id,
             DocumentOperation::Replace { document_id, .. } => *document_id,
+            DocumentOperation::Move { document_id, .. } => *document_id,
         };
 
         // Create a title for potential document creation
         let title = format!("Auto-created Document {}", document_id.to_string()[..8].to_string());
 
         // Try to apply the operation
-        let engine = self.crdt_engine.read().await;
-        match engine.apply_local_operation(&document_id, operation_clone.clone()).await {
-            Ok(_) => Ok(()),
+        let engine = self.engine().await?;
+        match engine.apply_typed_operation(&document_id, operation_clone.clone()).await {
+            Ok(ready) => {
+                drop(engine);
+                self.broadcast_operations(&document_id, ready).await;
+                Ok(())
+            },
             Err(e) => {
                 // Check if this is a "Document branch not found" error
                 let error_msg = e.to_string();
@@ -258,8 +835,8 @@ impl WebSocketServer {
                             tracing::info!("Created missing document branch for {}", document_id);
 
                             // Try the operation again with the newly created document
-                            let engine = self.crdt_engine.read().await;
-                            engine.apply_local_operation(&document_id, operation_clone).await?;
+                            let ready = self.engine().await?.apply_typed_operation(&document_id, operation_clone).await?;
+                            self.broadcast_operations(&document_id, ready).await;
                             return Ok(());
                         }
                     }
@@ -271,14 +848,34 @@ impl WebSocketServer {
         }
     }
 
-    /// Register a new client session
-    async fn register_session(&self, session_id: &str, user_id: String) -> Result<()> {
-        let mut sessions = self.sessions.write().await;
+    /// Send operations applied here to peers; runs of typing still open are sent on the next flush
+    async fn broadcast_operations(&self, document_id: &Uuid, ready: Vec<Vec<u8>>) {
+        let Some(network_engine) = &self.network_engine else {
+            return;
+        };
+        if ready.is_empty() {
+            return;
+        }
+
+        let mut network = network_engine.write().await;
+        for encoded in ready {
+            if let Err(e) = network.broadcast_operation(document_id, encoded).await {
+                tracing::warn!("Could not send an operation on document {} to peers: {}", document_id, e);
+            }
+        }
+    }
+
+    /// Register a new client session, or update an existing one
+    ///
+    /// A session registered here without a connection has no sender until `set_sender`.
+    async fn register_session(&self, session_id: &str, user_id: String, authenticated: bool) -> Result<()> {
+        let mut sessions = self.write_sessions().await?;
 
         // Check if this session already exists
         if sessions.contains_key(session_id) {
             // Instead of returning an error, update the existing session if the user_id is different
             if let Some(session) = sessions.get_mut(session_id) {
+                session.authenticated |= authenticated;
                 if session.user_id != user_id {
                     // Update user ID if it changed
                     session.user_id = user_id;
@@ -290,14 +887,13 @@ impl WebSocketServer {
             return Ok(());
         }
 
-        // Create a channel for sending messages to the client
-        let (sender, _) = mpsc::channel(32);
-
         // Create the session
         let session = ClientSession {
             user_id,
             document_id: None,
-            sender,
+            sender: None,
+            authenticated,
+            spectator: false,
         };
 
         // Add the session
@@ -307,21 +903,60 @@ impl WebSocketServer {
         Ok(())
     }
 
+    /// Register a newly connected client, which stays anonymous until it authenticates
+    pub async fn register_connection(&self, session_id: &str, sender: mpsc::Sender<WarpMessage>) -> Result<()> {
+        self.register_session(session_id, "anonymous".to_string(), false).await?;
+        self.set_sender(session_id, sender).await
+    }
+
+    /// Whether a session exists and has authenticated
+    async fn is_authenticated(&self, session_id: &str) -> Result<bool> {
+        let sessions = self.read_sessions().await?;
+        Ok(sessions.get(session_id).is_some_and(|session| session.authenticated))
+    }
+
+    /// The CRDT engine, once no one is changing it
+    async fn engine(&self) -> Result<RwLockReadGuard<'_, CrdtEngine>> {
+        Ok(self.lock_wait.read(&self.crdt_engine, || "the CRDT engine".to_string()).await?)
+    }
+
+    /// The client sessions, for reading
+    async fn read_sessions(&self) -> Result<RwLockReadGuard<'_, HashMap<String, ClientSession>>> {
+        Ok(self.lock_wait.read(&self.sessions, || "the WebSocket sessions".to_string()).await?)
+    }
+
+    /// The client sessions, for changing
+    async fn write_sessions(&self) -> Result<RwLockWriteGuard<'_, HashMap<String, ClientSession>>> {
+        Ok(self.lock_wait.write(&self.sessions, || "the WebSocket sessions".to_string()).await?)
+    }
+
     /// Get a client session
     async fn get_session(&self, session_id: &str) -> Result<ClientSession> {
-        let sessions = self.sessions.read().await;
+        let sessions = self.read_sessions().await?;
 
         sessions.get(session_id)
             .cloned()
             .ok_or_else(|| AppError::ApiError("Session not found".to_string()).into())
     }
 
+    /// Get the active document for a session
+    pub async fn get_active_document(&self, session_id: &str) -> Result<Option<Uuid>> {
+        let session = self.get_session(session_id).await?;
+        Ok(session.document_id)
+    }
+
     /// Set the active document for a session
-    async fn set_active_document(&self, session_id: &str, document_id: Uuid) -> Result<()> {
-        let mut sessions = self.sessions.write().await;
+    ///
+    /// Only users who can read the document may open it, even just to watch it.
+    async fn set_active_document(&self, session_id: &str, document_id: Uuid, spectator: bool) -> Result<()> {
+        let user_id = self.get_session(session_id).await?.user_id;
+        self.engine().await?.authorize(&document_id, &user_id, Role::Viewer).await?;
+
+        let mut sessions = self.write_sessions().await?;
 
         if let Some(session) = sessions.get_mut(session_id) {
             session.document_id = Some(document_id);
+            session.spectator = spectator;
             Ok(())
         } else {
             Err(AppError::ApiError("Session not found".to_string()).into())
@@ -331,23 +966,26 @@ impl WebSocketServer {
     /// Broadcast presence updates for a document
     async fn broadcast_presence(&self, document_id: Uuid) -> Result<()> {
         // Get all sessions for this document
-        let sessions = self.sessions.read().await;
+        let sessions = self.read_sessions().await?;
 
         // Get presence information
-        let engine = self.crdt_engine.read().await;
+        let engine = self.engine().await?;
         let presences = engine.get_document_presences(&document_id).await?;
 
-        // Create the message
-        let message = serde_json::to_string(&ApiMessage::PresenceUpdate {
-            document_id,
-            presence: presences[0].clone(), // Simplified, would actually send all presences
-        })?;
+        // Create one message per user present in the document
+        let messages = presences
+            .into_iter()
+            .map(|presence| serde_json::to_string(&ApiMessage::PresenceUpdate { document_id, presence }))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        // Send to all clients editing this document
+        // Send to all authenticated clients editing this document
         for session in sessions.values() {
-            if let Some(doc_id) = session.document_id {
-                if doc_id == document_id {
-                    if let Err(e) = session.sender.send(WarpMessage::text(message.clone())).await {
+            if let Some(doc_id) = session.document_id
+                && doc_id == document_id
+                && session.authenticated
+            {
+                for message in &messages {
+                    if let Err(e) = session.send(message.clone()).await {
                         eprintln!("Error sending presence update: {:?}", e);
                     }
                 }
@@ -357,10 +995,46 @@ impl WebSocketServer {
         Ok(())
     }
 
+    /// Broadcast a user's presence once `interval` has passed, unless a broadcast of it is
+    /// already due, so a burst of updates goes out as one carrying the latest
+    fn schedule_presence_broadcast(&self, document_id: Uuid, user_id: String, interval: Duration) {
+        let key = (document_id, user_id);
+        if !self.lock_pending_presence().insert(key.clone()) {
+            return;
+        }
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(interval).await;
+            // Updates from here on are due in the next window
+            server.lock_pending_presence().remove(&key);
+
+            let (document_id, user_id) = key;
+            let presence = match server.crdt_engine.read().await.get_document_presences(&document_id).await {
+                Ok(presences) => presences.into_iter().find(|presence| presence.user_id == user_id),
+                Err(e) => {
+                    tracing::warn!("Failed to read presence of {} in document {}: {}", user_id, document_id, e);
+                    return;
+                }
+            };
+
+            // The user may have left during the window
+            if let Some(presence) = presence
+                && let Err(e) = server.broadcast_to_document(document_id, &ApiMessage::PresenceUpdate { document_id, presence }).await
+            {
+                tracing::warn!("Error broadcasting presence update: {:?}", e);
+            }
+        });
+    }
+
+    fn lock_pending_presence(&self) -> std::sync::MutexGuard<'_, HashSet<(Uuid, String)>> {
+        self.pending_presence.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
     /// Broadcast a document update
     pub async fn broadcast_document_update(&self, document_id: Uuid, content: String) -> Result<()> {
         // Get all sessions for this document
-        let sessions = self.sessions.read().await;
+        let sessions = self.read_sessions().await?;
 
         // Create the message
         let message = serde_json::to_string(&ApiMessage::DocumentUpdate {
@@ -369,32 +1043,149 @@ impl WebSocketServer {
             version: "latest".to_string(),
         })?;
 
-        // Send to all clients editing this document
+        // Send to all authenticated clients editing this document
         for session in sessions.values() {
-            if let Some(doc_id) = session.document_id {
-                if doc_id == document_id {
-                    if let Err(e) = session.sender.send(WarpMessage::text(message.clone())).await {
-                        eprintln!("Error sending document update: {:?}", e);
-                    }
-                }
+            if let Some(doc_id) = session.document_id
+                && doc_id == document_id
+                && session.authenticated
+                && let Err(e) = session.send(message.clone()).await
+            {
+                eprintln!("Error sending document update: {:?}", e);
             }
         }
 
         Ok(())
     }
 
+    /// Send a message to every authenticated session editing a document
+    pub async fn broadcast_to_document(&self, document_id: Uuid, message: &ApiMessage) -> Result<()> {
+        let sessions = self.read_sessions().await?;
+        let message = serde_json::to_string(message)?;
+
+        let recipients = sessions
+            .values()
+            .filter(|s| s.authenticated && s.document_id == Some(document_id));
+        for session in recipients {
+            if let Err(e) = session.send(message.clone()).await {
+                tracing::warn!("Error sending message to session: {:?}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tell every session editing a deleted document that it is gone, and close it for them
+    pub async fn close_document(&self, document_id: Uuid) -> Result<()> {
+        let message = serde_json::to_string(&ApiMessage::DocumentDeleted { document_id })?;
+
+        // Clear the references before telling anyone, so nobody acts on the phantom afterwards
+        let recipients: Vec<ClientSession> = {
+            let mut sessions = self.write_sessions().await?;
+            sessions
+                .values_mut()
+                .filter(|s| s.document_id == Some(document_id))
+                .map(|s| {
+                    s.document_id = None;
+                    s.clone()
+                })
+                .collect()
+        };
+
+        for session in recipients.iter().filter(|s| s.authenticated) {
+            if let Err(e) = session.send(message.clone()).await {
+                tracing::warn!("Error sending document deletion to session: {:?}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Point every session editing a document at the ID it moved to, and tell them
+    pub async fn move_document(&self, old_id: Uuid, new_id: Uuid) -> Result<()> {
+        let message = serde_json::to_string(&ApiMessage::DocumentIdChanged { old_id, new_id })?;
+
+        let recipients: Vec<ClientSession> = {
+            let mut sessions = self.write_sessions().await?;
+            sessions
+                .values_mut()
+                .filter(|s| s.document_id == Some(old_id))
+                .map(|s| {
+                    s.document_id = Some(new_id);
+                    s.clone()
+                })
+                .collect()
+        };
+
+        for session in recipients.iter().filter(|s| s.authenticated) {
+            if let Err(e) = session.send(message.clone()).await {
+                tracing::warn!("Error sending document ID change to session: {:?}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send a message to every authenticated session of a user, whichever document they have open
+    ///
+    /// Returns the number of sessions the message was sent to.
+    pub async fn send_to_user(&self, user_id: &str, message: &ApiMessage) -> Result<usize> {
+        let sessions = self.read_sessions().await?;
+        let message = serde_json::to_string(message)?;
+
+        let mut sent = 0;
+        let recipients = sessions
+            .values()
+            .filter(|s| s.authenticated && s.user_id == user_id);
+        for session in recipients {
+            match session.send(message.clone()).await {
+                Ok(()) => sent += 1,
+                Err(e) => tracing::warn!("Error sending message to session: {:?}", e),
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Notify the users mentioned in a comment, other than its author
+    async fn notify_mentions(&self, comment: &Comment) {
+        let message = ApiMessage::Notification {
+            from: comment.user_id.clone(),
+            body: comment.body.clone(),
+        };
+
+        for user_id in comment.mentions().iter().filter(|user_id| **user_id != comment.user_id) {
+            if let Err(e) = self.send_to_user(user_id, &message).await {
+                tracing::warn!("Error notifying {} of a mention: {:?}", user_id, e);
+            }
+        }
+    }
+
     /// Get the sender for a session
     pub async fn get_sender(&self, session_id: &str) -> Result<mpsc::Sender<WarpMessage>> {
-        let sessions = self.sessions.read().await;
+        let sessions = self.read_sessions().await?;
 
         sessions.get(session_id)
-            .map(|session| session.sender.clone())
-            .ok_or_else(|| AppError::ApiError("Session not found".to_string()).into())
+            .ok_or_else(|| AppError::ApiError("Session not found".to_string()))?
+            .sender
+            .clone()
+            .ok_or_else(|| AppError::ApiError("Session has no connection".to_string()).into())
+    }
+
+    /// Set the channel used to send messages to a session's client
+    pub async fn set_sender(&self, session_id: &str, sender: mpsc::Sender<WarpMessage>) -> Result<()> {
+        let mut sessions = self.write_sessions().await?;
+
+        if let Some(session) = sessions.get_mut(session_id) {
+            session.sender = Some(sender);
+            Ok(())
+        } else {
+            Err(AppError::ApiError("Session not found".to_string()).into())
+        }
     }
 
     /// Remove a client session
     pub async fn remove_session(&self, session_id: &str) -> Result<()> {
-        let mut sessions = self.sessions.write().await;
+        let mut sessions = self.write_sessions().await?;
 
         // Get the session to check if it's editing a document
         let document_id = if let Some(session) = sessions.get(session_id) {
@@ -406,6 +1197,11 @@ impl WebSocketServer {
         // Remove the session
         sessions.remove(session_id);
 
+        // A bucket that has refilled is no different from the new one a user would get, so
+        // only those still recovering are kept
+        let now = std::time::Instant::now();
+        self.operation_buckets.retain(|_, bucket| !bucket.is_full(now));
+
         // If the session was editing a document, broadcast presence update
         if let Some(doc_id) = document_id {
             // Drop the lock before making nested async calls
@@ -417,10 +1213,25 @@ impl WebSocketServer {
         Ok(())
     }
 
+    /// Send every connected client a heartbeat each `interval`, or nothing if it is `None`
+    pub fn spawn_heartbeat(&self, interval: Option<Duration>) -> Option<tokio::task::JoinHandle<()>> {
+        let interval = interval?;
+        let server = self.clone();
+        Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                if let Err(e) = server.send_heartbeat().await {
+                    tracing::error!("Error sending heartbeat: {:?}", e);
+                }
+            }
+        }))
+    }
+
     /// Send a heartbeat message to all connected clients
     pub async fn send_heartbeat(&self) -> Result<()> {
         // Get all sessions
-        let sessions = self.sessions.read().await;
+        let sessions = self.read_sessions().await?;
 
         // Create a heartbeat message
         let heartbeat = serde_json::to_string(&ApiMessage::Heartbeat {
@@ -431,7 +1242,7 @@ impl WebSocketServer {
 
         // Send heartbeat to all connected clients
         for (session_id, session) in sessions.iter() {
-            if let Err(e) = session.sender.send(WarpMessage::text(heartbeat.clone())).await {
+            if let Err(e) = session.send(heartbeat.clone()).await {
                 tracing::warn!("Error sending heartbeat to session {}: {:?}", session_id, e);
             }
         }
@@ -449,7 +1260,7 @@ async fn handle_websocket_connection(websocket: warp::ws::WebSocket, server: Web
     let (mut ws_sender, mut ws_receiver) = websocket.split();
 
     // Create a channel for sending messages to the WebSocket
-    let (sender, mut receiver) = mpsc::channel::<WarpMessage>(32);
+    let (sender, mut receiver) = server.session_channel();
 
     // Forward messages from the channel to the WebSocket
     let forward_task = tokio::spawn(async move {
@@ -461,20 +1272,12 @@ async fn handle_websocket_connection(websocket: warp::ws::WebSocket, server: Web
         }
     });
 
-    // Register the session with a temporary user ID, will be updated on authentication
-    if let Err(e) = server.register_session(&session_id, "anonymous".to_string()).await {
+    // Register the session anonymously; it is upgraded on authentication
+    if let Err(e) = server.register_connection(&session_id, sender).await {
         eprintln!("Failed to register session: {:?}", e);
         return;
     }
 
-    // Update the session's sender
-    {
-        let mut sessions = server.sessions.write().await;
-        if let Some(session) = sessions.get_mut(&session_id) {
-            session.sender = sender;
-        }
-    }
-
     // Process incoming WebSocket messages
     while let Some(result) = ws_receiver.next().await {
         match result {
@@ -483,7 +1286,7 @@ async fn handle_websocket_connection(websocket: warp::ws::WebSocket, server: Web
                     let text = message.to_str().unwrap_or_default();
                 // Try to parse the message as an ApiMessage
                 tracing::info!("Received WebSocket message: {}", text);
-                match serde_json::from_str::<ApiMessage>(text) {
+                match ApiMessage::parse(text, server.strict_protocol) {
                         Ok(api_message) => {
                             tracing::info!("Parsed WebSocket message: {:?}", api_message);
                             // Handle the message
@@ -502,8 +1305,12 @@ async fn handle_websocket_connection(websocket: warp::ws::WebSocket, server: Web
                                 },
                                 Err(e) => {
                                     // Send error response
+                                    let code = match e.downcast_ref::<AppError>() {
+                                        Some(AppError::Forbidden(_)) => "forbidden",
+                                        _ => "error",
+                                    };
                                     let error_message = serde_json::to_string(&ApiMessage::Error {
-                                        code: "error".to_string(),
+                                        code: code.to_string(),
                                         message: format!("Error: {}", e),
                                     }).unwrap();
                                     tracing::error!("WebSocket error: {}", e);
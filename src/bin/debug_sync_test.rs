@@ -1,6 +1,7 @@
 use anyhow::Result;
 use p2p_latex_collab::{
     P2PLatexCollab,
+    network::protocol::ProtocolVersion,
     utils::config::{Config, GitConfig, NetworkConfig, ServerConfig, StorageConfig},
     crdt::operations::DocumentOperation,
 };
@@ -189,6 +190,17 @@ fn create_test_config(instance_id: u16) -> Config {
             api_port,
             ws_host: "127.0.0.1".to_string(),
             ws_port,
+            admin_token: None,
+            strict_protocol: false,
+            session_queue_depth: 32,
+            presence_inactive_after_secs: 60,
+            presence_remove_after_secs: 300,
+            api_addresses: vec![],
+            ws_addresses: vec![],
+            heartbeat_interval_secs: Some(30),
+            auth: None,
+            operation_rate_limit: None,
+            presence_broadcast_interval_ms: 100,
         },
         network: NetworkConfig {
             listen_addresses: vec![
@@ -205,21 +217,46 @@ fn create_test_config(instance_id: u16) -> Config {
             peer_id_seed: Some(format!("test-seed-{}", instance_id)),
             enable_mdns: true,
             enable_kad: true,
+            request_timeout_secs: 30,
+            connection_idle_timeout_secs: None,
+            protocol_versions: ProtocolVersion::ALL.to_vec(),
+            bootstrap_repo_url: None,
+            allowed_peers: None,
+            denied_peers: vec![],
+            max_operation_bytes: 1024 * 1024,
+            redial_backoff: Default::default(),
+            sync_mode: Default::default(),
+            poll_interval_secs: 30,
+            content_hash_interval_secs: 0,
+            real_network: false,
             external_addresses: vec![],
         },
         git: GitConfig {
             repositories_path: PathBuf::from(format!("./tmp/debug-test-repos-{}", instance_id)),
             sync_interval_secs: 60,
+            sync_quiet_period_secs: 10,
+            max_concurrent_operations: 4,
+            remote_check_interval_secs: 0,
             github_token: None,
             github_username: Some("test-user".to_string()),
             github_email: Some("test@example.com".to_string()),
+            github_api_url: "https://api.github.com".to_string(),
         },
         storage: StorageConfig {
             documents_path: PathBuf::from(format!("./tmp/debug-test-docs-{}", instance_id)),
             max_document_size_mb: 10,
             enable_autosave: true,
             autosave_interval_seconds: 60,
+            compress_at_rest: false,
+            encryption_key: None,
+            max_documents: 0,
+            trash_retention_secs: 30 * 24 * 60 * 60,
+            audit_log_path: None,
+            audit_log_max_bytes: 64 * 1024 * 1024,
+            write_ahead_log: false,
+            auto_create_missing_documents: false,
         },
+        debug: Default::default(),
     }
 }
 
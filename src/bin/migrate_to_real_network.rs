@@ -7,6 +7,8 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use p2p_latex_collab::crdt::engine::CrdtEngine;
 use p2p_latex_collab::network::engine::NetworkEngine;
+use p2p_latex_collab::network::polling::PollingSync;
+use p2p_latex_collab::network::rendezvous::GitRendezvous;
 use p2p_latex_collab::network::service::RealNetworkService;
 use p2p_latex_collab::utils::config::Config;
 
@@ -46,9 +48,24 @@ async fn main() -> Result<()> {
     println!("Created real network service");
 
     // Start the real service and get the event receiver
-    let _event_receiver = Arc::clone(&real_service).start_event_loop().await?;
+    let event_receiver = Arc::clone(&real_service).start_event_loop().await?;
     println!("Started real network service event loop");
 
+    // Poll peers for operations as well, or instead of gossiping them, if configured to
+    let _event_receiver = match PollingSync::from_config(&config.network, Arc::clone(&real_service), Arc::clone(&crdt_engine)) {
+        Some(polling) => {
+            println!("Polling peers every {}s", config.network.poll_interval_secs);
+            Arc::new(polling).start(event_receiver)
+        }
+        None => event_receiver,
+    };
+
+    // Meet other peers through the bootstrap repository, if one is configured
+    if let Some(rendezvous) = GitRendezvous::from_config(&config.network, &config.git) {
+        rendezvous.start(Arc::clone(&real_service));
+        println!("Started rendezvous through the bootstrap repository");
+    }
+
     // Get the local peer ID from the real service
     let real_peer_id = real_service.local_peer_id.to_string();
     println!("Real service local peer ID: {}", real_peer_id);
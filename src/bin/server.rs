@@ -1,5 +1,5 @@
 use anyhow::Result;
-use p2p_latex_collab::{utils::config::Config, P2PLatexCollab};
+use p2p_latex_collab::{utils::config::Config, utils::signals::run_until_shutdown, P2PLatexCollab};
 use tracing::{info, Level, debug};
 use tracing_subscriber::FmtSubscriber;
 use std::env;
@@ -39,16 +39,8 @@ async fn main() -> Result<()> {
     let app = P2PLatexCollab::new(&config).await?;
     info!("Application initialized successfully");
 
-    // Start the application components
-    app.start().await?;
-    info!("Application started successfully");
-
-    // Wait for termination signal
-    tokio::signal::ctrl_c().await?;
-    info!("Received termination signal, shutting down...");
-
-    // Stop the application components
-    app.stop().await?;
+    // Run until SIGINT or SIGTERM, then stop, flushing documents
+    run_until_shutdown(&app).await?;
     info!("Application stopped successfully");
 
     Ok(())
@@ -0,0 +1,393 @@
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use uuid::Uuid;
+
+use crate::api::protocol::{ApiMessage, Operation};
+use crate::utils::backoff::ExponentialBackoff;
+use crate::utils::errors::AppError;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type UpdateCallback = Arc<dyn Fn(Uuid, &str) + Send + Sync>;
+
+/// How a `TexSwarmClient` connects, reconnects and waits for responses
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Connection attempts made before giving up, both initially and after a disconnect
+    pub max_connect_attempts: u32,
+    /// Delays between connection attempts
+    pub reconnect_backoff: ExponentialBackoff,
+    /// How long a request waits for its response
+    pub request_timeout: Duration,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            max_connect_attempts: 5,
+            reconnect_backoff: ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(5))
+                .with_jitter(0.2),
+            request_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Response a request is waiting for
+///
+/// The server handles a connection's messages one at a time and answers in order, but
+/// broadcasts can arrive in between, so only a matching message completes a request.
+#[derive(Debug, Clone, Copy)]
+enum Expect {
+    /// The `auth_success` acknowledgement
+    Authenticated,
+    /// The content of an opened document
+    Opened(Uuid),
+    /// The content of a newly created document
+    Created,
+    /// The result of the operation with this ID
+    Applied(Uuid),
+}
+
+struct Pending {
+    expect: Expect,
+    /// Where the response goes; `None` for requests replayed after a reconnect
+    reply: Option<oneshot::Sender<Result<ApiMessage, String>>>,
+}
+
+/// State of the current connection and what to restore on the next one
+#[derive(Default)]
+struct Connection {
+    /// Outgoing messages, while connected
+    writer: Option<mpsc::UnboundedSender<String>>,
+    /// Requests waiting for a response, oldest first
+    pending: VecDeque<Pending>,
+    /// User ID and token to authenticate with again after a reconnect
+    credentials: Option<(String, Option<String>)>,
+    /// Document to open again after a reconnect
+    document_id: Option<Uuid>,
+}
+
+struct Shared {
+    url: String,
+    config: ClientConfig,
+    connection: Mutex<Connection>,
+    callbacks: RwLock<Vec<UpdateCallback>>,
+}
+
+/// Typed client for the WebSocket API
+///
+/// Requests are correlated with their responses internally. If the connection drops, the
+/// client reconnects, authenticates again and reopens the active document; requests that
+/// were in flight fail.
+pub struct TexSwarmClient {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+impl TexSwarmClient {
+    /// Connect to a server, e.g. `ws://127.0.0.1:8091/ws`
+    pub async fn connect(url: &str) -> Result<Self> {
+        Self::connect_with(url, ClientConfig::default()).await
+    }
+
+    /// Connect to a server with the given connection settings
+    pub async fn connect_with(url: &str, config: ClientConfig) -> Result<Self> {
+        let shared = Arc::new(Shared {
+            url: url.to_string(),
+            config,
+            connection: Mutex::new(Connection::default()),
+            callbacks: RwLock::new(Vec::new()),
+        });
+
+        let stream = connect_with_retry(&shared.url, &shared.config).await?;
+        let outgoing = shared.attach();
+        let task = tokio::spawn(run_connection(Arc::clone(&shared), stream, outgoing));
+
+        Ok(Self { shared, task })
+    }
+
+    /// Authenticate as a user
+    pub async fn authenticate(&self, user_id: &str, token: Option<String>) -> Result<()> {
+        let message = ApiMessage::Authentication {
+            user_id: user_id.to_string(),
+            token: token.clone(),
+        };
+
+        match self.request(vec![message], Expect::Authenticated).await? {
+            ApiMessage::Error { code, .. } if code == "auth_success" => {
+                self.shared.lock().credentials = Some((user_id.to_string(), token));
+                Ok(())
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Create a document, which becomes the active one
+    pub async fn create_document(&self, title: &str) -> Result<Uuid> {
+        let message = ApiMessage::CreateDocument {
+            title: title.to_string(),
+            repository_url: None,
+        };
+
+        match self.request(vec![message], Expect::Created).await? {
+            ApiMessage::DocumentUpdate { document_id, .. } => {
+                self.shared.lock().document_id = Some(document_id);
+                Ok(document_id)
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Open a document, making it the active one, and return its content
+    pub async fn open_document(&self, document_id: Uuid) -> Result<String> {
+        let message = ApiMessage::OpenDocument { document_id };
+
+        match self.request(vec![message], Expect::Opened(document_id)).await? {
+            ApiMessage::DocumentUpdate { content, .. } => {
+                self.shared.lock().document_id = Some(document_id);
+                Ok(content)
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Insert text at a position
+    pub async fn insert(&self, document_id: Uuid, position: usize, content: &str) -> Result<()> {
+        self.apply(Operation::Insert {
+            document_id,
+            position,
+            content: content.to_string(),
+        }).await
+    }
+
+    /// Delete the text in a range
+    pub async fn delete(&self, document_id: Uuid, range: Range<usize>) -> Result<()> {
+        self.apply(Operation::Delete { document_id, range }).await
+    }
+
+    /// Call `callback` with the document ID and content of every document update the
+    /// server pushes, including the content reloaded after a reconnect
+    pub fn on_update<F>(&self, callback: F)
+    where
+        F: Fn(Uuid, &str) + Send + Sync + 'static,
+    {
+        self.shared.callbacks.write().unwrap().push(Arc::new(callback));
+    }
+
+    /// Close the connection
+    pub fn close(self) {
+        self.task.abort();
+    }
+
+    /// Send an operation and wait until the server has applied it
+    async fn apply(&self, operation: Operation) -> Result<()> {
+        let operation_id = Uuid::new_v4();
+        let messages = vec![ApiMessage::DocumentOperation {
+            operation,
+            operation_id: Some(operation_id.to_string()),
+        }];
+
+        match self.request(messages, Expect::Applied(operation_id)).await? {
+            ApiMessage::OperationResult { result } if result.success => Ok(()),
+            ApiMessage::OperationResult { result } => Err(AppError::ApiError(
+                result.error.unwrap_or_else(|| "Operation failed".to_string()),
+            ).into()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Send messages and wait for the response matching `expect`
+    async fn request(&self, messages: Vec<ApiMessage>, expect: Expect) -> Result<ApiMessage> {
+        let (reply, response) = oneshot::channel();
+
+        {
+            // Queue the request while sending, so responses are matched in send order
+            let mut connection = self.shared.lock();
+            let writer = connection.writer.clone()
+                .ok_or_else(|| AppError::NetworkError("Not connected to the server".to_string()))?;
+            for message in &messages {
+                writer.send(serde_json::to_string(message)?)
+                    .map_err(|_| AppError::NetworkError("Not connected to the server".to_string()))?;
+            }
+            connection.pending.push_back(Pending { expect, reply: Some(reply) });
+        }
+
+        // A request that times out stays queued, so its late response isn't taken for another's
+        match tokio::time::timeout(self.shared.config.request_timeout, response).await {
+            Ok(Ok(Ok(message))) => Ok(message),
+            Ok(Ok(Err(e))) => Err(AppError::NetworkError(e).into()),
+            Ok(Err(_)) => Err(AppError::NetworkError("Client closed".to_string()).into()),
+            Err(_) => Err(AppError::NetworkError("Timed out waiting for a response".to_string()).into()),
+        }
+    }
+}
+
+impl Drop for TexSwarmClient {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap()
+    }
+
+    /// Start sending on a new connection, restoring the session of the previous one
+    fn attach(&self) -> mpsc::UnboundedReceiver<String> {
+        let (writer, outgoing) = mpsc::unbounded_channel();
+        let mut connection = self.lock();
+
+        let mut replay = Vec::new();
+        if let Some((user_id, token)) = connection.credentials.clone() {
+            replay.push((ApiMessage::Authentication { user_id, token }, Expect::Authenticated));
+        }
+        if let Some(document_id) = connection.document_id {
+            replay.push((ApiMessage::OpenDocument { document_id }, Expect::Opened(document_id)));
+        }
+        for (message, expect) in replay {
+            if let Ok(text) = serde_json::to_string(&message) {
+                let _ = writer.send(text);
+                connection.pending.push_back(Pending { expect, reply: None });
+            }
+        }
+
+        connection.writer = Some(writer);
+        outgoing
+    }
+
+    /// Stop sending, failing every request still waiting for a response
+    fn detach(&self, reason: &str) {
+        let mut connection = self.lock();
+        connection.writer = None;
+        for pending in connection.pending.drain(..) {
+            if let Some(reply) = pending.reply {
+                let _ = reply.send(Err(reason.to_string()));
+            }
+        }
+    }
+
+    /// Route a message from the server to the request waiting for it, or to the callbacks
+    fn dispatch(&self, message: ApiMessage) {
+        let unsolicited = {
+            let mut connection = self.lock();
+            match connection.pending.front() {
+                Some(pending) => match (pending.expect, &message) {
+                    (_, ApiMessage::Error { .. }) => self.complete(&mut connection, message),
+                    (Expect::Applied(id), ApiMessage::OperationResult { result }) if result.operation_id == id.to_string() => {
+                        self.complete(&mut connection, message)
+                    }
+                    (Expect::Opened(id), ApiMessage::DocumentUpdate { document_id, .. }) if *document_id == id => {
+                        self.complete(&mut connection, message)
+                    }
+                    (Expect::Created, ApiMessage::DocumentUpdate { version, .. }) if version == "initial" => {
+                        self.complete(&mut connection, message)
+                    }
+                    _ => Some(message),
+                },
+                None => Some(message),
+            }
+        };
+
+        if let Some(ApiMessage::DocumentUpdate { document_id, content, .. }) = unsolicited {
+            let callbacks = self.callbacks.read().unwrap().clone();
+            for callback in callbacks {
+                callback(document_id, &content);
+            }
+        }
+    }
+
+    /// Hand a response to the oldest request
+    ///
+    /// Returns the response if nobody is waiting for it, as for requests replayed after a
+    /// reconnect.
+    fn complete(&self, connection: &mut Connection, message: ApiMessage) -> Option<ApiMessage> {
+        match connection.pending.pop_front().and_then(|pending| pending.reply) {
+            Some(reply) => {
+                let _ = reply.send(Ok(message));
+                None
+            }
+            None => Some(message),
+        }
+    }
+}
+
+/// Pump messages over a connection, reconnecting whenever it drops
+async fn run_connection(shared: Arc<Shared>, mut stream: WsStream, mut outgoing: mpsc::UnboundedReceiver<String>) {
+    loop {
+        let (mut sink, mut incoming) = stream.split();
+
+        loop {
+            tokio::select! {
+                Some(text) = outgoing.recv() => {
+                    if let Err(e) = sink.send(Message::Text(text)).await {
+                        tracing::warn!("Error sending WebSocket message: {}", e);
+                        break;
+                    }
+                }
+                message = incoming.next() => match message {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<ApiMessage>(&text) {
+                        Ok(message) => shared.dispatch(message),
+                        Err(e) => tracing::warn!("Ignoring unparseable server message: {}", e),
+                    },
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        tracing::warn!("WebSocket connection error: {}", e);
+                        break;
+                    }
+                },
+            }
+        }
+
+        shared.detach("Connection to the server was lost");
+
+        stream = match connect_with_retry(&shared.url, &shared.config).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("Giving up reconnecting to {}: {}", shared.url, e);
+                return;
+            }
+        };
+        outgoing = shared.attach();
+        tracing::info!("Reconnected to {}", shared.url);
+    }
+}
+
+/// Connect, retrying with exponential backoff
+async fn connect_with_retry(url: &str, config: &ClientConfig) -> Result<WsStream> {
+    let mut backoff = config.reconnect_backoff.clone();
+    let mut attempt = 1;
+
+    loop {
+        match connect_async(url).await {
+            Ok((stream, _)) => return Ok(stream),
+            Err(e) if attempt >= config.max_connect_attempts => {
+                return Err(AppError::NetworkError(format!(
+                    "Failed to connect to {} after {} attempts: {}", url, attempt, e
+                )).into());
+            }
+            Err(e) => {
+                tracing::debug!("Connecting to {} failed (attempt {}): {}", url, attempt, e);
+                tokio::time::sleep(backoff.next_delay()).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Turn a response the request didn't ask for, usually a server error, into an error
+fn unexpected(message: ApiMessage) -> anyhow::Error {
+    match message {
+        ApiMessage::Error { code, message } => AppError::ApiError(format!("{}: {}", code, message)).into(),
+        other => AppError::ProtocolError(format!("Unexpected response: {:?}", other)).into(),
+    }
+}
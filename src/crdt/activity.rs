@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use uuid::Uuid;
+
+/// How many entries each document's activity feed keeps
+pub const ACTIVITY_FEED_CAPACITY: usize = 200;
+
+/// What happened in a document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ActivityKind {
+    /// A user edited the text
+    Edited { user_id: String },
+    /// A user joined the document
+    Joined { user_id: String },
+    /// A user left the document, or went silent for too long
+    Left { user_id: String },
+    /// A user added a comment
+    Commented { user_id: String, comment_id: Uuid },
+    /// The document was committed to its Git repository
+    SyncedToGit { commit: Option<String> },
+}
+
+/// An entry in a document's activity feed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityEvent {
+    /// Position in the document's feed, increasing with every new entry
+    pub sequence: u64,
+    pub document_id: Uuid,
+    pub at: chrono::DateTime<chrono::Utc>,
+    #[serde(flatten)]
+    pub kind: ActivityKind,
+}
+
+/// Recent activity in a document, oldest first, dropping the oldest entries once full
+#[derive(Debug, Clone)]
+pub struct ActivityFeed {
+    document_id: Uuid,
+    capacity: usize,
+    next_sequence: u64,
+    entries: VecDeque<ActivityEvent>,
+}
+
+impl ActivityFeed {
+    pub fn new(document_id: Uuid, capacity: usize) -> Self {
+        Self {
+            document_id,
+            capacity,
+            next_sequence: 0,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Record an activity, returning its entry if one was added
+    ///
+    /// An edit by the user who made the latest entry's edit refreshes that entry instead of
+    /// adding another, so a run of typing shows up once.
+    pub fn record(&mut self, kind: ActivityKind) -> Option<ActivityEvent> {
+        let now = chrono::Utc::now();
+
+        let repeated_edit = self
+            .entries
+            .back_mut()
+            .filter(|last| last.kind == kind && matches!(kind, ActivityKind::Edited { .. }));
+        if let Some(last) = repeated_edit {
+            last.at = now;
+            return None;
+        }
+
+        let event = ActivityEvent {
+            sequence: self.next_sequence,
+            document_id: self.document_id,
+            at: now,
+            kind,
+        };
+        self.next_sequence += 1;
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(event.clone());
+
+        Some(event)
+    }
+
+    /// The most recent `limit` entries, oldest first
+    pub fn recent(&self, limit: usize) -> Vec<ActivityEvent> {
+        let skip = self.entries.len().saturating_sub(limit);
+        self.entries.iter().skip(skip).cloned().collect()
+    }
+}
@@ -0,0 +1,65 @@
+use diamond_types::AgentId;
+use diamond_types::list::OpLog;
+use serde::{Deserialize, Serialize};
+
+/// Name registered on a scratch copy of an OpLog to find how many agents it has
+const UNREGISTERED_AGENT: &str = "\0unregistered";
+
+/// Persisted `user_id -> agent_id` mapping for a document's OpLog
+///
+/// Agent ids are allocated per OpLog instance in the order users are first seen, so
+/// decoding an exported OpLog into a fresh instance may hand out different ids. Storing
+/// the mapping next to the exported bytes lets an import restore the original ids.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentMap {
+    /// User IDs indexed by agent ID
+    agents: Vec<String>,
+}
+
+impl AgentMap {
+    /// Capture the agent mapping of an OpLog
+    pub fn from_oplog(oplog: &OpLog) -> Self {
+        // The OpLog doesn't expose its agent count, but registering a name no user can have
+        // on a copy hands out the next free ID, which is the count. Counting the agents seen
+        // in the history instead would drop any registered after the last one that edited.
+        let agent_count = oplog.clone().get_or_create_agent_id(UNREGISTERED_AGENT) as usize;
+
+        let agents = (0..agent_count)
+            .map(|agent| oplog.get_agent_name(agent as AgentId).to_string())
+            .collect();
+
+        Self { agents }
+    }
+
+    /// Create an empty OpLog with every agent pre-registered under its original ID
+    pub fn new_oplog(&self) -> OpLog {
+        let mut oplog = OpLog::new();
+        for user_id in &self.agents {
+            oplog.get_or_create_agent_id(user_id);
+        }
+        oplog
+    }
+
+    /// Get the agent ID for a user
+    pub fn agent_id(&self, user_id: &str) -> Option<AgentId> {
+        self.agents
+            .iter()
+            .position(|name| name == user_id)
+            .map(|agent| agent as AgentId)
+    }
+
+    /// Get the user ID for an agent
+    pub fn user_id(&self, agent: AgentId) -> Option<&str> {
+        self.agents.get(agent as usize).map(|name| name.as_str())
+    }
+
+    /// Number of agents in the mapping
+    pub fn len(&self) -> usize {
+        self.agents.len()
+    }
+
+    /// Whether the mapping contains no agents
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty()
+    }
+}
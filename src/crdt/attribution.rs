@@ -0,0 +1,74 @@
+use diamond_types::list::OpLog;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Text a move took out and put back, identified by the sequence numbers of the mover's
+/// delete and insert, which are the same on every replica
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MovedText {
+    pub mover: String,
+    pub deleted: Range<usize>,
+    pub inserted: Range<usize>,
+}
+
+impl MovedText {
+    /// The move made by an OpLog's latest operations, given how many characters it moved
+    ///
+    /// A move is applied as a delete and then an insert of the same length, both by the
+    /// mover, so their sequence numbers run on from one another.
+    pub fn last_in(oplog: &OpLog, len: usize) -> Option<Self> {
+        let last = oplog.len().checked_sub(1)?;
+        let id = oplog.local_to_remote_time(last);
+        let end = id.seq + 1;
+        let inserted = end.checked_sub(len)?..end;
+        let deleted = inserted.start.checked_sub(len)?..inserted.start;
+        Some(Self {
+            mover: id.agent.to_string(),
+            deleted,
+            inserted,
+        })
+    }
+}
+
+/// The moves made to a document, so text keeps its original authors after being moved
+///
+/// The OpLog credits moved text to whoever moved it, as that is the agent that inserted it
+/// again. Replaying the history against this works out who wrote it in the first place.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attribution {
+    moves: Vec<MovedText>,
+}
+
+impl Attribution {
+    pub fn record(&mut self, moved: MovedText) {
+        self.moves.push(moved);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// How many characters the `index`th move moved
+    pub fn moved_len(&self, index: usize) -> usize {
+        self.moves.get(index).map_or(0, |moved| moved.deleted.len())
+    }
+
+    /// The move whose delete is the operation `seq` of `agent`, and the offset into the
+    /// moved text of the character it deleted
+    pub fn deleted_by(&self, agent: &str, seq: usize) -> Option<(usize, usize)> {
+        self.find(agent, seq, |moved| &moved.deleted)
+    }
+
+    /// The move whose insert is the operation `seq` of `agent`, and the offset into the moved
+    /// text of the character it inserted
+    pub fn inserted_by(&self, agent: &str, seq: usize) -> Option<(usize, usize)> {
+        self.find(agent, seq, |moved| &moved.inserted)
+    }
+
+    fn find(&self, agent: &str, seq: usize, span: impl Fn(&MovedText) -> &Range<usize>) -> Option<(usize, usize)> {
+        self.moves.iter().enumerate().find_map(|(index, moved)| {
+            let span = span(moved);
+            (moved.mover == agent && span.contains(&seq)).then(|| (index, seq - span.start))
+        })
+    }
+}
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::utils::errors::AppError;
+
+/// How many messages each document's chat keeps
+pub const CHAT_HISTORY_CAPACITY: usize = 200;
+
+/// Longest chat message accepted, in characters
+pub const MAX_CHAT_BODY_LENGTH: usize = 4000;
+
+/// A message collaborators sent about a document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatEntry {
+    pub user_id: String,
+    pub body: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl ChatEntry {
+    /// Check that a message has something to say, and not too much of it
+    pub fn check_body(body: &str) -> Result<(), AppError> {
+        if body.trim().is_empty() {
+            return Err(AppError::OperationRejected("Chat messages can't be empty".to_string()));
+        }
+        if body.chars().count() > MAX_CHAT_BODY_LENGTH {
+            return Err(AppError::OperationRejected(format!(
+                "Chat messages are limited to {} characters",
+                MAX_CHAT_BODY_LENGTH
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Recent chat in a document, oldest first, dropping the oldest messages once full
+#[derive(Debug, Clone)]
+pub struct ChatLog {
+    capacity: usize,
+    entries: VecDeque<ChatEntry>,
+}
+
+impl ChatLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Add a message, returning false if the log already holds it
+    ///
+    /// Messages are kept in the order they were sent, so one that arrives late from a peer
+    /// still shows up in its place.
+    pub fn record(&mut self, entry: ChatEntry) -> bool {
+        if self.entries.contains(&entry) {
+            return false;
+        }
+
+        let position = self
+            .entries
+            .iter()
+            .rposition(|existing| existing.timestamp <= entry.timestamp)
+            .map_or(0, |index| index + 1);
+        self.entries.insert(position, entry);
+
+        if self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+        true
+    }
+
+    /// The most recent `limit` messages, oldest first
+    pub fn recent(&self, limit: usize) -> Vec<ChatEntry> {
+        let skip = self.entries.len().saturating_sub(limit);
+        self.entries.iter().skip(skip).cloned().collect()
+    }
+}
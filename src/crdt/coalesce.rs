@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use super::operations::DocumentOperation;
+
+/// How long a user may pause between keystrokes before their typing starts a new run
+pub const COALESCE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Text a user is still typing, not yet broadcast
+#[derive(Debug)]
+struct Run {
+    document_id: Uuid,
+    user_id: String,
+    position: usize,
+    content: String,
+    last_at: Instant,
+}
+
+impl Run {
+    /// Start a run with a keystroke
+    fn start(operation: &DocumentOperation, now: Instant) -> Option<Self> {
+        match operation {
+            DocumentOperation::Insert { document_id, user_id, position, content } if InsertCoalescer::is_keystroke(operation) => {
+                Some(Self {
+                    document_id: *document_id,
+                    user_id: user_id.clone(),
+                    position: *position,
+                    content: content.clone(),
+                    last_at: now,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Extend the run with a keystroke typed right after it, within the window
+    fn absorb(&mut self, operation: &DocumentOperation, now: Instant, window: Duration) -> bool {
+        let DocumentOperation::Insert { position, content, .. } = operation else {
+            return false;
+        };
+        if content.chars().count() != 1 || *position != self.end() || now - self.last_at > window {
+            return false;
+        }
+
+        self.content.push_str(content);
+        self.last_at = now;
+        true
+    }
+
+    /// Position just after the run, where the next keystroke continues it
+    fn end(&self) -> usize {
+        self.position + self.content.chars().count()
+    }
+
+    fn into_operation(self) -> DocumentOperation {
+        DocumentOperation::Insert {
+            document_id: self.document_id,
+            user_id: self.user_id,
+            position: self.position,
+            content: self.content,
+        }
+    }
+}
+
+/// Merges runs of single-character inserts by the same user into one insert
+///
+/// Typing "hello" would otherwise be broadcast as five operations and undone in five
+/// steps. The operations themselves are applied to the CRDT as they arrive; only their
+/// broadcast and undo granularity is coalesced.
+#[derive(Debug)]
+pub struct InsertCoalescer {
+    window: Duration,
+    runs: HashMap<(Uuid, String), Run>,
+}
+
+impl InsertCoalescer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            runs: HashMap::new(),
+        }
+    }
+
+    /// Whether an operation is a single keystroke that can start or extend a run
+    pub fn is_keystroke(operation: &DocumentOperation) -> bool {
+        matches!(operation, DocumentOperation::Insert { content, .. } if content.chars().count() == 1)
+    }
+
+    /// Add a typed operation that has just been applied
+    ///
+    /// Keystrokes are held back in a run. Returns the runs this operation ended, as of before
+    /// it was applied, in the order they should be broadcast ahead of the operation.
+    pub fn push(&mut self, operation: &DocumentOperation, now: Instant) -> Vec<DocumentOperation> {
+        let key = (operation.document_id(), operation.user_id().to_string());
+
+        let window = self.window;
+        if self.runs.get_mut(&key).is_some_and(|run| run.absorb(operation, now, window)) {
+            return Vec::new();
+        }
+
+        let mut ended: Vec<DocumentOperation> = self.runs.remove(&key).map(Run::into_operation).into_iter().collect();
+        ended.extend(self.apply(operation));
+
+        if let Some(run) = Run::start(operation, now) {
+            self.runs.insert(key, run);
+        }
+
+        ended
+    }
+
+    /// Account for an operation outside of the runs having been applied
+    ///
+    /// Runs the operation does not touch are shifted past it. Runs it edits inside of are
+    /// ended, as of before the operation, and returned.
+    pub fn apply(&mut self, operation: &DocumentOperation) -> Vec<DocumentOperation> {
+        let document_id = operation.document_id();
+        let touched = operation.affected_range();
+
+        let mut ended = Vec::new();
+        let keys: Vec<_> = self.runs.keys().filter(|(id, _)| *id == document_id).cloned().collect();
+        for key in keys {
+            let run = self.runs.get_mut(&key).expect("run exists");
+            if touched.end <= run.position {
+                run.position = operation.transform_position(run.position);
+            } else if touched.start < run.end() {
+                ended.extend(self.runs.remove(&key).map(Run::into_operation));
+            }
+        }
+
+        ended
+    }
+
+    /// End the runs nobody has typed into for longer than the window
+    pub fn close_expired(&mut self, now: Instant) -> Vec<DocumentOperation> {
+        let expired: Vec<_> = self
+            .runs
+            .iter()
+            .filter(|(_, run)| now - run.last_at > self.window)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|key| self.runs.remove(&key))
+            .map(Run::into_operation)
+            .collect()
+    }
+
+    /// End a user's run in a document, if they have one
+    pub fn close_user(&mut self, document_id: Uuid, user_id: &str) -> Option<DocumentOperation> {
+        self.runs.remove(&(document_id, user_id.to_string())).map(Run::into_operation)
+    }
+
+    /// Drop the runs in a document without ending them
+    pub fn discard_document(&mut self, document_id: Uuid) {
+        self.runs.retain(|(id, _), _| *id != document_id);
+    }
+}
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use uuid::Uuid;
+
+use super::operations::DocumentOperation;
+
+/// A review comment anchored to a range of document text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Comment {
+    /// Comment ID
+    pub id: Uuid,
+    /// Document ID
+    pub doc_id: Uuid,
+    /// Author of the comment
+    pub user_id: String,
+    /// Anchored range (start..end), rebased as the text changes
+    pub range: Range<usize>,
+    /// Comment text
+    pub body: String,
+    /// Whether the comment has been resolved
+    pub resolved: bool,
+    /// Creation time
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Comment {
+    pub fn new(doc_id: Uuid, user_id: String, range: Range<usize>, body: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            doc_id,
+            user_id,
+            range,
+            body,
+            resolved: false,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    /// Move the anchor to account for an applied operation
+    pub fn rebase(&mut self, operation: &DocumentOperation) {
+        self.range = operation.transform_range(&self.range);
+    }
+
+    pub fn resolve(&mut self) {
+        self.resolved = true;
+    }
+
+    /// Users mentioned in the body as `@user_id`, in order of first mention
+    pub fn mentions(&self) -> Vec<String> {
+        let mut mentions: Vec<String> = Vec::new();
+        for word in self.body.split_whitespace() {
+            let Some(name) = word.strip_prefix('@') else { continue };
+            // Trailing punctuation such as "@bob," or "@bob." isn't part of the name
+            let name = name.trim_end_matches(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'));
+            if !name.is_empty() && !mentions.iter().any(|m| m == name) {
+                mentions.push(name.to_string());
+            }
+        }
+        mentions
+    }
+}
@@ -0,0 +1,47 @@
+use similar::{DiffTag, TextDiff};
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::operations::DocumentOperation;
+
+/// How long to search for a minimal diff before settling for a coarser one
+const DIFF_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Work out the operations that turn `old` into `new`, touching only the text that changed
+///
+/// The operations are ordered from the end of the document to the start, so each one's
+/// positions are still valid after the ones before it have been applied.
+pub fn diff_operations(document_id: Uuid, user_id: &str, old: &str, new: &str) -> Vec<DocumentOperation> {
+    let diff = TextDiff::configure().timeout(DIFF_TIMEOUT).diff_chars(old, new);
+    let new_chars: Vec<char> = new.chars().collect();
+    let text = |range: std::ops::Range<usize>| new_chars[range].iter().collect::<String>();
+
+    diff.ops()
+        .iter()
+        .rev()
+        .filter_map(|op| {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            let operation = match tag {
+                DiffTag::Equal => return None,
+                DiffTag::Delete => DocumentOperation::Delete {
+                    document_id,
+                    user_id: user_id.to_string(),
+                    range: old_range,
+                },
+                DiffTag::Insert => DocumentOperation::Insert {
+                    document_id,
+                    user_id: user_id.to_string(),
+                    position: old_range.start,
+                    content: text(new_range),
+                },
+                DiffTag::Replace => DocumentOperation::Replace {
+                    document_id,
+                    user_id: user_id.to_string(),
+                    range: old_range,
+                    content: text(new_range),
+                },
+            };
+            Some(operation)
+        })
+        .collect()
+}
@@ -1,19 +1,192 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use uuid::Uuid;
 
+use crate::utils::errors::AppError;
+
+/// Character encoding a document is exchanged in at the API boundary
+///
+/// Inside the CRDT, content is always Unicode text; the encoding only governs how raw
+/// bytes are decoded on the way in and encoded on the way out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DocumentEncoding {
+    #[default]
+    Utf8,
+    Latin1,
+}
+
+impl DocumentEncoding {
+    /// Name of the encoding as used in a `charset` parameter
+    pub fn charset(&self) -> &'static str {
+        match self {
+            DocumentEncoding::Utf8 => "utf-8",
+            DocumentEncoding::Latin1 => "iso-8859-1",
+        }
+    }
+
+    /// Decode raw bytes into text
+    ///
+    /// Invalid UTF-8 sequences are replaced with U+FFFD rather than rejected. Every byte is
+    /// valid Latin-1, so Latin-1 decoding is lossless.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            DocumentEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            DocumentEncoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+        }
+    }
+
+    /// Encode text into raw bytes, failing for characters the encoding cannot represent
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, AppError> {
+        match self {
+            DocumentEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            DocumentEncoding::Latin1 => text
+                .chars()
+                .map(|c| {
+                    u8::try_from(c).map_err(|_| {
+                        AppError::CrdtError(format!("Character {:?} cannot be encoded as Latin-1", c))
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Who may learn that a document exists
+///
+/// Private documents are only reachable by peers that are given their ID. Public documents
+/// are also announced to the network, so any peer can discover and join them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DocumentVisibility {
+    #[default]
+    Private,
+    Public,
+}
+
+/// What a user may do with a document, from least to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Can only read
+    Viewer,
+    /// Can read and comment, but not edit the text
+    Commenter,
+    /// Can edit the text
+    Editor,
+    Owner,
+}
+
+impl Role {
+    pub fn can_comment(&self) -> bool {
+        *self >= Role::Commenter
+    }
+
+    pub fn can_edit(&self) -> bool {
+        *self >= Role::Editor
+    }
+}
+
+/// Most custom metadata entries a document may have
+pub const MAX_CUSTOM_METADATA_KEYS: usize = 32;
+
+/// Longest custom metadata key, in bytes
+pub const MAX_CUSTOM_METADATA_KEY_BYTES: usize = 64;
+
+/// Longest custom metadata value, in bytes
+pub const MAX_CUSTOM_METADATA_VALUE_BYTES: usize = 1024;
+
+/// Longest document slug, in bytes
+pub const MAX_SLUG_LENGTH: usize = 64;
+
 /// Document metadata and state
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Document {
     pub id: Uuid,
     pub title: String,
     pub owner: String,
+    /// Human-readable name the document can be found by in place of its ID, e.g. `my-paper`
+    #[serde(default)]
+    pub slug: Option<String>,
     pub collaborators: HashSet<String>,
+    #[serde(default)]
+    pub tags: HashSet<String>,
     pub repository_url: Option<String>,
+    /// Document this one was forked from, if any
+    #[serde(default)]
+    pub forked_from: Option<Uuid>,
+    #[serde(default)]
+    pub encoding: DocumentEncoding,
+    #[serde(default)]
+    pub visibility: DocumentVisibility,
+    /// Roles assigned to individual users
+    #[serde(default)]
+    pub roles: BTreeMap<String, Role>,
+    /// Key shared by creations of this document on other peers, if it was created with one
+    #[serde(default)]
+    pub dedup_key: Option<String>,
+    /// Free-form values users attach to the document, e.g. a journal name or DOI
+    #[serde(default)]
+    pub custom_metadata: HashMap<String, String>,
+    /// Whether the OpLog was ever sent to or merged from a peer, after which its history
+    /// can't be checkpointed, as the peer would hold operation IDs the checkpoint reuses
+    #[serde(default)]
+    pub history_shared: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// Stable key for the intent to create a document, so peers that independently create the
+/// same one can find each other
+///
+/// Peers only agree on the key if they agree on all three of the title, the owner and the
+/// nonce, e.g. one taken from a shared invitation link.
+pub fn dedup_key(title: &str, owner: &str, nonce: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    for part in [title, owner, nonce] {
+        // Length-prefixed, so moving characters between parts changes the key
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn check_custom_metadata_entry(key: &str, value: &str) -> Result<(), AppError> {
+    if key.is_empty() || key.len() > MAX_CUSTOM_METADATA_KEY_BYTES {
+        return Err(AppError::OperationRejected(format!(
+            "custom metadata keys must be 1 to {} bytes long",
+            MAX_CUSTOM_METADATA_KEY_BYTES
+        )));
+    }
+    if value.len() > MAX_CUSTOM_METADATA_VALUE_BYTES {
+        return Err(AppError::OperationRejected(format!(
+            "custom metadata value for {} is longer than {} bytes",
+            key, MAX_CUSTOM_METADATA_VALUE_BYTES
+        )));
+    }
+    Ok(())
+}
+
+/// Check that `slug` can name a document: 1 to `MAX_SLUG_LENGTH` lowercase letters, digits
+/// and inner hyphens, and not something that reads as a document ID
+pub fn check_slug(slug: &str) -> Result<(), AppError> {
+    let valid = !slug.is_empty()
+        && slug.len() <= MAX_SLUG_LENGTH
+        && slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && Uuid::parse_str(slug).is_err();
+    if !valid {
+        return Err(AppError::OperationRejected(format!(
+            "slug {:?} must be 1 to {} lowercase letters, digits and inner hyphens, and not a UUID",
+            slug, MAX_SLUG_LENGTH
+        )));
+    }
+    Ok(())
+}
+
 impl Document {
     pub fn new(id: Uuid, title: String, owner: String) -> Self {
         let now = chrono::Utc::now();
@@ -22,8 +195,17 @@ impl Document {
             id,
             title,
             owner,
+            slug: None,
             collaborators: HashSet::new(),
+            tags: HashSet::new(),
             repository_url: None,
+            forked_from: None,
+            encoding: DocumentEncoding::default(),
+            visibility: DocumentVisibility::default(),
+            roles: BTreeMap::new(),
+            dedup_key: None,
+            custom_metadata: HashMap::new(),
+            history_shared: false,
             created_at: now,
             updated_at: now,
         }
@@ -41,6 +223,27 @@ impl Document {
         user_id == self.owner || self.collaborators.contains(user_id)
     }
 
+    /// The role a user has in this document, if any
+    ///
+    /// The owner is always `Owner` and collaborators without an assigned role are editors.
+    /// Until the first role is assigned, the document is open and everyone is an editor.
+    pub fn role_of(&self, user_id: &str) -> Option<Role> {
+        if user_id == self.owner {
+            return Some(Role::Owner);
+        }
+
+        match self.roles.get(user_id) {
+            Some(role) => Some(*role),
+            None if self.collaborators.contains(user_id) || self.roles.is_empty() => Some(Role::Editor),
+            None => None,
+        }
+    }
+
+    pub fn set_role(&mut self, user_id: String, role: Role) {
+        self.roles.insert(user_id, role);
+        self.updated_at = chrono::Utc::now();
+    }
+
     pub fn set_repository_url(&mut self, url: String) {
         self.repository_url = Some(url);
         self.updated_at = chrono::Utc::now();
@@ -50,4 +253,59 @@ impl Document {
         self.title = title;
         self.updated_at = chrono::Utc::now();
     }
+
+    /// Make another user the owner, demoting the previous owner to a collaborator
+    pub fn transfer_ownership(&mut self, new_owner: String) {
+        let previous = std::mem::replace(&mut self.owner, new_owner);
+        self.collaborators.remove(&self.owner);
+        self.collaborators.insert(previous);
+        self.updated_at = chrono::Utc::now();
+    }
+
+    pub fn set_encoding(&mut self, encoding: DocumentEncoding) {
+        self.encoding = encoding;
+        self.updated_at = chrono::Utc::now();
+    }
+
+    pub fn set_visibility(&mut self, visibility: DocumentVisibility) {
+        self.visibility = visibility;
+        self.updated_at = chrono::Utc::now();
+    }
+
+    pub fn set_tags(&mut self, tags: HashSet<String>) {
+        self.tags = tags;
+        self.updated_at = chrono::Utc::now();
+    }
+
+    /// Set a custom metadata value, within the limits on keys and value sizes
+    pub fn set_custom_metadata(&mut self, key: String, value: String) -> Result<(), AppError> {
+        check_custom_metadata_entry(&key, &value)?;
+        if !self.custom_metadata.contains_key(&key) && self.custom_metadata.len() >= MAX_CUSTOM_METADATA_KEYS {
+            return Err(AppError::OperationRejected(format!(
+                "documents can't have more than {} custom metadata keys",
+                MAX_CUSTOM_METADATA_KEYS
+            )));
+        }
+
+        self.custom_metadata.insert(key, value);
+        self.updated_at = chrono::Utc::now();
+        Ok(())
+    }
+
+    /// Replace all custom metadata at once, e.g. with a peer's copy
+    pub fn replace_custom_metadata(&mut self, custom_metadata: HashMap<String, String>) -> Result<(), AppError> {
+        if custom_metadata.len() > MAX_CUSTOM_METADATA_KEYS {
+            return Err(AppError::OperationRejected(format!(
+                "documents can't have more than {} custom metadata keys",
+                MAX_CUSTOM_METADATA_KEYS
+            )));
+        }
+        for (key, value) in &custom_metadata {
+            check_custom_metadata_entry(key, value)?;
+        }
+
+        self.custom_metadata = custom_metadata;
+        self.updated_at = chrono::Utc::now();
+        Ok(())
+    }
 }
@@ -12,6 +12,8 @@ pub struct DocumentBranchManager {
     crdt_engine: Arc<RwLock<CrdtEngine>>,
     /// Tracks documents that have been requested but don't exist yet
     pending_documents: RwLock<HashMap<Uuid, String>>,
+    /// Whether missing documents are created rather than reported
+    auto_create: bool,
 }
 
 impl DocumentBranchManager {
@@ -19,10 +21,20 @@ impl DocumentBranchManager {
         Self {
             crdt_engine,
             pending_documents: RwLock::new(HashMap::new()),
+            auto_create: false,
         }
     }
 
-    /// Check if a document exists and create it if it doesn't
+    /// Create missing documents instead of reporting them, which also turns a mistyped ID
+    /// into a new, empty document
+    pub fn with_auto_create(mut self, enabled: bool) -> Self {
+        self.auto_create = enabled;
+        self
+    }
+
+    /// Check if a document exists and create it if it doesn't, returning whether it existed
+    ///
+    /// Unless auto-creation is enabled, a missing document is an error.
     pub async fn ensure_document_exists(&self, document_id: &Uuid, title: &str) -> Result<bool> {
         let engine = self.crdt_engine.read().await;
 
@@ -35,13 +47,14 @@ impl DocumentBranchManager {
             Err(e) => {
                 if let Some(app_error) = e.downcast_ref::<AppError>() {
                     match app_error {
-                        AppError::DocumentNotFound(_) | AppError::CrdtError(_) => {
+                        AppError::DocumentNotFound(_) | AppError::CrdtError(_) if self.auto_create => {
                             // Document doesn't exist, we'll create it
                             drop(engine); // Drop the read lock before acquiring a write lock
 
-                            // Create the document
+                            // Create the document under the ID it was asked for by
                             let engine = self.crdt_engine.write().await;
-                            let _ = engine.create_document(title.to_string(), "system".to_string()).await?;
+                            let created = engine.create_document(title.to_string(), "system".to_string()).await?;
+                            engine.adopt_document_id(&created, *document_id).await?;
 
                             // Register as created
                             let mut pending = self.pending_documents.write().await;
@@ -65,10 +78,13 @@ impl DocumentBranchManager {
         Ok(())
     }
 
-    /// Create any registered pending documents
+    /// Create any registered pending documents, or forget them if auto-creation is disabled
     pub async fn create_pending_documents(&self) -> Result<usize> {
         let mut pending = self.pending_documents.write().await;
         let pending_docs: Vec<(Uuid, String)> = pending.drain().collect();
+        if !self.auto_create {
+            return Ok(0);
+        }
 
         let mut created_count = 0;
 
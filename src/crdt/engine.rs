@@ -1,13 +1,52 @@
 use anyhow::Result;
+use diamond_types::list::remote_ids::RemoteId;
 use diamond_types::list::{Branch, OpLog};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::ops::Range;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{broadcast, RwLock};
 use uuid::Uuid;
 
-use super::document::Document;
-use super::operations::{DocumentOperation, OperationEncoder};
+use super::activity::{ActivityEvent, ActivityFeed, ActivityKind, ACTIVITY_FEED_CAPACITY};
+use super::chat::{ChatEntry, ChatLog, CHAT_HISTORY_CAPACITY};
+use super::agent_map::AgentMap;
+use super::attribution::{Attribution, MovedText};
+use super::coalesce::{InsertCoalescer, COALESCE_WINDOW};
+use super::diff;
+use super::comments::Comment;
+use super::events::{DocumentEvent, MetadataChange};
+use super::freeze::{FrozenDocument, QueuedChange};
+use super::history::{self, OperationKind, OperationRecord, Patch};
+use super::intercept::{InterceptDecision, OperationInterceptor};
+use super::integrity::{HashComparison, IntegrityIssue};
+use super::document::{check_slug, Document, DocumentEncoding, DocumentVisibility, Role};
+use super::operations::{move_target, DocumentOperation, OperationEncoder};
+use super::presence;
+use super::stats::DocumentStats;
+use crate::api::protocol::UserPresence;
 use crate::utils::errors::AppError;
+use crate::utils::lock_wait::LockWait;
+use crate::utils::timing::OperationTimings;
 use crate::network::peer::PeerInfo;
+use crate::storage::at_rest::OplogStore;
+use crate::storage::wal::{WalEntry, WriteAheadLog};
+
+/// How long deleted documents stay restorable unless configured otherwise
+pub const DEFAULT_TRASH_RETENTION: std::time::Duration = std::time::Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often documents past their trash retention are removed
+pub const TRASH_REAP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Undo steps kept for each user of a document; older steps are forgotten
+pub const MAX_UNDO_STEPS: usize = 200;
+
+/// Apply times kept for each document; the history before the oldest has no time
+pub const MAX_APPLIED_TIMES: usize = 4096;
+
+/// Agent external content updates are attributed to when the caller names no source
+pub const SYSTEM_AGENT: &str = "system";
 
 /// The CrdtEngine manages all the documents and their corresponding CRDT data structures
 #[derive(Debug)]
@@ -15,28 +54,226 @@ pub struct CrdtEngine {
     // Map of document IDs to their Document objects
     documents: dashmap::DashMap<Uuid, Arc<RwLock<Document>>>,
 
+    // Map of document slugs to the IDs of the documents they name
+    slugs: dashmap::DashMap<String, Uuid>,
+
     // Map of document IDs to their operation logs
     oplogs: dashmap::DashMap<Uuid, Arc<RwLock<OpLog>>>,
 
     // Map of document IDs to their branches
     branches: dashmap::DashMap<Uuid, Arc<RwLock<Branch>>>,
 
+    // Map of document IDs to their content as of the branch's current version, cleared
+    // whenever the branch changes
+    content_cache: dashmap::DashMap<Uuid, Arc<str>>,
+
+    // Number of times a document's content was rendered from its branch
+    content_renders: AtomicU64,
+
+    // Map of document IDs to their review comments
+    comments: dashmap::DashMap<Uuid, Vec<Comment>>,
+
+    // Map of document IDs to the presence of each user, keyed by user ID
+    presences: dashmap::DashMap<Uuid, std::collections::HashMap<String, UserPresence>>,
+
+    // When each present user was last heard from, keyed like `presences`
+    presence_seen: dashmap::DashMap<Uuid, std::collections::HashMap<String, Instant>>,
+
+    // Map of document IDs to each user's undo steps, stored as the operations that revert them
+    undo_stacks: dashmap::DashMap<Uuid, std::collections::HashMap<String, Vec<DocumentOperation>>>,
+
+    // Map of document IDs to the moves made to them, which credit moved text to its authors
+    attributions: dashmap::DashMap<Uuid, Attribution>,
+
+    // Map of document IDs to their recent activity, kept in memory only
+    activity: dashmap::DashMap<Uuid, ActivityFeed>,
+
+    // Map of document IDs to the chat about them, kept in memory only
+    chat: dashmap::DashMap<Uuid, ChatLog>,
+
+    // Map of document IDs to when this node applied the operations from each version on, the
+    // latest `MAX_APPLIED_TIMES` kept in memory only
+    applied_at: dashmap::DashMap<Uuid, std::collections::BTreeMap<usize, chrono::DateTime<chrono::Utc>>>,
+
+    // Hooks that may reject or rewrite operations before they are applied
+    interceptors: std::sync::RwLock<Vec<Arc<dyn OperationInterceptor>>>,
+
+    // Runs of typing not yet broadcast
+    coalescer: Mutex<InsertCoalescer>,
+
+    // Encoded operations ready to broadcast on the next flush
+    ready_operations: Mutex<Vec<(Uuid, Vec<u8>)>>,
+
+    // Number of remote payloads rejected as malformed
+    rejected_payloads: AtomicU64,
+
+    // Identifies this node as the origin of the operations it encodes
+    node_id: String,
+
+    // Most documents held at once, or 0 for no limit
+    max_documents: usize,
+
+    // Most bytes of text a single operation may insert, or 0 for no limit
+    max_operation_bytes: usize,
+
+    // Map of deleted document IDs to when they were deleted; they are kept, hidden, until
+    // the retention period is over
+    trash: dashmap::DashMap<Uuid, chrono::DateTime<chrono::Utc>>,
+
+    // How long deleted documents stay restorable
+    trash_retention: std::time::Duration,
+
+    // Number of this node's own operations received back from the network and skipped
+    skipped_echoes: AtomicU64,
+
+    // Map of document IDs to the freezes in place on them, which hold back all edits
+    frozen: dashmap::DashMap<Uuid, FrozenDocument>,
+
+    // Where operations are logged before they are applied, if anywhere
+    write_ahead_log: Option<WriteAheadLog>,
+
+    // Where OpLogs are written to disk, so a checkpoint can save the history it replaces
+    oplog_store: Option<OplogStore>,
+
+    // How long operations, merges and Git syncs have taken
+    timings: OperationTimings,
+
+    // How long to wait for a document's OpLog or branch before giving up
+    lock_wait: LockWait,
+
     // Operation encoder for serialization/deserialization
     encoder: OperationEncoder,
+
+    // Channel for notifying listeners about document changes
+    events: broadcast::Sender<DocumentEvent>,
 }
 
 impl CrdtEngine {
     pub fn new() -> Result<Self> {
+        let (events, _) = broadcast::channel(256);
+        let node_id = Uuid::new_v4().to_string();
+
         Ok(Self {
             documents: dashmap::DashMap::new(),
+            slugs: dashmap::DashMap::new(),
             oplogs: dashmap::DashMap::new(),
             branches: dashmap::DashMap::new(),
-            encoder: OperationEncoder::new(),
+            content_cache: dashmap::DashMap::new(),
+            content_renders: AtomicU64::new(0),
+            comments: dashmap::DashMap::new(),
+            presences: dashmap::DashMap::new(),
+            presence_seen: dashmap::DashMap::new(),
+            undo_stacks: dashmap::DashMap::new(),
+            attributions: dashmap::DashMap::new(),
+            activity: dashmap::DashMap::new(),
+            chat: dashmap::DashMap::new(),
+            applied_at: dashmap::DashMap::new(),
+            interceptors: std::sync::RwLock::new(Vec::new()),
+            coalescer: Mutex::new(InsertCoalescer::new(COALESCE_WINDOW)),
+            ready_operations: Mutex::new(Vec::new()),
+            rejected_payloads: AtomicU64::new(0),
+            encoder: OperationEncoder::with_origin(node_id.clone()),
+            node_id,
+            max_documents: 0,
+            max_operation_bytes: 0,
+            trash: dashmap::DashMap::new(),
+            trash_retention: DEFAULT_TRASH_RETENTION,
+            skipped_echoes: AtomicU64::new(0),
+            frozen: dashmap::DashMap::new(),
+            write_ahead_log: None,
+            oplog_store: None,
+            timings: OperationTimings::default(),
+            lock_wait: LockWait::default(),
+            events,
         })
     }
 
+    /// Limit the number of documents held at once, with 0 meaning no limit
+    pub fn with_max_documents(mut self, max_documents: usize) -> Self {
+        self.max_documents = max_documents;
+        self
+    }
+
+    /// Keep deleted documents restorable for `retention` before they are removed for good
+    pub fn with_trash_retention(mut self, retention: std::time::Duration) -> Self {
+        self.trash_retention = retention;
+        self
+    }
+
+    /// Limit the text a single operation may insert to `max_operation_bytes`; 0 means no limit
+    pub fn with_max_operation_bytes(mut self, max_operation_bytes: usize) -> Self {
+        self.max_operation_bytes = max_operation_bytes;
+        self
+    }
+
+    /// Log every operation to `write_ahead_log` before applying it
+    pub fn with_write_ahead_log(mut self, write_ahead_log: Option<WriteAheadLog>) -> Self {
+        self.write_ahead_log = write_ahead_log;
+        self
+    }
+
+    /// Write a document's OpLog to `oplog_store` on either side of checkpointing it, so the
+    /// history on disk is never one the write-ahead log doesn't line up with
+    pub fn with_oplog_store(mut self, oplog_store: Option<OplogStore>) -> Self {
+        self.oplog_store = oplog_store;
+        self
+    }
+
+    /// Warn about operations, merges and Git syncs taking longer than `slow_threshold`, if set
+    pub fn with_slow_op_threshold(mut self, slow_threshold: Option<std::time::Duration>) -> Self {
+        self.timings = OperationTimings::new(slow_threshold);
+        self
+    }
+
+    /// Give up on operations, merges and reads that wait longer than `timeout` for a document's
+    /// OpLog or branch, failing with `AppError::LockTimeout` instead of stalling
+    pub fn with_lock_wait_timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+        self.lock_wait = LockWait::new(timeout);
+        self
+    }
+
+    /// How long operations, merges and Git syncs have taken
+    pub fn operation_timings(&self) -> &OperationTimings {
+        &self.timings
+    }
+
+    /// Fail if an operation inserts more text than a single operation may
+    fn check_operation_size(&self, operation: &DocumentOperation) -> Result<()> {
+        let bytes = operation.inserted_bytes();
+        if self.max_operation_bytes > 0 && bytes > self.max_operation_bytes {
+            return Err(anyhow::anyhow!(AppError::OperationRejected(format!(
+                "inserting {} bytes exceeds the limit of {} bytes per operation; split the insert into smaller chunks",
+                bytes, self.max_operation_bytes
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Fail if the engine holds as many documents as it may
+    fn check_capacity(&self) -> Result<()> {
+        if self.max_documents > 0 && self.documents.len() >= self.max_documents {
+            return Err(anyhow::anyhow!(AppError::CrdtError("document capacity reached".to_string())));
+        }
+        Ok(())
+    }
+
+    /// Subscribe to document change events
+    pub fn subscribe_events(&self) -> broadcast::Receiver<DocumentEvent> {
+        self.events.subscribe()
+    }
+
+    /// Emit a document change event
+    fn emit_event(&self, event: DocumentEvent) {
+        // Sending only fails when nobody is listening, which is fine
+        let _ = self.events.send(event);
+    }
+
     /// Create a new document
+    ///
+    /// Fails once the engine holds as many documents as its limit allows.
     pub async fn create_document(&self, title: String, owner: String) -> Result<Uuid> {
+        self.check_capacity()?;
+
         let doc_id = Uuid::new_v4();
         let doc = Document::new(doc_id, title, owner);
 
@@ -54,265 +291,2381 @@ impl CrdtEngine {
         Ok(doc_id)
     }
 
-    /// Get a document by ID
-    pub async fn get_document(&self, doc_id: &Uuid) -> Result<Arc<RwLock<Document>>> {
-        self.documents
-            .get(doc_id)
-            .map(|item| item.value().clone())
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))
+    /// Create a new document that peers creating the same one independently will reconcile
+    /// with, see `document::dedup_key`
+    pub async fn create_document_with_dedup(&self, title: String, owner: String, nonce: &str) -> Result<Uuid> {
+        let key = super::document::dedup_key(&title, &owner, nonce);
+        let doc_id = self.create_document(title, owner).await?;
+        self.get_document(&doc_id).await?.write().await.dedup_key = Some(key);
+
+        Ok(doc_id)
     }
 
-    /// Apply a local operation to a document
-    pub async fn apply_local_operation(&self, doc_id: &Uuid, operation: DocumentOperation) -> Result<Vec<u8>> {
-        let oplog = self
-            .oplogs
-            .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+    /// Move a document to another ID, keeping its history, comments and presence
+    ///
+    /// Used to merge into the copy of a document another peer created independently.
+    /// Operations waiting to be broadcast under the old ID are dropped; the document's full
+    /// history reaches peers on the next sync.
+    pub async fn adopt_document_id(&self, doc_id: &Uuid, new_id: Uuid) -> Result<()> {
+        if self.documents.contains_key(&new_id) {
+            return Err(anyhow::anyhow!(AppError::CrdtError(format!("Document {} already exists", new_id))));
+        }
+        let Some((_, document)) = self.documents.remove(doc_id) else {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
+        };
+        let slug = {
+            let mut doc = document.write().await;
+            doc.id = new_id;
+            doc.slug.clone()
+        };
+        self.documents.insert(new_id, Arc::clone(&document));
+        if let Some(slug) = slug {
+            self.slugs.insert(slug, new_id);
+        }
 
-        let branch = self
-            .branches
-            .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+        fn rekey<V>(map: &dashmap::DashMap<Uuid, V>, from: &Uuid, to: Uuid) {
+            if let Some((_, value)) = map.remove(from) {
+                map.insert(to, value);
+            }
+        }
+        rekey(&self.oplogs, doc_id, new_id);
+        rekey(&self.branches, doc_id, new_id);
+        rekey(&self.content_cache, doc_id, new_id);
+        rekey(&self.comments, doc_id, new_id);
+        if let Some(mut comments) = self.comments.get_mut(&new_id) {
+            comments.iter_mut().for_each(|comment| comment.doc_id = new_id);
+        }
+        rekey(&self.presences, doc_id, new_id);
+        rekey(&self.presence_seen, doc_id, new_id);
+        rekey(&self.undo_stacks, doc_id, new_id);
+        rekey(&self.attributions, doc_id, new_id);
+        rekey(&self.trash, doc_id, new_id);
+        rekey(&self.applied_at, doc_id, new_id);
+        rekey(&self.chat, doc_id, new_id);
+        self.activity.remove(doc_id);
+        self.frozen.remove(doc_id);
+        self.lock_coalescer().discard_document(*doc_id);
+        self.lock_ready().retain(|(id, _)| id != doc_id);
 
-        // Apply the operation to the oplog
-        {
-            let mut oplog_write = oplog.value().write().await;
-            match &operation {
-                DocumentOperation::Insert { user_id, position, content, .. } => {
-                    let agent_id = oplog_write.get_or_create_agent_id(&user_id);
-                    oplog_write.add_insert(agent_id, *position, &content);
-                },
-                DocumentOperation::Delete { user_id, range, .. } => {
-                    let agent_id = oplog_write.get_or_create_agent_id(&user_id);
-                    oplog_write.add_delete_without_content(agent_id, range.clone());
-                },
-                DocumentOperation::Replace { user_id, range, content, .. } => {
-                    let agent_id = oplog_write.get_or_create_agent_id(&user_id);
-
-                    // Delete then insert
-                    oplog_write.add_delete_without_content(agent_id, range.clone());
-                    oplog_write.add_insert(agent_id, range.start, &content);
-                }
+        // Saved under the new ID before the old copy goes, so a crash leaves one of them
+        if let Some(store) = &self.oplog_store {
+            store.save_metadata(&*document.read().await)?;
+            store.save_agent_map(&new_id, &self.export_agent_map(&new_id).await?)?;
+            store.save_attribution(&new_id, &self.export_attribution(&new_id))?;
+            if let Some(deleted_at) = self.trash.get(&new_id).map(|entry| *entry.value()) {
+                store.save_trashed(&new_id, &deleted_at)?;
             }
+            store.save(&new_id, &self.export_document(&new_id).await?)?;
+            store.remove(doc_id)?;
+        }
+        if let Some(wal) = &self.write_ahead_log {
+            wal.checkpoint(doc_id, usize::MAX)?;
         }
 
-        // Update the branch
-        {
-            let mut branch_write = branch.value().write().await;
-            let oplog_read = oplog.value().read().await;
-            branch_write.merge(&oplog_read, oplog_read.local_version_ref());
+        self.emit_event(DocumentEvent::DocumentIdChanged {
+            old_id: *doc_id,
+            new_id,
+        });
+
+        Ok(())
+    }
+
+    /// Move a document to the trash
+    ///
+    /// The document disappears from lists and lookups, and its users are sent away, but it
+    /// can be restored with `restore_document` until the trash retention period is over.
+    pub async fn delete_document(&self, doc_id: &Uuid) -> Result<()> {
+        if !self.documents.contains_key(doc_id) || self.trash.contains_key(doc_id) {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
         }
 
-        // Encode the operation for broadcasting
-        let encoded = self.encoder.encode_operation(&operation)?;
+        // Saved first, so a document never comes back from a restart it was deleted before
+        let deleted_at = chrono::Utc::now();
+        if let Some(store) = &self.oplog_store {
+            store.save_trashed(doc_id, &deleted_at)?;
+        }
+        self.trash.insert(*doc_id, deleted_at);
+        self.presences.remove(doc_id);
+        self.presence_seen.remove(doc_id);
+        self.lock_coalescer().discard_document(*doc_id);
+        self.lock_ready().retain(|(id, _)| id != doc_id);
 
-        Ok(encoded)
+        self.emit_event(DocumentEvent::DocumentDeleted { document_id: *doc_id });
+
+        Ok(())
     }
 
-    /// Apply a remote operation to a document (received from the network)
-    pub async fn apply_remote_operation(&self, doc_id: &Uuid, encoded_operation: &[u8]) -> Result<()> {
-        let oplog = self
-            .oplogs
-            .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+    /// Bring a document back out of the trash
+    pub async fn restore_document(&self, doc_id: &Uuid) -> Result<()> {
+        if !self.trash.contains_key(doc_id) {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
+        }
+        if let Some(store) = &self.oplog_store {
+            store.remove_trashed(doc_id)?;
+        }
+        self.trash.remove(doc_id);
+        Ok(())
+    }
 
-        let branch = self
-            .branches
-            .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+    /// Put a document back in the trash it was in when saved, keeping when it was deleted
+    pub fn import_trashed(&self, doc_id: &Uuid, deleted_at: chrono::DateTime<chrono::Utc>) {
+        self.presences.remove(doc_id);
+        self.presence_seen.remove(doc_id);
+        self.trash.insert(*doc_id, deleted_at);
+    }
 
-        // Decode the operation
-        let operation = self.encoder.decode_operation(encoded_operation)?;
+    /// List the documents in the trash, with when each was deleted, oldest first
+    pub async fn list_trashed_documents(&self) -> Result<Vec<(Arc<RwLock<Document>>, chrono::DateTime<chrono::Utc>)>> {
+        let mut trashed: Vec<_> = self
+            .trash
+            .iter()
+            .filter_map(|entry| {
+                let document = self.documents.get(entry.key())?.value().clone();
+                Some((document, *entry.value()))
+            })
+            .collect();
+        trashed.sort_by_key(|(_, deleted_at)| *deleted_at);
+        Ok(trashed)
+    }
 
-        // Apply the operation to the oplog
-        {
-            let mut oplog_write = oplog.value().write().await;
-            match &operation {
-                DocumentOperation::Insert { user_id, position, content, .. } => {
-                    let agent_id = oplog_write.get_or_create_agent_id(&user_id);
-                    oplog_write.add_insert(agent_id, *position, &content);
-                },
-                DocumentOperation::Delete { user_id, range, .. } => {
-                    let agent_id = oplog_write.get_or_create_agent_id(&user_id);
-                    oplog_write.add_delete_without_content(agent_id, range.clone());
-                },
-                DocumentOperation::Replace { user_id, range, content, .. } => {
-                    let agent_id = oplog_write.get_or_create_agent_id(&user_id);
-
-                    // Delete then insert
-                    oplog_write.add_delete_without_content(agent_id, range.clone());
-                    oplog_write.add_insert(agent_id, range.start, &content);
-                }
-            }
+    /// Remove documents that have been in the trash for longer than the retention period, as
+    /// of `now`, returning how many were removed
+    pub async fn reap_trash(&self, now: chrono::DateTime<chrono::Utc>) -> Result<usize> {
+        let retention = chrono::Duration::from_std(self.trash_retention).unwrap_or(chrono::Duration::MAX);
+        let expired: Vec<Uuid> = self
+            .trash
+            .iter()
+            .filter(|entry| now - *entry.value() >= retention)
+            .map(|entry| *entry.key())
+            .collect();
+
+        for doc_id in &expired {
+            self.purge_document(doc_id).await?;
         }
+        Ok(expired.len())
+    }
 
-        // Update the branch
-        {
-            let mut branch_write = branch.value().write().await;
-            let oplog_read = oplog.value().read().await;
-            branch_write.merge(&oplog_read, oplog_read.local_version_ref());
+    /// Remove a document for good, along with its history, comments, presence and pending
+    /// operations, whether or not it is in the trash
+    pub async fn purge_document(&self, doc_id: &Uuid) -> Result<()> {
+        let Some((_, document)) = self.documents.remove(doc_id) else {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
+        };
+        if let Some(slug) = &document.read().await.slug {
+            self.slugs.remove(slug);
+        }
+        let trashed = self.trash.remove(doc_id).is_some();
+
+        self.oplogs.remove(doc_id);
+        self.branches.remove(doc_id);
+        self.content_cache.remove(doc_id);
+        self.comments.remove(doc_id);
+        self.presences.remove(doc_id);
+        self.presence_seen.remove(doc_id);
+        self.undo_stacks.remove(doc_id);
+        self.attributions.remove(doc_id);
+        self.activity.remove(doc_id);
+        self.chat.remove(doc_id);
+        self.applied_at.remove(doc_id);
+        self.lock_coalescer().discard_document(*doc_id);
+        self.lock_ready().retain(|(id, _)| id != doc_id);
+
+        // Or it would be loaded again on the next start
+        if let Some(store) = &self.oplog_store {
+            store.remove(doc_id)?;
+        }
+        if let Some(wal) = &self.write_ahead_log {
+            wal.checkpoint(doc_id, usize::MAX)?;
+        }
+
+        // Users were already sent away when the document was trashed
+        if !trashed {
+            self.emit_event(DocumentEvent::DocumentDeleted { document_id: *doc_id });
         }
 
         Ok(())
     }
 
-    /// Get the current content of a document
-    pub async fn get_document_content(&self, doc_id: &Uuid) -> Result<String> {
-        let branch = self
-            .branches
-            .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+    /// Cross-check the document, OpLog and branch maps, reporting every document missing an
+    /// OpLog or branch and every OpLog or branch that belongs to no document
+    pub fn verify_integrity(&self) -> Vec<IntegrityIssue> {
+        let mut issues = Vec::new();
+        for entry in self.documents.iter() {
+            let doc_id = *entry.key();
+            if !self.oplogs.contains_key(&doc_id) {
+                issues.push(IntegrityIssue::MissingOplog(doc_id));
+            } else if !self.branches.contains_key(&doc_id) {
+                issues.push(IntegrityIssue::MissingBranch(doc_id));
+            }
+        }
+        for entry in self.oplogs.iter() {
+            if !self.documents.contains_key(entry.key()) {
+                issues.push(IntegrityIssue::OrphanedOplog(*entry.key()));
+            }
+        }
+        for entry in self.branches.iter() {
+            if !self.documents.contains_key(entry.key()) {
+                issues.push(IntegrityIssue::OrphanedBranch(*entry.key()));
+            }
+        }
+
+        issues.sort_by_key(|issue| issue.document_id());
+        issues
+    }
 
-        let branch_read = branch.value().read().await;
+    /// Fix what `verify_integrity` reports, returning the issues fixed
+    ///
+    /// Missing branches are rebuilt from the OpLog. A document missing its OpLog gets a new
+    /// one holding its branch's content, if it has a branch, attributed to `SYSTEM_AGENT`.
+    /// OpLogs and branches without a document are dropped, since nothing can reach them.
+    pub async fn repair_integrity(&self) -> Result<Vec<IntegrityIssue>> {
+        let issues = self.verify_integrity();
 
-        // Extract the content as a string
-        let content = branch_read.content().to_string();
+        for issue in &issues {
+            let doc_id = issue.document_id();
+            match issue {
+                IntegrityIssue::MissingBranch(_) => {
+                    let Some(oplog) = self.oplogs.get(&doc_id).map(|entry| entry.value().clone()) else {
+                        continue;
+                    };
+                    let branch = Branch::new_at_tip(&*oplog.read().await);
+                    self.branches.insert(doc_id, Arc::new(RwLock::new(branch)));
+                }
+                IntegrityIssue::MissingOplog(_) => {
+                    let mut oplog = OpLog::new();
+                    if let Some(branch) = self.branches.get(&doc_id).map(|entry| entry.value().clone()) {
+                        let content = branch.read().await.content().to_string();
+                        if !content.is_empty() {
+                            let agent = oplog.get_or_create_agent_id(SYSTEM_AGENT);
+                            oplog.add_insert(agent, 0, &content);
+                        }
+                    }
+                    self.branches.insert(doc_id, Arc::new(RwLock::new(Branch::new_at_tip(&oplog))));
+                    self.oplogs.insert(doc_id, Arc::new(RwLock::new(oplog)));
+                }
+                IntegrityIssue::OrphanedOplog(_) => {
+                    self.oplogs.remove(&doc_id);
+                }
+                IntegrityIssue::OrphanedBranch(_) => {
+                    self.branches.remove(&doc_id);
+                }
+            }
+            self.content_cache.remove(&doc_id);
+        }
 
-        Ok(content)
+        Ok(issues)
     }
 
-    /// Update a document's content from external source (e.g., Git)
-    pub async fn update_document_content(&self, doc_id: &Uuid, content: String) -> Result<()> {
-        let oplog = self
-            .oplogs
-            .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+    /// Leave the maps in the state that `issue` describes, as a half-finished create or
+    /// delete would
+    #[cfg(test)]
+    pub(crate) fn break_integrity(&self, issue: &IntegrityIssue) {
+        let doc_id = issue.document_id();
+        match issue {
+            IntegrityIssue::MissingBranch(_) => {
+                self.branches.remove(&doc_id);
+            }
+            IntegrityIssue::MissingOplog(_) => {
+                self.oplogs.remove(&doc_id);
+            }
+            IntegrityIssue::OrphanedOplog(_) => {
+                self.documents.remove(&doc_id);
+                self.branches.remove(&doc_id);
+            }
+            IntegrityIssue::OrphanedBranch(_) => {
+                self.documents.remove(&doc_id);
+                self.oplogs.remove(&doc_id);
+            }
+        }
+    }
 
-        let branch = self
-            .branches
+    /// Get a document by ID
+    pub async fn get_document(&self, doc_id: &Uuid) -> Result<Arc<RwLock<Document>>> {
+        self.documents
             .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
-
-        // Clear the current content and add the new content
-        {
-            let mut branch_write = branch.value().write().await;
-            let mut oplog_write = oplog.value().write().await;
+            .filter(|_| !self.trash.contains_key(doc_id))
+            .map(|item| item.value().clone())
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))
+    }
 
-            // Create a system agent for this operation
-            let agent_id = oplog_write.get_or_create_agent_id("system");
+    /// Whether a document is here and not in the trash, without creating or loading anything
+    pub fn document_exists(&self, doc_id: &Uuid) -> bool {
+        self.documents.contains_key(doc_id) && !self.trash.contains_key(doc_id)
+    }
 
-            // Delete all content and insert the new content
-            let content_len = branch_write.len();
-            if content_len > 0 {
-                oplog_write.add_delete_without_content(agent_id, 0..content_len);
-            }
+    /// Find the document `id` names, which is either its ID or its slug
+    pub fn resolve_document_id(&self, id: &str) -> Result<Uuid> {
+        if let Ok(doc_id) = Uuid::parse_str(id) {
+            return Ok(doc_id);
+        }
+        self.slugs
+            .get(id)
+            .map(|item| *item.value())
+            .ok_or_else(|| anyhow::anyhow!(AppError::UnknownDocument(id.to_string())))
+    }
 
-            oplog_write.add_insert(agent_id, 0, &content);
+    /// Give a document a slug to be found by in place of its ID, replacing any it had, or take
+    /// its slug away
+    ///
+    /// Slugs are unique: one another document has, even in the trash, is refused.
+    pub async fn set_document_slug(&self, doc_id: &Uuid, slug: Option<String>) -> Result<()> {
+        let document = self.get_document(doc_id).await?;
+        let mut doc = document.write().await;
+        if doc.slug == slug {
+            return Ok(());
+        }
 
-            // Update the branch
-            branch_write.merge(&oplog_write, oplog_write.local_version_ref());
+        if let Some(slug) = &slug {
+            check_slug(slug)?;
+            match self.slugs.entry(slug.clone()) {
+                dashmap::mapref::entry::Entry::Occupied(entry) => {
+                    return Err(anyhow::anyhow!(AppError::SlugInUse(slug.clone(), *entry.get())));
+                }
+                dashmap::mapref::entry::Entry::Vacant(entry) => {
+                    entry.insert(*doc_id);
+                }
+            }
+        }
+        if let Some(previous) = doc.slug.take() {
+            self.slugs.remove(&previous);
         }
+        doc.slug = slug;
+        doc.updated_at = chrono::Utc::now();
 
         Ok(())
     }
 
-    /// List all documents
-    pub async fn list_documents(&self) -> Result<Vec<Arc<RwLock<Document>>>> {
-        let mut docs = Vec::new();
-        for item in self.documents.iter() {
-            docs.push(item.value().clone());
-        }
-        Ok(docs)
+    /// Apply a local operation to a document
+    ///
+    /// The operation is its own undo step and is returned encoded, to be broadcast right away.
+    pub async fn apply_local_operation(&self, doc_id: &Uuid, operation: DocumentOperation) -> Result<Vec<u8>> {
+        let bytes = operation.inserted_bytes();
+        self.timings
+            .time("apply_local_operation", doc_id, bytes, async {
+                self.check_not_frozen(doc_id)?;
+                self.check_operation_size(&operation)?;
+                let operation = self.intercept(doc_id, operation).await?;
+                self.apply_checked_local_operation(doc_id, operation).await
+            })
+            .await
     }
 
-    /// Import a document from an OpLog binary representation
-    pub async fn import_document(&self, title: String, owner: String, encoded_oplog: &[u8]) -> Result<Uuid> {
-        let doc_id = Uuid::new_v4();
-        let doc = Document::new(doc_id, title, owner);
+    /// Apply a batch of local operations in order, all or none
+    ///
+    /// Every operation is passed through the interceptors before any is applied, so one that
+    /// would be rejected fails the whole batch. The batch is then applied to a copy of the
+    /// document that replaces it only once every operation has applied, each checked against
+    /// the copy as the operations before it left it. Each operation is its own undo step.
+    /// Returns the encoded operations to broadcast and the version the document is at afterwards.
+    pub async fn apply_operation_batch(&self, doc_id: &Uuid, operations: Vec<DocumentOperation>) -> Result<(Vec<Vec<u8>>, usize)> {
+        self.check_not_frozen(doc_id)?;
 
-        // Create a new OpLog and decode the binary data into it
-        let mut oplog = OpLog::new();
-        oplog.decode_and_add(encoded_oplog)?;
+        let mut checked = Vec::with_capacity(operations.len());
+        for (index, operation) in operations.into_iter().enumerate() {
+            self.check_operation_size(&operation)?;
+            let (operation, _) = self.run_interceptors(operation)?;
 
-        // Create a branch for viewing the document
-        let branch = Branch::new_at_tip(&oplog);
+            let range = operation.affected_range();
+            if operation.document_id() != *doc_id || range.start > range.end {
+                return Err(anyhow::anyhow!(AppError::OperationRejected(format!(
+                    "operation {} of the batch doesn't fit document {}",
+                    index, doc_id
+                ))));
+            }
+            checked.push(operation);
+        }
 
-        // Store the document and its CRDT structures
-        self.documents.insert(doc_id, Arc::new(RwLock::new(doc)));
-        self.oplogs.insert(doc_id, Arc::new(RwLock::new(oplog)));
-        self.branches.insert(doc_id, Arc::new(RwLock::new(branch)));
+        let (inverses, version) = self.apply_operations_atomically(doc_id, &checked).await?;
+        let mut encoded = Vec::with_capacity(checked.len());
+        for (operation, inverse) in checked.iter().zip(inverses) {
+            encoded.push(self.finish_local_operation(doc_id, operation, inverse)?);
+        }
 
-        Ok(doc_id)
+        Ok((encoded, version))
     }
 
-    /// Export a document to an OpLog binary representation
-    pub async fn export_document(&self, doc_id: &Uuid) -> Result<Vec<u8>> {
-        let oplog = self
-            .oplogs
-            .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+    /// Apply a local operation that has been through the checks and interceptors
+    async fn apply_checked_local_operation(&self, doc_id: &Uuid, operation: DocumentOperation) -> Result<Vec<u8>> {
+        let inverse = self.inverse_of(doc_id, &operation).await?;
+        self.apply_operation(doc_id, &operation).await?;
+        self.finish_local_operation(doc_id, &operation, inverse)
+    }
 
-        let oplog_read = oplog.value().read().await;
-        let encoded = oplog_read.encode(diamond_types::list::encoding::EncodeOptions::default());
+    /// Record the undo step of a local operation just applied, and encode it for broadcasting
+    fn finish_local_operation(&self, doc_id: &Uuid, operation: &DocumentOperation, inverse: DocumentOperation) -> Result<Vec<u8>> {
+        self.record_undo(doc_id, operation.user_id(), inverse);
 
-        Ok(encoded)
+        // Runs of typing this operation edited inside of are broadcast on the next flush
+        let ended = self.lock_coalescer().apply(operation);
+        let ended = self.end_runs(doc_id, ended, operation)?;
+        self.lock_ready().extend(ended.into_iter().map(|encoded| (*doc_id, encoded)));
+
+        // Encode the operation for broadcasting
+        self.encoder.encode_operation(operation)
     }
 
-    /// Synchronize with another peer by exchanging oplogs
-    pub async fn sync_document(&self, doc_id: &Uuid, encoded_oplog: &[u8]) -> Result<Vec<u8>> {
-        let oplog = self
-            .oplogs
-            .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+    /// Apply an operation typed by a user, coalescing consecutive keystrokes
+    ///
+    /// The operation is applied right away, but keystrokes are held back until the user's run
+    /// of typing ends, so that it is broadcast as one operation and undone in one step.
+    /// Returns the encoded operations that are ready to broadcast, in order. Runs that end
+    /// because the user paused are returned by `flush_coalesced_operations`.
+    pub async fn apply_typed_operation(&self, doc_id: &Uuid, operation: DocumentOperation) -> Result<Vec<Vec<u8>>> {
+        self.check_not_frozen(doc_id)?;
+        self.check_operation_size(&operation)?;
+        let operation = self.intercept(doc_id, operation).await?;
+        let inverse = self.inverse_of(doc_id, &operation).await?;
+        self.apply_operation(doc_id, &operation).await?;
 
-        let branch = self
-            .branches
-            .get(doc_id)
-            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+        let ended = self.lock_coalescer().push(&operation, Instant::now());
+        let mut ready = self.end_runs(doc_id, ended, &operation)?;
 
-        // Apply the remote oplog to our local oplog
-        {
-            let mut oplog_write = oplog.value().write().await;
-            oplog_write.decode_and_add(encoded_oplog)?;
+        if !InsertCoalescer::is_keystroke(&operation) {
+            self.record_undo(doc_id, operation.user_id(), inverse);
+            ready.push(self.encoder.encode_operation(&operation)?);
         }
 
-        // Update the branch
-        {
-            let mut branch_write = branch.value().write().await;
-            let oplog_read = oplog.value().read().await;
-            branch_write.merge(&oplog_read, oplog_read.local_version_ref());
+        Ok(ready)
+    }
+
+    /// End the runs of typing that have been idle for longer than the coalescing window
+    ///
+    /// Returns every encoded operation waiting to be broadcast, with its document ID.
+    pub fn flush_coalesced_operations(&self) -> Result<Vec<(Uuid, Vec<u8>)>> {
+        let expired = self.lock_coalescer().close_expired(Instant::now());
+
+        let mut ready = std::mem::take(&mut *self.lock_ready());
+        for run in expired {
+            let doc_id = run.document_id();
+            self.record_undo(&doc_id, run.user_id(), Self::invert(&run, |_| String::new()));
+            ready.push((doc_id, self.encoder.encode_operation(&run)?));
         }
 
-        // Export our oplog to send back
-        let oplog_read = oplog.value().read().await;
-        let encoded = oplog_read.encode(diamond_types::list::encoding::EncodeOptions::default());
+        Ok(ready)
+    }
 
-        Ok(encoded)
+    /// Undo a user's most recent change to a document
+    ///
+    /// A run of typing the user has not finished yet is ended and undone as a whole. Returns
+    /// the encoded operations to broadcast, which is empty if there was nothing to undo.
+    pub async fn undo(&self, doc_id: &Uuid, user_id: &str) -> Result<Vec<Vec<u8>>> {
+        self.check_not_frozen(doc_id)?;
+        let mut ready = Vec::new();
+        if let Some(run) = self.lock_coalescer().close_user(*doc_id, user_id) {
+            self.record_undo(doc_id, user_id, Self::invert(&run, |_| String::new()));
+            ready.push(self.encoder.encode_operation(&run)?);
+        }
+
+        let inverse = self
+            .undo_stacks
+            .get_mut(doc_id)
+            .and_then(|mut stacks| stacks.get_mut(user_id).and_then(|stack| stack.pop()));
+        let Some(inverse) = inverse else {
+            return Ok(ready);
+        };
+
+        self.apply_operation(doc_id, &inverse).await?;
+
+        let ended = self.lock_coalescer().apply(&inverse);
+        ready.extend(self.end_runs(doc_id, ended, &inverse)?);
+        ready.push(self.encoder.encode_operation(&inverse)?);
+
+        Ok(ready)
     }
 
-    /// Get user presence information for a document (dummy implementation)
-    pub async fn get_document_presences(&self, _doc_id: &Uuid) -> Result<Vec<crate::api::protocol::UserPresence>> {
-        // Dummy implementation returning a single user presence
-        Ok(vec![crate::api::protocol::UserPresence {
-            user_id: "user1".to_string(),
-            display_name: "User 1".to_string(),
-            cursor_position: Some(0),
-            selection: None,
-            is_active: true,
-            last_activity: chrono::Utc::now().to_rfc3339(),
-        }])
+    /// Apply a remote operation to a document (received from the network)
+    ///
+    /// Malformed operations, and operations that don't fit the document, are rejected before
+    /// anything is changed. This node's own operations, echoed back by the network, are
+    /// skipped, as they were applied when they were made.
+    pub async fn apply_remote_operation(&self, doc_id: &Uuid, encoded_operation: &[u8]) -> Result<()> {
+        self.timings
+            .time("apply_remote_operation", doc_id, encoded_operation.len(), async {
+                // Held back until the document thaws
+                if let Some(mut frozen) = self.frozen.get_mut(doc_id) {
+                    frozen.queue(QueuedChange::Operation(encoded_operation.to_vec()));
+                    return Ok(());
+                }
+
+                let operation = match self.decode_remote_operation(doc_id, encoded_operation) {
+                    Ok(Some(operation)) => operation,
+                    Ok(None) => {
+                        self.skipped_echoes.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        self.rejected_payloads.fetch_add(1, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                };
+                if let Err(e) = self.check_operation_size(&operation) {
+                    // Nothing the sender can fix by resending, so drop it rather than fail the caller
+                    self.rejected_payloads.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!("Dropped remote operation on document {}: {}", doc_id, e);
+                    return Ok(());
+                }
+                let operation = self.intercept(doc_id, operation).await?;
+
+                if let Err(e) = self.apply_operation(doc_id, &operation).await {
+                    if matches!(e.downcast_ref::<AppError>(), Some(AppError::ProtocolError(_))) {
+                        self.rejected_payloads.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Err(e);
+                }
+
+                // Runs of typing the operation edited inside of are broadcast on the next flush
+                let ended = self.lock_coalescer().apply(&operation);
+                let ended = self.end_runs(doc_id, ended, &operation)?;
+                self.lock_ready().extend(ended.into_iter().map(|encoded| (*doc_id, encoded)));
+
+                Ok(())
+            })
+            .await
     }
 
-    /// Update user presence in a document (dummy implementation)
-    pub async fn update_user_presence(&self, doc_id: Uuid, presence: crate::api::protocol::UserPresence) -> Result<()> {
-        // Just log that we received the presence update
-        tracing::info!("User {} presence updated in document {}", presence.user_id, doc_id);
+    /// Reject local edits to a document, and queue remote ones, until it is unfrozen as many
+    /// times as it was frozen
+    ///
+    /// Edits made through `update_document_content` and `merge_external_content` still go
+    /// through, so the operation that froze the document can change it.
+    pub fn freeze_document(&self, doc_id: &Uuid) -> Result<()> {
+        if !self.documents.contains_key(doc_id) {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
+        }
+
+        self.frozen.entry(*doc_id).or_default().holds += 1;
         Ok(())
     }
 
-    /// Get the peers for a document
-    pub async fn get_document_peers(&self, _doc_id: &Uuid) -> Result<Vec<PeerInfo>> {
-        // This would normally be implemented to get peers from the document's subscribers
-        // For now, we'll return an empty list
-        Ok(Vec::new())
-    }
+    /// Lift a freeze, applying the changes from peers queued meanwhile once the last is lifted
+    ///
+    /// Queued changes that fail to apply are logged and dropped. If more arrived than
+    /// could be queued, none are applied and `DocumentEvent::ResyncNeeded` asks for the
+    /// document to be synced with peers instead.
+    pub async fn unfreeze_document(&self, doc_id: &Uuid) -> Result<()> {
+        let thawed = self
+            .frozen
+            .remove_if_mut(doc_id, |_, frozen| {
+                frozen.holds = frozen.holds.saturating_sub(1);
+                frozen.holds == 0
+            })
+            .map(|(_, frozen)| frozen);
+        let Some(thawed) = thawed else {
+            return Ok(());
+        };
 
-    /// Get all document IDs
-    pub async fn get_all_documents(&self) -> Result<Vec<Uuid>> {
-        let mut doc_ids = Vec::new();
-        for item in self.documents.iter() {
+        if thawed.overflowed {
+            tracing::warn!("Too many operations arrived while document {} was frozen; syncing it with peers", doc_id);
+            self.emit_event(DocumentEvent::ResyncNeeded { document_id: *doc_id });
+            return Ok(());
+        }
+
+        for change in thawed.queued {
+            let applied = match &change {
+                QueuedChange::Operation(encoded) => self.apply_remote_operation(doc_id, encoded).await,
+                QueuedChange::OpLog(encoded) => self.sync_document(doc_id, encoded).await.map(|_| ()),
+            };
+            if let Err(e) = applied {
+                tracing::warn!("Dropped change queued while document {} was frozen: {}", doc_id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a document is frozen
+    pub fn is_frozen(&self, doc_id: &Uuid) -> bool {
+        self.frozen.contains_key(doc_id)
+    }
+
+    fn check_not_frozen(&self, doc_id: &Uuid) -> Result<()> {
+        if self.is_frozen(doc_id) {
+            return Err(AppError::CrdtError("document frozen".to_string()).into());
+        }
+        Ok(())
+    }
+
+    /// Register a hook to run on every local and remote operation before it is applied
+    pub fn register_interceptor(&self, interceptor: Arc<dyn OperationInterceptor>) {
+        self.interceptors
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(interceptor);
+    }
+
+    /// Run an operation through the registered interceptors, returning what should be applied
+    ///
+    /// Fails if an interceptor rejects the operation, or rewrites it into one that doesn't fit
+    /// the document.
+    async fn intercept(&self, doc_id: &Uuid, operation: DocumentOperation) -> Result<DocumentOperation> {
+        let (operation, modified) = self.run_interceptors(operation)?;
+        if !modified {
+            return Ok(operation);
+        }
+
+        let len = self.get_document_snapshot(doc_id).await?.chars().count();
+        let range = operation.affected_range();
+        if operation.document_id() != *doc_id || range.start > range.end || range.end > len {
+            return Err(anyhow::anyhow!(AppError::OperationRejected(format!(
+                "An interceptor rewrote an operation on document {} into one that doesn't fit it",
+                doc_id
+            ))));
+        }
+
+        Ok(operation)
+    }
+
+    /// Pass an operation through every interceptor, returning what is left of it and whether
+    /// any interceptor rewrote it
+    fn run_interceptors(&self, operation: DocumentOperation) -> Result<(DocumentOperation, bool)> {
+        let interceptors = self.interceptors.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut operation = operation;
+        let mut modified = false;
+        for interceptor in interceptors.iter() {
+            match interceptor.before_apply(&operation) {
+                InterceptDecision::Allow => {}
+                InterceptDecision::Reject(reason) => {
+                    return Err(anyhow::anyhow!(AppError::OperationRejected(reason)));
+                }
+                InterceptDecision::Modify(replacement) => {
+                    operation = replacement;
+                    modified = true;
+                }
+            }
+        }
+        Ok((operation, modified))
+    }
+
+    /// Decode a remote operation and check that it can be applied to a document of length `len`
+    ///
+    /// Returns `None` for operations that originated on this node.
+    fn decode_remote_operation(&self, doc_id: &Uuid, encoded_operation: &[u8]) -> Result<Option<DocumentOperation>> {
+        let (operation, origin) = self
+            .encoder
+            .decode_stamped_operation(encoded_operation)
+            .map_err(|e| anyhow::anyhow!(AppError::ProtocolError(format!("Malformed operation: {}", e))))?;
+
+        if origin.as_deref() == Some(self.node_id.as_str()) {
+            return Ok(None);
+        }
+
+        if operation.document_id() != *doc_id {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Operation for document {} received for document {}",
+                operation.document_id(),
+                doc_id
+            ))));
+        }
+
+        let range = operation.affected_range();
+        if range.start > range.end {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(format!("Operation range {:?} is reversed", range))));
+        }
+
+        Ok(Some(operation))
+    }
+
+    /// Apply the operations recovered from a write-ahead log that a document doesn't reflect yet
+    ///
+    /// Entries logged before the document reached its current version, e.g. those its saved
+    /// OpLog already reflects, are skipped. An entry logged after it, with the operations in
+    /// between missing, stops the replay, as it would apply to content the document doesn't
+    /// have; `DocumentEvent::ResyncNeeded` asks for the document to be synced with peers
+    /// instead. Returns how many operations were applied.
+    pub async fn replay_logged_operations(&self, doc_id: &Uuid, entries: &[WalEntry]) -> Result<usize> {
+        let mut replayed = 0;
+        for entry in entries {
+            let version = self.get_versioned_snapshot(doc_id).await?.1;
+            if entry.base_version < version {
+                continue;
+            }
+            if entry.base_version > version {
+                tracing::warn!(
+                    "The write-ahead log of document {} skips from version {} to {}; syncing it with peers",
+                    doc_id,
+                    version,
+                    entry.base_version
+                );
+                self.emit_event(DocumentEvent::ResyncNeeded { document_id: *doc_id });
+                break;
+            }
+
+            let operation = self
+                .encoder
+                .decode_operation(&entry.operation)
+                .map_err(|e| anyhow::anyhow!(AppError::StorageError(format!("Malformed write-ahead log entry: {}", e))))?
+                .with_document_id(*doc_id);
+            self.apply_operation(doc_id, &operation).await?;
+            replayed += 1;
+        }
+
+        Ok(replayed)
+    }
+
+    /// ID this node stamps the operations it encodes with
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Number of this node's own operations received back from the network and skipped
+    pub fn skipped_echo_count(&self) -> u64 {
+        self.skipped_echoes.load(Ordering::Relaxed)
+    }
+
+    /// Number of remote payloads rejected as malformed since the engine started
+    pub fn rejected_payload_count(&self) -> u64 {
+        self.rejected_payloads.load(Ordering::Relaxed)
+    }
+
+    /// Apply an operation to a document's OpLog and branch, and keep everything anchored to
+    /// the document's text in step with it
+    async fn apply_operation(&self, doc_id: &Uuid, operation: &DocumentOperation) -> Result<()> {
+        self.apply_operations_from(doc_id, |_| vec![operation.clone()]).await?;
+        Ok(())
+    }
+
+    /// Apply, in order, the operations `derive` works out from a document's branch, and keep
+    /// everything anchored to the document's text in step with them
+    ///
+    /// The OpLog and branch stay locked from when the branch is read until the last operation
+    /// lands, so no other edit can change the content the operations are worked out from or
+    /// checked against. Each operation is logged before it is applied, so a crash can't lose
+    /// it. Returns the operations applied.
+    async fn apply_operations_from(
+        &self,
+        doc_id: &Uuid,
+        derive: impl FnOnce(&Branch) -> Vec<DocumentOperation>,
+    ) -> Result<Vec<DocumentOperation>> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let branch = self
+            .branches
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+
+        let mut applied = Vec::new();
+        let result: Result<()> = async {
+            let mut branch_write = self.lock_wait.write(branch.value(), || format!("the branch of document {}", doc_id)).await?;
+            let mut oplog_write = self.lock_wait.write(oplog.value(), || format!("the OpLog of document {}", doc_id)).await?;
+            for operation in derive(&branch_write) {
+                check_in_bounds(&operation, branch_write.len())?;
+                let conflict_hint = self.prepare_operation(doc_id, &operation);
+
+                let first_version = oplog_write.len();
+                if let Some(wal) = &self.write_ahead_log {
+                    wal.append_async(doc_id, first_version, self.encoder.encode_operation(&operation)?).await?;
+                }
+                if let Err(e) = operation.apply(&mut oplog_write, &branch_write) {
+                    // Otherwise it would be replayed on the next start
+                    if let Some(wal) = &self.write_ahead_log {
+                        wal.discard_from(doc_id, first_version)?;
+                    }
+                    return Err(e.into());
+                }
+                if let Some(moved) = moved_text(&operation, &oplog_write) {
+                    self.attributions.entry(*doc_id).or_default().record(moved);
+                }
+                branch_write.merge(&oplog_write, oplog_write.local_version_ref());
+                self.content_cache.remove(doc_id);
+                applied.push((operation, first_version, conflict_hint));
+            }
+            Ok(())
+        }
+        .await;
+
+        // Operations applied before one failed are kept, so they are announced all the same
+        for (operation, first_version, conflict_hint) in &applied {
+            self.operation_applied(doc_id, operation, *first_version, conflict_hint.clone());
+        }
+        result?;
+
+        Ok(applied.into_iter().map(|(operation, _, _)| operation).collect())
+    }
+
+    /// Apply operations in order to copies of a document's OpLog and branch, swapping them in
+    /// only once every operation has applied, so a failure partway through leaves the document
+    /// untouched
+    ///
+    /// Each operation is checked against the copy as the ones before it left it. Returns the
+    /// operation that reverts each one, as of the content it was applied to, and the version
+    /// the document is at afterwards.
+    async fn apply_operations_atomically(&self, doc_id: &Uuid, operations: &[DocumentOperation]) -> Result<(Vec<DocumentOperation>, usize)> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let branch = self
+            .branches
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+
+        let conflict_hints: Vec<_> = operations.iter().map(|operation| self.prepare_operation(doc_id, operation)).collect();
+
+        let (inverses, first_versions, version) = {
+            let mut branch_write = self.lock_wait.write(branch.value(), || format!("the branch of document {}", doc_id)).await?;
+            let mut oplog_write = self.lock_wait.write(oplog.value(), || format!("the OpLog of document {}", doc_id)).await?;
+            let start_version = oplog_write.len();
+            let mut new_oplog = oplog_write.clone();
+            let mut new_branch = branch_write.clone();
+            let mut inverses = Vec::with_capacity(operations.len());
+            let mut first_versions = Vec::with_capacity(operations.len());
+            let mut moves = Vec::new();
+
+            // Each operation is logged before it is applied, as in `apply_operation`
+            let applied: Result<()> = async {
+                for (index, operation) in operations.iter().enumerate() {
+                    if check_in_bounds(operation, new_branch.len()).is_err() {
+                        return Err(anyhow::anyhow!(AppError::OperationRejected(format!(
+                            "operation {} of the batch doesn't fit document {}, which is {} characters long at that point",
+                            index,
+                            doc_id,
+                            new_branch.len()
+                        ))));
+                    }
+                    inverses.push(Self::invert(operation, |range| slice_text(&new_branch, range)));
+                    first_versions.push(new_oplog.len());
+                    if let Some(wal) = &self.write_ahead_log {
+                        wal.append_async(doc_id, new_oplog.len(), self.encoder.encode_operation(operation)?).await?;
+                    }
+                    operation.apply(&mut new_oplog, &new_branch)?;
+                    moves.extend(moved_text(operation, &new_oplog));
+                    new_branch.merge(&new_oplog, new_oplog.local_version_ref());
+                }
+                Ok(())
+            }
+            .await;
+            if let Err(e) = applied {
+                if let Some(wal) = &self.write_ahead_log {
+                    wal.discard_from(doc_id, start_version)?;
+                }
+                return Err(e);
+            }
+
+            // Read before the locks are released, so a later edit can't be counted in
+            let version = new_branch.local_version_ref().iter().max().map_or(0, |latest| latest + 1);
+            *oplog_write = new_oplog;
+            *branch_write = new_branch;
+            self.content_cache.remove(doc_id);
+            if !moves.is_empty() {
+                let mut attribution = self.attributions.entry(*doc_id).or_default();
+                moves.into_iter().for_each(|moved| attribution.record(moved));
+            }
+            (inverses, first_versions, version)
+        };
+
+        for ((operation, first_version), conflict_hint) in operations.iter().zip(first_versions).zip(conflict_hints) {
+            self.operation_applied(doc_id, operation, first_version, conflict_hint);
+        }
+
+        Ok((inverses, version))
+    }
+
+    /// Note that a user is editing, and work out whom else the operation runs into
+    ///
+    /// Positions in the presence map are as of before the operation, so this comes first.
+    fn prepare_operation(&self, doc_id: &Uuid, operation: &DocumentOperation) -> Option<presence::ConflictHint> {
+        self.touch_presence(doc_id, operation.user_id());
+        self.presences
+            .get(doc_id)
+            .and_then(|presences| presence::conflict_hint(operation, presences.values()))
+    }
+
+    /// Keep everything anchored to a document's text in step with an operation just applied
+    /// at `first_version`, and announce it
+    fn operation_applied(&self, doc_id: &Uuid, operation: &DocumentOperation, first_version: usize, conflict_hint: Option<presence::ConflictHint>) {
+        self.record_applied_at(doc_id, first_version);
+
+        // Keep comment anchors and undo steps attached to the text they refer to
+        self.rebase_comments(doc_id, operation);
+        self.rebase_undo_steps(doc_id, operation);
+
+        let mut records = history::records_for_operation(operation, first_version);
+        for record in &mut records {
+            record.conflict_hint = conflict_hint.clone();
+        }
+        self.emit_event(DocumentEvent::OperationApplied {
+            document_id: *doc_id,
+            records,
+        });
+        self.record_activity(doc_id, ActivityKind::Edited {
+            user_id: operation.user_id().to_string(),
+        });
+    }
+
+    /// Get the current content of a document
+    pub async fn get_document_content(&self, doc_id: &Uuid) -> Result<String> {
+        Ok(self.get_document_snapshot(doc_id).await?.to_string())
+    }
+
+    /// Get the current content of a document as a shared snapshot
+    ///
+    /// The content is rendered from the branch once per version and cached until the next
+    /// change, so repeated reads between edits are cheap.
+    pub async fn get_document_snapshot(&self, doc_id: &Uuid) -> Result<Arc<str>> {
+        Ok(self.get_versioned_snapshot(doc_id).await?.0)
+    }
+
+    /// Get the current content of a document together with its version, read at the same time
+    ///
+    /// The version is the number of operations the content reflects, to pass back to
+    /// `operations_since`.
+    pub async fn get_versioned_snapshot(&self, doc_id: &Uuid) -> Result<(Arc<str>, usize)> {
+        let branch = self
+            .branches
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+
+        let branch_read = self.lock_wait.read(branch.value(), || format!("the branch of document {}", doc_id)).await?;
+        let version = branch_read.local_version_ref().iter().max().map_or(0, |latest| latest + 1);
+        if let Some(content) = self.content_cache.get(doc_id) {
+            return Ok((Arc::clone(content.value()), version));
+        }
+
+        // Cache while still holding the branch, so a concurrent edit can't be cached over
+        let content: Arc<str> = Arc::from(branch_read.content().to_string());
+        self.content_renders.fetch_add(1, Ordering::Relaxed);
+        self.content_cache.insert(*doc_id, Arc::clone(&content));
+
+        Ok((content, version))
+    }
+
+    /// Number of times document content was rendered from a branch since the engine started
+    pub fn content_render_count(&self) -> u64 {
+        self.content_renders.load(Ordering::Relaxed)
+    }
+
+    /// Get the current content of a document, encoded in the document's encoding
+    pub async fn get_document_bytes(&self, doc_id: &Uuid) -> Result<Vec<u8>> {
+        let encoding = self.get_document(doc_id).await?.read().await.encoding;
+        let content = self.get_document_snapshot(doc_id).await?;
+
+        Ok(encoding.encode(&content)?)
+    }
+
+    /// Insert raw bytes into a document, decoding them with the document's encoding
+    ///
+    /// Returns the encoded operation, to be broadcast like any other local operation.
+    pub async fn insert_bytes(&self, doc_id: &Uuid, user_id: String, position: usize, bytes: &[u8]) -> Result<Vec<u8>> {
+        let encoding = self.get_document(doc_id).await?.read().await.encoding;
+
+        let operation = DocumentOperation::Insert {
+            document_id: *doc_id,
+            user_id,
+            position,
+            content: encoding.decode(bytes),
+        };
+
+        self.apply_local_operation(doc_id, operation).await
+    }
+
+    /// Set the encoding a document's content is exchanged in
+    pub async fn set_document_encoding(&self, doc_id: &Uuid, encoding: DocumentEncoding) -> Result<()> {
+        self.get_document(doc_id).await?.write().await.set_encoding(encoding);
+        Ok(())
+    }
+
+    /// Set whether a document is announced to the network
+    pub async fn set_document_visibility(&self, doc_id: &Uuid, visibility: DocumentVisibility) -> Result<()> {
+        self.get_document(doc_id).await?.write().await.set_visibility(visibility);
+        Ok(())
+    }
+
+    /// Update a document's content from external source (e.g., Git)
+    ///
+    /// Only the spans that differ are changed, so unchanged text keeps its history and
+    /// attribution, and comments anchored to it stay put.
+    pub async fn update_document_content(&self, doc_id: &Uuid, content: String) -> Result<()> {
+        self.update_document_content_from(doc_id, content, SYSTEM_AGENT).await
+    }
+
+    /// Update a document's content from an external source, attributing the changes to the
+    /// agent `source`, e.g. `"git:origin"` or `"import"`
+    pub async fn update_document_content_from(&self, doc_id: &Uuid, content: String, source: &str) -> Result<()> {
+        // Worked out and applied under the same locks, so no edit made in between is undone
+        self.apply_operations_from(doc_id, |branch| {
+            diff::diff_operations(*doc_id, source, &branch.content().to_string(), &content)
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Merge a version of the document that was edited outside the CRDT, e.g. in Git
+    ///
+    /// `base` is the content both sides started from. The external changes are rebased over
+    /// whatever was edited here since, applied as `user_id`, and queued for broadcast like
+    /// any other operation. Returns whether there were external changes.
+    pub async fn merge_external_content(&self, doc_id: &Uuid, user_id: &str, base: &str, external: &str) -> Result<bool> {
+        // The local edits are worked out under the same locks the changes are applied under,
+        // so none made in between is missed. Both sets of operations run from the end of the
+        // document to the start, so each change only needs rebasing over the local edits, not
+        // over the other changes.
+        let applied = self
+            .apply_operations_from(doc_id, |branch| {
+                let local = diff::diff_operations(*doc_id, user_id, base, &branch.content().to_string());
+                diff::diff_operations(*doc_id, user_id, base, external)
+                    .into_iter()
+                    .map(|operation| local.iter().fold(operation, |operation, edit| operation.transformed_by(edit)))
+                    .collect()
+            })
+            .await?;
+        if applied.is_empty() {
+            return Ok(false);
+        }
+
+        for operation in applied {
+            let ended = self.lock_coalescer().apply(&operation);
+            let mut ready = self.end_runs(doc_id, ended, &operation)?;
+            ready.push(self.encoder.encode_operation(&operation)?);
+            self.lock_ready().extend(ready.into_iter().map(|encoded| (*doc_id, encoded)));
+        }
+
+        Ok(true)
+    }
+
+    /// List all documents
+    pub async fn list_documents(&self) -> Result<Vec<Arc<RwLock<Document>>>> {
+        let mut docs = Vec::new();
+        for item in self.documents.iter().filter(|item| !self.trash.contains_key(item.key())) {
+            docs.push(item.value().clone());
+        }
+        Ok(docs)
+    }
+
+    /// Import a document from an OpLog binary representation
+    pub async fn import_document(&self, title: String, owner: String, encoded_oplog: &[u8]) -> Result<Uuid> {
+        self.import_document_with_agents(title, owner, encoded_oplog, &AgentMap::default()).await
+    }
+
+    /// Import a document from an OpLog binary representation, restoring a previously
+    /// exported agent mapping so every user keeps its original agent ID
+    pub async fn import_document_with_agents(
+        &self,
+        title: String,
+        owner: String,
+        encoded_oplog: &[u8],
+        agent_map: &AgentMap,
+    ) -> Result<Uuid> {
+        let doc_id = Uuid::new_v4();
+        self.import_saved_document(Document::new(doc_id, title, owner), encoded_oplog, agent_map).await?;
+        Ok(doc_id)
+    }
+
+    /// Bring back a document saved to disk, under its own ID and with its metadata, from its
+    /// OpLog and agent mapping
+    pub async fn import_saved_document(&self, document: Document, encoded_oplog: &[u8], agent_map: &AgentMap) -> Result<()> {
+        self.check_capacity()?;
+        let doc_id = document.id;
+        if self.documents.contains_key(&doc_id) {
+            return Err(anyhow::anyhow!(AppError::CrdtError(format!("Document {} already exists", doc_id))));
+        }
+
+        // Register the known agents first, then decode the binary data into the OpLog and a
+        // branch for viewing the document
+        let (oplog, branch) =
+            Self::merge_remote_oplog(agent_map.new_oplog(), Branch::new(), encoded_oplog.to_vec()).await?;
+
+        // Store the document and its CRDT structures
+        if let Some(slug) = &document.slug {
+            self.slugs.insert(slug.clone(), doc_id);
+        }
+        self.documents.insert(doc_id, Arc::new(RwLock::new(document)));
+        self.oplogs.insert(doc_id, Arc::new(RwLock::new(oplog)));
+        self.branches.insert(doc_id, Arc::new(RwLock::new(branch)));
+
+        Ok(())
+    }
+
+    /// Fork a document into a new document with an independent history from here on
+    pub async fn fork_document(&self, doc_id: &Uuid, new_title: String, owner: String) -> Result<Uuid> {
+        let encoded = self.export_document(doc_id).await?;
+        let agent_map = self.export_agent_map(doc_id).await?;
+
+        let encoding = self.get_document(doc_id).await?.read().await.encoding;
+
+        let fork_id = self.import_document_with_agents(new_title, owner, &encoded, &agent_map).await?;
+        self.import_attribution(&fork_id, self.export_attribution(doc_id));
+        {
+            let fork = self.get_document(&fork_id).await?;
+            let mut fork = fork.write().await;
+            fork.forked_from = Some(*doc_id);
+            fork.encoding = encoding;
+        }
+
+        Ok(fork_id)
+    }
+
+    /// Export the `user_id -> agent_id` mapping of a document, to be stored alongside
+    /// the exported OpLog
+    pub async fn export_agent_map(&self, doc_id: &Uuid) -> Result<AgentMap> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = oplog.value().read().await;
+        Ok(AgentMap::from_oplog(&oplog_read))
+    }
+
+    /// Get the moves made to a document, which say who wrote the text they moved
+    pub fn export_attribution(&self, doc_id: &Uuid) -> Attribution {
+        self.attributions.get(doc_id).map(|attribution| attribution.clone()).unwrap_or_default()
+    }
+
+    /// Restore the moves made to a document, e.g. as saved beside its OpLog
+    pub fn import_attribution(&self, doc_id: &Uuid, attribution: Attribution) {
+        if attribution.is_empty() {
+            self.attributions.remove(doc_id);
+        } else {
+            self.attributions.insert(*doc_id, attribution);
+        }
+    }
+
+    /// Get the author of each span of operations in a document's history
+    pub async fn get_operation_authors(&self, doc_id: &Uuid) -> Result<Vec<(String, Range<usize>)>> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = oplog.value().read().await;
+        let authors = oplog_read
+            .iter_mappings()
+            .map(|span| {
+                let user_id = oplog_read.get_agent_name(span.agent).to_string();
+                (user_id, span.seq_range.start..span.seq_range.end)
+            })
+            .collect();
+
+        Ok(authors)
+    }
+
+    /// Get the decoded operation history of a document, for debugging divergence
+    pub async fn get_operation_log(&self, doc_id: &Uuid) -> Result<Vec<OperationRecord>> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = self.lock_wait.read(oplog.value(), || format!("the OpLog of document {}", doc_id)).await?;
+        Ok(history::operation_records(&oplog_read))
+    }
+
+    /// A document's history as patches, in the order they were applied, optionally limited to
+    /// those after the frontier `since` and within the frontier `until`
+    ///
+    /// Frontiers are lists of `(agent, seq)` item IDs, so they mean the same on every replica;
+    /// each patch's `id` is the frontier just after it. A patch a frontier falls inside of
+    /// counts as after it. Frontiers naming operations this node hasn't seen are rejected.
+    pub async fn get_patches(
+        &self,
+        doc_id: &Uuid,
+        since: Option<&[(String, usize)]>,
+        until: Option<&[(String, usize)]>,
+    ) -> Result<Vec<Patch>> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = self.lock_wait.read(oplog.value(), || format!("the OpLog of document {}", doc_id)).await?;
+        let to_local = |frontier: &[(String, usize)]| {
+            let ids: Vec<RemoteId> = frontier.iter().map(|(agent, seq)| RemoteId { agent: agent.as_str().into(), seq: *seq }).collect();
+            oplog_read
+                .try_remote_to_local_version(ids.iter())
+                .map_err(|_| anyhow::anyhow!(AppError::ProtocolError(format!("Unknown version {:?} of document {}", frontier, doc_id))))
+        };
+        let since = since.map(to_local).transpose()?;
+        let until = until.map(to_local).transpose()?;
+
+        Ok(self.patches(doc_id, &oplog_read, |last| {
+            since.as_ref().is_none_or(|since| !oplog_read.version_contains_time(since, last))
+                && until.as_ref().is_none_or(|until| oplog_read.version_contains_time(until, last))
+        }))
+    }
+
+    /// A document's patches starting at or after local version `since` and before `until`
+    pub async fn get_patches_between(&self, doc_id: &Uuid, since: usize, until: usize) -> Result<Vec<Patch>> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = self.lock_wait.read(oplog.value(), || format!("the OpLog of document {}", doc_id)).await?;
+        Ok(self.patches(doc_id, &oplog_read, |last| (since..until).contains(&last)))
+    }
+
+    /// The patches of a document whose last item `keep` accepts, with when they were applied
+    fn patches(&self, doc_id: &Uuid, oplog: &OpLog, keep: impl Fn(usize) -> bool) -> Vec<Patch> {
+        let applied_at = self.applied_at.get(doc_id);
+        history::operation_records(oplog)
+            .into_iter()
+            .filter_map(|record| {
+                let last = record.version + record.len.saturating_sub(1);
+                if !keep(last) {
+                    return None;
+                }
+                let id = oplog.local_to_remote_time(last);
+                // Versions from before the oldest time kept have none
+                let timestamp = applied_at
+                    .as_ref()
+                    .and_then(|times| times.range(..=record.version).next_back().map(|(_, timestamp)| *timestamp));
+                Some(Patch::new(record, format!("{}:{}", id.agent, id.seq), timestamp))
+            })
+            .collect()
+    }
+
+    /// Operation counts and character contributions of each user who edited a document
+    pub async fn document_stats(&self, doc_id: &Uuid) -> Result<DocumentStats> {
+        let patches = self.get_patches(doc_id, None, None).await?;
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = oplog.value().read().await;
+        let authors = history::content_authors(&oplog_read, &self.export_attribution(doc_id));
+        Ok(DocumentStats::new(&patches, authors.into_iter().map(|agent| oplog_read.get_agent_name(agent))))
+    }
+
+    /// Note that the operations of a document from `first_version` on were just applied
+    fn record_applied_at(&self, doc_id: &Uuid, first_version: usize) {
+        let mut times = self.applied_at.entry(*doc_id).or_default();
+        times.insert(first_version, chrono::Utc::now());
+        if times.len() > MAX_APPLIED_TIMES {
+            times.pop_first();
+        }
+    }
+
+    /// The operations applied to a document from version `since` onwards, in order, trimmed to
+    /// start exactly at `since`
+    ///
+    /// Returns `None` if they can't be replayed onto the content the document had at `since`:
+    /// when `since` is ahead of the document, when edits made concurrently were merged in, or
+    /// when a run of deletes straddles `since`.
+    pub async fn operations_since(&self, doc_id: &Uuid, since: usize) -> Result<Option<Vec<OperationRecord>>> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = oplog.value().read().await;
+        if since > oplog_read.len() || !history::is_linear_since(&oplog_read, since) {
+            return Ok(None);
+        }
+
+        let mut records = Vec::new();
+        for record in history::operation_records(&oplog_read) {
+            if record.version >= since {
+                records.push(record);
+            } else if record.version + record.len > since {
+                let Some(trimmed) = history::trim_record(&record, since - record.version) else {
+                    return Ok(None);
+                };
+                records.push(trimmed);
+            }
+        }
+
+        // Without the inserted text the records can't be replayed
+        if records.iter().any(|r| r.kind == OperationKind::Insert && r.content.is_none()) {
+            return Ok(None);
+        }
+
+        Ok(Some(records))
+    }
+
+    /// Stream a document's operations from version `since` onwards: first the existing history,
+    /// then each new operation as it is applied
+    pub async fn stream_operations(
+        &self,
+        doc_id: &Uuid,
+        since: usize,
+    ) -> Result<impl futures::Stream<Item = OperationRecord> + Send + use<>> {
+        use futures::StreamExt;
+
+        // Subscribe before reading the history so no operation can slip in between
+        let events = self.subscribe_events();
+        let history = self.get_operation_log(doc_id).await?;
+        let live_from = history.last().map_or(0, |r| r.version + r.len).max(since);
+        let backlog: Vec<OperationRecord> = history.into_iter().filter(|r| r.version >= since).collect();
+
+        let doc_id = *doc_id;
+        let live = futures::stream::unfold(events, move |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(DocumentEvent::OperationApplied { document_id, records }) if document_id == doc_id => {
+                        return Some((records, events));
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Operation stream for {} skipped {} events", doc_id, skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .flat_map(futures::stream::iter)
+        .filter(move |record| futures::future::ready(record.version >= live_from));
+
+        Ok(futures::stream::iter(backlog).chain(live))
+    }
+
+    /// Replay an exported OpLog into a scratch branch and return the resulting content.
+    ///
+    /// This is a dry run: the live document is never modified.
+    pub async fn replay_oplog(&self, doc_id: &Uuid, encoded_oplog: &[u8]) -> Result<String> {
+        if !self.documents.contains_key(doc_id) {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
+        }
+
+        Self::replay_content(encoded_oplog)
+    }
+
+    /// Check that a document's live branch matches a fresh replay of its full OpLog.
+    ///
+    /// Returns `false` and logs where the two diverge if they don't match.
+    pub async fn verify_convergence(&self, doc_id: &Uuid) -> Result<bool> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let branch = self
+            .branches
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+
+        let encoded = {
+            let oplog_read = self.lock_wait.read(oplog.value(), || format!("the OpLog of document {}", doc_id)).await?;
+            oplog_read.encode(diamond_types::list::encoding::EncodeOptions::default())
+        };
+        let replayed = Self::replay_content(&encoded)?;
+        let live = self.lock_wait.read(branch.value(), || format!("the branch of document {}", doc_id)).await?.content().to_string();
+
+        if replayed == live {
+            return Ok(true);
+        }
+
+        let at = replayed
+            .chars()
+            .zip(live.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let snippet = |text: &str| text.chars().skip(at).take(40).collect::<String>();
+        tracing::warn!(
+            "Document {} diverged at char {}: replayed {:?} ({} chars), live {:?} ({} chars)",
+            doc_id,
+            at,
+            snippet(&replayed),
+            replayed.chars().count(),
+            snippet(&live),
+            live.chars().count()
+        );
+
+        Ok(false)
+    }
+
+    /// Decode an encoded OpLog into `oplog` and merge the result into `branch`, off the async
+    /// runtime
+    ///
+    /// diamond-types can panic rather than return an error on an inconsistent OpLog, e.g. one
+    /// referencing a parent it doesn't contain. The panic is caught and reported as a failed
+    /// merge, so one bad payload can't bring down the node.
+    async fn merge_remote_oplog(oplog: OpLog, branch: Branch, encoded_oplog: Vec<u8>) -> Result<(OpLog, Branch)> {
+        let merged = tokio::task::spawn_blocking(move || {
+            std::panic::catch_unwind(AssertUnwindSafe(move || {
+                let (mut oplog, mut branch) = (oplog, branch);
+                oplog
+                    .decode_and_add(&encoded_oplog)
+                    .map_err(|e| AppError::ProtocolError(format!("Invalid OpLog: {:?}", e)))?;
+                branch.merge(&oplog, oplog.local_version_ref());
+                Ok((oplog, branch))
+            }))
+        })
+        .await?;
+
+        merged
+            .unwrap_or_else(|_| {
+                tracing::warn!("diamond-types panicked while merging an OpLog");
+                Err(AppError::CrdtError("oplog merge failed".to_string()))
+            })
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Decode an encoded OpLog into a fresh OpLog and check out its content
+    fn replay_content(encoded_oplog: &[u8]) -> Result<String> {
+        let mut scratch = OpLog::new();
+        scratch
+            .decode_and_add(encoded_oplog)
+            .map_err(|e| anyhow::anyhow!(AppError::CrdtError(format!("Invalid OpLog: {:?}", e))))?;
+
+        Ok(scratch.checkout_tip().content().to_string())
+    }
+
+    /// Export a document to an OpLog binary representation
+    pub async fn export_document(&self, doc_id: &Uuid) -> Result<Vec<u8>> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = self.lock_wait.read(oplog.value(), || format!("the OpLog of document {}", doc_id)).await?;
+        let encoded = oplog_read.encode(diamond_types::list::encoding::EncodeOptions::default());
+
+        Ok(encoded)
+    }
+
+    /// The version a document's OpLog is at, encoded for a peer to pass to `export_since`
+    pub async fn encoded_version(&self, doc_id: &Uuid) -> Result<Vec<u8>> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = oplog.value().read().await;
+        let version: Vec<(String, usize)> = oplog_read
+            .remote_version()
+            .into_iter()
+            .map(|id| (id.agent.to_string(), id.seq))
+            .collect();
+
+        Ok(serde_json::to_vec(&version)?)
+    }
+
+    /// Hex-encoded SHA-256 of a document's current content, for peers to check their copies
+    /// against
+    pub async fn content_hash(&self, doc_id: &Uuid) -> Result<String> {
+        use sha2::{Digest, Sha256};
+
+        let content = self.get_document_snapshot(doc_id).await?;
+        Ok(Sha256::digest(content.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    /// Compare a document to a peer's copy, given the version the peer encoded with
+    /// `encoded_version` and the hash of its content
+    ///
+    /// Content can only be expected to match when both copies hold the same operations, so
+    /// copies at different versions aren't compared.
+    pub async fn compare_content_hash(&self, doc_id: &Uuid, peer_version: &[u8], peer_hash: &str) -> Result<HashComparison> {
+        let decode = |version: &[u8]| {
+            serde_json::from_slice::<Vec<(String, usize)>>(version).ok().map(|mut version| {
+                version.sort();
+                version
+            })
+        };
+
+        let local_version = self.encoded_version(doc_id).await?;
+        match (decode(&local_version), decode(peer_version)) {
+            (Some(local), Some(peer)) if local == peer => {}
+            _ => return Ok(HashComparison::NotComparable),
+        }
+
+        Ok(if self.content_hash(doc_id).await? == peer_hash {
+            HashComparison::Matches
+        } else {
+            HashComparison::Diverged
+        })
+    }
+
+    /// Export the operations of a document a peer at `version` lacks, returning whether the
+    /// whole OpLog had to be exported
+    ///
+    /// Without a version, or with one holding operations this node hasn't seen, the whole OpLog
+    /// is exported. Nothing is exported if the peer is up to date.
+    pub async fn export_since(&self, doc_id: &Uuid, version: Option<&[u8]>) -> Result<(Vec<u8>, bool)> {
+        self.mark_history_shared(doc_id).await?;
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+        let oplog_read = oplog.value().read().await;
+        let from = version
+            .and_then(|version| serde_json::from_slice::<Vec<(String, usize)>>(version).ok())
+            .and_then(|version| {
+                let ids: Vec<RemoteId> = version
+                    .into_iter()
+                    .map(|(agent, seq)| RemoteId { agent: agent.as_str().into(), seq })
+                    .collect();
+                oplog_read.try_remote_to_local_version(ids.iter()).ok()
+            });
+
+        let options = diamond_types::list::encoding::EncodeOptions::default();
+        Ok(match from {
+            Some(from) if from.as_slice() == oplog_read.local_version_ref() => (Vec::new(), false),
+            Some(from) => (oplog_read.encode_from(options, &from), false),
+            None => (oplog_read.encode(options), true),
+        })
+    }
+
+    /// Record that a document's OpLog is leaving for, or arriving from, a peer
+    ///
+    /// Done before the OpLog is locked, so a checkpoint that takes the lock first is either
+    /// refused or finished before any of the history is shared.
+    async fn mark_history_shared(&self, doc_id: &Uuid) -> Result<()> {
+        let Some(document) = self.documents.get(doc_id).map(|item| item.value().clone()) else {
+            return Ok(());
+        };
+        if document.read().await.history_shared {
+            return Ok(());
+        }
+        let metadata = {
+            let mut document = document.write().await;
+            document.history_shared = true;
+            document.clone()
+        };
+        if let Some(store) = &self.oplog_store {
+            store.save_metadata(&metadata)?;
+        }
+        Ok(())
+    }
+
+    /// Collapse a document's history into a single insert per run of text by the same author,
+    /// returning how many bytes the exported OpLog shrank by
+    ///
+    /// Concurrent operations wait for the checkpoint to finish, so none is lost. Deleted text
+    /// and the order of edits are dropped, so version numbers restart and the document's
+    /// agents reuse sequence numbers they had in the old history. Documents whose OpLog was
+    /// ever synced with a peer are refused, as the peer could merge its copy of the old
+    /// history back and have operation IDs mean two different things.
+    ///
+    /// The write-ahead log is emptied, as its entries are numbered by the old history. When an
+    /// OpLog store is configured, the full history is saved before that and the compacted one
+    /// after, so a crash at any point loses nothing.
+    pub async fn checkpoint_document(&self, doc_id: &Uuid) -> Result<u64> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?
+            .value()
+            .clone();
+        let branch = self
+            .branches
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?
+            .value()
+            .clone();
+
+        let document = self
+            .documents
+            .get(doc_id)
+            .map(|item| item.value().clone())
+            .ok_or_else(|| anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)))?;
+
+        // Same lock order as applying an operation: branch, then oplog
+        let mut branch_write = self.lock_wait.write(&branch, || format!("the branch of document {}", doc_id)).await?;
+        let mut oplog_write = self.lock_wait.write(&oplog, || format!("the OpLog of document {}", doc_id)).await?;
+
+        let metadata = document.read().await.clone();
+        if metadata.history_shared {
+            return Err(anyhow::anyhow!(AppError::OperationRejected(format!(
+                "document {} was synced with peers, which hold the history a checkpoint would rewrite",
+                doc_id
+            ))));
+        }
+
+        let encode = |oplog: &OpLog| oplog.encode(diamond_types::list::encoding::EncodeOptions::default());
+        let encoded_before = encode(&oplog_write);
+        let agent_map = AgentMap::from_oplog(&oplog_write);
+
+        // Nothing can be logged while the OpLog is locked, so once it is saved every entry
+        // is reflected on disk
+        if let Some(store) = &self.oplog_store {
+            store.save_metadata(&metadata)?;
+            store.save_agent_map(doc_id, &agent_map)?;
+            store.save(doc_id, &encoded_before)?;
+        }
+        if let Some(wal) = &self.write_ahead_log {
+            wal.checkpoint(doc_id, usize::MAX)?;
+        }
+
+        let content = oplog_write.checkout_tip().content().to_string();
+        let authors = history::content_authors(&oplog_write, &self.export_attribution(doc_id));
+        if authors.len() != content.chars().count() {
+            return Err(anyhow::anyhow!(AppError::CrdtError(format!(
+                "Cannot work out the authors of document {}'s content",
+                doc_id
+            ))));
+        }
+
+        // Keep every agent under its original ID, and the text under whoever wrote it
+        let mut compacted = agent_map.new_oplog();
+        let mut position = 0;
+        let mut chars = content.chars();
+        for span in authors.chunk_by(|a, b| a == b) {
+            let chunk: String = chars.by_ref().take(span.len()).collect();
+            compacted.add_insert(span[0], position, &chunk);
+            position += span.len();
+        }
+
+        // The moved text is now inserted under its authors, so there is no move left to credit
+        let encoded_after = encode(&compacted);
+        if let Some(store) = &self.oplog_store {
+            store.save_attribution(doc_id, &Attribution::default())?;
+            store.save(doc_id, &encoded_after)?;
+        }
+        self.attributions.remove(doc_id);
+        *branch_write = Branch::new_at_tip(&compacted);
+        *oplog_write = compacted;
+        self.content_cache.remove(doc_id);
+        self.applied_at.remove(doc_id);
+
+        Ok(encoded_before.len().saturating_sub(encoded_after.len()) as u64)
+    }
+
+    /// Synchronize with another peer by exchanging oplogs
+    ///
+    /// A frozen document's merge is queued until it thaws, and its OpLog is sent back as it is.
+    pub async fn sync_document(&self, doc_id: &Uuid, encoded_oplog: &[u8]) -> Result<Vec<u8>> {
+        self.timings
+            .time("sync_document", doc_id, encoded_oplog.len(), async {
+                self.mark_history_shared(doc_id).await?;
+
+                // Held back until the document thaws, like a remote operation
+                if let Some(mut frozen) = self.frozen.get_mut(doc_id) {
+                    frozen.queue(QueuedChange::OpLog(encoded_oplog.to_vec()));
+                    drop(frozen);
+                    return self.export_document(doc_id).await;
+                }
+
+                let oplog = self
+                    .oplogs
+                    .get(doc_id)
+                    .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+
+                let branch = self
+                    .branches
+                    .get(doc_id)
+                    .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+
+                // Apply the remote oplog to copies of our local oplog and branch, so that a payload
+                // that fails to decode or merge partway through leaves the document untouched
+                {
+                    let mut branch_write = self.lock_wait.write(branch.value(), || format!("the branch of document {}", doc_id)).await?;
+                    let mut oplog_write = self.lock_wait.write(oplog.value(), || format!("the OpLog of document {}", doc_id)).await?;
+                    let merged = Self::merge_remote_oplog(oplog_write.clone(), branch_write.clone(), encoded_oplog.to_vec()).await;
+                    let (merged_oplog, merged_branch) = match merged {
+                        Ok(merged) => merged,
+                        Err(e) => {
+                            self.rejected_payloads.fetch_add(1, Ordering::Relaxed);
+                            return Err(e);
+                        }
+                    };
+                    if merged_oplog.len() > oplog_write.len() {
+                        self.record_applied_at(doc_id, oplog_write.len());
+                    }
+                    *oplog_write = merged_oplog;
+                    *branch_write = merged_branch;
+                    self.content_cache.remove(doc_id);
+                }
+
+                // Export our oplog to send back
+                let encoded = {
+                    let oplog_read = self.lock_wait.read(oplog.value(), || format!("the OpLog of document {}", doc_id)).await?;
+                    oplog_read.encode(diamond_types::list::encoding::EncodeOptions::default())
+                };
+
+                // Catch divergence right where it is introduced while developing
+                #[cfg(debug_assertions)]
+                {
+                    drop(oplog);
+                    drop(branch);
+                    if !self.verify_convergence(doc_id).await? {
+                        tracing::error!("Document {} failed the convergence check after sync", doc_id);
+                    }
+                }
+
+                Ok(encoded)
+            })
+            .await
+    }
+
+    /// Content a document would have after syncing with a remote OpLog, leaving the document
+    /// as it is
+    ///
+    /// Lets clients see what a sync will do before committing to it, e.g. when replicas have
+    /// diverged.
+    pub async fn preview_merge(&self, doc_id: &Uuid, encoded_oplog: &[u8]) -> Result<String> {
+        let oplog = self
+            .oplogs
+            .get(doc_id)
+            .map(|item| Arc::clone(item.value()))
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document not found: {}", doc_id))))?;
+        let branch = self
+            .branches
+            .get(doc_id)
+            .map(|item| Arc::clone(item.value()))
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+
+        let (oplog, branch) = {
+            let branch_read = self.lock_wait.read(&branch, || format!("the branch of document {}", doc_id)).await?;
+            let oplog_read = self.lock_wait.read(&oplog, || format!("the OpLog of document {}", doc_id)).await?;
+            (oplog_read.clone(), branch_read.clone())
+        };
+        let (_, merged) = Self::merge_remote_oplog(oplog, branch, encoded_oplog.to_vec()).await?;
+
+        Ok(merged.content().to_string())
+    }
+
+    /// Get user presence information for a document
+    pub async fn get_document_presences(&self, doc_id: &Uuid) -> Result<Vec<UserPresence>> {
+        let mut presences: Vec<UserPresence> = self
+            .presences
+            .get(doc_id)
+            .map(|p| p.values().cloned().collect())
+            .unwrap_or_default();
+        presences.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+
+        Ok(presences)
+    }
+
+    /// Number of users currently marked active in a document
+    ///
+    /// Users silent for longer than the presence decay allows are marked inactive by
+    /// `sweep_presences`, so they drop out of the count then.
+    pub fn active_collaborator_count(&self, doc_id: &Uuid) -> usize {
+        self.presences
+            .get(doc_id)
+            .map(|presences| presences.values().filter(|presence| presence.is_active).count())
+            .unwrap_or(0)
+    }
+
+    /// Update user presence in a document, assigning the user's color if the client didn't pick one
+    pub async fn update_user_presence(&self, doc_id: Uuid, mut presence: UserPresence) -> Result<UserPresence> {
+        if presence.color.is_empty() {
+            presence.color = presence::user_color(&presence.user_id);
+        }
+
+        tracing::debug!("User {} presence updated in document {}", presence.user_id, doc_id);
+        self.presence_seen
+            .entry(doc_id)
+            .or_default()
+            .insert(presence.user_id.clone(), Instant::now());
+        let joined = self
+            .presences
+            .entry(doc_id)
+            .or_default()
+            .insert(presence.user_id.clone(), presence.clone())
+            .is_none();
+
+        if joined {
+            self.record_activity(&doc_id, ActivityKind::Joined {
+                user_id: presence.user_id.clone(),
+            });
+        }
+
+        Ok(presence)
+    }
+
+    /// Record that a user present in a document was just heard from
+    ///
+    /// A user `sweep_presences` marked inactive is marked active again. Users without a
+    /// presence in the document are ignored.
+    pub fn touch_presence(&self, doc_id: &Uuid, user_id: &str) {
+        let revived = {
+            let Some(mut seen) = self.presence_seen.get_mut(doc_id) else {
+                return;
+            };
+            let Some(last_seen) = seen.get_mut(user_id) else {
+                return;
+            };
+            *last_seen = Instant::now();
+
+            let mut presences = self.presences.get_mut(doc_id);
+            match presences.as_mut().and_then(|presences| presences.get_mut(user_id)) {
+                Some(presence) if !presence.is_active => {
+                    presence.is_active = true;
+                    Some(presence.clone())
+                }
+                _ => None,
+            }
+        };
+
+        if let Some(presence) = revived {
+            self.emit_event(DocumentEvent::PresenceChanged {
+                document_id: *doc_id,
+                presence,
+            });
+        }
+    }
+
+    /// Mark users who have been silent for too long inactive, and remove those silent for
+    /// longer still, as of `now`
+    ///
+    /// Every transition is emitted as an event. Returns the number of transitions.
+    pub fn sweep_presences(&self, now: Instant, decay: presence::PresenceDecay) -> usize {
+        let mut events = Vec::new();
+
+        for mut entry in self.presence_seen.iter_mut() {
+            let doc_id = *entry.key();
+            let Some(mut presences) = self.presences.get_mut(&doc_id) else {
+                continue;
+            };
+
+            entry.value_mut().retain(|user_id, last_seen| {
+                let silence = now.saturating_duration_since(*last_seen);
+                if silence >= decay.remove_after {
+                    presences.remove(user_id);
+                    events.push(DocumentEvent::PresenceRemoved {
+                        document_id: doc_id,
+                        user_id: user_id.clone(),
+                    });
+                    return false;
+                }
+
+                let decayed = presences
+                    .get_mut(user_id)
+                    .filter(|presence| presence.is_active && silence >= decay.inactive_after);
+                if let Some(presence) = decayed {
+                    presence.is_active = false;
+                    events.push(DocumentEvent::PresenceChanged {
+                        document_id: doc_id,
+                        presence: presence.clone(),
+                    });
+                }
+                true
+            });
+        }
+
+        let transitions = events.len();
+        for event in events {
+            let left = match &event {
+                DocumentEvent::PresenceRemoved { document_id, user_id } => Some((*document_id, user_id.clone())),
+                _ => None,
+            };
+            self.emit_event(event);
+            if let Some((doc_id, user_id)) = left {
+                self.record_activity(&doc_id, ActivityKind::Left { user_id });
+            }
+        }
+        transitions
+    }
+
+    /// Add an entry to a document's activity feed and notify listeners about it
+    pub fn record_activity(&self, doc_id: &Uuid, kind: ActivityKind) {
+        let event = self
+            .activity
+            .entry(*doc_id)
+            .or_insert_with(|| ActivityFeed::new(*doc_id, ACTIVITY_FEED_CAPACITY))
+            .record(kind);
+
+        if let Some(event) = event {
+            self.emit_event(DocumentEvent::ActivityRecorded { event });
+        }
+    }
+
+    /// Get the most recent `limit` entries of a document's activity feed, oldest first
+    pub async fn get_activity(&self, doc_id: &Uuid, limit: usize) -> Result<Vec<ActivityEvent>> {
+        if !self.documents.contains_key(doc_id) {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
+        }
+
+        Ok(self
+            .activity
+            .get(doc_id)
+            .map(|feed| feed.recent(limit))
+            .unwrap_or_default())
+    }
+
+    /// Add a message to a document's chat and notify listeners about it, returning false if
+    /// the chat already holds it
+    pub async fn record_chat(&self, doc_id: &Uuid, entry: ChatEntry) -> Result<bool> {
+        if !self.documents.contains_key(doc_id) {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
+        }
+        ChatEntry::check_body(&entry.body)?;
+
+        let recorded = self
+            .chat
+            .entry(*doc_id)
+            .or_insert_with(|| ChatLog::new(CHAT_HISTORY_CAPACITY))
+            .record(entry.clone());
+
+        if recorded {
+            self.emit_event(DocumentEvent::ChatReceived { document_id: *doc_id, entry });
+        }
+        Ok(recorded)
+    }
+
+    /// Get the most recent `limit` messages of a document's chat, oldest first
+    pub async fn get_chat_history(&self, doc_id: &Uuid, limit: usize) -> Result<Vec<ChatEntry>> {
+        if !self.documents.contains_key(doc_id) {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
+        }
+
+        Ok(self
+            .chat
+            .get(doc_id)
+            .map(|log| log.recent(limit))
+            .unwrap_or_default())
+    }
+
+    /// Get the peers for a document
+    pub async fn get_document_peers(&self, _doc_id: &Uuid) -> Result<Vec<PeerInfo>> {
+        // This would normally be implemented to get peers from the document's subscribers
+        // For now, we'll return an empty list
+        Ok(Vec::new())
+    }
+
+    /// Get all document IDs
+    pub async fn get_all_documents(&self) -> Result<Vec<Uuid>> {
+        let mut doc_ids = Vec::new();
+        for item in self.documents.iter().filter(|item| !self.trash.contains_key(item.key())) {
             doc_ids.push(*item.key());
         }
         Ok(doc_ids)
     }
+
+    /// Rename a document
+    pub async fn rename_document(&self, doc_id: &Uuid, title: String, changed_by: Option<&str>) -> Result<()> {
+        let document = self.get_document(doc_id).await?;
+        document.write().await.update_title(title.clone());
+
+        self.emit_metadata_change(doc_id, MetadataChange {
+            user: changed_by.map(str::to_string),
+            title: Some(title),
+            ..Default::default()
+        });
+
+        Ok(())
+    }
+
+    /// Transfer ownership of a document; the previous owner becomes a collaborator.
+    ///
+    /// Callers are responsible for checking that the requester is the current owner.
+    pub async fn transfer_ownership(&self, doc_id: &Uuid, new_owner: String, changed_by: Option<&str>) -> Result<()> {
+        let document = self.get_document(doc_id).await?;
+        let collaborators = {
+            let mut doc = document.write().await;
+            if doc.owner == new_owner {
+                return Ok(());
+            }
+            doc.transfer_ownership(new_owner.clone());
+            Self::sorted_collaborators(&doc)
+        };
+
+        self.emit_metadata_change(doc_id, MetadataChange {
+            user: changed_by.map(str::to_string),
+            owner: Some(new_owner),
+            collaborators: Some(collaborators),
+            ..Default::default()
+        });
+
+        Ok(())
+    }
+
+    /// Replace the tags of a document
+    pub async fn set_document_tags(&self, doc_id: &Uuid, tags: Vec<String>, changed_by: Option<&str>) -> Result<()> {
+        let document = self.get_document(doc_id).await?;
+        let tags = {
+            let mut doc = document.write().await;
+            doc.set_tags(tags.into_iter().collect());
+            let mut tags: Vec<String> = doc.tags.iter().cloned().collect();
+            tags.sort();
+            tags
+        };
+
+        self.emit_metadata_change(doc_id, MetadataChange {
+            user: changed_by.map(str::to_string),
+            tags: Some(tags),
+            ..Default::default()
+        });
+
+        Ok(())
+    }
+
+    /// Set one of a document's custom metadata values
+    pub async fn set_custom_metadata(&self, doc_id: &Uuid, key: String, value: String, changed_by: Option<&str>) -> Result<()> {
+        let document = self.get_document(doc_id).await?;
+        let custom_metadata = {
+            let mut doc = document.write().await;
+            doc.set_custom_metadata(key, value)?;
+            doc.custom_metadata.clone()
+        };
+
+        self.emit_metadata_change(doc_id, MetadataChange {
+            user: changed_by.map(str::to_string),
+            custom_metadata: Some(custom_metadata),
+            ..Default::default()
+        });
+
+        Ok(())
+    }
+
+    /// Replace all of a document's custom metadata, e.g. with a peer's copy
+    ///
+    /// Nothing is changed, or announced, if the metadata is the same already.
+    pub async fn replace_custom_metadata(&self, doc_id: &Uuid, custom_metadata: std::collections::HashMap<String, String>, changed_by: Option<&str>) -> Result<()> {
+        let document = self.get_document(doc_id).await?;
+        {
+            let mut doc = document.write().await;
+            if doc.custom_metadata == custom_metadata {
+                return Ok(());
+            }
+            doc.replace_custom_metadata(custom_metadata.clone())?;
+        }
+
+        self.emit_metadata_change(doc_id, MetadataChange {
+            user: changed_by.map(str::to_string),
+            custom_metadata: Some(custom_metadata),
+            ..Default::default()
+        });
+
+        Ok(())
+    }
+
+    /// Get one of a document's custom metadata values, if set
+    pub async fn get_custom_metadata(&self, doc_id: &Uuid, key: &str) -> Result<Option<String>> {
+        let document = self.get_document(doc_id).await?;
+        let value = document.read().await.custom_metadata.get(key).cloned();
+        Ok(value)
+    }
+
+    /// Add a collaborator to a document
+    pub async fn add_collaborator(&self, doc_id: &Uuid, user_id: String, changed_by: Option<&str>) -> Result<bool> {
+        let document = self.get_document(doc_id).await?;
+        let (added, collaborators) = {
+            let mut doc = document.write().await;
+            let added = doc.add_collaborator(user_id);
+            (added, Self::sorted_collaborators(&doc))
+        };
+
+        if added {
+            self.emit_metadata_change(doc_id, MetadataChange {
+                user: changed_by.map(str::to_string),
+                collaborators: Some(collaborators),
+                ..Default::default()
+            });
+        }
+
+        Ok(added)
+    }
+
+    /// Remove a collaborator from a document
+    pub async fn remove_collaborator(&self, doc_id: &Uuid, user_id: &str, changed_by: Option<&str>) -> Result<bool> {
+        let document = self.get_document(doc_id).await?;
+        let (removed, collaborators) = {
+            let mut doc = document.write().await;
+            let removed = doc.remove_collaborator(user_id);
+            (removed, Self::sorted_collaborators(&doc))
+        };
+
+        if removed {
+            self.emit_metadata_change(doc_id, MetadataChange {
+                user: changed_by.map(str::to_string),
+                collaborators: Some(collaborators),
+                ..Default::default()
+            });
+        }
+
+        Ok(removed)
+    }
+
+    /// Set the Git repository URL of a document
+    ///
+    /// Fails if another document is linked to the same repository, as both would write the
+    /// same files in it.
+    pub async fn set_repository_url(&self, doc_id: &Uuid, url: String, changed_by: Option<&str>) -> Result<()> {
+        let document = self.get_document(doc_id).await?;
+        self.check_repository_available(doc_id, &url).await?;
+        document.write().await.set_repository_url(url.clone());
+
+        self.emit_metadata_change(doc_id, MetadataChange {
+            user: changed_by.map(str::to_string),
+            repository_url: Some(url),
+            ..Default::default()
+        });
+
+        Ok(())
+    }
+
+    /// Fail if a document other than `doc_id` is linked to the repository at `url`
+    pub async fn check_repository_available(&self, doc_id: &Uuid, url: &str) -> Result<()> {
+        let others: Vec<(Uuid, Arc<RwLock<Document>>)> = self
+            .documents
+            .iter()
+            .filter(|item| item.key() != doc_id && !self.trash.contains_key(item.key()))
+            .map(|item| (*item.key(), Arc::clone(item.value())))
+            .collect();
+
+        let wanted = normalize_repository_url(url);
+        for (other_id, document) in others {
+            let document = document.read().await;
+            if document.repository_url.as_deref().map(normalize_repository_url) == Some(wanted) {
+                return Err(anyhow::anyhow!(AppError::RepositoryInUse(url.to_string(), other_id)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Assign a user a role in a document
+    pub async fn set_role(&self, doc_id: &Uuid, user_id: String, role: Role, changed_by: Option<&str>) -> Result<()> {
+        let document = self.get_document(doc_id).await?;
+        let roles = {
+            let mut doc = document.write().await;
+            doc.set_role(user_id, role);
+            doc.roles.clone()
+        };
+
+        self.emit_metadata_change(doc_id, MetadataChange {
+            user: changed_by.map(str::to_string),
+            roles: Some(roles),
+            ..Default::default()
+        });
+
+        Ok(())
+    }
+
+    /// Check that a user's role in a document is at least `required`, returning the role
+    pub async fn authorize(&self, doc_id: &Uuid, user_id: &str, required: Role) -> Result<Role> {
+        let document = self.get_document(doc_id).await?;
+        Self::check_role(&*document.read().await, user_id, required)
+    }
+
+    /// Check that a user holds at least the `required` role in a document in the trash
+    pub async fn authorize_trashed(&self, doc_id: &Uuid, user_id: &str, required: Role) -> Result<Role> {
+        let document = self
+            .documents
+            .get(doc_id)
+            .filter(|_| self.trash.contains_key(doc_id))
+            .map(|item| item.value().clone())
+            .ok_or_else(|| anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)))?;
+        Self::check_role(&*document.read().await, user_id, required)
+    }
+
+    fn check_role(doc: &Document, user_id: &str, required: Role) -> Result<Role> {
+        let doc_id = doc.id;
+        let role = doc.role_of(user_id);
+
+        match role {
+            Some(role) if role >= required => Ok(role),
+            Some(role) => Err(anyhow::anyhow!(AppError::Forbidden(format!(
+                "{} is a {:?} of document {}, which requires {:?} or above", user_id, role, doc_id, required
+            )))),
+            None => Err(anyhow::anyhow!(AppError::Forbidden(format!(
+                "{} has no access to document {}", user_id, doc_id
+            )))),
+        }
+    }
+
+    /// Emit a metadata change event for a document
+    fn emit_metadata_change(&self, doc_id: &Uuid, change: MetadataChange) {
+        self.emit_event(DocumentEvent::MetadataChanged {
+            document_id: *doc_id,
+            change,
+        });
+    }
+
+    fn sorted_collaborators(doc: &Document) -> Vec<String> {
+        let mut collaborators: Vec<String> = doc.collaborators.iter().cloned().collect();
+        collaborators.sort();
+        collaborators
+    }
+
+    /// Add a comment anchored to a range of a document's text
+    pub async fn add_comment(&self, doc_id: &Uuid, user_id: String, range: Range<usize>, body: String) -> Result<Comment> {
+        let branch = self
+            .branches
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+
+        let content_len = branch.value().read().await.len();
+        if range.start > range.end || range.end > content_len {
+            return Err(anyhow::anyhow!(AppError::CrdtError(format!(
+                "Invalid comment range {}..{} for document of length {}",
+                range.start, range.end, content_len
+            ))));
+        }
+
+        let comment = Comment::new(*doc_id, user_id, range, body);
+        self.comments.entry(*doc_id).or_default().push(comment.clone());
+
+        self.emit_event(DocumentEvent::CommentUpdated {
+            document_id: *doc_id,
+            comment: comment.clone(),
+        });
+        self.record_activity(doc_id, ActivityKind::Commented {
+            user_id: comment.user_id.clone(),
+            comment_id: comment.id,
+        });
+
+        Ok(comment)
+    }
+
+    /// Get all comments on a document
+    pub async fn get_comments(&self, doc_id: &Uuid) -> Result<Vec<Comment>> {
+        if !self.documents.contains_key(doc_id) {
+            return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id)));
+        }
+
+        Ok(self.comments.get(doc_id).map(|c| c.value().clone()).unwrap_or_default())
+    }
+
+    /// Mark a comment as resolved
+    pub async fn resolve_comment(&self, doc_id: &Uuid, comment_id: &Uuid) -> Result<Comment> {
+        let comment = {
+            let mut comments = self
+                .comments
+                .get_mut(doc_id)
+                .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Comment not found: {}", comment_id))))?;
+
+            let comment = comments
+                .iter_mut()
+                .find(|c| c.id == *comment_id)
+                .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Comment not found: {}", comment_id))))?;
+
+            comment.resolve();
+            comment.clone()
+        };
+
+        self.emit_event(DocumentEvent::CommentUpdated {
+            document_id: *doc_id,
+            comment: comment.clone(),
+        });
+
+        Ok(comment)
+    }
+
+    /// Rebase the comment anchors of a document after an operation
+    fn rebase_comments(&self, doc_id: &Uuid, operation: &DocumentOperation) {
+        if let Some(mut comments) = self.comments.get_mut(doc_id) {
+            for comment in comments.iter_mut() {
+                comment.rebase(operation);
+            }
+        }
+    }
+
+    fn rebase_undo_steps(&self, doc_id: &Uuid, operation: &DocumentOperation) {
+        if let Some(mut stacks) = self.undo_stacks.get_mut(doc_id) {
+            for step in stacks.values_mut().flatten() {
+                *step = step.transformed_by(operation);
+            }
+        }
+    }
+
+    /// Push an undo step for a user, forgetting their oldest once they have `MAX_UNDO_STEPS`
+    fn record_undo(&self, doc_id: &Uuid, user_id: &str, inverse: DocumentOperation) {
+        let mut stacks = self.undo_stacks.entry(*doc_id).or_default();
+        let stack = stacks.entry(user_id.to_string()).or_default();
+        stack.push(inverse);
+        if stack.len() > MAX_UNDO_STEPS {
+            let excess = stack.len() - MAX_UNDO_STEPS;
+            stack.drain(..excess);
+        }
+    }
+
+    /// Build the operation that reverts another, before it is applied
+    ///
+    /// Only the text the operation removes is read from the document.
+    async fn inverse_of(&self, doc_id: &Uuid, operation: &DocumentOperation) -> Result<DocumentOperation> {
+        let removed = match operation {
+            DocumentOperation::Delete { range, .. } | DocumentOperation::Replace { range, .. } => range.clone(),
+            DocumentOperation::Insert { .. } | DocumentOperation::Move { .. } => {
+                return Ok(Self::invert(operation, |_| String::new()));
+            }
+        };
+
+        let branch = self
+            .branches
+            .get(doc_id)
+            .ok_or_else(|| anyhow::anyhow!(AppError::CrdtError(format!("Document branch not found: {}", doc_id))))?;
+        let branch_read = self.lock_wait.read(branch.value(), || format!("the branch of document {}", doc_id)).await?;
+        let text = slice_text(&branch_read, &removed);
+        Ok(Self::invert(operation, |_| text.clone()))
+    }
+
+    /// Build the operation that reverts another, given the text of a range before it is applied
+    ///
+    /// Only reverting a delete or a replace reads any text, so runs of typing pass none.
+    fn invert(operation: &DocumentOperation, text: impl Fn(&Range<usize>) -> String) -> DocumentOperation {
+        match operation {
+            DocumentOperation::Insert { document_id, user_id, position, content } => DocumentOperation::Delete {
+                document_id: *document_id,
+                user_id: user_id.clone(),
+                range: *position..*position + content.chars().count(),
+            },
+            DocumentOperation::Delete { document_id, user_id, range } => DocumentOperation::Insert {
+                document_id: *document_id,
+                user_id: user_id.clone(),
+                position: range.start,
+                content: text(range),
+            },
+            DocumentOperation::Replace { document_id, user_id, range, content } => DocumentOperation::Replace {
+                document_id: *document_id,
+                user_id: user_id.clone(),
+                range: range.start..range.start + content.chars().count(),
+                content: text(range),
+            },
+            DocumentOperation::Move { .. } if operation.is_noop_move() => operation.clone(),
+            // Move the text back from where it landed to where it came from
+            DocumentOperation::Move { document_id, user_id, source, dest } => {
+                let target = move_target(source, *dest);
+                DocumentOperation::Move {
+                    document_id: *document_id,
+                    user_id: user_id.clone(),
+                    source: target..target + source.len(),
+                    dest: if *dest < source.start { source.end } else { source.start },
+                }
+            }
+        }
+    }
+
+    /// Record undo steps for runs of typing that `operation` ended, and encode them
+    ///
+    /// The runs are as of before the operation, so their undo steps are rebased over it.
+    fn end_runs(&self, doc_id: &Uuid, runs: Vec<DocumentOperation>, operation: &DocumentOperation) -> Result<Vec<Vec<u8>>> {
+        runs.into_iter()
+            .map(|run| {
+                let inverse = Self::invert(&run, |_| String::new()).transformed_by(operation);
+                self.record_undo(doc_id, run.user_id(), inverse);
+                self.encoder.encode_operation(&run)
+            })
+            .collect()
+    }
+
+    fn lock_coalescer(&self) -> std::sync::MutexGuard<'_, InsertCoalescer> {
+        self.coalescer.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_ready(&self) -> std::sync::MutexGuard<'_, Vec<(Uuid, Vec<u8>)>> {
+        self.ready_operations.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The text an operation just applied to an OpLog moved, if it was a move
+fn moved_text(operation: &DocumentOperation, oplog: &OpLog) -> Option<MovedText> {
+    match operation {
+        DocumentOperation::Move { source, .. } if !operation.is_noop_move() => MovedText::last_in(oplog, source.len()),
+        _ => None,
+    }
+}
+
+/// Check that the range an operation touches lies within a document of `len` characters
+fn check_in_bounds(operation: &DocumentOperation, len: usize) -> Result<()> {
+    let range = operation.affected_range();
+    if range.start > range.end || range.end > len {
+        return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+            "Operation range {:?} is out of bounds for a document of length {}",
+            range, len
+        ))));
+    }
+    Ok(())
+}
+
+/// The text of a range of a branch, cut short at the end of the document
+fn slice_text(branch: &Branch, range: &Range<usize>) -> String {
+    let len = branch.len();
+    branch.content().slice_chars(range.start.min(len)..range.end.min(len)).collect()
+}
+
+/// A repository URL reduced to what identifies the repository, so `https://host/repo.git/` and
+/// `https://host/repo` compare equal
+fn normalize_repository_url(url: &str) -> &str {
+    let url = url.trim().trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url)
 }
@@ -0,0 +1,84 @@
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+use super::activity::ActivityEvent;
+use super::chat::ChatEntry;
+use super::comments::Comment;
+use super::document::Role;
+use crate::api::protocol::UserPresence;
+use super::history::OperationRecord;
+
+/// Events emitted by the CRDT engine when document state changes
+#[derive(Debug, Clone)]
+pub enum DocumentEvent {
+    /// A comment was added or changed
+    CommentUpdated {
+        document_id: Uuid,
+        comment: Comment,
+    },
+
+    /// Operations were applied to a document, locally or from a peer
+    OperationApplied {
+        document_id: Uuid,
+        records: Vec<OperationRecord>,
+    },
+
+    /// Document metadata changed
+    MetadataChanged {
+        document_id: Uuid,
+        change: MetadataChange,
+    },
+
+    /// A document was deleted
+    DocumentDeleted {
+        document_id: Uuid,
+    },
+
+    /// A document moved to another ID, with its history, to merge with a peer's copy of it
+    DocumentIdChanged {
+        old_id: Uuid,
+        new_id: Uuid,
+    },
+
+    /// A user's presence decayed after they went silent, rather than being updated by them
+    PresenceChanged {
+        document_id: Uuid,
+        presence: UserPresence,
+    },
+
+    /// A user who went silent for too long was removed from a document's presence
+    PresenceRemoved {
+        document_id: Uuid,
+        user_id: String,
+    },
+
+    /// An entry was added to a document's activity feed
+    ActivityRecorded {
+        event: ActivityEvent,
+    },
+
+    /// A chat message about a document was sent here or arrived from a peer
+    ChatReceived {
+        document_id: Uuid,
+        entry: ChatEntry,
+    },
+
+    /// Remote operations to a document were dropped, so it needs syncing with peers to catch up
+    ResyncNeeded {
+        document_id: Uuid,
+    },
+}
+
+/// The metadata fields that changed, leaving unchanged fields as `None`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetadataChange {
+    /// User who made the change, when known; changes from peers or Git have none
+    pub user: Option<String>,
+    pub title: Option<String>,
+    pub owner: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub collaborators: Option<Vec<String>>,
+    pub repository_url: Option<String>,
+    pub roles: Option<BTreeMap<String, Role>>,
+    pub custom_metadata: Option<HashMap<String, String>>,
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use super::engine::CrdtEngine;
+
+/// Most remote operations queued for a frozen document; past this the queue is dropped and
+/// the document is synced with peers once it thaws instead
+pub const MAX_QUEUED_WHILE_FROZEN: usize = 1024;
+
+/// A change from a peer held back while its document is frozen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueuedChange {
+    /// An encoded operation, for `CrdtEngine::apply_remote_operation`
+    Operation(Vec<u8>),
+    /// An encoded OpLog, for `CrdtEngine::sync_document`
+    OpLog(Vec<u8>),
+}
+
+/// A document no edits may be made to for now, e.g. while a Git operation works from its content
+#[derive(Debug, Default)]
+pub struct FrozenDocument {
+    /// How many freezes are in place; the document thaws when the last is lifted
+    pub holds: usize,
+    /// Changes received from peers meanwhile, applied in order on thawing
+    pub queued: Vec<QueuedChange>,
+    /// Whether more operations arrived than could be queued, so they were all dropped
+    pub overflowed: bool,
+}
+
+impl FrozenDocument {
+    /// Hold back a change from a peer until the document thaws, giving up on the queue once
+    /// it is full
+    pub fn queue(&mut self, change: QueuedChange) {
+        if self.overflowed {
+            return;
+        }
+        if self.queued.len() >= MAX_QUEUED_WHILE_FROZEN {
+            self.queued = Vec::new();
+            self.overflowed = true;
+            return;
+        }
+        self.queued.push(change);
+    }
+}
+
+/// Keeps a document frozen until it is released or dropped
+///
+/// Releasing applies the remote operations queued meanwhile before returning. Dropping an
+/// unreleased guard, e.g. on an early return, thaws the document in the background.
+pub struct FreezeGuard {
+    engine: Arc<RwLock<CrdtEngine>>,
+    doc_id: Uuid,
+    released: bool,
+}
+
+impl FreezeGuard {
+    /// Freeze a document
+    pub async fn new(engine: Arc<RwLock<CrdtEngine>>, doc_id: Uuid) -> Result<Self> {
+        engine.read().await.freeze_document(&doc_id)?;
+        Ok(Self { engine, doc_id, released: false })
+    }
+
+    /// Lift the freeze, waiting for the queued remote operations to be applied
+    pub async fn release(mut self) {
+        self.released = true;
+        unfreeze(&self.engine, self.doc_id).await;
+    }
+}
+
+impl Drop for FreezeGuard {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+
+        let (engine, doc_id) = (Arc::clone(&self.engine), self.doc_id);
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move { unfreeze(&engine, doc_id).await });
+            }
+            Err(_) => tracing::error!("Document {} stays frozen: no runtime to thaw it on", doc_id),
+        }
+    }
+}
+
+async fn unfreeze(engine: &RwLock<CrdtEngine>, doc_id: Uuid) {
+    if let Err(e) = engine.read().await.unfreeze_document(&doc_id).await {
+        tracing::warn!("Failed to unfreeze document {}: {}", doc_id, e);
+    }
+}
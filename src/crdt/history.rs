@@ -0,0 +1,325 @@
+use diamond_types::AgentId;
+use diamond_types::list::OpLog;
+use diamond_types::list::operation::{OpKind, Operation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::attribution::Attribution;
+use super::operations::{move_target, DocumentOperation};
+use super::presence::ConflictHint;
+
+/// Kind of a recorded operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OperationKind {
+    Insert,
+    Delete,
+}
+
+/// A single operation from a document's OpLog, attributed to the user who made it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationRecord {
+    /// Local version of the first item the operation touched
+    pub version: usize,
+    /// User ID of the author
+    pub agent: String,
+    /// Whether the operation inserted or deleted text
+    pub kind: OperationKind,
+    /// Character position in the document at the time of the operation
+    pub position: usize,
+    /// Number of characters inserted or deleted
+    pub len: usize,
+    /// Inserted or deleted text, if the OpLog retained it
+    pub content: Option<String>,
+    /// Set on operations as they are applied when they land on another user's cursor or
+    /// selection; never set on recorded history
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict_hint: Option<ConflictHint>,
+}
+
+/// An operation from a document's history, for reviewing the history offline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Patch {
+    /// Local version of the first item the operation touched
+    pub version: usize,
+    /// ID of the last item the operation touched, as `agent:seq`, which names the version
+    /// just after it on every replica
+    pub id: String,
+    /// User ID of the author
+    pub author: String,
+    pub kind: OperationKind,
+    /// Character position in the document at the time of the operation
+    pub position: usize,
+    /// Number of characters inserted or deleted
+    pub len: usize,
+    /// Inserted or deleted text, if the OpLog retained it
+    pub content: Option<String>,
+    /// When this node applied the operation; unknown for operations it loaded rather than
+    /// applied since it started
+    pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Patch {
+    pub fn new(record: OperationRecord, id: String, timestamp: Option<chrono::DateTime<chrono::Utc>>) -> Self {
+        Self {
+            version: record.version,
+            id,
+            author: record.agent,
+            kind: record.kind,
+            position: record.position,
+            len: record.len,
+            content: record.content,
+            timestamp,
+        }
+    }
+}
+
+/// Parse a frontier given as the comma-separated `agent:seq` IDs of its items
+pub fn parse_frontier(frontier: &str) -> Option<Vec<(String, usize)>> {
+    frontier
+        .split(',')
+        .map(|id| {
+            let (agent, seq) = id.trim().rsplit_once(':')?;
+            Some((agent.to_string(), seq.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Decode the full history of an OpLog, in the order operations were added to it
+///
+/// The OpLog run-length encodes adjacent operations even when they come from different
+/// agents, so operations are split wherever the author changes.
+pub fn operation_records(oplog: &OpLog) -> Vec<OperationRecord> {
+    // Agent spans cover the OpLog's local versions in order
+    let mut agent_spans = Vec::new();
+    let mut version = 0;
+    for span in oplog.iter_mappings() {
+        let len = span.seq_range.end - span.seq_range.start;
+        agent_spans.push((version..version + len, span.agent));
+        version += len;
+    }
+
+    let mut records = Vec::new();
+    let mut version = 0;
+    for op in oplog.iter() {
+        let op_len = op.loc.span.end - op.loc.span.start;
+        let mut offset = 0;
+
+        while offset < op_len {
+            let at = version + offset;
+            let (range, agent) = agent_spans
+                .iter()
+                .find(|(range, _)| range.contains(&at))
+                .cloned()
+                .unwrap_or((at..version + op_len, 0));
+            let end = (range.end - version).min(op_len);
+
+            records.push(split_record(&op, offset..end, at, oplog.get_agent_name(agent)));
+            offset = end;
+        }
+
+        version += op_len;
+    }
+
+    records
+}
+
+/// Whether the operations from version `since` onwards were made one after the other, on top
+/// of every operation before them
+///
+/// Operation records only replay cleanly onto the content as of `since` if so, since the
+/// positions of an operation made concurrently refer to content no one else had.
+pub fn is_linear_since(oplog: &OpLog, since: usize) -> bool {
+    let entries: Vec<_> = oplog.iter_history().collect();
+
+    // Each operation from `since` on builds on the one right before it
+    let sequential = entries
+        .iter()
+        .filter(|entry| entry.span.start >= since)
+        .all(|entry| match entry.span.start {
+            0 => entry.parents.is_empty(),
+            start => entry.parents.as_slice() == [start - 1],
+        });
+    if !sequential || since == 0 {
+        return sequential;
+    }
+
+    // ... and the operation right before `since` builds on every operation before it
+    let mut seen = vec![false; since];
+    let mut pending = vec![since - 1];
+    while let Some(version) = pending.pop() {
+        if seen[version] {
+            continue;
+        }
+        let Some(entry) = entries
+            .partition_point(|entry| entry.span.start <= version)
+            .checked_sub(1)
+            .map(|index| &entries[index])
+        else {
+            return false;
+        };
+        seen[entry.span.start..=version].fill(true);
+        pending.extend(entry.parents.iter().copied());
+    }
+
+    seen.iter().all(|&seen| seen)
+}
+
+/// The part of an insert record from `offset` characters in onwards
+///
+/// Returns `None` for deletes, whose runs may shrink either forwards or backwards.
+pub fn trim_record(record: &OperationRecord, offset: usize) -> Option<OperationRecord> {
+    if record.kind != OperationKind::Insert {
+        return None;
+    }
+
+    Some(OperationRecord {
+        version: record.version + offset,
+        position: record.position + offset,
+        len: record.len - offset,
+        content: Some(record.content.as_ref()?.chars().skip(offset).collect()),
+        ..record.clone()
+    })
+}
+
+/// Describe a document operation that was just added to an OpLog at `first_version`
+pub fn records_for_operation(operation: &DocumentOperation, first_version: usize) -> Vec<OperationRecord> {
+    let insert = |user_id: &str, version: usize, position: usize, content: &str| OperationRecord {
+        version,
+        agent: user_id.to_string(),
+        kind: OperationKind::Insert,
+        position,
+        len: content.chars().count(),
+        content: Some(content.to_string()),
+        conflict_hint: None,
+    };
+    let delete = |user_id: &str, range: &std::ops::Range<usize>| OperationRecord {
+        version: first_version,
+        agent: user_id.to_string(),
+        kind: OperationKind::Delete,
+        position: range.start,
+        len: range.end - range.start,
+        content: None,
+        conflict_hint: None,
+    };
+
+    match operation {
+        DocumentOperation::Insert { user_id, position, content, .. } => {
+            vec![insert(user_id, first_version, *position, content)]
+        }
+        DocumentOperation::Delete { user_id, range, .. } => vec![delete(user_id, range)],
+        DocumentOperation::Replace { user_id, range, content, .. } => vec![
+            delete(user_id, range),
+            insert(user_id, first_version + range.len(), range.start, content),
+        ],
+        DocumentOperation::Move { .. } if operation.is_noop_move() => Vec::new(),
+        // The moved text is re-inserted under its original authors, but is described here as
+        // one insert by whoever moved it
+        DocumentOperation::Move { user_id, source, dest, .. } => vec![
+            delete(user_id, source),
+            OperationRecord {
+                version: first_version + source.len(),
+                agent: user_id.to_string(),
+                kind: OperationKind::Insert,
+                position: move_target(source, *dest),
+                len: source.len(),
+                content: None,
+                conflict_hint: None,
+            },
+        ],
+    }
+}
+
+/// Work out which agent wrote each character of an OpLog's current content
+///
+/// Replays the OpLog's operations in order, tracking the author of every character they leave
+/// in the document. Text put back by one of the moves in `attribution` keeps the author it had
+/// when the move took it out.
+pub fn content_authors(oplog: &OpLog, attribution: &Attribution) -> Vec<AgentId> {
+    let mut agent_spans = Vec::new();
+    let mut version = 0;
+    for span in oplog.iter_mappings() {
+        let len = span.seq_range.end - span.seq_range.start;
+        agent_spans.push((version..version + len, span.agent, span.seq_range.start));
+        version += len;
+    }
+    let origin_at = |version: usize| {
+        agent_spans
+            .iter()
+            .find(|(range, _, _)| range.contains(&version))
+            .map_or((0, 0), |(range, agent, seq)| (*agent, seq + version - range.start))
+    };
+
+    // Authors of the text each move took out, indexed like the moves in `attribution`
+    let mut taken: HashMap<usize, Vec<Option<AgentId>>> = HashMap::new();
+
+    let mut authors = Vec::new();
+    for (versions, op) in oplog.iter_xf_operations() {
+        let Some(op) = op else { continue };
+        let span = op.loc.span.start..op.loc.span.end;
+        match op.kind {
+            OpKind::Ins => {
+                let inserted: Vec<AgentId> = (versions.start..versions.end)
+                    .map(|version| {
+                        let (agent, seq) = origin_at(version);
+                        if attribution.is_empty() {
+                            return agent;
+                        }
+                        attribution
+                            .inserted_by(oplog.get_agent_name(agent), seq)
+                            .and_then(|(index, offset)| taken.get(&index)?.get(offset).copied().flatten())
+                            .unwrap_or(agent)
+                    })
+                    .collect();
+                authors.splice(span.start..span.start, inserted);
+            }
+            OpKind::Del => {
+                let removed: Vec<AgentId> = authors.drain(span).collect();
+                if attribution.is_empty() {
+                    continue;
+                }
+
+                // A forward delete takes out the characters in order, a backspace from the end
+                for (i, version) in (versions.start..versions.end).enumerate() {
+                    let (agent, seq) = origin_at(version);
+                    let Some((index, offset)) = attribution.deleted_by(oplog.get_agent_name(agent), seq) else {
+                        continue;
+                    };
+                    let author = if op.loc.fwd { removed.get(i) } else { removed.len().checked_sub(i + 1).and_then(|i| removed.get(i)) };
+                    let moved = taken.entry(index).or_insert_with(|| vec![None; attribution.moved_len(index)]);
+                    if let Some(slot) = moved.get_mut(offset) {
+                        *slot = author.copied();
+                    }
+                }
+            }
+        }
+    }
+
+    authors
+}
+
+/// Build the record for the `offsets` slice of an operation
+fn split_record(op: &Operation, offsets: std::ops::Range<usize>, version: usize, agent: &str) -> OperationRecord {
+    let len = offsets.end - offsets.start;
+    let content = op
+        .content
+        .as_ref()
+        .map(|c| c.chars().skip(offsets.start).take(len).collect());
+
+    let (kind, position) = match op.kind {
+        OpKind::Ins => (OperationKind::Insert, op.loc.span.start + offsets.start),
+        // Forward deletes repeatedly remove the character at the start of the span, while
+        // backspaces walk backwards from its end
+        OpKind::Del if op.loc.fwd => (OperationKind::Delete, op.loc.span.start),
+        OpKind::Del => (OperationKind::Delete, op.loc.span.end - offsets.end),
+    };
+
+    OperationRecord {
+        version,
+        agent: agent.to_string(),
+        kind,
+        position,
+        len,
+        content,
+        conflict_hint: None,
+    }
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+use uuid::Uuid;
+
+/// A mismatch between the engine's document, OpLog and branch maps, which creating,
+/// importing or deleting a document can leave behind if it fails halfway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    /// A document has an OpLog but no branch to read it through
+    MissingBranch(Uuid),
+    /// A document has no OpLog
+    MissingOplog(Uuid),
+    /// An OpLog belongs to no document
+    OrphanedOplog(Uuid),
+    /// A branch belongs to no document
+    OrphanedBranch(Uuid),
+}
+
+impl IntegrityIssue {
+    /// ID of the document the issue is about
+    pub fn document_id(&self) -> Uuid {
+        match self {
+            IntegrityIssue::MissingBranch(id)
+            | IntegrityIssue::MissingOplog(id)
+            | IntegrityIssue::OrphanedOplog(id)
+            | IntegrityIssue::OrphanedBranch(id) => *id,
+        }
+    }
+}
+
+impl fmt::Display for IntegrityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityIssue::MissingBranch(id) => write!(f, "document {} has no branch", id),
+            IntegrityIssue::MissingOplog(id) => write!(f, "document {} has no OpLog", id),
+            IntegrityIssue::OrphanedOplog(id) => write!(f, "OpLog {} belongs to no document", id),
+            IntegrityIssue::OrphanedBranch(id) => write!(f, "branch {} belongs to no document", id),
+        }
+    }
+}
+
+/// How a document compares to a peer's copy, going by the content hash the peer sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashComparison {
+    /// Both copies are at the same version and have the same content
+    Matches,
+    /// Both copies are at the same version, yet their content differs
+    Diverged,
+    /// The copies are at different versions, or the peer sent no hash, so nothing can be told
+    NotComparable,
+}
@@ -0,0 +1,21 @@
+use super::operations::DocumentOperation;
+
+/// What an interceptor decided to do with an operation
+#[derive(Debug, Clone)]
+pub enum InterceptDecision {
+    /// Apply the operation as it is
+    Allow,
+    /// Refuse the operation, leaving the document unchanged
+    Reject(String),
+    /// Apply this operation instead
+    Modify(DocumentOperation),
+}
+
+/// Hook that sees every local and remote operation before the engine applies it
+///
+/// Interceptors run in the order they were registered, each seeing the operation as the
+/// previous one left it. Undo steps and merges of external content are not intercepted, as
+/// they only restore or bring in text that was already let through.
+pub trait OperationInterceptor: std::fmt::Debug + Send + Sync {
+    fn before_apply(&self, operation: &DocumentOperation) -> InterceptDecision;
+}
@@ -0,0 +1,71 @@
+use std::ops::Range;
+use uuid::Uuid;
+
+use super::operations::DocumentOperation;
+use crate::utils::errors::AppError;
+
+/// Build the operations that wrap a range of text, e.g. in `\textbf{` and `}`
+///
+/// `before` is inserted at the start of the range and `after` at its end, shifted by the
+/// length of `before`. Apply the operations in order.
+pub fn wrap_range(
+    document_id: Uuid,
+    user_id: &str,
+    range: Range<usize>,
+    before: &str,
+    after: &str,
+) -> Vec<DocumentOperation> {
+    let shifted_end = range.end + before.chars().count();
+
+    vec![
+        DocumentOperation::Insert {
+            document_id,
+            user_id: user_id.to_string(),
+            position: range.start,
+            content: before.to_string(),
+        },
+        DocumentOperation::Insert {
+            document_id,
+            user_id: user_id.to_string(),
+            position: shifted_end,
+            content: after.to_string(),
+        },
+    ]
+}
+
+/// Build the operations that wrap a range of text in a LaTeX command, e.g. `\textbf{...}`
+pub fn wrap_in_command(
+    document_id: Uuid,
+    user_id: &str,
+    range: Range<usize>,
+    command: &str,
+) -> Result<Vec<DocumentOperation>, AppError> {
+    validate_name(command)?;
+    Ok(wrap_range(document_id, user_id, range, &format!("\\{}{{", command), "}"))
+}
+
+/// Build the operations that insert an empty `\begin{env}`/`\end{env}` pair at a position
+pub fn insert_environment(
+    document_id: Uuid,
+    user_id: &str,
+    position: usize,
+    env_name: &str,
+) -> Result<Vec<DocumentOperation>, AppError> {
+    validate_name(env_name)?;
+    Ok(wrap_range(
+        document_id,
+        user_id,
+        position..position,
+        &format!("\\begin{{{}}}\n", env_name),
+        &format!("\n\\end{{{}}}", env_name),
+    ))
+}
+
+/// Check that a command or environment name is a plain LaTeX name, optionally starred
+fn validate_name(name: &str) -> Result<(), AppError> {
+    let base = name.strip_suffix('*').unwrap_or(name);
+    if base.is_empty() || !base.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(AppError::CrdtError(format!("Invalid LaTeX name: {:?}", name)));
+    }
+    Ok(())
+}
@@ -2,3 +2,18 @@ pub mod engine;
 pub mod document;
 pub mod operations;
 pub mod document_branch_manager;
+pub mod agent_map;
+pub mod comments;
+pub mod events;
+pub mod history;
+pub mod presence;
+pub mod latex_ops;
+pub mod coalesce;
+pub mod diff;
+pub mod activity;
+pub mod chat;
+pub mod intercept;
+pub mod integrity;
+pub mod freeze;
+pub mod stats;
+pub mod attribution;
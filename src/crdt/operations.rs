@@ -1,4 +1,4 @@
-use diamond_types::{list::OpLog};
+use diamond_types::list::{Branch, OpLog};
 use serde::{Deserialize, Serialize};
 use std::ops::Range;
 use uuid::Uuid;
@@ -39,11 +39,27 @@ pub enum DocumentOperation {
         /// New content
         content: String,
     },
+    /// Move the text in a range so that it starts at another position
+    ///
+    /// Whatever text is in the source range when the move is applied is moved, so edits that
+    /// landed inside it beforehand travel with it. Positions inside the source range, such as
+    /// comment anchors and undo steps, follow it to its destination.
+    Move {
+        /// Document ID
+        document_id: Uuid,
+        /// User ID
+        user_id: String,
+        /// Range to move (start..end)
+        source: Range<usize>,
+        /// Position to move the text to, as of before the move. A destination inside the
+        /// source range leaves the document unchanged.
+        dest: usize,
+    },
 }
 
 impl DocumentOperation {
-    /// Apply this operation to the given OpLog
-    pub fn apply(&self, oplog: &mut OpLog) -> Result<(), AppError> {
+    /// Apply this operation to the given OpLog, whose tip `branch` is checked out at
+    pub fn apply(&self, oplog: &mut OpLog, branch: &Branch) -> Result<(), AppError> {
         match self {
             DocumentOperation::Insert { user_id, position, content, .. } => {
                 let agent_id = oplog.get_or_create_agent_id(&user_id);
@@ -66,27 +82,334 @@ impl DocumentOperation {
 
                 Ok(())
             }
+            DocumentOperation::Move { user_id, source, dest, .. } => {
+                if self.is_noop_move() {
+                    return Ok(());
+                }
+
+                // Taken out and put back by the mover; who wrote the text is kept in the
+                // document's `Attribution`
+                let text: String = branch.content().slice_chars(source.clone()).collect();
+                let agent_id = oplog.get_or_create_agent_id(user_id);
+                oplog.add_delete_without_content(agent_id, source.clone());
+                oplog.add_insert(agent_id, move_target(source, *dest), &text);
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl DocumentOperation {
+    /// Get the ID of the document this operation targets
+    pub fn document_id(&self) -> Uuid {
+        match self {
+            DocumentOperation::Insert { document_id, .. } => *document_id,
+            DocumentOperation::Delete { document_id, .. } => *document_id,
+            DocumentOperation::Replace { document_id, .. } => *document_id,
+            DocumentOperation::Move { document_id, .. } => *document_id,
+        }
+    }
+
+    /// The same operation, targeting another document
+    pub fn with_document_id(mut self, id: Uuid) -> Self {
+        match &mut self {
+            DocumentOperation::Insert { document_id, .. }
+            | DocumentOperation::Delete { document_id, .. }
+            | DocumentOperation::Replace { document_id, .. }
+            | DocumentOperation::Move { document_id, .. } => *document_id = id,
+        }
+        self
+    }
+
+    /// Get the ID of the user who made this operation
+    pub fn user_id(&self) -> &str {
+        match self {
+            DocumentOperation::Insert { user_id, .. } => user_id,
+            DocumentOperation::Delete { user_id, .. } => user_id,
+            DocumentOperation::Replace { user_id, .. } => user_id,
+            DocumentOperation::Move { user_id, .. } => user_id,
+        }
+    }
+
+    /// Number of bytes of text this operation inserts
+    pub fn inserted_bytes(&self) -> usize {
+        match self {
+            DocumentOperation::Insert { content, .. } => content.len(),
+            DocumentOperation::Replace { content, .. } => content.len(),
+            DocumentOperation::Delete { .. } | DocumentOperation::Move { .. } => 0,
+        }
+    }
+
+    /// Range of the document this operation touches, which is empty for inserts and spans
+    /// both the source and destination of moves
+    pub fn affected_range(&self) -> Range<usize> {
+        match self {
+            DocumentOperation::Insert { position, .. } => *position..*position,
+            DocumentOperation::Delete { range, .. } => range.clone(),
+            DocumentOperation::Replace { range, .. } => range.clone(),
+            DocumentOperation::Move { source, dest, .. } => source.start.min(*dest)..source.end.max(*dest),
+        }
+    }
+
+    /// Whether this is a move that leaves the document unchanged
+    pub fn is_noop_move(&self) -> bool {
+        match self {
+            DocumentOperation::Move { source, dest, .. } => {
+                source.is_empty() || (source.start..=source.end).contains(dest)
+            }
+            _ => false,
         }
     }
+
+    /// Rebase this operation over another one that was applied after it was made
+    pub fn transformed_by(&self, other: &DocumentOperation) -> DocumentOperation {
+        let mut operation = self.clone();
+        match &mut operation {
+            DocumentOperation::Insert { position, .. } => *position = other.transform_position(*position),
+            DocumentOperation::Delete { range, .. } => *range = other.transform_range(range),
+            DocumentOperation::Replace { range, .. } => *range = other.transform_range(range),
+            DocumentOperation::Move { source, dest, .. } => {
+                *source = other.transform_range(source);
+                *dest = other.transform_position(*dest);
+            }
+        }
+        operation
+    }
+
+    /// Shift a position to account for this operation having been applied.
+    /// Text inserted exactly at the position is placed before it.
+    pub fn transform_position(&self, position: usize) -> usize {
+        match self {
+            DocumentOperation::Insert { position: at, content, .. } => {
+                shift_for_insert(position, *at, content.chars().count(), true)
+            }
+            DocumentOperation::Delete { range, .. } => shift_for_delete(position, range),
+            DocumentOperation::Replace { range, content, .. } => {
+                let position = shift_for_delete(position, range);
+                shift_for_insert(position, range.start, content.chars().count(), true)
+            }
+            DocumentOperation::Move { .. } if self.is_noop_move() => position,
+            DocumentOperation::Move { source, dest, .. } => shift_for_move(position, source, *dest, true),
+        }
+    }
+
+    /// Shift a range (such as a selection or comment anchor) to account for this
+    /// operation having been applied. Text inserted at either edge stays outside the range.
+    pub fn transform_range(&self, range: &Range<usize>) -> Range<usize> {
+        let start = self.transform_position(range.start);
+        let end = match self {
+            DocumentOperation::Insert { position: at, content, .. } => {
+                shift_for_insert(range.end, *at, content.chars().count(), false)
+            }
+            DocumentOperation::Delete { range: deleted, .. } => shift_for_delete(range.end, deleted),
+            DocumentOperation::Replace { range: replaced, content, .. } => {
+                let end = shift_for_delete(range.end, replaced);
+                shift_for_insert(end, replaced.start, content.chars().count(), false)
+            }
+            DocumentOperation::Move { .. } if self.is_noop_move() => range.end,
+            DocumentOperation::Move { source, dest, .. } => shift_for_move(range.end, source, *dest, false),
+        };
+
+        start..end.max(start)
+    }
+}
+
+fn shift_for_insert(position: usize, at: usize, len: usize, inclusive: bool) -> usize {
+    if at < position || (inclusive && at == position) {
+        position + len
+    } else {
+        position
+    }
+}
+
+fn shift_for_delete(position: usize, deleted: &Range<usize>) -> usize {
+    if position <= deleted.start {
+        position
+    } else if position >= deleted.end {
+        position - deleted.len()
+    } else {
+        deleted.start
+    }
+}
+
+/// Where moved text starts once the source range has been deleted
+pub fn move_target(source: &Range<usize>, dest: usize) -> usize {
+    if dest >= source.end {
+        dest - source.len()
+    } else {
+        dest
+    }
+}
+
+/// Shift a position for a move. Positions inside the source range follow the moved text; a
+/// range ending exactly at the end of the source counts as inside it, one ending at its start
+/// doesn't.
+fn shift_for_move(position: usize, source: &Range<usize>, dest: usize, inclusive: bool) -> usize {
+    let inside = if inclusive {
+        source.contains(&position)
+    } else {
+        source.start < position && position <= source.end
+    };
+
+    let target = move_target(source, dest);
+    if inside {
+        target + (position - source.start)
+    } else {
+        shift_for_insert(shift_for_delete(position, source), target, source.len(), inclusive)
+    }
+}
+
+/// Version of the encoded operation format written by default
+///
+/// Encoded operations start with a version byte:
+/// - 1: the operation as JSON, with the node it originated on
+/// - 2: as version 1, plus an operation ID
+///
+/// Operations encoded before the version byte was introduced are bare version 1 JSON.
+pub const OPERATION_FORMAT_VERSION: u8 = 2;
+
+/// An operation as decoded from the wire, upgraded to the current format
+#[derive(Debug, Clone)]
+pub struct DecodedOperation {
+    pub operation: DocumentOperation,
+    /// ID of the operation; synthesized from the payload for formats that didn't carry one,
+    /// so every node decoding the same payload agrees on it
+    pub op_id: Uuid,
+    /// Node the operation was first applied on, if it was stamped with one
+    pub origin_node_id: Option<String>,
 }
 
 /// Interface for encoding and decoding operations for network transmission
 #[derive(Debug)]
-pub struct OperationEncoder;
+pub struct OperationEncoder {
+    /// Node stamped on every encoded operation, if any
+    origin_node_id: Option<String>,
+    /// Format version operations are encoded in
+    format_version: u8,
+}
+
+impl Default for OperationEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An operation on the wire in format version 1, with the node it was first applied on
+///
+/// Operations encoded before origins were stamped decode with no origin.
+#[derive(Debug, Serialize, Deserialize)]
+struct StampedOperationV1 {
+    #[serde(flatten)]
+    operation: DocumentOperation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin_node_id: Option<String>,
+}
+
+/// An operation on the wire in format version 2, which adds an operation ID
+#[derive(Debug, Serialize, Deserialize)]
+struct StampedOperationV2 {
+    #[serde(flatten)]
+    operation: DocumentOperation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin_node_id: Option<String>,
+    op_id: Uuid,
+}
 
 impl OperationEncoder {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            origin_node_id: None,
+            format_version: OPERATION_FORMAT_VERSION,
+        }
+    }
+
+    /// An encoder that stamps every operation it encodes as originating from `node_id`
+    pub fn with_origin(node_id: String) -> Self {
+        Self {
+            origin_node_id: Some(node_id),
+            ..Self::new()
+        }
+    }
+
+    /// Encode in an older format version, for peers that don't understand the current one
+    pub fn with_format_version(mut self, version: u8) -> anyhow::Result<Self> {
+        if !(1..=OPERATION_FORMAT_VERSION).contains(&version) {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Unsupported operation format version {}, expected 1 to {}",
+                version, OPERATION_FORMAT_VERSION
+            ))));
+        }
+        self.format_version = version;
+        Ok(self)
     }
 
-    /// Encode an operation for network transmission
+    /// Encode an operation for network transmission, under a new operation ID
     pub fn encode_operation(&self, operation: &DocumentOperation) -> anyhow::Result<Vec<u8>> {
-        // For simplicity, we'll use serde_json to encode operations
-        Ok(serde_json::to_vec(operation)?)
+        let mut bytes = vec![self.format_version];
+        match self.format_version {
+            1 => serde_json::to_writer(&mut bytes, &StampedOperationV1 {
+                operation: operation.clone(),
+                origin_node_id: self.origin_node_id.clone(),
+            })?,
+            _ => serde_json::to_writer(&mut bytes, &StampedOperationV2 {
+                operation: operation.clone(),
+                origin_node_id: self.origin_node_id.clone(),
+                op_id: Uuid::new_v4(),
+            })?,
+        }
+        Ok(bytes)
+    }
+
+    /// Decode an operation in any known format version, upgrading it to the current one
+    pub fn decode(&self, bytes: &[u8]) -> anyhow::Result<DecodedOperation> {
+        match bytes.first() {
+            // Encoded before the version byte was introduced
+            Some(b'{') => upgrade_v1(bytes),
+            Some(1) => upgrade_v1(&bytes[1..]),
+            Some(2) => {
+                let stamped: StampedOperationV2 = serde_json::from_slice(&bytes[1..])?;
+                Ok(DecodedOperation {
+                    operation: stamped.operation,
+                    op_id: stamped.op_id,
+                    origin_node_id: stamped.origin_node_id,
+                })
+            }
+            Some(version) if *version > OPERATION_FORMAT_VERSION => Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Operation format version {} is newer than the latest supported version {}",
+                version, OPERATION_FORMAT_VERSION
+            )))),
+            Some(version) => Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Unknown operation format version {}", version
+            )))),
+            None => Err(anyhow::anyhow!(AppError::ProtocolError("Empty operation".to_string()))),
+        }
+    }
+
+    /// Decode an operation from network transmission, along with the node it originated on
+    pub fn decode_stamped_operation(&self, bytes: &[u8]) -> anyhow::Result<(DocumentOperation, Option<String>)> {
+        let decoded = self.decode(bytes)?;
+        Ok((decoded.operation, decoded.origin_node_id))
     }
 
     /// Decode an operation from network transmission
     pub fn decode_operation(&self, bytes: &[u8]) -> anyhow::Result<DocumentOperation> {
-        Ok(serde_json::from_slice(bytes)?)
+        Ok(self.decode(bytes)?.operation)
     }
 }
+
+/// Decode a version 1 operation, deriving its ID from its JSON
+fn upgrade_v1(json: &[u8]) -> anyhow::Result<DecodedOperation> {
+    use sha2::{Digest, Sha256};
+
+    let stamped: StampedOperationV1 = serde_json::from_slice(json)?;
+    let digest = Sha256::digest(json);
+    let mut id = [0u8; 16];
+    id.copy_from_slice(&digest[..16]);
+
+    Ok(DecodedOperation {
+        operation: stamped.operation,
+        op_id: uuid::Builder::from_random_bytes(id).into_uuid(),
+        origin_node_id: stamped.origin_node_id,
+    })
+}
@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::operations::DocumentOperation;
+use crate::api::protocol::UserPresence;
+
+/// Hues used for collaborator colors, spaced so neighbouring entries are easy to tell apart
+const PALETTE_HUES: [u16; 12] = [0, 210, 120, 30, 270, 180, 330, 60, 240, 150, 300, 90];
+
+/// Lightness levels, doubling the palette to 24 colors
+const PALETTE_LIGHTNESS: [u8; 2] = [45, 30];
+
+/// Deterministically assign a display color to a user
+///
+/// The same user always gets the same color, on every peer and across sessions.
+pub fn user_color(user_id: &str) -> String {
+    let hash = fnv1a(user_id.as_bytes());
+    let index = (hash % (PALETTE_HUES.len() * PALETTE_LIGHTNESS.len()) as u64) as usize;
+
+    let hue = PALETTE_HUES[index % PALETTE_HUES.len()];
+    let lightness = PALETTE_LIGHTNESS[index / PALETTE_HUES.len()];
+
+    format!("hsl({}, 70%, {}%)", hue, lightness)
+}
+
+/// 64-bit FNV-1a, which unlike the std hasher is stable across Rust versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// How many characters either side of another user's cursor or selection count as their spot
+pub const CONFLICT_MARGIN: usize = 2;
+
+/// Advisory warning that an operation landed where other users are working
+///
+/// The CRDT converges regardless; this only lets clients point out hot spots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictHint {
+    /// Users whose cursor or selection the operation overlapped, sorted
+    pub user_ids: Vec<String>,
+}
+
+/// Check an operation against the cursors and selections of the other active users in a
+/// document, as of before the operation is applied
+pub fn conflict_hint<'a>(
+    operation: &DocumentOperation,
+    presences: impl IntoIterator<Item = &'a UserPresence>,
+) -> Option<ConflictHint> {
+    let touched = operation.affected_range();
+
+    let mut user_ids: Vec<String> = presences
+        .into_iter()
+        .filter(|presence| presence.is_active && presence.user_id != operation.user_id())
+        .filter(|presence| {
+            let spot = match (&presence.selection, presence.cursor_position) {
+                (Some(selection), _) => selection.clone(),
+                (None, Some(cursor)) => cursor..cursor,
+                (None, None) => return false,
+            };
+            let start = spot.start.saturating_sub(CONFLICT_MARGIN);
+            let end = spot.end.saturating_add(CONFLICT_MARGIN);
+            touched.start <= end && start <= touched.end
+        })
+        .map(|presence| presence.user_id.clone())
+        .collect();
+
+    if user_ids.is_empty() {
+        return None;
+    }
+    user_ids.sort();
+
+    Some(ConflictHint { user_ids })
+}
+
+/// How often silent users are checked for
+pub const PRESENCE_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// When the presence of a user who has gone silent decays
+///
+/// Any message from the user counts as a sign of life: an operation, a presence update or
+/// a heartbeat acknowledgement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresenceDecay {
+    /// Silence after which the user is marked inactive
+    pub inactive_after: Duration,
+    /// Silence after which the user's presence is removed altogether
+    pub remove_after: Duration,
+}
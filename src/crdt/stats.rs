@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::history::{OperationKind, Patch};
+
+/// Who contributed what to a document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentStats {
+    /// Operations in the document's history
+    pub total_operations: usize,
+    /// Current length in characters
+    pub length: usize,
+    /// Each user who edited the document, sorted by user ID
+    pub users: Vec<UserStats>,
+}
+
+/// One user's contributions to a document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserStats {
+    pub user_id: String,
+    /// Operations in the document's history made by the user
+    pub operations: usize,
+    /// Characters the user inserted, including ones since deleted
+    pub inserted: usize,
+    /// Characters the user deleted
+    pub deleted: usize,
+    /// Characters of the current content the user wrote
+    pub characters: usize,
+    /// When this node applied the user's latest operation; unknown if it loaded rather than
+    /// applied it since it started
+    pub last_edit: Option<DateTime<Utc>>,
+}
+
+impl DocumentStats {
+    /// Tally a document's history, given as patches, and the author of each character of its
+    /// current content
+    pub fn new<'a>(patches: &[Patch], authors: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut users: BTreeMap<&str, UserStats> = BTreeMap::new();
+        let user = |user_id: &str| UserStats {
+            user_id: user_id.to_string(),
+            operations: 0,
+            inserted: 0,
+            deleted: 0,
+            characters: 0,
+            last_edit: None,
+        };
+
+        for patch in patches {
+            let stats = users.entry(&patch.author).or_insert_with(|| user(&patch.author));
+            stats.operations += 1;
+            match patch.kind {
+                OperationKind::Insert => stats.inserted += patch.len,
+                OperationKind::Delete => stats.deleted += patch.len,
+            }
+            stats.last_edit = stats.last_edit.max(patch.timestamp);
+        }
+
+        let mut length = 0;
+        for author in authors {
+            users.entry(author).or_insert_with(|| user(author)).characters += 1;
+            length += 1;
+        }
+
+        Self {
+            total_operations: patches.len(),
+            length,
+            users: users.into_values().collect(),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::config::GitConfig;
+use crate::utils::errors::AppError;
+
+/// A repository as returned by the GitHub API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitHubRepository {
+    pub html_url: String,
+    pub clone_url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateRepositoryRequest<'a> {
+    name: &'a str,
+    private: bool,
+}
+
+/// Minimal client for the parts of the GitHub REST API used for publishing documents
+#[derive(Debug, Clone)]
+pub struct GitHubClient {
+    api_url: String,
+    token: String,
+    username: Option<String>,
+    client: reqwest::Client,
+}
+
+impl GitHubClient {
+    /// Create a client from the Git configuration, which must include a GitHub token
+    pub fn from_config(config: &GitConfig) -> Result<Self> {
+        let token = config.github_token.clone().ok_or_else(|| {
+            anyhow::anyhow!(AppError::ConfigError("github_token is not configured".to_string()))
+        })?;
+
+        Ok(Self {
+            api_url: config.github_api_url.trim_end_matches('/').to_string(),
+            token,
+            username: config.github_username.clone(),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Create a repository named `owner/name`
+    ///
+    /// Repositories owned by the configured user are created under the user's account, any
+    /// other owner is taken to be an organization.
+    pub async fn create_repository(&self, full_name: &str, private: bool) -> Result<GitHubRepository> {
+        let (owner, name) = full_name
+            .split_once('/')
+            .filter(|(owner, name)| !owner.is_empty() && !name.is_empty() && !name.contains('/'))
+            .ok_or_else(|| anyhow::anyhow!(AppError::GitError(format!(
+                "Invalid GitHub repository {:?}, expected owner/name",
+                full_name
+            ))))?;
+
+        let url = if self.username.as_deref() == Some(owner) {
+            format!("{}/user/repos", self.api_url)
+        } else {
+            format!("{}/orgs/{}/repos", self.api_url, owner)
+        };
+
+        let response = self
+            .client
+            .post(&url)
+            .bearer_auth(&self.token)
+            .header(reqwest::header::USER_AGENT, "texswarm")
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .json(&CreateRepositoryRequest { name, private })
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!(AppError::GitError(format!("GitHub request failed: {}", e))))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!(AppError::GitError(format!(
+                "GitHub refused to create {}: {} {}",
+                full_name, status, body
+            ))));
+        }
+
+        response
+            .json::<GitHubRepository>()
+            .await
+            .map_err(|e| anyhow::anyhow!(AppError::GitError(format!("Unexpected GitHub response: {}", e))))
+    }
+}
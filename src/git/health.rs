@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Whether a document's remote could last be reached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteStatus {
+    /// The remote that was checked
+    pub repository_url: String,
+    /// When the remote was last checked
+    pub last_checked: DateTime<Utc>,
+    /// When the remote last answered; unset if it never has since this node started
+    pub last_success: Option<DateTime<Utc>>,
+    /// Why the last check failed; unset if it succeeded
+    pub last_error: Option<String>,
+}
+
+impl RemoteStatus {
+    /// Whether the remote answered the last check
+    pub fn is_reachable(&self) -> bool {
+        self.last_error.is_none()
+    }
+
+    /// Record the outcome of checking `repository_url` at `checked`, on top of what is known
+    /// from earlier checks, if any
+    pub fn record(previous: Option<&RemoteStatus>, repository_url: &str, checked: DateTime<Utc>, error: Option<String>) -> Self {
+        // A success recorded for another remote says nothing about this one
+        let last_success = previous
+            .filter(|previous| previous.repository_url == repository_url)
+            .and_then(|previous| previous.last_success);
+
+        Self {
+            repository_url: repository_url.to_string(),
+            last_checked: checked,
+            last_success: if error.is_none() { Some(checked) } else { last_success },
+            last_error: error,
+        }
+    }
+}
@@ -3,13 +3,18 @@ use git2::Repository;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex, OwnedMutexGuard, OwnedSemaphorePermit, RwLock, Semaphore};
+use tokio::time::Instant;
 use uuid::Uuid;
 
 use crate::crdt::engine::CrdtEngine;
+use crate::crdt::events::DocumentEvent;
+use crate::git::github::GitHubClient;
+use crate::git::health::RemoteStatus;
 use crate::git::repository::RepositoryManager;
-use crate::git::sync::GitSync;
-use crate::utils::config::Config;
+use crate::git::sync::{sanitize_filename, GitSync, GIT_SOURCE};
+use crate::utils::config::{ensure_writable_dir, Config};
 use crate::utils::errors::AppError;
 
 /// The GitManager handles Git repository operations and document synchronization
@@ -19,12 +24,40 @@ pub struct GitManager {
     repositories: HashMap<Uuid, RepositoryManager>,
     crdt_engine: Arc<RwLock<CrdtEngine>>,
     git_synchronizer: GitSync,
+    /// Slots for Git operations, so only so many block a thread at once
+    operation_slots: Arc<Semaphore>,
+    /// One lock per document, held for any Git operation on its repository, since git2
+    /// can't safely change a repository from two places at once
+    document_locks: Arc<dashmap::DashMap<Uuid, Arc<Mutex<()>>>>,
+    /// Outcome of the latest reachability check of each linked document's remote
+    remote_status: Arc<dashmap::DashMap<Uuid, RemoteStatus>>,
+}
+
+/// Held while a Git operation on a document runs: a free operation slot and the document's
+/// repository to itself
+///
+/// The document itself is only frozen while content from the repository is merged into it,
+/// see `GitSync::pull_into_crdt`, so edits carry on while the remote is fetched or pushed to.
+pub struct GitOperationGuard {
+    _document: OwnedMutexGuard<()>,
+    _slot: OwnedSemaphorePermit,
+    document_id: Uuid,
+    document_locks: Arc<dashmap::DashMap<Uuid, Arc<Mutex<()>>>>,
+}
+
+impl Drop for GitOperationGuard {
+    fn drop(&mut self) {
+        // Drop the document's lock once nobody holds or waits for it, so the map doesn't keep
+        // one for every document ever synced. Anyone taking it clones it under the map's lock,
+        // so the count can't go up while it is checked.
+        self.document_locks.remove_if(&self.document_id, |_, lock| Arc::strong_count(lock) <= 2);
+    }
 }
 
 impl GitManager {
     pub fn new(config: &Config, crdt_engine: Arc<RwLock<CrdtEngine>>) -> Result<Self> {
-        // Ensure the repositories directory exists
-        std::fs::create_dir_all(&config.git.repositories_path)?;
+        // Ensure the repositories directory exists and can be written to
+        ensure_writable_dir("git.repositories_path", &config.git.repositories_path)?;
 
         // Create the Git synchronizer
         let repo_manager = RepositoryManager::new(config.git.clone());
@@ -41,9 +74,223 @@ impl GitManager {
             repositories: HashMap::new(),
             crdt_engine,
             git_synchronizer,
+            operation_slots: Arc::new(Semaphore::new(config.git.max_concurrent_operations)),
+            document_locks: Arc::new(dashmap::DashMap::new()),
+            remote_status: Arc::new(dashmap::DashMap::new()),
+        })
+    }
+
+    /// Wait until no other Git operation runs on the document's repository and an operation
+    /// slot is free, holding both until the guard is dropped
+    ///
+    /// Only take it while holding a lock on the manager, or a caller waiting for the manager
+    /// may hold up the guard's release.
+    pub async fn lock_document(&self, doc_id: &Uuid) -> Result<GitOperationGuard> {
+        let lock = Arc::clone(self.document_locks.entry(*doc_id).or_default().value());
+
+        // Wait for the document before taking a slot, so waiting doesn't hold one
+        let document = lock.lock_owned().await;
+        let slot = Arc::clone(&self.operation_slots)
+            .acquire_owned()
+            .await
+            .map_err(|_| AppError::GitError("Git operations have been shut down".to_string()))?;
+
+        Ok(GitOperationGuard {
+            _document: document,
+            _slot: slot,
+            document_id: *doc_id,
+            document_locks: Arc::clone(&self.document_locks),
         })
     }
 
+    /// How many documents have a Git operation running or waiting on them
+    pub fn locked_documents(&self) -> usize {
+        self.document_locks.len()
+    }
+
+    /// Pull a document's repository into it, then commit its content and push, waiting for
+    /// other Git operations on the document to finish first
+    ///
+    /// Only needs shared access, so documents sync concurrently up to the operation limit.
+    /// Returns whether there was anything to commit.
+    pub async fn sync_to_repository(&self, doc_id: &Uuid) -> Result<bool> {
+        let _guard = self.lock_document(doc_id).await?;
+        self.git_synchronizer.sync_document(doc_id).await
+    }
+
+    /// Sync a document to its repository right away if it is linked to one, returning whether
+    /// there was anything to commit
+    ///
+    /// Git operations can't move between threads, so the sync runs on a blocking thread of its
+    /// own and callers on any task can await it.
+    pub async fn sync_now(manager: Arc<RwLock<GitManager>>, doc_id: Uuid) -> Result<bool> {
+        tokio::task::spawn_blocking(move || {
+            tokio::runtime::Handle::current().block_on(async {
+                let manager = manager.read().await;
+                if !manager.has_repository_url(&doc_id).await {
+                    return Ok(false);
+                }
+                manager.sync_to_repository(&doc_id).await
+            })
+        })
+        .await?
+    }
+
+    /// Sync each document to its repository once it has been edited and then left alone for
+    /// `quiet_period`, so a burst of edits becomes a single commit
+    ///
+    /// Edits are learned from `events`; changes pulled in from the repository itself don't
+    /// count. Runs until the engine goes away.
+    pub async fn run_quiet_sync(
+        manager: Arc<RwLock<GitManager>>,
+        mut events: broadcast::Receiver<DocumentEvent>,
+        quiet_period: Duration,
+    ) {
+        let mut last_edits: HashMap<Uuid, Instant> = HashMap::new();
+
+        loop {
+            let next_due = last_edits.values().min().map(|edited| *edited + quiet_period);
+
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(DocumentEvent::OperationApplied { document_id, records }) => {
+                        if records.iter().any(|record| record.agent != GIT_SOURCE) {
+                            last_edits.insert(document_id, Instant::now());
+                        }
+                    }
+                    Ok(DocumentEvent::DocumentDeleted { document_id }) => {
+                        last_edits.remove(&document_id);
+                    }
+                    Ok(DocumentEvent::DocumentIdChanged { old_id, new_id }) => {
+                        if let Some(edited) = last_edits.remove(&old_id) {
+                            last_edits.insert(new_id, edited);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("Quiet-period Git sync missed {} engine events", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                    let now = Instant::now();
+                    let quiet: Vec<Uuid> = last_edits
+                        .iter()
+                        .filter(|(_, edited)| now.duration_since(**edited) >= quiet_period)
+                        .map(|(doc_id, _)| *doc_id)
+                        .collect();
+
+                    // Only the syncs themselves take a blocking thread, not the wait between them
+                    for doc_id in quiet {
+                        last_edits.remove(&doc_id);
+
+                        if let Err(e) = Self::sync_now(Arc::clone(&manager), doc_id).await {
+                            tracing::warn!("Failed to sync document {} after edits: {}", doc_id, e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The synchronizer documents are synced with, to run `GitSync::start_sync_task` on
+    pub fn synchronizer(&self) -> GitSync {
+        self.git_synchronizer.clone()
+    }
+
+    /// How long edited documents are left alone before `run_quiet_sync` syncs them, if at all
+    pub fn sync_quiet_period(&self) -> Option<Duration> {
+        self.config.git.sync_quiet_period()
+    }
+
+    /// How often every linked document is synced regardless of edits, if it is
+    pub fn sync_interval(&self) -> Option<Duration> {
+        self.config.git.sync_interval()
+    }
+
+    /// How often documents' remotes are checked for being reachable, if they are
+    pub fn remote_check_interval(&self) -> Option<Duration> {
+        self.config.git.remote_check_interval()
+    }
+
+    /// Check that each linked document's remote still answers every `interval`, starting
+    /// straight away, so a deleted repository or revoked token is noticed before a sync fails
+    pub async fn run_remote_checks(manager: Arc<RwLock<GitManager>>, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+
+            // Check without holding the manager's lock, so a slow remote holds nothing up
+            let manager = manager.read().await.clone();
+            match manager.check_remotes().await {
+                Ok(0) => {}
+                Ok(unreachable) => tracing::warn!("{} Git remotes are unreachable", unreachable),
+                Err(e) => tracing::warn!("Failed to check Git remotes: {}", e),
+            }
+        }
+    }
+
+    /// Check that each linked document's remote still answers, recording the outcome, and
+    /// return how many didn't
+    ///
+    /// Nothing is fetched from the remotes, and the documents' Git locks aren't taken, so
+    /// checking never waits for, or holds up, a sync.
+    pub async fn check_remotes(&self) -> Result<usize> {
+        let linked: Vec<(Uuid, String)> = {
+            let engine = self.crdt_engine.read().await;
+            let mut linked = Vec::new();
+            for doc_id in engine.get_all_documents().await? {
+                if let Ok(document) = engine.get_document(&doc_id).await
+                    && let Some(url) = document.read().await.repository_url.clone()
+                {
+                    linked.push((doc_id, url));
+                }
+            }
+            linked
+        };
+        self.remote_status.retain(|doc_id, _| linked.iter().any(|(linked_id, _)| linked_id == doc_id));
+
+        let mut unreachable = 0;
+        for (doc_id, url) in linked {
+            let repo_manager = self.git_synchronizer.repo_manager.clone();
+            let remote = url.clone();
+            let error = match tokio::task::spawn_blocking(move || repo_manager.check_remote(&remote)).await {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(e) => Some(format!("Remote check panicked: {}", e)),
+            };
+            if let Some(error) = &error {
+                unreachable += 1;
+                tracing::warn!("Remote of document {} is unreachable: {}", doc_id, error);
+            }
+
+            let previous = self.remote_status.get(&doc_id).map(|status| status.clone());
+            let status = RemoteStatus::record(previous.as_ref(), &url, chrono::Utc::now(), error);
+            self.remote_status.insert(doc_id, status);
+        }
+
+        Ok(unreachable)
+    }
+
+    /// Outcome of the latest reachability check of a document's remote, if it was checked
+    pub fn remote_status(&self, doc_id: &Uuid) -> Option<RemoteStatus> {
+        self.remote_status.get(doc_id).map(|status| status.clone())
+    }
+
+    /// Find the document `id` names, which is either its ID or its slug
+    pub async fn resolve_document_id(&self, id: &str) -> Result<Uuid> {
+        self.crdt_engine.read().await.resolve_document_id(id)
+    }
+
+    /// Whether a document exists and is linked to a repository
+    async fn has_repository_url(&self, doc_id: &Uuid) -> bool {
+        let engine = self.crdt_engine.read().await;
+        match engine.get_document(doc_id).await {
+            Ok(document) => document.read().await.repository_url.is_some(),
+            Err(_) => false,
+        }
+    }
+
     /// Create a new repository for a document
     pub async fn create_repository(&mut self, doc_id: &Uuid, name: &str) -> Result<String> {
         // Get the document to verify it exists
@@ -56,17 +303,56 @@ impl GitManager {
         // and get its remote URL
         let repo_url = format!("https://example.com/repos/{}.git", name);
 
+        // Update the document with the repository URL, unless another document has it
+        drop(doc); // Drop the read lock before the engine takes a write lock
+        engine.set_repository_url(doc_id, repo_url.clone(), None).await?;
+
         // Store the repository
         self.repositories.insert(*doc_id, self.git_synchronizer.repo_manager.clone());
 
-        // Update the document with the repository URL
-        drop(doc); // Drop the read lock before acquiring a write lock
-        let mut doc = document.write().await;
-        doc.set_repository_url(repo_url.clone());
-
         return Ok(repo_url);
     }
 
+    /// Publish a document to a new GitHub repository in one step
+    ///
+    /// Creates `owner/name` on GitHub with the configured token, makes it the origin of the
+    /// document's local repository, and commits and pushes the current content. Returns the
+    /// repository's HTML URL.
+    pub async fn publish_document(&mut self, doc_id: &Uuid, github_repo: &str, private: bool) -> Result<String> {
+        let _guard = self.lock_document(doc_id).await?;
+
+        let github = GitHubClient::from_config(&self.config.git)?;
+
+        let (title, content) = {
+            let engine = self.crdt_engine.read().await;
+            let document = engine.get_document(doc_id).await?;
+            let title = document.read().await.title.clone();
+            (title, engine.get_document_content(doc_id).await?)
+        };
+
+        let published = github.create_repository(github_repo, private).await?;
+        self.crdt_engine
+            .read()
+            .await
+            .check_repository_available(doc_id, &published.clone_url)
+            .await?;
+
+        let repo_manager = self.git_synchronizer.repo_manager.clone();
+        {
+            let repo = repo_manager.init_with_remote(doc_id, &published.clone_url)?;
+            repo_manager.save_document(&repo, &content, &sanitize_filename(&title), &format!("Publish {}", title))?;
+        }
+
+        self.repositories.insert(*doc_id, repo_manager);
+        self.crdt_engine
+            .read()
+            .await
+            .set_repository_url(doc_id, published.clone_url, None)
+            .await?;
+
+        Ok(published.html_url)
+    }
+
     /// Clone an existing repository for a document
     pub async fn clone_repository(&mut self, doc_id: &Uuid, url: &str) -> Result<()> {
         // Get the document to verify it exists
@@ -77,6 +363,9 @@ impl GitManager {
         // Create a repository path (not used directly, but keeping for documentation)
         let _repo_path = self.get_repository_path(doc_id);
 
+        // Two documents in one repository would overwrite each other's files
+        engine.check_repository_available(doc_id, url).await?;
+
         // Clone the repository
         let _repo = self.git_synchronizer.repo_manager.clone_or_open(url, doc_id)?;
 
@@ -84,19 +373,19 @@ impl GitManager {
         self.repositories.insert(*doc_id, self.git_synchronizer.repo_manager.clone());
 
         // Update the document with the repository URL
-        drop(doc); // Drop the read lock before acquiring a write lock
-        let mut doc = document.write().await;
-        doc.set_repository_url(url.to_string());
+        drop(doc); // Drop the read lock before the engine takes a write lock
+        engine.set_repository_url(doc_id, url.to_string(), None).await?;
 
         Ok(())
     }
 
     /// Synchronize a document with its Git repository
     pub async fn sync_document(&mut self, doc_id: &Uuid) -> Result<()> {
+        let _guard = self.lock_document(doc_id).await?;
+
         // Get the document
         let repo_url_opt;
         let doc_title;
-        let content;
 
         {
             let engine = self.crdt_engine.read().await;
@@ -104,9 +393,6 @@ impl GitManager {
             let doc = document.read().await;
             repo_url_opt = doc.repository_url.clone();
             doc_title = doc.title.clone();
-
-            // Get the content from the CRDT document before dropping locks
-            content = engine.get_document_content(doc_id).await?;
         } // All locks are dropped here
 
         // Get the repository for this document
@@ -128,7 +414,15 @@ impl GitManager {
         let repo_path = repo.path.clone();
         let repo_obj = Repository::open(&repo_path)
             .map_err(|e| AppError::GitError(format!("Failed to open repository at {}: {}", repo_path.display(), e)))?;
-        self.git_synchronizer.sync_document_to_repo(&repo_obj, content, &format!("Update document {}", doc_title)).await?;
+
+        // Merge in what was committed to the remote since the last sync
+        let filename = sanitize_filename(&doc_title);
+        if repo_obj.find_remote("origin").is_ok() {
+            self.git_synchronizer.pull_into_crdt(&repo_obj, doc_id, &filename).await?;
+        }
+        self.git_synchronizer
+            .commit_document(&repo_obj, doc_id, &filename, &format!("Update document {}", doc_title))
+            .await?;
 
         // Push changes to remote if available
         match repo_obj.find_remote("origin") {
@@ -146,63 +440,36 @@ impl GitManager {
         Ok(())
     }
 
-    /// Synchronize a document with its Git repository, using a blocking approach
-    /// that avoids using async code with git2 (which isn't Send/Sync)
-    pub fn sync_document_blocking(&mut self, doc_id: &Uuid, content: String) -> Result<()> {
-        // Get the repository URL from the document database or configuration
-        let repo_url = match self.get_repository_url(doc_id) {
-            Some(url) => url,
-            None => return Err(anyhow::anyhow!(AppError::RepositoryNotFound(*doc_id))),
-        };
-
-        // Get the document title
-        let doc_title = match self.get_document_title(doc_id) {
-            Some(title) => title,
-            None => return Err(anyhow::anyhow!(AppError::DocumentNotFound(*doc_id))),
-        };
-
-        // Construct a repository manager
-        let repo_manager = self.git_synchronizer.repo_manager.clone();
-
-        // This is a blocking call that creates/opens a repository
-        let repo = repo_manager.clone_or_open(&repo_url, doc_id)?;
-
-        // Update the repository
-        self.git_synchronizer.sync_document_to_repo_blocking(
-            &repo,
-            content,
-            &format!("Update {}", doc_title)
-        )?;
+    /// Repack a document's local repository, returning how many bytes it shrank by, or `None`
+    /// if the document has no repository on disk
+    ///
+    /// This blocks while git2 packs the objects, so call it from a blocking task.
+    pub fn compact_repository(&self, doc_id: &Uuid) -> Result<Option<u64>> {
+        let repo_path = self.get_repository_path(doc_id);
+        if !repo_path.join(".git").exists() {
+            return Ok(None);
+        }
 
-        Ok(())
+        let repo = Repository::open(&repo_path)
+            .map_err(|e| AppError::GitError(format!("Failed to open repository: {}", e)))?;
+        self.git_synchronizer.repo_manager.gc(&repo).map(Some)
     }
 
     // Helper methods to get document information without async
-    fn get_repository_url(&self, doc_id: &Uuid) -> Option<String> {
-        // Since we don't have direct access to documents, we need to use the repositories map
-        self.repositories.get(doc_id).and_then(|_| {
-            // Check if we have a repository for this document
-            // If we do, we need to get the URL from somewhere else
-            // For now, just return a placeholder value
-            Some("https://github.com/example/placeholder.git".to_string())
-        })
-    }
-
-    fn get_document_title(&self, doc_id: &Uuid) -> Option<String> {
-        // Since we don't have direct access to document titles, return a placeholder
-        Some(format!("Document-{}", doc_id))
-    }
-
     /// Pull changes from a remote repository and update the document
     pub async fn pull_changes(&mut self, doc_id: &Uuid) -> Result<()> {
-        // Get the document URL
+        let _guard = self.lock_document(doc_id).await?;
+
+        // Get the document URL and the file its title names
         let repo_url_opt;
+        let filename;
 
         {
             let engine = self.crdt_engine.read().await;
             let document = engine.get_document(doc_id).await?;
             let doc = document.read().await;
             repo_url_opt = doc.repository_url.clone();
+            filename = sanitize_filename(&doc.title);
         } // All locks are dropped here
 
         // Get the repository for this document
@@ -226,14 +493,8 @@ impl GitManager {
             .map_err(|e| AppError::GitError(format!("Failed to open repository at {}: {}", repo_path.display(), e)))?;
                 match repo_obj.find_remote("origin") {
             Ok(_) => {
-                self.git_synchronizer.pull_changes(&repo_obj).await?;
-
-                // Get the updated content from the repository
-                let content = self.git_synchronizer.get_document_from_repo(&repo_obj).await?;
-
-                // Update the CRDT document with the new content
-                let engine = self.crdt_engine.write().await;
-                engine.update_document_content(doc_id, content).await?;
+                // Merge the remote's changes into the CRDT document
+                self.git_synchronizer.pull_into_crdt(&repo_obj, doc_id, &filename).await?;
             },
             Err(e) if e.code() == git2::ErrorCode::NotFound => {
                 // No remote, continue without pulling
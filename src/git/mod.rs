@@ -1,3 +1,5 @@
 pub mod repository;
 pub mod sync;
 pub mod manager;
+pub mod github;
+pub mod health;
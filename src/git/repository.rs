@@ -1,12 +1,19 @@
 use anyhow::Result;
-use git2::{Repository, Signature, PushOptions, RemoteCallbacks};
+use git2::{build::CheckoutBuilder, Buf, FetchOptions, Oid, Repository, Signature, PushOptions, RemoteCallbacks, TreeWalkMode, TreeWalkResult};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::fs;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
+use crate::crdt::document::DocumentEncoding;
 use crate::utils::config::GitConfig;
 use crate::utils::errors::AppError;
 
+/// How old an unreachable loose object must be before [`RepositoryManager::gc`] deletes it
+pub const LOOSE_OBJECT_GRACE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
 /// Repository manager for handling git operations
 #[derive(Clone)]
 pub struct RepositoryManager {
@@ -35,8 +42,8 @@ impl RepositoryManager {
             let repo = Repository::open(&repo_path)
                 .map_err(|e| AppError::GitError(format!("Failed to open repository: {}", e)))?;
 
-            // Pull latest changes
-            self.pull(&repo)?;
+            // Fetch the latest changes; merging them is up to whoever syncs the document
+            self.fetch(&repo)?;
 
             Ok(repo)
         } else {
@@ -54,11 +61,195 @@ impl RepositoryManager {
         }
     }
 
+    /// Initialize a document's repository if it doesn't exist, and point its origin at a URL
+    pub fn init_with_remote(&self, document_id: &Uuid, remote_url: &str) -> Result<Repository> {
+        let repo_path = self.get_repo_path(document_id);
+
+        let repo = if repo_path.exists() {
+            Repository::open(&repo_path)
+                .map_err(|e| AppError::GitError(format!("Failed to open repository: {}", e)))?
+        } else {
+            Repository::init(&repo_path)
+                .map_err(|e| AppError::GitError(format!("Failed to initialize repository: {}", e)))?
+        };
+
+        match repo.find_remote("origin") {
+            Ok(_) => repo.remote_set_url("origin", remote_url)
+                .map_err(|e| AppError::GitError(format!("Failed to update remote: {}", e)))?,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => {
+                repo.remote("origin", remote_url)
+                    .map_err(|e| AppError::GitError(format!("Failed to add remote: {}", e)))?;
+            }
+            Err(e) => return Err(AppError::GitError(format!("Failed to check remote: {}", e)).into()),
+        }
+
+        Ok(repo)
+    }
+
     /// Get the path to a repository
     fn get_repo_path(&self, document_id: &Uuid) -> PathBuf {
         self.config.repositories_path.join(document_id.to_string())
     }
 
+    /// Fetch from the remote, returning the commit the remote has for the current branch
+    ///
+    /// Returns `None` if the remote doesn't have the branch yet.
+    pub fn fetch(&self, repo: &Repository) -> Result<Option<Oid>> {
+        let mut remote = repo.find_remote("origin")
+            .map_err(|e| AppError::GitError(format!("Failed to find remote: {}", e)))?;
+
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(self.remote_callbacks());
+
+        // Fetch with the remote's configured refspecs, which don't fail on missing branches
+        remote.fetch::<&str>(&[], Some(&mut fetch_options), None)
+            .map_err(|e| AppError::GitError(format!("Failed to fetch from remote: {}", e)))?;
+
+        let remote_ref = format!("refs/remotes/origin/{}", Self::branch_name(repo)?);
+        match repo.find_reference(&remote_ref) {
+            Ok(reference) => Ok(Some(reference.peel_to_commit()
+                .map_err(|e| AppError::GitError(format!("Failed to get remote commit: {}", e)))?
+                .id())),
+            Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(AppError::GitError(format!("Failed to find remote reference: {}", e)).into()),
+        }
+    }
+
+    /// Connect to a remote the way fetching would, which has it advertise its references, but
+    /// without fetching anything, like `git ls-remote`
+    pub fn check_remote(&self, url: &str) -> Result<()> {
+        let mut remote = git2::Remote::create_detached(url)
+            .map_err(|e| AppError::GitError(format!("Invalid remote {}: {}", url, e)))?;
+        // Listing the advertised references crashes git2 on a remote that has none, and
+        // connecting already fails for one that can't be read
+        remote.connect_auth(git2::Direction::Fetch, Some(self.remote_callbacks()), None)
+            .map_err(|e| AppError::GitError(format!("Failed to connect to remote {}: {}", url, e)))?;
+
+        Ok(())
+    }
+
+    /// The commit the current branch is at, or `None` if nothing has been committed yet
+    pub fn head_commit(&self, repo: &Repository) -> Result<Option<Oid>> {
+        match repo.head() {
+            Ok(head) => Ok(Some(head.peel_to_commit()
+                .map_err(|e| AppError::GitError(format!("Failed to get HEAD commit: {}", e)))?
+                .id())),
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => Ok(None),
+            Err(e) => Err(AppError::GitError(format!("Failed to get HEAD: {}", e)).into()),
+        }
+    }
+
+    /// Content of a file as of a commit, or an empty string if the commit doesn't have it
+    pub fn read_file_at(&self, repo: &Repository, commit: Oid, filename: &str) -> Result<String> {
+        let tree = repo.find_commit(commit)
+            .and_then(|commit| commit.tree())
+            .map_err(|e| AppError::GitError(format!("Failed to get tree of commit {}: {}", commit, e)))?;
+
+        let entry = match tree.get_path(Path::new(filename)) {
+            Ok(entry) => entry,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(String::new()),
+            Err(e) => return Err(AppError::GitError(format!("Failed to find {}: {}", filename, e)).into()),
+        };
+        let blob = repo.find_blob(entry.id())
+            .map_err(|e| AppError::GitError(format!("Failed to read {}: {}", filename, e)))?;
+
+        // Files are not guaranteed to be valid UTF-8, so decode them lossily
+        Ok(DocumentEncoding::Utf8.decode(blob.content()))
+    }
+
+    /// Commit `content` as `filename` on top of both the current branch and the remote's
+    /// commit `remote`, and check the result out
+    ///
+    /// Other files are merged as Git would, and conflicts in `filename` are resolved by taking
+    /// `content`. If the branch is merely behind and `content` is what the remote already
+    /// has, the branch is fast-forwarded instead.
+    pub fn merge_remote(&self, repo: &Repository, remote: Oid, filename: &str, content: &str, message: &str) -> Result<()> {
+        let remote_commit = repo.find_commit(remote)
+            .map_err(|e| AppError::GitError(format!("Failed to get remote commit: {}", e)))?;
+        let head_commit = match self.head_commit(repo)? {
+            Some(head) => Some(repo.find_commit(head)
+                .map_err(|e| AppError::GitError(format!("Failed to get HEAD commit: {}", e)))?),
+            None => None,
+        };
+
+        let behind = match &head_commit {
+            Some(head) => repo.graph_descendant_of(remote, head.id())
+                .map_err(|e| AppError::GitError(format!("Failed to compare commits: {}", e)))?,
+            None => true,
+        };
+
+        if behind && self.read_file_at(repo, remote, filename)? == content {
+            let branch_ref = format!("refs/heads/{}", Self::branch_name(repo)?);
+            repo.reference(&branch_ref, remote, true, "Fast-forward to remote")
+                .map_err(|e| AppError::GitError(format!("Failed to fast-forward: {}", e)))?;
+        } else {
+            let mut index = match &head_commit {
+                Some(head) => repo.merge_commits(head, &remote_commit, None)
+                    .map_err(|e| AppError::GitError(format!("Failed to merge commits: {}", e)))?,
+                None => {
+                    let mut index = git2::Index::new()
+                        .map_err(|e| AppError::GitError(format!("Failed to create index: {}", e)))?;
+                    index.read_tree(&remote_commit.tree()
+                        .map_err(|e| AppError::GitError(format!("Failed to get remote tree: {}", e)))?)
+                        .map_err(|e| AppError::GitError(format!("Failed to read remote tree: {}", e)))?;
+                    index
+                }
+            };
+
+            // The document itself was already merged by the caller, so drop any conflict in it
+            for stage in 1..=3 {
+                let _ = index.remove(Path::new(filename), stage);
+            }
+            let blob = repo.blob(content.as_bytes())
+                .map_err(|e| AppError::GitError(format!("Failed to write blob: {}", e)))?;
+            index.add(&git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                file_size: content.len() as u32,
+                id: blob,
+                flags: 0,
+                flags_extended: 0,
+                path: filename.as_bytes().to_vec(),
+            })
+            .map_err(|e| AppError::GitError(format!("Failed to add file to index: {}", e)))?;
+
+            if index.has_conflicts() {
+                return Err(AppError::GitError("Merge conflicts detected".to_string()).into());
+            }
+
+            let tree = repo.find_tree(index.write_tree_to(repo)
+                .map_err(|e| AppError::GitError(format!("Failed to write tree: {}", e)))?)
+                .map_err(|e| AppError::GitError(format!("Failed to find tree: {}", e)))?;
+            let signature = self.create_signature()?;
+            let parents: Vec<&git2::Commit> = head_commit.iter().chain([&remote_commit]).collect();
+
+            repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+                .map_err(|e| AppError::GitError(format!("Failed to create merge commit: {}", e)))?;
+        }
+
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))
+            .map_err(|e| AppError::GitError(format!("Failed to check out merge: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Name of the current branch, even if nothing has been committed to it yet
+    fn branch_name(repo: &Repository) -> Result<String> {
+        let head = repo.find_reference("HEAD")
+            .map_err(|e| AppError::GitError(format!("Failed to get HEAD: {}", e)))?;
+
+        Ok(head
+            .symbolic_target()
+            .and_then(|target| target.strip_prefix("refs/heads/"))
+            .unwrap_or("master")
+            .to_string())
+    }
+
     /// Pull the latest changes from the remote repository
     pub fn pull(&self, repo: &Repository) -> Result<()> {
         // Get the default remote
@@ -125,8 +316,8 @@ impl RepositoryManager {
         Ok(())
     }
 
-    /// Save a document to the repository
-    pub fn save_document(&self, repo: &Repository, content: &str, filename: &str, message: &str) -> Result<()> {
+    /// Save a document to the repository, returning whether it had changed and was committed
+    pub fn save_document(&self, repo: &Repository, content: &str, filename: &str, message: &str) -> Result<bool> {
         // Get the repository path
         let repo_path = repo.path().parent().ok_or_else(|| AppError::GitError("Could not get repository path".to_string()))?;
         let file_path = repo_path.join(filename);
@@ -155,12 +346,20 @@ impl RepositoryManager {
         let tree = repo.find_tree(tree_id)
             .map_err(|e| AppError::GitError(format!("Failed to find tree: {}", e)))?;
 
-        // Get the parent commit
-        let head = repo.head()
-            .map_err(|e| AppError::GitError(format!("Failed to get HEAD: {}", e)))?;
-
-        let parent_commit = head.peel_to_commit()
-            .map_err(|e| AppError::GitError(format!("Failed to get HEAD commit: {}", e)))?;
+        // Get the parent commit, which a freshly initialized repository doesn't have yet
+        let parent_commit = match repo.head() {
+            Ok(head) => Some(head.peel_to_commit()
+                .map_err(|e| AppError::GitError(format!("Failed to get HEAD commit: {}", e)))?),
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(e) => return Err(AppError::GitError(format!("Failed to get HEAD: {}", e)).into()),
+        };
+        let parents: Vec<&git2::Commit> = parent_commit.iter().collect();
+
+        // Nothing changed since the last commit, so there is nothing to commit
+        if parent_commit.as_ref().is_some_and(|parent| parent.tree_id() == tree.id()) {
+            self.push(repo)?;
+            return Ok(false);
+        }
 
         // Create the commit
         repo.commit(
@@ -169,14 +368,14 @@ impl RepositoryManager {
             &signature,
             message,
             &tree,
-            &[&parent_commit],
+            &parents,
         )
         .map_err(|e| AppError::GitError(format!("Failed to create commit: {}", e)))?;
 
         // Push the changes
         self.push(repo)?;
 
-        Ok(())
+        Ok(true)
     }
 
     /// Push changes to the remote repository
@@ -185,19 +384,9 @@ impl RepositoryManager {
         let mut remote = repo.find_remote("origin")
             .map_err(|e| AppError::GitError(format!("Failed to find remote: {}", e)))?;
 
-        // Set up callbacks
-        let mut callbacks = RemoteCallbacks::new();
-
-        // Add authentication if GitHub token is provided
-        if let Some(token) = &self.config.github_token {
-            callbacks.credentials(move |_url, _username, _allowed| {
-                git2::Cred::userpass_plaintext("x-access-token", token)
-            });
-        }
-
         // Create push options
         let mut push_options = PushOptions::new();
-        push_options.remote_callbacks(callbacks);
+        push_options.remote_callbacks(self.remote_callbacks());
 
         // Get the current branch
         let head = repo.head()
@@ -213,6 +402,19 @@ impl RepositoryManager {
         Ok(())
     }
 
+    /// Callbacks for talking to the remote, authenticating with the GitHub token if provided
+    fn remote_callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut callbacks = RemoteCallbacks::new();
+
+        if let Some(token) = &self.config.github_token {
+            callbacks.credentials(move |_url, _username, _allowed| {
+                git2::Cred::userpass_plaintext("x-access-token", token)
+            });
+        }
+
+        callbacks
+    }
+
     /// Create a signature for commits
     fn create_signature(&self) -> Result<Signature> {
         let name = self.config.github_username.clone()
@@ -224,10 +426,81 @@ impl RepositoryManager {
             .map_err(|e| AppError::GitError(format!("Failed to create signature: {}", e)).into())
     }
 
+    /// Pack the loose objects reachable from any reference and prune unreachable loose
+    /// objects older than [`LOOSE_OBJECT_GRACE`], returning how many bytes the object store
+    /// shrank by
+    ///
+    /// Objects that are already packed are left alone, so running it again is cheap.
+    pub fn gc(&self, repo: &Repository) -> Result<u64> {
+        let objects_dir = repo.path().join("objects");
+        let size_before = dir_size(&objects_dir)?;
+        let loose = loose_objects(&objects_dir)?;
+
+        // Every commit reachable from a reference, with its tree and everything under it
+        let mut reachable = HashSet::new();
+        let mut walk = repo.revwalk()
+            .map_err(|e| AppError::GitError(format!("Failed to walk history: {}", e)))?;
+        walk.push_glob("*")
+            .map_err(|e| AppError::GitError(format!("Failed to walk references: {}", e)))?;
+        for commit in walk {
+            let commit = commit
+                .and_then(|oid| repo.find_commit(oid))
+                .map_err(|e| AppError::GitError(format!("Failed to read commit: {}", e)))?;
+            let tree = commit.tree()
+                .map_err(|e| AppError::GitError(format!("Failed to read tree: {}", e)))?;
+            reachable.insert(commit.id());
+            reachable.insert(tree.id());
+            tree.walk(TreeWalkMode::PreOrder, |_, entry| {
+                reachable.insert(entry.id());
+                TreeWalkResult::Ok
+            })
+            .map_err(|e| AppError::GitError(format!("Failed to walk tree: {}", e)))?;
+        }
+
+        let to_pack: Vec<Oid> = loose.keys().filter(|oid| reachable.contains(oid)).copied().collect();
+        if !to_pack.is_empty() {
+            let mut builder = repo.packbuilder()
+                .map_err(|e| AppError::GitError(format!("Failed to create pack builder: {}", e)))?;
+            for oid in &to_pack {
+                builder.insert_object(*oid, None)
+                    .map_err(|e| AppError::GitError(format!("Failed to add object to pack: {}", e)))?;
+            }
+            let mut pack = Buf::new();
+            builder.write_buf(&mut pack)
+                .map_err(|e| AppError::GitError(format!("Failed to build pack: {}", e)))?;
+
+            let odb = repo.odb()
+                .map_err(|e| AppError::GitError(format!("Failed to open object database: {}", e)))?;
+            let mut writer = odb.packwriter()
+                .map_err(|e| AppError::GitError(format!("Failed to write pack: {}", e)))?;
+            writer.write_all(&pack).map_err(AppError::IoError)?;
+            writer.commit()
+                .map_err(|e| AppError::GitError(format!("Failed to index pack: {}", e)))?;
+        }
+
+        // Packed objects are now duplicates; unreachable ones are dropped once they are old
+        // enough that no operation in flight could still be about to reference them
+        let now = SystemTime::now();
+        for (oid, path) in &loose {
+            let expired = || {
+                fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() > LOOSE_OBJECT_GRACE)
+            };
+            if reachable.contains(oid) || expired() {
+                fs::remove_file(path).map_err(AppError::IoError)?;
+            }
+        }
+
+        let size_after = dir_size(&objects_dir)?;
+        Ok(size_before.saturating_sub(size_after))
+    }
+
     /// Create a bootstrap file in the repository
     pub fn create_bootstrap_file(&self, repo: &Repository, peers: &[String], filename: &str) -> Result<()> {
         let content = peers.join("\n");
-        self.save_document(repo, &content, filename, "Update bootstrap peers")
+        self.save_document(repo, &content, filename, "Update bootstrap peers")?;
+        Ok(())
     }
 
     /// Read the bootstrap file from the repository
@@ -254,3 +527,34 @@ impl RepositoryManager {
         Ok(peers)
     }
 }
+
+/// Loose objects in an object directory, by ID
+fn loose_objects(objects_dir: &Path) -> Result<HashMap<Oid, PathBuf>> {
+    let mut objects = HashMap::new();
+    for fanout in fs::read_dir(objects_dir).map_err(AppError::IoError)? {
+        let fanout = fanout.map_err(AppError::IoError)?;
+        let prefix = fanout.file_name().to_string_lossy().into_owned();
+        if prefix.len() != 2 || !fanout.path().is_dir() {
+            continue;
+        }
+        for object in fs::read_dir(fanout.path()).map_err(AppError::IoError)? {
+            let object = object.map_err(AppError::IoError)?;
+            let name = format!("{}{}", prefix, object.file_name().to_string_lossy());
+            if let Ok(oid) = Oid::from_str(&name) {
+                objects.insert(oid, object.path());
+            }
+        }
+    }
+    Ok(objects)
+}
+
+/// Total size of the files under a directory
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir).map_err(AppError::IoError)? {
+        let entry = entry.map_err(AppError::IoError)?;
+        let metadata = entry.metadata().map_err(AppError::IoError)?;
+        size += if metadata.is_dir() { dir_size(&entry.path())? } else { metadata.len() };
+    }
+    Ok(size)
+}
@@ -6,10 +6,78 @@ use tokio::sync::RwLock;
 use tokio::time;
 use uuid::Uuid;
 
+use super::manager::GitManager;
 use super::repository::RepositoryManager;
-use crate::crdt::engine::CrdtEngine;
+use crate::crdt::activity::ActivityKind;
+use crate::crdt::document::DocumentEncoding;
+use crate::crdt::engine::{CrdtEngine, SYSTEM_AGENT};
+use crate::crdt::freeze::FreezeGuard;
 use crate::utils::errors::AppError;
 
+/// Agent changes pulled from the remote are attributed to
+pub const GIT_SOURCE: &str = "git:origin";
+
+/// Domain of the addresses collaborators are credited under in commit trailers, as users have
+/// no email address of their own
+pub const CO_AUTHOR_EMAIL_DOMAIN: &str = "users.noreply.texswarm";
+
+/// How often `GitSync::start_sync_task` looks for documents due a sync
+pub const SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest filename, in characters and including the extension, a document is saved under
+pub const MAX_FILENAME_LENGTH: usize = 100;
+
+/// Turn a document title into a `.tex` filename that is safe to write inside a repository
+///
+/// Anything but letters, digits, `-`, `_` and `.` is replaced, runs of whitespace and
+/// replaced characters become a single `_`, and leading dots are dropped, so the result is
+/// always a single, visible path component however hostile the title.
+pub fn sanitize_filename(title: &str) -> String {
+    let title = title.trim();
+    let title = match title.len().checked_sub(4) {
+        Some(stem) if title.is_char_boundary(stem) && title[stem..].eq_ignore_ascii_case(".tex") => &title[..stem],
+        _ => title,
+    };
+
+    let mut stem = String::with_capacity(title.len());
+    for c in title.chars() {
+        let c = if c.is_alphanumeric() || matches!(c, '-' | '.') { c } else { '_' };
+        if c == '_' && stem.ends_with('_') {
+            continue;
+        }
+        stem.push(c);
+    }
+
+    let stem: String = stem
+        .trim_start_matches(['.', '_'])
+        .chars()
+        .take(MAX_FILENAME_LENGTH - ".tex".len())
+        .collect();
+    let stem = stem.trim_end_matches(['.', '_']);
+
+    if stem.is_empty() {
+        "document.tex".to_string()
+    } else {
+        format!("{}.tex", stem)
+    }
+}
+
+/// Append a `Co-authored-by:` trailer crediting each of `contributors` to a commit message
+pub fn with_co_author_trailers(message: &str, contributors: &[String]) -> String {
+    if contributors.is_empty() {
+        return message.to_string();
+    }
+
+    let trailers: Vec<String> = contributors
+        .iter()
+        .map(|user| match user.contains('@') {
+            true => format!("Co-authored-by: {} <{}>", user, user),
+            false => format!("Co-authored-by: {} <{}@{}>", user, user, CO_AUTHOR_EMAIL_DOMAIN),
+        })
+        .collect();
+    format!("{}\n\n{}", message.trim_end(), trailers.join("\n"))
+}
+
 /// Manages synchronization between the CRDT and Git repository
 #[derive(Clone)]
 pub struct GitSync {
@@ -21,6 +89,8 @@ pub struct GitSync {
     sync_interval: Duration,
     /// Last synchronization time for each document
     last_sync: Arc<RwLock<std::collections::HashMap<Uuid, Instant>>>,
+    /// Version of each document as of its last commit
+    committed_versions: Arc<RwLock<std::collections::HashMap<Uuid, usize>>>,
 }
 
 impl GitSync {
@@ -35,69 +105,90 @@ impl GitSync {
             crdt_engine,
             sync_interval,
             last_sync: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            committed_versions: Arc::new(RwLock::new(std::collections::HashMap::new())),
         }
     }
 
     /// Start the periodic synchronization task
-    pub async fn start_sync_task(self) {
-        let mut interval = time::interval(Duration::from_secs(30)); // Check every 30 seconds
+    ///
+    /// Every document whose last sync is older than the sync interval is synced through
+    /// `manager`, so the sync waits its turn behind other Git operations on the document like
+    /// any other. Documents not linked to a repository are skipped.
+    pub async fn start_sync_task(self, manager: Arc<RwLock<GitManager>>) {
+        let mut interval = time::interval(SYNC_CHECK_INTERVAL);
 
         loop {
             interval.tick().await;
-            if let Err(e) = self.check_for_sync().await {
-                eprintln!("Error during git sync: {:?}", e);
+            let due = match self.due_documents().await {
+                Ok(due) => due,
+                Err(e) => {
+                    tracing::warn!("Failed to find documents due a Git sync: {}", e);
+                    continue;
+                }
+            };
+
+            for doc_id in due {
+                if let Err(e) = GitManager::sync_now(Arc::clone(&manager), doc_id).await {
+                    tracing::warn!("Error syncing document {}: {}", doc_id, e);
+                }
             }
         }
     }
 
-    /// Check if any documents need to be synchronized
-    async fn check_for_sync(&self) -> Result<()> {
-        let engine = self.crdt_engine.read().await;
-        let documents = engine.get_all_documents().await?;
-        let last_sync_read = self.last_sync.read().await;
+    /// The documents not synced within the sync interval
+    async fn due_documents(&self) -> Result<Vec<Uuid>> {
+        let documents = self.crdt_engine.read().await.get_all_documents().await?;
 
-        for doc_id in documents {
-            // Check if document needs synchronization
-            let needs_sync = match last_sync_read.get(&doc_id) {
+        let last_sync_read = self.last_sync.read().await;
+        Ok(documents
+            .into_iter()
+            .filter(|doc_id| match last_sync_read.get(doc_id) {
                 Some(last) => last.elapsed() >= self.sync_interval,
                 None => true,
-            };
-
-            if needs_sync {
-                if let Err(e) = self.sync_document(&doc_id).await {
-                    eprintln!("Error syncing document {}: {:?}", doc_id, e);
-                }
-            }
-        }
-
-        Ok(())
+            })
+            .collect())
     }
 
     /// Synchronize a specific document
-    pub async fn sync_document(&self, document_id: &Uuid) -> Result<()> {
+    ///
+    /// Changes made directly in the remote repository are merged into the document first,
+    /// then the document is committed and pushed. Returns whether there was anything to commit.
+    pub async fn sync_document(&self, document_id: &Uuid) -> Result<bool> {
+        let started = Instant::now();
+        let result = self.pull_commit_and_push(document_id).await;
+        let bytes = result.as_ref().map(|(bytes, _)| *bytes).unwrap_or(0);
+        self.crdt_engine
+            .read()
+            .await
+            .operation_timings()
+            .record("git_sync", document_id, bytes, started.elapsed());
+        result.map(|(_, committed)| committed)
+    }
+
+    /// Sync a document with its repository, returning the size of the content committed and
+    /// whether it had changed
+    async fn pull_commit_and_push(&self, document_id: &Uuid) -> Result<(usize, bool)> {
         // Get the document data
-        let engine = self.crdt_engine.read().await;
-        let document = engine.get_document(document_id).await?;
-        let doc = document.read().await;
+        let (repo_url, title) = {
+            let engine = self.crdt_engine.read().await;
+            let document = engine.get_document(document_id).await?;
+            let doc = document.read().await;
 
-        // Check if the document has a repository URL
-        let repo_url = doc.repository_url.clone().ok_or_else(||
-            AppError::GitError(format!("Document {} has no repository URL", document_id)))?;
+            // Check if the document has a repository URL
+            let repo_url = doc.repository_url.clone().ok_or_else(||
+                AppError::GitError(format!("Document {} has no repository URL", document_id)))?;
+            (repo_url, doc.title.clone())
+        };
 
         // Open or clone the repository
         let repo = self.repo_manager.clone_or_open(&repo_url, document_id)?;
 
-        // Get the document content
-        let content = engine.get_document_content(document_id).await?;
+        // Bring in what was committed to the remote since the last sync
+        let filename = sanitize_filename(&title);
+        self.pull_into_crdt(&repo, document_id, &filename).await?;
 
         // Save the document to the repository
-        let filename = format!("{}.tex", doc.title.replace(" ", "_"));
-        self.repo_manager.save_document(
-            &repo,
-            &content,
-            &filename,
-            &format!("Update document {}", doc.title),
-        )?;
+        let (bytes, committed) = self.commit_document(&repo, document_id, &filename, &format!("Update document {}", title)).await?;
 
         // Update the bootstrap file
         self.update_bootstrap_file(&repo, document_id).await?;
@@ -108,19 +199,52 @@ impl GitSync {
             last_sync.insert(*document_id, Instant::now());
         }
 
-        Ok(())
+        let commit = self.repo_manager.head_commit(&repo)?.map(|oid| oid.to_string());
+        self.crdt_engine
+            .read()
+            .await
+            .record_activity(document_id, ActivityKind::SyncedToGit { commit });
+
+        Ok((bytes, committed))
     }
 
-    /// Synchronize a document's content to the repository
-    pub async fn sync_document_to_repo(&self, repo: &Repository, content: String, message: &str) -> Result<()> {
-        // Get the main document filename
-        let filename = "document.tex"; // Using a standard filename
+    /// Commit a document's current content to the repository, returning its size and whether
+    /// it had changed since the last commit
+    ///
+    /// The commit message credits, with `Co-authored-by:` trailers, every user whose operations
+    /// were applied since the document was last committed, in the order they first edited it.
+    pub async fn commit_document(&self, repo: &Repository, document_id: &Uuid, filename: &str, message: &str) -> Result<(usize, bool)> {
+        let since = self.committed_versions.read().await.get(document_id).copied().unwrap_or(0);
+        let (content, version, contributors) = {
+            let engine = self.crdt_engine.read().await;
+            let (content, version) = engine.get_versioned_snapshot(document_id).await?;
+            let mut contributors: Vec<String> = Vec::new();
+            for patch in engine.get_patches_between(document_id, since, version).await? {
+                if patch.author != GIT_SOURCE && patch.author != SYSTEM_AGENT && !contributors.contains(&patch.author) {
+                    contributors.push(patch.author);
+                }
+            }
+            (content, version, contributors)
+        };
+
+        let committed = self.repo_manager.save_document(
+            repo,
+            &content,
+            filename,
+            &with_co_author_trailers(message, &contributors),
+        )?;
+        self.committed_versions.write().await.insert(*document_id, version);
+
+        Ok((content.len(), committed))
+    }
 
+    /// Synchronize a document's content to the repository, under the filename its title gives
+    pub async fn sync_document_to_repo(&self, repo: &Repository, title: &str, content: String, message: &str) -> Result<()> {
         // Save the document to the repository
         self.repo_manager.save_document(
             repo,
             &content,
-            filename,
+            &sanitize_filename(title),
             message,
         )?;
 
@@ -128,15 +252,12 @@ impl GitSync {
     }
 
     /// Synchronize a document's content to the repository - blocking version
-    pub fn sync_document_to_repo_blocking(&self, repo: &Repository, content: String, message: &str) -> Result<()> {
-        // Get the main document filename
-        let filename = "document.tex"; // Using a standard filename
-
+    pub fn sync_document_to_repo_blocking(&self, repo: &Repository, title: &str, content: String, message: &str) -> Result<()> {
         // Save the document to the repository
         self.repo_manager.save_document(
             repo,
             &content,
-            filename,
+            &sanitize_filename(title),
             message,
         )?;
 
@@ -162,6 +283,55 @@ impl GitSync {
         Ok(())
     }
 
+    /// Merge what was committed to the remote repository since the last sync into the CRDT
+    ///
+    /// Edits made to the document since the commit both sides share are kept: the remote's
+    /// changes are rebased over them, and the result is committed on top of both sides so the
+    /// next push fast-forwards. The merged changes are broadcast like any other operation.
+    /// Returns whether the remote had new commits.
+    pub async fn pull_into_crdt(&self, repo: &Repository, document_id: &Uuid, filename: &str) -> Result<bool> {
+        let Some(remote) = self.repo_manager.fetch(repo)? else {
+            return Ok(false);
+        };
+
+        let head = self.repo_manager.head_commit(repo)?;
+        let base = match head {
+            Some(head) if head == remote => return Ok(false),
+            Some(head) => {
+                let up_to_date = repo.graph_descendant_of(head, remote)
+                    .map_err(|e| AppError::GitError(format!("Failed to compare commits: {}", e)))?;
+                if up_to_date {
+                    return Ok(false);
+                }
+
+                match repo.merge_base(head, remote) {
+                    Ok(base) => self.repo_manager.read_file_at(repo, base, filename)?,
+                    Err(e) if e.code() == git2::ErrorCode::NotFound => String::new(),
+                    Err(e) => return Err(AppError::GitError(format!("Failed to find merge base: {}", e)).into()),
+                }
+            }
+            None => String::new(),
+        };
+        let external = self.repo_manager.read_file_at(repo, remote, filename)?;
+
+        // No edit may land between merging into the document and committing the merged content,
+        // or the merge commit would miss it. Peers' changes are queued until the freeze lifts.
+        let frozen = FreezeGuard::new(Arc::clone(&self.crdt_engine), *document_id).await?;
+        let merged = async {
+            let content = {
+                let engine = self.crdt_engine.read().await;
+                engine.merge_external_content(document_id, GIT_SOURCE, &base, &external).await?;
+                engine.get_document_content(document_id).await?
+            };
+            self.repo_manager.merge_remote(repo, remote, filename, &content, "Merge remote changes")
+        }
+        .await;
+        frozen.release().await;
+        merged?;
+
+        Ok(true)
+    }
+
     /// Update the bootstrap file with current peers
     async fn update_bootstrap_file(&self, repo: &Repository, document_id: &Uuid) -> Result<()> {
         // Get the list of connected peers
@@ -200,10 +370,11 @@ impl GitSync {
         let file_path = repo_path.join(filename);
 
         // Read the file content
-        let content = std::fs::read_to_string(file_path)
+        let content = std::fs::read(file_path)
             .map_err(|e| AppError::IoError(e))?;
 
-        Ok(content)
+        // Files are not guaranteed to be valid UTF-8, so decode them lossily
+        Ok(DocumentEncoding::Utf8.decode(&content))
     }
 
     /// Read bootstrap peers from a repository
@@ -231,15 +402,14 @@ impl GitSync {
         Ok(result)
     }
 
-    /// Get document content from the repository
-    pub async fn get_document_from_repo(&self, repo: &Repository) -> Result<String> {
+    /// Get a document's content from the repository, from the file its title gives
+    pub async fn get_document_from_repo(&self, repo: &Repository, title: &str) -> Result<String> {
         // Get the repository path
         let repo_path = repo.path().parent().ok_or_else(||
             AppError::GitError("Could not get repository path".to_string()))?;
 
-        // Try to find the main document file
-        let filename = "document.tex"; // Using the standard filename
-        let file_path = repo_path.join(filename);
+        let filename = sanitize_filename(title);
+        let file_path = repo_path.join(&filename);
 
         // Check if the file exists
         if !file_path.exists() {
@@ -247,9 +417,10 @@ impl GitSync {
         }
 
         // Read the file content
-        let content = std::fs::read_to_string(file_path)
+        let content = std::fs::read(file_path)
             .map_err(|e| AppError::IoError(e))?;
 
-        Ok(content)
+        // Files are not guaranteed to be valid UTF-8, so decode them lossily
+        Ok(DocumentEncoding::Utf8.decode(&content))
     }
 }
@@ -1,4 +1,5 @@
 pub mod api;
+pub mod client;
 pub mod crdt;
 pub mod git;
 pub mod network;
@@ -21,8 +22,26 @@ pub struct P2PLatexCollab {
 
 impl P2PLatexCollab {
     pub async fn new(config: &utils::config::Config) -> anyhow::Result<Self> {
-        let crdt_engine = Arc::new(RwLock::new(crdt::engine::CrdtEngine::new()?));
-        let network_engine = Arc::new(RwLock::new(network::engine::NetworkEngine::new(&config.network, Arc::clone(&crdt_engine)).await?));
+        // Fail early, and say which path is the problem, rather than partway through startup
+        config.check_storage_paths()?;
+
+        let write_ahead_log = storage::wal::WriteAheadLog::from_config(&config.storage);
+        let crdt_engine = Arc::new(RwLock::new(
+            crdt::engine::CrdtEngine::new()?
+                .with_max_documents(config.storage.max_documents)
+                .with_max_operation_bytes(config.network.max_operation_bytes)
+                .with_trash_retention(std::time::Duration::from_secs(config.storage.trash_retention_secs))
+                .with_write_ahead_log(write_ahead_log.clone())
+                .with_oplog_store(Some(storage::at_rest::OplogStore::from_config(&config.storage)))
+                .with_slow_op_threshold(config.debug.slow_op_threshold())
+                .with_lock_wait_timeout(config.debug.lock_wait_timeout()),
+        ));
+        let network_engine = Arc::new(RwLock::new(
+            network::engine::NetworkEngine::new(&config.network, Arc::clone(&crdt_engine))
+                .await?
+                .with_max_transfer_bytes(usize::try_from(config.storage.max_document_size_mb.saturating_mul(1024 * 1024)).unwrap_or(usize::MAX))
+                .with_rendezvous(network::rendezvous::GitRendezvous::from_config(&config.network, &config.git)),
+        ));
         let git_manager = Arc::new(RwLock::new(git::manager::GitManager::new(config, Arc::clone(&crdt_engine))?));
 
         // Initialize the document persistence service with auto-save every 5 minutes
@@ -30,7 +49,10 @@ impl P2PLatexCollab {
             Arc::clone(&crdt_engine),
             Arc::clone(&git_manager),
             300, // 5 minutes in seconds
-        ));
+        )
+        .with_oplog_store(storage::at_rest::OplogStore::from_config(&config.storage))
+        .with_auto_create_missing_documents(config.storage.auto_create_missing_documents)
+        .with_write_ahead_log(write_ahead_log));
 
         // Create API server with persistence service
         let mut api_server = api::server::ApiServer::new(
@@ -42,6 +64,12 @@ impl P2PLatexCollab {
 
         // Add the persistence service to the API server
         api_server.set_persistence_service(Arc::clone(&document_persistence));
+
+        // Audit what users do, when configured to
+        let events = crdt_engine.read().await.subscribe_events();
+        if let Some(audit_log) = storage::audit_log::AuditLog::from_config(&config.storage, events).await? {
+            api_server.set_audit_log(audit_log);
+        }
         let api_server = Arc::new(api_server);
 
         Ok(Self {
@@ -54,12 +82,31 @@ impl P2PLatexCollab {
     }
 
     pub async fn start(&self) -> anyhow::Result<()> {
+        // Bring back the documents saved before the last shutdown, and what was logged since
+        let loaded = self.document_persistence.load_saved_documents().await?;
+        tracing::info!("Loaded {} saved documents", loaded);
+
+        // Fix documents left half-created or half-deleted before anything reads them
+        for issue in self.crdt_engine.read().await.repair_integrity().await? {
+            tracing::warn!("Repaired document state: {}", issue);
+        }
+
         // Start the network engine
         {
             let mut network = self.network_engine.write().await;
             network.start().await?;
         }
 
+        // Let peers check their copies of our documents against ours
+        if let Some(interval) = self.network_engine.read().await.content_hash_interval() {
+            let network_engine = Arc::clone(&self.network_engine);
+            tokio::spawn(network::engine::NetworkEngine::run_content_hash_heartbeat(network_engine, interval));
+        }
+
+        // Catch up on documents whose queued remote operations had to be dropped
+        let events = self.crdt_engine.read().await.subscribe_events();
+        tokio::spawn(network::engine::NetworkEngine::run_resync_requests(Arc::clone(&self.network_engine), events));
+
         // Start the API server
         self.api_server.start().await?;
 
@@ -69,6 +116,40 @@ impl P2PLatexCollab {
             persistence_service.clone().start().await;
         });
 
+        // Sync documents to Git that haven't been for a whole sync interval. This runs next to
+        // the quiet-period sync below as a backstop, for edits it never heard of, e.g. events
+        // missed while lagging behind, or changes pushed to the remote
+        if self.git_manager.read().await.sync_interval().is_some() {
+            let synchronizer = self.git_manager.read().await.synchronizer();
+            tokio::spawn(synchronizer.start_sync_task(Arc::clone(&self.git_manager)));
+        }
+
+        // Commit documents to Git once their editors pause
+        if let Some(quiet_period) = self.git_manager.read().await.sync_quiet_period() {
+            let git_manager = Arc::clone(&self.git_manager);
+            let events = self.crdt_engine.read().await.subscribe_events();
+            tokio::spawn(git::manager::GitManager::run_quiet_sync(git_manager, events, quiet_period));
+        }
+
+        // Keep an eye on whether documents' remotes can still be reached
+        if let Some(interval) = self.git_manager.read().await.remote_check_interval() {
+            let git_manager = Arc::clone(&self.git_manager);
+            tokio::spawn(git::manager::GitManager::run_remote_checks(git_manager, interval));
+        }
+
+        // Remove documents whose time in the trash is up
+        let crdt_engine = Arc::clone(&self.crdt_engine);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(crdt::engine::TRASH_REAP_INTERVAL).await;
+                match crdt_engine.read().await.reap_trash(chrono::Utc::now()).await {
+                    Ok(0) => {}
+                    Ok(reaped) => tracing::info!("Removed {} documents from the trash", reaped),
+                    Err(e) => tracing::warn!("Failed to empty the trash: {}", e),
+                }
+            }
+        });
+
         Ok(())
     }
 
@@ -76,6 +157,10 @@ impl P2PLatexCollab {
         // Stop the API server
         self.api_server.stop().await?;
 
+        // Flush documents to disk while nothing can change them any more
+        let saved = self.document_persistence.save_all_documents().await?;
+        tracing::info!("Saved {} documents before shutting down", saved);
+
         // Stop the network engine
         {
             let mut network = self.network_engine.write().await;
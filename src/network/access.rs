@@ -0,0 +1,40 @@
+use libp2p::PeerId;
+use std::collections::HashSet;
+
+use crate::utils::config::NetworkConfig;
+
+/// Which peers may stay connected to this node
+#[derive(Debug, Clone, Default)]
+pub struct PeerAccess {
+    /// Only these peers are admitted, if set
+    allowed: Option<HashSet<PeerId>>,
+    /// Peers that are never admitted, even if allowed
+    denied: HashSet<PeerId>,
+}
+
+impl PeerAccess {
+    /// Build the access lists from the configured peer IDs, skipping any that don't parse
+    pub fn from_config(config: &NetworkConfig) -> Self {
+        let parse = |ids: &[String]| -> HashSet<PeerId> {
+            ids.iter()
+                .filter_map(|id| match id.parse() {
+                    Ok(peer_id) => Some(peer_id),
+                    Err(e) => {
+                        tracing::warn!("Ignoring invalid peer ID {:?} in access list: {}", id, e);
+                        None
+                    }
+                })
+                .collect()
+        };
+
+        Self {
+            allowed: config.allowed_peers.as_deref().map(parse),
+            denied: parse(&config.denied_peers),
+        }
+    }
+
+    /// Whether a peer may stay connected
+    pub fn permits(&self, peer_id: &PeerId) -> bool {
+        !self.denied.contains(peer_id) && self.allowed.as_ref().is_none_or(|allowed| allowed.contains(peer_id))
+    }
+}
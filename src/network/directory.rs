@@ -0,0 +1,144 @@
+use anyhow::Result;
+use dashmap::DashMap;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::crdt::document::{Document, DocumentVisibility};
+use crate::network::protocol::NetworkMessage;
+use crate::utils::errors::AppError;
+
+/// Well-known topic public documents are announced on
+pub const ANNOUNCE_TOPIC: &str = "doc-announce";
+
+/// How often public documents are announced again, for peers that joined the network since
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A document another peer has announced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveredDocument {
+    pub document_id: Uuid,
+    pub title: String,
+    pub owner: String,
+    /// Peer the most recent announcement came from
+    pub announced_by: String,
+    pub last_announced: chrono::DateTime<chrono::Utc>,
+}
+
+/// A document peers are currently collaborating on, which this node could join
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveSession {
+    pub document_id: Uuid,
+    pub title: String,
+    pub owner: String,
+    /// Number of peers subscribed to the document
+    pub collaborators: usize,
+    /// Number of users with an active presence in the document
+    pub active_users: usize,
+}
+
+/// An announced document that another peer created independently of one of ours
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DedupMatch {
+    pub dedup_key: String,
+    /// Our copy of the document
+    pub local_document: Uuid,
+    /// The announced copy
+    pub announced_document: Uuid,
+    pub announced_by: PeerId,
+}
+
+impl DedupMatch {
+    /// The ID our copy should move to, if the announcing peer's copy wins
+    ///
+    /// Both peers settle on the copy of the peer with the lower ID; the other side merges
+    /// into ours when it sees our announcement.
+    pub fn canonical_id(&self, local_peer: &PeerId) -> Option<Uuid> {
+        (self.announced_by < *local_peer && self.announced_document != self.local_document)
+            .then_some(self.announced_document)
+    }
+}
+
+/// Registry of documents announced on the network
+#[derive(Debug, Default)]
+pub struct DocumentDirectory {
+    documents: DashMap<Uuid, DiscoveredDocument>,
+    /// Documents created here with a dedup key, not yet reconciled with other peers' copies
+    pending_creations: DashMap<String, Uuid>,
+}
+
+impl DocumentDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the announcement for a document, or `None` if the document is not public
+    pub fn announcement(doc: &Document) -> Option<Vec<u8>> {
+        if doc.visibility != DocumentVisibility::Public {
+            return None;
+        }
+
+        let message = NetworkMessage::AnnounceDocument {
+            document_id: doc.id,
+            title: doc.title.clone(),
+            owner: doc.owner.clone(),
+            dedup_key: doc.dedup_key.clone(),
+        };
+        serde_json::to_vec(&message).ok()
+    }
+
+    /// Watch for other peers announcing their own copy of a document created here
+    pub fn add_pending_creation(&self, dedup_key: String, document_id: Uuid) {
+        self.pending_creations.insert(dedup_key, document_id);
+    }
+
+    /// Stop watching for copies of a document, e.g. once it has moved to the canonical ID
+    pub fn remove_pending_creation(&self, dedup_key: &str) {
+        self.pending_creations.remove(dedup_key);
+    }
+
+    /// Record an announcement received on the announcement topic
+    ///
+    /// Returns the match if the announced document is another peer's copy of one created here.
+    pub fn record(&self, source: PeerId, data: &[u8]) -> Result<Option<DedupMatch>> {
+        let message: NetworkMessage = serde_json::from_slice(data)
+            .map_err(|e| anyhow::anyhow!(AppError::ProtocolError(format!("Malformed announcement: {}", e))))?;
+
+        let NetworkMessage::AnnounceDocument { document_id, title, owner, dedup_key } = message else {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(
+                "Unexpected message on the announcement topic".to_string()
+            )));
+        };
+
+        self.documents.insert(document_id, DiscoveredDocument {
+            document_id,
+            title,
+            owner,
+            announced_by: source.to_string(),
+            last_announced: chrono::Utc::now(),
+        });
+
+        let dedup_match = dedup_key
+            .and_then(|key| self.pending_creations.get(&key).map(|local| (key.clone(), *local)))
+            .map(|(dedup_key, local_document)| DedupMatch {
+                dedup_key,
+                local_document,
+                announced_document: document_id,
+                announced_by: source,
+            });
+        Ok(dedup_match)
+    }
+
+    /// Look up an announced document
+    pub fn get(&self, document_id: &Uuid) -> Option<DiscoveredDocument> {
+        self.documents.get(document_id).map(|entry| entry.value().clone())
+    }
+
+    /// All documents announced so far, most recently announced first
+    pub fn list(&self) -> Vec<DiscoveredDocument> {
+        let mut documents: Vec<_> = self.documents.iter().map(|entry| entry.value().clone()).collect();
+        documents.sort_by_key(|document| std::cmp::Reverse(document.last_announced));
+        documents
+    }
+}
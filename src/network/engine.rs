@@ -3,12 +3,22 @@ use dashmap::DashMap;
 // Remove the unused GossipsubTopic import
 use libp2p::{PeerId, request_response};
 use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, RwLock};
 use uuid::Uuid;
 
+use crate::crdt::chat::ChatEntry;
+use crate::crdt::document::{Document, DocumentVisibility};
 use crate::crdt::engine::CrdtEngine;
+use crate::crdt::events::DocumentEvent;
+use crate::network::directory::{ActiveSession, DiscoveredDocument, DocumentDirectory, ANNOUNCE_TOPIC};
+use crate::network::pause::PropagationPause;
 use crate::network::peer::PeerRegistry;
+use crate::network::polling::{self, PollingSync};
 use crate::network::protocol::{CollabRequest, CollabResponse, NetworkMessage};
+use crate::network::rendezvous::GitRendezvous;
+use crate::network::service::{RealNetworkService, TopicMetrics};
+use crate::network::service_wrapper::NetworkServiceWrapper;
+use crate::network::transfer::{IncomingTransfers, OutgoingTransfers, TRANSFER_CHUNK_SIZE};
 use crate::utils::config::NetworkConfig;
 use crate::utils::errors::AppError;
 
@@ -21,15 +31,28 @@ pub enum DocumentTopic {
     Presence(Uuid),
     /// Topic for document metadata updates
     Metadata(Uuid),
+    /// Topic for chat about a document
+    Chat(Uuid),
 }
 
 impl DocumentTopic {
+    /// Every topic of a document
+    pub fn all(doc_id: Uuid) -> [DocumentTopic; 4] {
+        [
+            DocumentTopic::Operations(doc_id),
+            DocumentTopic::Presence(doc_id),
+            DocumentTopic::Metadata(doc_id),
+            DocumentTopic::Chat(doc_id),
+        ]
+    }
+
     /// Convert to a topic string
     pub fn to_topic_string(&self) -> String {
         match self {
             DocumentTopic::Operations(id) => format!("doc-ops/{}", id),
             DocumentTopic::Presence(id) => format!("doc-presence/{}", id),
             DocumentTopic::Metadata(id) => format!("doc-meta/{}", id),
+            DocumentTopic::Chat(id) => format!("doc-chat/{}", id),
         }
     }
 }
@@ -62,6 +85,12 @@ pub enum NetworkEvent {
         source: PeerId,
         response: CollabResponse,
     },
+    /// A request failed or timed out before the peer responded
+    RequestFailed {
+        request_id: String,
+        peer: PeerId,
+        error: String,
+    },
 }
 
 /// Network service for P2P communication
@@ -109,6 +138,15 @@ impl NetworkService {
         Ok(())
     }
 
+    pub async fn resubscribe_topics(&mut self) {
+        // Placeholder implementation
+    }
+
+    pub async fn gossipsub_metrics(&self) -> Vec<TopicMetrics> {
+        // Placeholder implementation
+        Vec::new()
+    }
+
     pub async fn send_response(
         &mut self,
         _channel: request_response::ResponseChannel<CollabResponse>,
@@ -131,13 +169,50 @@ impl Clone for NetworkService {
 /// The NetworkEngine manages the P2P network connections and message routing
 #[derive(Debug)]
 pub struct NetworkEngine {
-    service: Option<NetworkService>,
+    service: Option<NetworkServiceWrapper>,
     peer_registry: Arc<RwLock<PeerRegistry>>,
     crdt_engine: Arc<RwLock<CrdtEngine>>,
     config: NetworkConfig,
 
-    // Map of document IDs to the set of peer IDs that are subscribed to that document
-    document_subscribers: DashMap<Uuid, Vec<String>>,
+    // Map of document IDs to the set of peer IDs that are subscribed to that document,
+    // shared with the event loop so peers joining through it are seen here
+    document_subscribers: Arc<DashMap<Uuid, Vec<String>>>,
+
+    // Number of outstanding subscribe_to_document calls for each document, shared with the
+    // event loop so it knows which documents to catch up on when peers return
+    document_subscriptions: Arc<DashMap<Uuid, usize>>,
+
+    // Documents other peers have announced
+    document_directory: Arc<DocumentDirectory>,
+
+    // Oplogs being received from and sent to peers in chunks
+    incoming_transfers: Arc<IncomingTransfers>,
+    outgoing_transfers: Arc<OutgoingTransfers>,
+
+    // Whether operations are held back instead of propagated, shared with the event loop
+    propagation: Arc<PropagationPause>,
+
+    // Sync requests waiting on a peer, by request ID, so a failed one can be retried elsewhere
+    pending_syncs: Arc<DashMap<String, PendingSync>>,
+
+    // Shared repository peers are met through, and the task keeping up with it once started
+    rendezvous: Option<GitRendezvous>,
+    rendezvous_task: Option<tokio::task::JoinHandle<()>>,
+
+    // Polling of peers for subscribed documents when the sync mode polls, and the task doing
+    // it once started
+    polling: Option<Arc<PollingSync>>,
+    polling_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+/// A request for a document's operations that a peer hasn't answered yet
+#[derive(Debug, Clone)]
+struct PendingSync {
+    document_id: Uuid,
+    /// Version this node was at when asking, or `None` for the whole OpLog
+    version: Option<Vec<u8>>,
+    /// Peers already asked, the latest last
+    tried: Vec<PeerId>,
 }
 
 impl NetworkEngine {
@@ -150,13 +225,65 @@ impl NetworkEngine {
             peer_registry: peer_registry.clone(),
             crdt_engine,
             config: config.clone(),
-            document_subscribers: dashmap::DashMap::new(),
+            document_subscribers: Arc::new(DashMap::new()),
+            document_subscriptions: Arc::new(DashMap::new()),
+            document_directory: Arc::new(DocumentDirectory::new()),
+            incoming_transfers: Arc::new(IncomingTransfers::new()),
+            outgoing_transfers: Arc::new(OutgoingTransfers::new()),
+            propagation: Arc::new(PropagationPause::new()),
+            pending_syncs: Arc::new(DashMap::new()),
+            rendezvous: None,
+            rendezvous_task: None,
+            polling: None,
+            polling_task: None,
         })
     }
 
+    /// Reject oplogs peers send in chunks once they are longer than `max_bytes`
+    pub fn with_max_transfer_bytes(mut self, max_bytes: usize) -> Self {
+        self.incoming_transfers = Arc::new(IncomingTransfers::new().with_max_total(max_bytes));
+        self
+    }
+
+    /// Meet peers through a shared Git repository once started, if one is given
+    pub fn with_rendezvous(mut self, rendezvous: Option<GitRendezvous>) -> Self {
+        self.rendezvous = rendezvous;
+        self
+    }
+
     pub async fn start(&mut self) -> Result<()> {
         // Initialize the network service with the configuration
-        let service = NetworkService::new(self.config.clone()).await?;
+        let mut service = if self.config.real_network {
+            let real = Arc::new(RealNetworkService::new(self.config.clone()).await?);
+            let events = Arc::clone(&real).start_event_loop().await?;
+            NetworkServiceWrapper::Real(real, events)
+        } else {
+            NetworkServiceWrapper::Mock(NetworkService::new(self.config.clone()).await?)
+        };
+        service.subscribe_to_topic(ANNOUNCE_TOPIC.to_string()).await?;
+
+        // Dial the peers listed in the rendezvous repository, and list this node there
+        if let Some(rendezvous) = self.rendezvous.clone() {
+            match &service {
+                NetworkServiceWrapper::Real(real, _) => self.rendezvous_task = Some(rendezvous.start(Arc::clone(real))),
+                NetworkServiceWrapper::Mock(_) => tracing::warn!("Ignoring the bootstrap repository, as the real network is disabled"),
+            }
+        }
+
+        // Poll peers for the subscribed documents; the event loop merges what they send back
+        if self.config.sync_mode.polls() {
+            match &service {
+                NetworkServiceWrapper::Real(real, _) => {
+                    let polling = PollingSync::from_config(&self.config, Arc::clone(real), Arc::clone(&self.crdt_engine)).map(Arc::new);
+                    if let Some(polling) = polling {
+                        self.document_subscriptions.iter().for_each(|entry| polling.track_document(*entry.key()));
+                        self.polling_task = Some(Arc::clone(&polling).start_polling());
+                        self.polling = Some(polling);
+                    }
+                }
+                NetworkServiceWrapper::Mock(_) => tracing::warn!("Not polling peers, as the real network is disabled"),
+            }
+        }
         self.service = Some(service);
 
         // Start the main network event loop as a background task
@@ -169,13 +296,21 @@ impl NetworkEngine {
     /// Get the local peer ID
     pub async fn get_local_peer_id(&self) -> Result<String> {
         if let Some(service) = &self.service {
-            Ok(service.local_peer_id.to_string())
+            Ok(service.local_peer_id().to_string())
         } else {
             Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())))
         }
     }
 
     pub async fn stop(&mut self) -> Result<()> {
+        if let Some(task) = self.rendezvous_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.polling_task.take() {
+            task.abort();
+        }
+        self.polling = None;
+
         // Network service will be dropped when Option is cleared
         self.service = None;
         Ok(())
@@ -187,7 +322,13 @@ impl NetworkEngine {
             let mut event_receiver = service.take_event_receiver();
             let peer_registry = Arc::clone(&self.peer_registry);
             let crdt_engine = self.crdt_engine.clone();
-            let document_subscribers = self.document_subscribers.clone();
+            let document_subscribers = Arc::clone(&self.document_subscribers);
+            let document_subscriptions = Arc::clone(&self.document_subscriptions);
+            let document_directory = Arc::clone(&self.document_directory);
+            let incoming_transfers = Arc::clone(&self.incoming_transfers);
+            let outgoing_transfers = Arc::clone(&self.outgoing_transfers);
+            let propagation = Arc::clone(&self.propagation);
+            let pending_syncs = Arc::clone(&self.pending_syncs);
             let mut service_clone = service.clone();
 
             // Spawn the event loop as a background task
@@ -195,15 +336,110 @@ impl NetworkEngine {
                 while let Some(event) = event_receiver.recv().await {
                     match event {
                         // Handle received messages
-                        NetworkEvent::MessageReceived { source: _, topic, data } => {
+                        NetworkEvent::MessageReceived { source, topic, data } => {
+                            if topic == ANNOUNCE_TOPIC {
+                                match document_directory.record(source, &data) {
+                                    Ok(Some(dedup_match)) => {
+                                        // Another peer created the same document; settle on one copy
+                                        let Some(canonical) = dedup_match.canonical_id(&service_clone.local_peer_id()) else {
+                                            continue;
+                                        };
+                                        let engine = crdt_engine.read().await;
+                                        match engine.adopt_document_id(&dedup_match.local_document, canonical).await {
+                                            Ok(()) => {
+                                                document_directory.remove_pending_creation(&dedup_match.dedup_key);
+                                                tracing::info!(
+                                                    "Merged document {} into {} created by peer {}",
+                                                    dedup_match.local_document, canonical, source
+                                                );
+                                                drop(engine);
+                                                move_subscription(
+                                                    &mut service_clone,
+                                                    &document_subscriptions,
+                                                    &document_subscribers,
+                                                    dedup_match.local_document,
+                                                    canonical,
+                                                ).await;
+
+                                                // Fetch the history of the copy we merged into
+                                                let version = crdt_engine.read().await.encoded_version(&canonical).await.ok();
+                                                let sync = PendingSync { document_id: canonical, version, tried: vec![source] };
+                                                if let Err(e) = send_sync_request(&mut service_clone, &pending_syncs, source, sync).await {
+                                                    tracing::warn!("Failed to ask peer {} for document {}: {}", source, canonical, e);
+                                                }
+                                            }
+                                            Err(e) => tracing::warn!("Failed to merge duplicate document: {}", e),
+                                        }
+                                    }
+                                    Ok(None) => {}
+                                    Err(e) => {
+                                        let strikes = peer_registry.write().await.penalize(&source);
+                                        tracing::warn!(
+                                            "Rejected announcement from peer {} ({} invalid so far): {}",
+                                            source, strikes, e
+                                        );
+                                    }
+                                }
+                                continue;
+                            }
+
                             let topic_str = topic.clone();
+                            if let Some(doc_id) = topic_str.strip_prefix("doc-meta/").and_then(|id| Uuid::parse_str(id).ok()) {
+                                let engine = crdt_engine.read().await;
+                                match NetworkEngine::apply_metadata_update(&engine, &doc_id, &data).await {
+                                    Ok(()) => {}
+                                    Err(e) if matches!(e.downcast_ref::<AppError>(), Some(AppError::ProtocolError(_))) => {
+                                        let strikes = peer_registry.write().await.penalize(&source);
+                                        tracing::warn!(
+                                            "Rejected metadata update from peer {} ({} invalid so far): {}",
+                                            source, strikes, e
+                                        );
+                                    }
+                                    Err(e) => tracing::warn!("Failed to apply metadata update: {}", e),
+                                }
+                                continue;
+                            }
+
+                            if let Some(doc_id) = topic_str.strip_prefix("doc-chat/").and_then(|id| Uuid::parse_str(id).ok()) {
+                                let engine = crdt_engine.read().await;
+                                if let Err(e) = NetworkEngine::apply_chat_message(&engine, &doc_id, &data).await {
+                                    let strikes = peer_registry.write().await.penalize(&source);
+                                    tracing::warn!(
+                                        "Rejected chat message from peer {} ({} invalid so far): {}",
+                                        source, strikes, e
+                                    );
+                                }
+                                continue;
+                            }
+
+                            if let Some(doc_id) = topic_str.strip_prefix("doc-presence/").and_then(|id| Uuid::parse_str(id).ok()) {
+                                let Ok(NetworkMessage::ContentHash { document_id, version, content_hash }) =
+                                    serde_json::from_slice::<NetworkMessage>(&data)
+                                else {
+                                    continue;
+                                };
+                                if document_id != doc_id {
+                                    continue;
+                                }
+
+                                let compared = crdt_engine.read().await.compare_content_hash(&doc_id, &version, &content_hash).await;
+                                match compared {
+                                    Ok(comparison) if polling::needs_full_resync(&doc_id, &source, comparison, false) => {
+                                        resync_from(&mut service_clone, &pending_syncs, source, doc_id).await;
+                                    },
+                                    Ok(_) => {},
+                                    Err(e) => tracing::debug!("Could not compare document {} with peer {}: {}", doc_id, source, e),
+                                }
+                                continue;
+                            }
+
                             // Parse the topic string to identify document and event type
                             if let Some(topic_parts) = topic_str.strip_prefix("doc-ops/") {
-                                if let Ok(doc_id) = Uuid::parse_str(topic_parts) {
+                                if let Ok(doc_id) = Uuid::parse_str(topic_parts)
+                                    && !propagation.hold_inbound(source, doc_id, &data).await
+                                {
                                     let engine = crdt_engine.read().await;
-                                    if let Err(e) = engine.apply_remote_operation(&doc_id, &data).await {
-                                        tracing::warn!("Failed to apply remote operation: {}", e);
-                                    }
+                                    apply_operation_from(&engine, &peer_registry, source, &doc_id, &data).await;
                                 }
                             }
                         },
@@ -218,10 +454,7 @@ impl NetworkEngine {
                                     };
 
                                     // Add to document subscribers
-                                    let mut subs = document_subscribers.entry(document_id).or_insert_with(Vec::new);
-                                    if !subs.contains(&source.to_string()) {
-                                        subs.push(source.to_string());
-                                    }
+                                    add_subscriber(&document_subscribers, document_id, source.to_string());
 
                                     // Send response
                                     let response = NetworkMessage::JoinResponse {
@@ -235,28 +468,159 @@ impl NetworkEngine {
                                         tracing::warn!("Failed to send join response: {}", e);
                                     }
                                 },
-                                NetworkMessage::SyncRequest { document_id, user_id: _, version: _ } => {
-                                    // Handle document sync request
-                                    let _engine = crdt_engine.read().await;
-                                    // This would need to retrieve operations since the version vector
-                                    let response = NetworkMessage::SyncResponse {
-                                        document_id,
-                                        operations: Vec::new(), // Would need implementation to get ops
-                                        is_full_sync: true,
+                                NetworkMessage::SyncRequest { document_id, user_id: _, version } => {
+                                    // Send back whatever the peer lacks of the document
+                                    let response = {
+                                        let engine = crdt_engine.read().await;
+                                        polling::sync_response(&engine, document_id, version.as_deref()).await
+                                    };
+
+                                    // Operations too large for one response follow in chunks.
+                                    // Without them the peer has nothing to compare our copy
+                                    // with yet, so the version and hash are left out.
+                                    let (response, transfer) = match response {
+                                        NetworkMessage::SyncResponse { document_id, operations, is_full_sync, .. }
+                                            if operations.len() > TRANSFER_CHUNK_SIZE =>
+                                        {
+                                            let transfer_id = outgoing_transfers.start(document_id, operations);
+                                            let response = NetworkMessage::SyncResponse {
+                                                document_id,
+                                                operations: Vec::new(),
+                                                is_full_sync,
+                                                version: None,
+                                                content_hash: None,
+                                            };
+                                            (response, Some(transfer_id))
+                                        }
+                                        response => (response, None),
                                     };
 
                                     if let Err(e) = service_clone.send_response(channel, response).await {
                                         tracing::warn!("Failed to send sync response: {}", e);
                                     }
+                                    if let Some(transfer_id) = transfer {
+                                        send_next_chunk(&mut service_clone, &outgoing_transfers, source, &transfer_id).await;
+                                    }
+                                },
+                                NetworkMessage::OplogChunk { document_id, header, data } => {
+                                    incoming_transfers.expire(std::time::Instant::now());
+
+                                    let acked = match incoming_transfers.receive(header, &data) {
+                                        Ok(receipt) => {
+                                            if let Some(oplog) = receipt.complete
+                                                && !propagation.defer_sync(document_id).await
+                                            {
+                                                let engine = crdt_engine.read().await;
+                                                if let Err(e) = engine.sync_document(&document_id, &oplog).await {
+                                                    tracing::warn!("Failed to merge transferred oplog for {}: {}", document_id, e);
+                                                }
+                                            }
+                                            receipt.acked
+                                        }
+                                        Err(e) => {
+                                            // Point the sender back at what we do have
+                                            let strikes = peer_registry.write().await.penalize(&source);
+                                            tracing::warn!(
+                                                "Rejected oplog chunk from peer {} ({} invalid so far): {}",
+                                                source, strikes, e
+                                            );
+                                            incoming_transfers.resume_offset(&header.transfer_id).unwrap_or(0)
+                                        }
+                                    };
+
+                                    let response = NetworkMessage::ChunkAck {
+                                        transfer_id: header.transfer_id,
+                                        offset: acked,
+                                    };
+                                    if let Err(e) = service_clone.send_response(channel, response).await {
+                                        tracing::warn!("Failed to acknowledge oplog chunk: {}", e);
+                                    }
                                 },
                                 _ => {
                                     tracing::warn!("Unhandled request type");
                                 }
                             }
                         },
+                        NetworkEvent::ResponseReceived { request_id, source, response } => {
+                            pending_syncs.remove(&request_id);
+                            match response.0 {
+                                NetworkMessage::ChunkAck { transfer_id, offset } => {
+                                    outgoing_transfers.expire(std::time::Instant::now());
+                                    let acked = outgoing_transfers.acked(&transfer_id);
+                                    match outgoing_transfers.acknowledge(&transfer_id, offset) {
+                                        Ok(true) => tracing::debug!("Transfer {} to peer {} complete", transfer_id, source),
+                                        // The peer rejected the chunk; resending it would be too, so
+                                        // the transfer is left to expire
+                                        Ok(false) if acked.is_some_and(|acked| offset <= acked) => {
+                                            tracing::warn!("Peer {} made no progress on transfer {}; giving up", source, transfer_id);
+                                        },
+                                        Ok(false) => send_next_chunk(&mut service_clone, &outgoing_transfers, source, &transfer_id).await,
+                                        Err(e) => tracing::warn!("Ignoring chunk acknowledgement from peer {}: {}", source, e),
+                                    }
+                                },
+                                NetworkMessage::SyncResponse { document_id, operations, is_full_sync, version, content_hash } => {
+                                    // Synced whole on resume instead
+                                    if propagation.defer_sync(document_id).await {
+                                        continue;
+                                    }
+                                    let peer_copy = version.as_deref().zip(content_hash.as_deref());
+                                    let applied = polling::apply_sync_response(&*crdt_engine.read().await, &document_id, &operations, peer_copy).await;
+                                    match applied {
+                                        Ok(comparison) if polling::needs_full_resync(&document_id, &source, comparison, is_full_sync) => {
+                                            resync_from(&mut service_clone, &pending_syncs, source, document_id).await;
+                                        },
+                                        Ok(_) => {},
+                                        Err(e) => tracing::warn!("Failed to merge document {} synced from peer {}: {}", document_id, source, e),
+                                    }
+                                },
+                                _ => {}
+                            }
+                        },
                         NetworkEvent::PeerConnected(peer_id) => {
-                            let mut registry = peer_registry.write().await;
-                            registry.add_peer(peer_id);
+                            peer_registry.write().await.add_peer(peer_id);
+
+                            // After churn the mesh for our documents' topics may be gone, and
+                            // operations made while nobody was connected never reached anyone
+                            let documents: Vec<Uuid> = document_subscriptions.iter().map(|entry| *entry.key()).collect();
+                            if documents.is_empty() {
+                                continue;
+                            }
+                            service_clone.resubscribe_topics().await;
+                            for document_id in documents {
+                                // Ask for what the peer has beyond our version, or all of it
+                                // if we don't hold the document yet
+                                let version = crdt_engine.read().await.encoded_version(&document_id).await.ok();
+                                let sync = PendingSync {
+                                    document_id,
+                                    version,
+                                    tried: vec![peer_id],
+                                };
+                                tracing::debug!("Requesting sync of document {} from reconnected peer {}", document_id, peer_id);
+                                if let Err(e) = send_sync_request(&mut service_clone, &pending_syncs, peer_id, sync).await {
+                                    tracing::warn!("Failed to ask peer {} for document {}: {}", peer_id, document_id, e);
+                                }
+                            }
+                        },
+                        NetworkEvent::RequestFailed { request_id, peer, error } if polling::is_poll_request(&request_id) => {
+                            // The next poll asks again
+                            tracing::debug!("Polling peer {} failed: {}", peer, error);
+                        },
+                        NetworkEvent::RequestFailed { request_id, peer, error } => {
+                            tracing::warn!("Request {} to peer {} failed: {}", request_id, peer, error);
+
+                            // Ask the next peer that hasn't been asked for the document yet
+                            let Some((_, sync)) = pending_syncs.remove(&request_id) else {
+                                continue;
+                            };
+                            let next = peer_registry.read().await.active_peers().map(|p| p.peer_id).find(|p| !sync.tried.contains(p));
+                            let Some(next) = next else {
+                                tracing::warn!("No other peer to sync document {} from", sync.document_id);
+                                continue;
+                            };
+                            tracing::info!("Retrying sync of document {} with peer {}", sync.document_id, next);
+                            if let Err(e) = send_sync_request(&mut service_clone, &pending_syncs, next, sync).await {
+                                tracing::warn!("Failed to ask peer {} for the document: {}", next, e);
+                            }
                         },
                         NetworkEvent::PeerDisconnected(peer_id) => {
                             // Remove peer from registry and all document subscribers
@@ -282,9 +646,16 @@ impl NetworkEngine {
 
     pub async fn broadcast_operation(&mut self, doc_id: &Uuid, operation: Vec<u8>) -> Result<()> {
         if let Some(service) = &mut self.service {
-            // Publish to the operations topic for this document
-            let topic_str = DocumentTopic::Operations(*doc_id).to_topic_string();
-            service.publish_to_topic(topic_str, operation.clone()).await?;
+            if self.propagation.hold_outbound(*doc_id, &operation).await {
+                return Ok(());
+            }
+
+            // Publish to the operations topic for this document, unless peers only poll for
+            // operations
+            if self.config.sync_mode.gossips() {
+                let topic_str = DocumentTopic::Operations(*doc_id).to_topic_string();
+                service.publish_to_topic(topic_str, operation.clone()).await?;
+            }
 
             // Also directly deliver the operation to all subscribed peers
             // This ensures operations propagate even if the gossipsub propagation fails
@@ -292,7 +663,7 @@ impl NetworkEngine {
                 for peer_id_str in subscribers.value() {
                     if let Ok(peer_id) = peer_id_str.parse::<PeerId>() {
                         // Skip sending to ourselves
-                        if peer_id.to_string() == service.local_peer_id.to_string() {
+                        if peer_id.to_string() == service.local_peer_id().to_string() {
                             continue;
                         }
 
@@ -315,8 +686,92 @@ impl NetworkEngine {
         Ok(())
     }
 
+    /// Hold back operations, both those this node makes and those it receives, until
+    /// `resume` is called, keeping connections and subscriptions as they are
+    pub fn pause(&self) {
+        self.propagation.pause();
+    }
+
+    /// Propagate operations again, publishing and applying those held back while paused
+    ///
+    /// Documents whose operations were dropped for want of room are pushed to, or synced
+    /// from, peers whole instead. If publishing fails, propagation stays paused with what
+    /// wasn't released still held, so resuming can be retried. Returns the number of inbound
+    /// and outbound operations released.
+    pub async fn resume(&mut self) -> Result<(usize, usize)> {
+        let propagation = Arc::clone(&self.propagation);
+        let (mut inbound, mut outbound) = propagation.resume().await;
+        let released = (inbound.operations.len(), outbound.operations.len());
+
+        for sent in 0..outbound.operations.len() {
+            let (doc_id, operation) = outbound.operations[sent].clone();
+            if let Err(e) = self.broadcast_operation(&doc_id, operation).await {
+                outbound.operations.drain(..sent);
+                propagation.hold_back(outbound).await;
+                return Err(e);
+            }
+        }
+        for doc_id in outbound.dropped {
+            if let Err(e) = self.push_document(doc_id).await {
+                tracing::warn!("Failed to push document {} to peers: {}", doc_id, e);
+            }
+        }
+
+        {
+            let engine = self.crdt_engine.read().await;
+            for held in inbound.operations.drain(..) {
+                apply_operation_from(&engine, &self.peer_registry, held.source, &held.document_id, &held.data).await;
+            }
+        }
+        let resync: Vec<Uuid> = inbound.dropped.drain().collect();
+        drop(inbound);
+        for doc_id in resync {
+            if let Err(e) = self.request_sync_from_peers(doc_id).await {
+                tracing::warn!("Failed to request sync of document {}: {}", doc_id, e);
+            }
+        }
+
+        Ok(released)
+    }
+
+    /// Send every connected peer a document's whole OpLog, in chunks, returning the number of
+    /// peers it was sent to
+    async fn push_document(&mut self, doc_id: Uuid) -> Result<usize> {
+        let Some(service) = &mut self.service else {
+            return Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())));
+        };
+
+        let (oplog, _) = self.crdt_engine.read().await.export_since(&doc_id, None).await?;
+        let peer_ids = self.peer_registry.read().await.active_peers().map(|p| p.peer_id).collect::<Vec<_>>();
+        for peer_id in &peer_ids {
+            let transfer_id = self.outgoing_transfers.start(doc_id, oplog.clone());
+            send_next_chunk(service, &self.outgoing_transfers, *peer_id, &transfer_id).await;
+        }
+
+        Ok(peer_ids.len())
+    }
+
+    /// Whether operations are being held back
+    pub fn is_paused(&self) -> bool {
+        self.propagation.is_paused()
+    }
+
+    /// Number of inbound and outbound operations held back so far
+    pub async fn held_operations(&self) -> (usize, usize) {
+        self.propagation.held().await
+    }
+
+    /// Subscribe to a document's topics
+    ///
+    /// Subscriptions are reference counted, so each call must be balanced by a call to
+    /// `unsubscribe_from_document`. Only the first subscription joins the topics.
     pub async fn subscribe_to_document(&mut self, doc_id: Uuid) -> Result<()> {
         if let Some(service) = &mut self.service {
+            if let Some(mut subscriptions) = self.document_subscriptions.get_mut(&doc_id) {
+                *subscriptions += 1;
+                return Ok(());
+            }
+
             // Subscribe to the document operations topic
             let topic_str = DocumentTopic::Operations(doc_id).to_topic_string();
             service.subscribe_to_topic(topic_str).await?;
@@ -328,15 +783,22 @@ impl NetworkEngine {
             let metadata_topic = DocumentTopic::Metadata(doc_id).to_topic_string();
             service.subscribe_to_topic(metadata_topic).await?;
 
+            let chat_topic = DocumentTopic::Chat(doc_id).to_topic_string();
+            service.subscribe_to_topic(chat_topic).await?;
+
+            self.document_subscriptions.insert(doc_id, 1);
+            if let Some(polling) = &self.polling {
+                polling.track_document(doc_id);
+            }
+
             // Add ourselves to the document subscribers
             let local_peer_id = self.get_local_peer_id().await?;
-            let mut subscribers = self.document_subscribers.entry(doc_id).or_insert_with(Vec::new);
-            if !subscribers.contains(&local_peer_id) {
-                subscribers.push(local_peer_id);
-            }
+            add_subscriber(&self.document_subscribers, doc_id, local_peer_id);
 
             // Request document content from any connected peer that has it
-            self.request_document_sync(doc_id).await?;
+            if let Err(e) = self.request_sync_from_peers(doc_id).await {
+                tracing::warn!("Failed to request sync of document {}: {}", doc_id, e);
+            }
 
             // Broadcast our join to other peers
             // This allows other peers to be aware that we're now editing this document
@@ -359,27 +821,72 @@ impl NetworkEngine {
         Ok(())
     }
 
-    /// Request document synchronization from peers
-    async fn request_document_sync(&self, doc_id: Uuid) -> Result<()> {
-        if let Some(_service) = &self.service {
-            // Get all connected peers from the peer registry
-            let registry = self.peer_registry.read().await;
-            let peer_ids = registry.active_peers().map(|p| p.peer_id).collect::<Vec<_>>();
+    /// Ask every connected peer for the operations it has of a document beyond the version
+    /// this node is at, or for all of them if it doesn't hold the document yet, returning the
+    /// number of requests sent
+    ///
+    /// The responses are merged by the event loop as they arrive.
+    pub async fn request_sync_from_peers(&mut self, doc_id: Uuid) -> Result<usize> {
+        let Some(service) = &mut self.service else {
+            return Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())));
+        };
 
-            // Get our local user information for the request
-            let local_peer_id_str = self.get_local_peer_id().await?;
+        let version = self.crdt_engine.read().await.encoded_version(&doc_id).await.ok();
+        let peer_ids = self.peer_registry.read().await.active_peers().map(|p| p.peer_id).collect::<Vec<_>>();
 
-            // Create a sync request message
-            let _request = NetworkMessage::SyncRequest {
+        // Every peer asked counts as tried, so a failure only moves on to peers seen since
+        for peer_id in &peer_ids {
+            let sync = PendingSync {
                 document_id: doc_id,
-                user_id: local_peer_id_str,
-                version: None, // Full sync request
+                version: version.clone(),
+                tried: peer_ids.clone(),
+            };
+            send_sync_request(service, &self.pending_syncs, *peer_id, sync).await?;
+        }
+
+        Ok(peer_ids.len())
+    }
+
+    /// Leave a document's topics however many subscriptions to it are held, e.g. once it has
+    /// been deleted
+    pub async fn leave_document(&mut self, doc_id: Uuid) -> Result<()> {
+        let Some(mut subscriptions) = self.document_subscriptions.get_mut(&doc_id) else {
+            return Ok(());
+        };
+        *subscriptions = 1;
+        drop(subscriptions);
+
+        self.unsubscribe_from_document(doc_id).await
+    }
+
+    /// Unsubscribe from a document
+    ///
+    /// The document's topics are only left once every subscription to it has been released.
+    pub async fn unsubscribe_from_document(&mut self, doc_id: Uuid) -> Result<()> {
+        if let Some(service) = &mut self.service {
+            let Some(mut subscriptions) = self.document_subscriptions.get_mut(&doc_id) else {
+                return Err(anyhow::anyhow!(AppError::NetworkError(format!("Not subscribed to document: {}", doc_id))));
             };
+            *subscriptions -= 1;
+            if *subscriptions > 0 {
+                return Ok(());
+            }
+            drop(subscriptions);
+            self.document_subscriptions.remove(&doc_id);
+            if let Some(polling) = &self.polling {
+                polling.untrack_document(&doc_id);
+            }
+
+            for topic in DocumentTopic::all(doc_id) {
+                service.unsubscribe_from_topic(topic.to_topic_string()).await?;
+            }
 
-            // In a full implementation, we would send this request to peers
-            // through the request-response protocol
-            tracing::debug!("Requesting document sync for document: {} from {} peers",
-                         doc_id, peer_ids.len());
+            // Remove ourselves from the document subscribers
+            let local_peer_id = service.local_peer_id().to_string();
+            if let Some(mut subscribers) = self.document_subscribers.get_mut(&doc_id) {
+                subscribers.retain(|peer_id| *peer_id != local_peer_id);
+            }
+            self.document_subscribers.remove_if(&doc_id, |_, subscribers| subscribers.is_empty());
         } else {
             return Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())));
         }
@@ -387,18 +894,246 @@ impl NetworkEngine {
         Ok(())
     }
 
-    /// Unsubscribe from a document
-    pub async fn unsubscribe_from_document(&mut self, doc_id: Uuid) -> Result<()> {
+    /// Announce a public document to the network
+    pub async fn announce_document(&mut self, doc: &Document) -> Result<()> {
+        let Some(announcement) = DocumentDirectory::announcement(doc) else {
+            return Err(anyhow::anyhow!(AppError::Unauthorized(format!("Document {} is not public", doc.id))));
+        };
+
+        if let Some(dedup_key) = &doc.dedup_key {
+            self.document_directory.add_pending_creation(dedup_key.clone(), doc.id);
+        }
+
         if let Some(service) = &mut self.service {
-            let topic_str = DocumentTopic::Operations(doc_id).to_topic_string();
-            service.unsubscribe_from_topic(topic_str).await?;
+            service.publish_to_topic(ANNOUNCE_TOPIC.to_string(), announcement).await
         } else {
+            Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())))
+        }
+    }
+
+    /// Send peers a document's current metadata, which they adopt in full
+    pub async fn broadcast_metadata(&mut self, doc: &Document) -> Result<()> {
+        let update = Self::metadata_update(doc)?;
+
+        if let Some(service) = &mut self.service {
+            service.publish_to_topic(DocumentTopic::Metadata(doc.id).to_topic_string(), update).await
+        } else {
+            Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())))
+        }
+    }
+
+    /// A `MetadataUpdate` carrying a document's current metadata, encoded for the metadata topic
+    pub fn metadata_update(doc: &Document) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&NetworkMessage::MetadataUpdate {
+            document_id: doc.id,
+            title: Some(doc.title.clone()),
+            repository_url: doc.repository_url.clone(),
+            custom_metadata: Some(doc.custom_metadata.clone()),
+        })?)
+    }
+
+    /// Apply a `MetadataUpdate` a peer sent on a document's metadata topic
+    ///
+    /// Fields the update leaves out, or that are unchanged, are left alone.
+    pub async fn apply_metadata_update(engine: &CrdtEngine, doc_id: &Uuid, data: &[u8]) -> Result<()> {
+        let message: NetworkMessage = serde_json::from_slice(data)
+            .map_err(|e| anyhow::anyhow!(AppError::ProtocolError(format!("Malformed metadata update: {}", e))))?;
+        let NetworkMessage::MetadataUpdate { document_id, title, repository_url, custom_metadata } = message else {
+            return Err(anyhow::anyhow!(AppError::ProtocolError("Unexpected message on a metadata topic".to_string())));
+        };
+        if document_id != *doc_id {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Metadata update for document {} received for document {}",
+                document_id, doc_id
+            ))));
+        }
+
+        let current = engine.get_document(doc_id).await?.read().await.clone();
+        if let Some(title) = title.filter(|title| *title != current.title) {
+            engine.rename_document(doc_id, title, None).await?;
+        }
+        if let Some(url) = repository_url.filter(|url| current.repository_url.as_ref() != Some(url)) {
+            engine.set_repository_url(doc_id, url, None).await?;
+        }
+        if let Some(custom_metadata) = custom_metadata {
+            engine.replace_custom_metadata(doc_id, custom_metadata, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send peers the hash of each subscribed document's content, returning the number of
+    /// documents it was sent for
+    pub async fn broadcast_content_hashes(&mut self) -> Result<usize> {
+        let Some(service) = &mut self.service else {
             return Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())));
+        };
+
+        let documents: Vec<Uuid> = self.document_subscriptions.iter().map(|entry| *entry.key()).collect();
+        let mut sent = 0;
+        for document_id in documents {
+            let message = {
+                let engine = self.crdt_engine.read().await;
+                NetworkMessage::ContentHash {
+                    document_id,
+                    version: engine.encoded_version(&document_id).await?,
+                    content_hash: engine.content_hash(&document_id).await?,
+                }
+            };
+
+            // Nobody else may be subscribed yet, which only means there is nobody to compare with
+            let topic = DocumentTopic::Presence(document_id).to_topic_string();
+            match service.publish_to_topic(topic, serde_json::to_vec(&message)?).await {
+                Ok(()) => sent += 1,
+                Err(e) => tracing::debug!("Content hash of document {} not sent: {}", document_id, e),
+            }
         }
 
+        Ok(sent)
+    }
+
+    /// How often content hashes are sent to peers, if they are
+    pub fn content_hash_interval(&self) -> Option<std::time::Duration> {
+        self.config.content_hash_interval()
+    }
+
+    /// Send peers the hash of each subscribed document's content every `interval`, so copies
+    /// that diverged are noticed even when no operations are flowing
+    pub async fn run_content_hash_heartbeat(network: Arc<RwLock<NetworkEngine>>, interval: std::time::Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            if let Err(e) = network.write().await.broadcast_content_hashes().await {
+                tracing::debug!("Content hashes not sent: {}", e);
+            }
+        }
+    }
+
+    /// Ask peers for the operations of documents the engine reports it has fallen behind on,
+    /// until the engine goes away
+    pub async fn run_resync_requests(network: Arc<RwLock<NetworkEngine>>, mut events: broadcast::Receiver<DocumentEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(DocumentEvent::ResyncNeeded { document_id }) => {
+                    if let Err(e) = network.write().await.request_sync_from_peers(document_id).await {
+                        tracing::warn!("Failed to resync document {}: {}", document_id, e);
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!("Resync requests missed {} engine events", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Send a chat message about a document to the peers subscribed to it
+    pub async fn broadcast_chat(&mut self, doc_id: &Uuid, entry: &ChatEntry) -> Result<()> {
+        let message = serde_json::to_vec(&NetworkMessage::Chat {
+            document_id: *doc_id,
+            user_id: entry.user_id.clone(),
+            body: entry.body.clone(),
+            timestamp: entry.timestamp,
+        })?;
+
+        if let Some(service) = &mut self.service {
+            service.publish_to_topic(DocumentTopic::Chat(*doc_id).to_topic_string(), message).await
+        } else {
+            Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())))
+        }
+    }
+
+    /// Add a `Chat` message a peer sent on a document's chat topic to the document's chat
+    pub async fn apply_chat_message(engine: &CrdtEngine, doc_id: &Uuid, data: &[u8]) -> Result<()> {
+        let message: NetworkMessage = serde_json::from_slice(data)
+            .map_err(|e| anyhow::anyhow!(AppError::ProtocolError(format!("Malformed chat message: {}", e))))?;
+        let NetworkMessage::Chat { document_id, user_id, body, timestamp } = message else {
+            return Err(anyhow::anyhow!(AppError::ProtocolError("Unexpected message on a chat topic".to_string())));
+        };
+        if document_id != *doc_id {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Chat message for document {} received for document {}",
+                document_id, doc_id
+            ))));
+        }
+
+        engine.record_chat(doc_id, ChatEntry { user_id, body, timestamp }).await?;
         Ok(())
     }
 
+    /// Documents announced by other peers that this node could join
+    ///
+    /// Documents this node is already subscribed to are left out.
+    pub async fn get_discovered_documents(&self) -> Result<Vec<DiscoveredDocument>> {
+        Ok(self
+            .document_directory
+            .list()
+            .into_iter()
+            .filter(|document| !self.document_subscriptions.contains_key(&document.document_id))
+            .collect())
+    }
+
+    /// The registry announcements received by this node are recorded in
+    pub fn document_directory(&self) -> Arc<DocumentDirectory> {
+        Arc::clone(&self.document_directory)
+    }
+
+    /// Record a peer as subscribed to a document
+    pub fn add_document_subscriber(&self, doc_id: Uuid, peer_id: String) {
+        add_subscriber(&self.document_subscribers, doc_id, peer_id);
+    }
+
+    /// Whether a document's history is being exchanged with peers: this node is subscribed
+    /// to it, or peers are known to be
+    pub fn is_document_shared(&self, doc_id: &Uuid) -> bool {
+        self.document_subscriptions.contains_key(doc_id)
+            || self.document_subscribers.get(doc_id).is_some_and(|subscribers| !subscribers.is_empty())
+    }
+
+    /// Documents that currently have subscribed peers, busiest first
+    ///
+    /// Only public documents are listed: local ones whose visibility is public, and remote
+    /// ones that have been announced, which only happens for public documents.
+    pub async fn get_active_sessions(&self) -> Result<Vec<ActiveSession>> {
+        let active: Vec<(Uuid, usize)> = self
+            .document_subscribers
+            .iter()
+            .filter(|entry| !entry.value().is_empty())
+            .map(|entry| (*entry.key(), entry.value().len()))
+            .collect();
+
+        let engine = self.crdt_engine.read().await;
+        let mut sessions = Vec::new();
+        for (document_id, collaborators) in active {
+            let (title, owner) = match engine.get_document(&document_id).await {
+                Ok(doc) => {
+                    let doc = doc.read().await;
+                    if doc.visibility != DocumentVisibility::Public {
+                        continue;
+                    }
+                    (doc.title.clone(), doc.owner.clone())
+                }
+                Err(_) => match self.document_directory.get(&document_id) {
+                    Some(discovered) => (discovered.title, discovered.owner),
+                    None => continue,
+                },
+            };
+
+            let active_users = engine
+                .get_document_presences(&document_id)
+                .await?
+                .iter()
+                .filter(|presence| presence.is_active)
+                .count();
+
+            sessions.push(ActiveSession { document_id, title, owner, collaborators, active_users });
+        }
+        sessions.sort_by(|a, b| b.collaborators.cmp(&a.collaborators).then_with(|| a.title.cmp(&b.title)));
+
+        Ok(sessions)
+    }
+
     /// Get the number of connected peers
     pub async fn get_connected_peer_count(&self) -> Result<usize> {
         let registry = self.peer_registry.read().await;
@@ -411,9 +1146,139 @@ impl NetworkEngine {
         Ok(registry.active_peers().map(|p| p.peer_id.to_string()).collect())
     }
 
-    /// Get all subscribed documents
+    /// Get the documents this node is subscribed to, sorted
     pub async fn get_subscribed_documents(&self) -> Result<Vec<Uuid>> {
-        Ok(self.document_subscribers.iter().map(|entry| *entry.key()).collect())
+        let mut documents: Vec<Uuid> = self.document_subscriptions.iter().map(|entry| *entry.key()).collect();
+        documents.sort();
+        Ok(documents)
+    }
+
+    /// Get the gossipsub mesh health and message counts of each subscribed topic
+    pub async fn get_gossipsub_metrics(&self) -> Result<Vec<TopicMetrics>> {
+        if let Some(service) = &self.service {
+            Ok(service.gossipsub_metrics().await)
+        } else {
+            Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())))
+        }
+    }
+
+    /// Get the addresses the network is configured to listen on
+    pub fn get_listen_addresses(&self) -> Vec<String> {
+        self.config.listen_addresses.clone()
+    }
+}
+
+/// Ask a peer for its whole OpLog of a document that diverged from the peer's copy
+async fn resync_from(service: &mut NetworkServiceWrapper, pending_syncs: &DashMap<String, PendingSync>, peer: PeerId, document_id: Uuid) {
+    let sync = PendingSync {
+        document_id,
+        version: None,
+        tried: Vec::new(),
+    };
+    if let Err(e) = send_sync_request(service, pending_syncs, peer, sync).await {
+        tracing::warn!("Failed to ask peer {} to resync document {}: {}", peer, document_id, e);
+    }
+}
+
+/// Ask a peer for the operations of a document beyond `sync.version`, keeping track of the
+/// request until the peer answers or it fails
+async fn send_sync_request(
+    service: &mut NetworkServiceWrapper,
+    pending_syncs: &DashMap<String, PendingSync>,
+    peer: PeerId,
+    mut sync: PendingSync,
+) -> Result<()> {
+    let request = NetworkMessage::SyncRequest {
+        document_id: sync.document_id,
+        user_id: service.local_peer_id().to_string(),
+        version: sync.version.clone(),
+    };
+    let request_id = format!("sync/{}/{}", sync.document_id, Uuid::new_v4());
+    if !sync.tried.contains(&peer) {
+        sync.tried.push(peer);
+    }
+
+    pending_syncs.insert(request_id.clone(), sync);
+    if let Err(e) = service.send_request(peer, request, request_id.clone()).await {
+        pending_syncs.remove(&request_id);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Move a document's subscriptions, however many are held, from its old topics to those of
+/// the ID it moved to
+async fn move_subscription(
+    service: &mut NetworkServiceWrapper,
+    document_subscriptions: &DashMap<Uuid, usize>,
+    document_subscribers: &DashMap<Uuid, Vec<String>>,
+    old_id: Uuid,
+    new_id: Uuid,
+) {
+    let Some((_, subscriptions)) = document_subscriptions.remove(&old_id) else {
+        return;
+    };
+    *document_subscriptions.entry(new_id).or_insert(0) += subscriptions;
+    document_subscribers.remove(&old_id);
+    add_subscriber(document_subscribers, new_id, service.local_peer_id().to_string());
+
+    for (old, new) in DocumentTopic::all(old_id).into_iter().zip(DocumentTopic::all(new_id)) {
+        if let Err(e) = service.unsubscribe_from_topic(old.to_topic_string()).await {
+            tracing::warn!("Failed to leave topic {}: {}", old.to_topic_string(), e);
+        }
+        if let Err(e) = service.subscribe_to_topic(new.to_topic_string()).await {
+            tracing::warn!("Failed to join topic {}: {}", new.to_topic_string(), e);
+        }
+    }
+}
+
+/// Send a peer the first chunk of a transfer it hasn't acknowledged, if any is left
+async fn send_next_chunk(
+    service: &mut NetworkServiceWrapper,
+    outgoing_transfers: &OutgoingTransfers,
+    peer: PeerId,
+    transfer_id: &Uuid,
+) {
+    let Some(document_id) = outgoing_transfers.document_id(transfer_id) else {
+        return;
+    };
+    let Some((header, data)) = outgoing_transfers.next_chunk(transfer_id) else {
+        return;
+    };
+
+    let request_id = format!("chunk/{}/{}", transfer_id, header.offset);
+    let request = NetworkMessage::OplogChunk { document_id, header, data };
+    if let Err(e) = service.send_request(peer, request, request_id).await {
+        tracing::warn!("Failed to send chunk of transfer {} to peer {}: {}", transfer_id, peer, e);
+    }
+}
+
+/// Apply an operation a peer sent, penalizing the peer if it is malformed
+async fn apply_operation_from(
+    engine: &CrdtEngine,
+    peer_registry: &RwLock<PeerRegistry>,
+    source: PeerId,
+    doc_id: &Uuid,
+    data: &[u8],
+) {
+    match engine.apply_remote_operation(doc_id, data).await {
+        Ok(()) => {}
+        Err(e) if matches!(e.downcast_ref::<AppError>(), Some(AppError::ProtocolError(_))) => {
+            let strikes = peer_registry.write().await.penalize(&source);
+            tracing::warn!(
+                "Rejected operation from peer {} ({} invalid so far): {}",
+                source, strikes, e
+            );
+        }
+        Err(e) => tracing::warn!("Failed to apply remote operation: {}", e),
+    }
+}
+
+/// Add a peer to a document's subscribers, unless it is already one
+fn add_subscriber(subscribers: &DashMap<Uuid, Vec<String>>, doc_id: Uuid, peer_id: String) {
+    let mut subscribers = subscribers.entry(doc_id).or_default();
+    if !subscribers.contains(&peer_id) {
+        subscribers.push(peer_id);
     }
 }
 
@@ -1,135 +0,0 @@
-// This is a proposed fix for the NetworkEngine class
-// It can be merged into the main engine.rs file once it's tested
-
-use anyhow::Result;
-use dashmap::DashMap;
-use libp2p::PeerId;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use uuid::Uuid;
-
-use crate::crdt::engine::CrdtEngine;
-use crate::network::peer::PeerRegistry;
-use crate::network::engine::{DocumentTopic, NetworkService};
-use crate::network::protocol::NetworkMessage;
-use crate::utils::config::NetworkConfig;
-use crate::utils::errors::AppError;
-
-/// The NetworkEngine manages the P2P network connections and message routing
-#[derive(Debug)]
-pub struct NetworkEngine {
-    service: Option<NetworkService>,
-    #[allow(dead_code)]
-    peer_registry: Arc<RwLock<PeerRegistry>>,
-    #[allow(dead_code)]
-    crdt_engine: Arc<RwLock<CrdtEngine>>,
-    config: NetworkConfig,
-
-    // Map of document IDs to the set of peer IDs that are subscribed to that document
-    document_subscribers: DashMap<Uuid, Vec<String>>,
-}
-
-impl NetworkEngine {
-    pub async fn new(config: &NetworkConfig, crdt_engine: Arc<RwLock<CrdtEngine>>) -> Result<Self> {
-        // Create a peer registry with the specified timeout duration
-        let peer_registry = Arc::new(RwLock::new(PeerRegistry::new(std::time::Duration::from_secs(300))));
-
-        Ok(Self {
-            service: None,
-            peer_registry: peer_registry.clone(),
-            crdt_engine,
-            config: config.clone(),
-            document_subscribers: dashmap::DashMap::new(),
-        })
-    }
-
-    pub async fn start(&mut self) -> Result<()> {
-        // Initialize the network service with the configuration
-        let service = NetworkService::new(self.config.clone()).await?;
-        self.service = Some(service);
-
-        // Start the main network event loop as a background task
-        self.start_event_loop().await?;
-
-        Ok(())
-    }
-
-    /// Get the local peer ID
-    pub async fn get_local_peer_id(&self) -> Result<String> {
-        if let Some(service) = &self.service {
-            Ok(service.local_peer_id.to_string())
-        } else {
-            Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())))
-        }
-    }
-
-    pub async fn stop(&mut self) -> Result<()> {
-        // Network service will be dropped when Option is cleared
-        self.service = None;
-        Ok(())
-    }
-
-    async fn start_event_loop(&mut self) -> Result<()> {
-        // Implementation details would be similar to engine.rs
-        Ok(())
-    }
-
-    /// Broadcast an operation to all subscribed peers
-    pub async fn broadcast_operation(&mut self, doc_id: &Uuid, operation: Vec<u8>) -> Result<()> {
-        if let Some(service) = &mut self.service {
-            // Publish to the operations topic for this document
-            let topic_str = DocumentTopic::Operations(*doc_id).to_topic_string();
-            service.publish_to_topic(topic_str, operation.clone()).await?;
-
-            // Also directly deliver the operation to all subscribed peers
-            // This ensures operations propagate even if the gossipsub propagation fails
-            if let Some(subscribers) = self.document_subscribers.get(doc_id) {
-                for peer_id_str in subscribers.value() {
-                    if let Ok(peer_id) = peer_id_str.parse::<PeerId>() {
-                        // For a real implementation, we'd need to directly send the operation
-                        // to the peer. This would involve using request_response or a direct
-                        // connection to the peer.
-                        tracing::debug!("Would send operation directly to peer: {}", peer_id);
-                    }
-                }
-            }
-        } else {
-            return Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())));
-        }
-
-        Ok(())
-    }
-
-    /// Subscribe to a document
-    pub async fn subscribe_to_document(&mut self, doc_id: Uuid) -> Result<()> {
-        if let Some(service) = &mut self.service {
-            let topic_str = DocumentTopic::Operations(doc_id).to_topic_string();
-            service.subscribe_to_topic(topic_str).await?;
-
-            // Also add ourselves to the subscribers
-            let local_peer_id = self.get_local_peer_id().await?;
-            let mut subscribers = self.document_subscribers.entry(doc_id).or_insert_with(Vec::new);
-            if !subscribers.contains(&local_peer_id) {
-                subscribers.push(local_peer_id);
-            }
-
-            // Now, we need to explicitly send a join request to peers to get document content
-            // This would be done by sending a JoinRequest message to known peers
-            let _request = NetworkMessage::JoinRequest {
-                document_id: doc_id,
-                user_id: "user".to_string(), // This would be the actual user ID
-                user_name: "User".to_string(), // This would be the actual user name
-            };
-
-            // In a real implementation, we'd send this request to peers
-            // For now, just log it
-            tracing::debug!("Would send join request for document: {}", doc_id);
-        } else {
-            return Err(anyhow::anyhow!(AppError::NetworkError("Network service not initialized".to_string())));
-        }
-
-        Ok(())
-    }
-
-    // Additional methods would be implemented here
-}
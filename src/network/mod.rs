@@ -2,7 +2,12 @@ pub mod peer;
 pub mod swarm;
 pub mod protocol;
 pub mod discovery;
+pub mod access;
+pub mod directory;
+pub mod rendezvous;
+pub mod transfer;
+pub mod polling;
+pub mod pause;
 pub mod engine;
-pub mod engine_fix;
 pub mod service;
 pub mod service_wrapper;
@@ -0,0 +1,137 @@
+use libp2p::PeerId;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+/// Most operations held in each direction while paused; past this, operations are dropped
+/// and their documents synced with peers on resume instead
+pub const MAX_HELD_OPERATIONS: usize = 1024;
+
+/// An operation received from a peer while propagation was paused
+#[derive(Debug, Clone)]
+pub struct HeldOperation {
+    pub source: PeerId,
+    pub document_id: Uuid,
+    pub data: Vec<u8>,
+}
+
+/// Operations held in one direction, and the documents some were dropped from
+#[derive(Debug)]
+pub struct Held<T> {
+    pub operations: Vec<T>,
+    /// Documents whose operations couldn't all be held, so they need syncing as a whole
+    pub dropped: HashSet<Uuid>,
+}
+
+impl<T> Default for Held<T> {
+    fn default() -> Self {
+        Self {
+            operations: Vec::new(),
+            dropped: HashSet::new(),
+        }
+    }
+}
+
+impl<T> Held<T> {
+    fn hold(&mut self, document_id: Uuid, operation: T) {
+        if self.operations.len() >= MAX_HELD_OPERATIONS {
+            self.dropped.insert(document_id);
+        } else {
+            self.operations.push(operation);
+        }
+    }
+}
+
+/// Switch for holding back operations, in both directions, without dropping connections
+///
+/// While paused, operations this node makes are kept instead of published, and operations
+/// received from peers are kept instead of applied; both are released, in the order they were
+/// held, on resume. Past `MAX_HELD_OPERATIONS` in a direction, operations are dropped and
+/// their documents noted, to be pushed to or pulled from peers whole on resume, as are the
+/// documents whose sync responses arrive while paused. This makes network partitions
+/// reproducible in tests and while debugging.
+#[derive(Debug, Default)]
+pub struct PropagationPause {
+    paused: AtomicBool,
+    inbound: Mutex<Held<HeldOperation>>,
+    outbound: Mutex<Held<(Uuid, Vec<u8>)>>,
+}
+
+impl PropagationPause {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Start holding operations back
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Keep an operation received from a peer if paused, returning whether it was kept
+    pub async fn hold_inbound(&self, source: PeerId, document_id: Uuid, data: &[u8]) -> bool {
+        let mut inbound = self.inbound.lock().await;
+        if !self.is_paused() {
+            return false;
+        }
+        inbound.hold(document_id, HeldOperation { source, document_id, data: data.to_vec() });
+        true
+    }
+
+    /// Note that a document is to be synced with peers on resume if paused, instead of merging
+    /// what a peer sent of it now, returning whether it was noted
+    pub async fn defer_sync(&self, document_id: Uuid) -> bool {
+        let mut inbound = self.inbound.lock().await;
+        if !self.is_paused() {
+            return false;
+        }
+        inbound.dropped.insert(document_id);
+        true
+    }
+
+    /// Keep an operation this node made if paused, returning whether it was kept
+    pub async fn hold_outbound(&self, document_id: Uuid, data: &[u8]) -> bool {
+        let mut outbound = self.outbound.lock().await;
+        if !self.is_paused() {
+            return false;
+        }
+        outbound.hold(document_id, (document_id, data.to_vec()));
+        true
+    }
+
+    /// Stop holding operations back, returning those held so far
+    ///
+    /// The returned guard holds off new inbound operations until it is dropped, so the caller
+    /// applies the held ones before any that arrive after resuming.
+    pub async fn resume(&self) -> (MutexGuard<'_, Held<HeldOperation>>, Held<(Uuid, Vec<u8>)>) {
+        let inbound = self.inbound.lock().await;
+        let mut outbound = self.outbound.lock().await;
+        self.paused.store(false, Ordering::SeqCst);
+        let outbound = std::mem::take(&mut *outbound);
+        (inbound, outbound)
+    }
+
+    /// Pause again with outbound operations that couldn't be released put back in front of
+    /// any held since, e.g. when publishing them failed
+    ///
+    /// Called while still holding the guard `resume` returned, so no inbound operation slips
+    /// through in between.
+    pub async fn hold_back(&self, unsent: Held<(Uuid, Vec<u8>)>) {
+        let mut outbound = self.outbound.lock().await;
+        self.paused.store(true, Ordering::SeqCst);
+        let held_since = std::mem::replace(&mut *outbound, unsent);
+        outbound.dropped.extend(held_since.dropped);
+        for (document_id, data) in held_since.operations {
+            outbound.hold(document_id, (document_id, data));
+        }
+    }
+
+    /// Number of inbound and outbound operations held
+    pub async fn held(&self) -> (usize, usize) {
+        (self.inbound.lock().await.operations.len(), self.outbound.lock().await.operations.len())
+    }
+}
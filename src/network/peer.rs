@@ -34,6 +34,10 @@ pub struct PeerInfo {
     /// When this peer was last seen
     #[serde(with = "instant_serde")]
     pub last_seen: Instant,
+
+    /// Number of malformed messages received from this peer
+    #[serde(default)]
+    pub invalid_messages: u32,
 }
 
 impl PeerInfo {
@@ -46,6 +50,7 @@ impl PeerInfo {
             active_documents: Vec::new(),
             addresses: Vec::new(),
             last_seen: Instant::now(),
+            invalid_messages: 0,
         }
     }
 
@@ -129,6 +134,13 @@ impl PeerRegistry {
         false
     }
 
+    /// Record that a peer sent a malformed message, returning its total count
+    pub fn penalize(&mut self, peer_id: &PeerId) -> u32 {
+        let peer = self.update_peer(*peer_id);
+        peer.invalid_messages += 1;
+        peer.invalid_messages
+    }
+
     /// Clean up inactive peers
     pub fn cleanup_inactive(&mut self) {
         let inactive: Vec<PeerId> = self.peers.iter()
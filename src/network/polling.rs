@@ -0,0 +1,260 @@
+use anyhow::Result;
+use dashmap::DashSet;
+use libp2p::PeerId;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
+use uuid::Uuid;
+
+use super::protocol::{CollabRequest, CollabResponse, NetworkMessage};
+use super::service::{NetworkEvent, RealNetworkService};
+use crate::crdt::engine::CrdtEngine;
+use crate::crdt::integrity::HashComparison;
+use crate::utils::config::NetworkConfig;
+
+/// Prefix of the IDs of requests sent while polling, so their failures aren't reported further
+const POLL_REQUEST_PREFIX: &str = "poll/";
+
+/// Document sync by polling connected peers with `SyncRequest`s, for networks where gossipsub
+/// is blocked
+///
+/// Every interval, each connected peer is asked for the operations it has of each tracked
+/// document beyond the version this node is at, and whatever it returns is merged. Sync
+/// requests from peers are answered the same way, so two polling nodes converge within an
+/// interval of each other without gossipsub.
+#[derive(Debug)]
+pub struct PollingSync {
+    service: Arc<RealNetworkService>,
+    crdt_engine: Arc<RwLock<CrdtEngine>>,
+    documents: DashSet<Uuid>,
+    interval: Duration,
+}
+
+impl PollingSync {
+    pub fn new(service: Arc<RealNetworkService>, crdt_engine: Arc<RwLock<CrdtEngine>>, interval: Duration) -> Self {
+        Self {
+            service,
+            crdt_engine,
+            documents: DashSet::new(),
+            interval,
+        }
+    }
+
+    /// Polling sync, if the configured sync mode polls peers
+    pub fn from_config(
+        config: &NetworkConfig,
+        service: Arc<RealNetworkService>,
+        crdt_engine: Arc<RwLock<CrdtEngine>>,
+    ) -> Option<Self> {
+        config
+            .sync_mode
+            .polls()
+            .then(|| Self::new(service, crdt_engine, Duration::from_secs(config.poll_interval_secs)))
+    }
+
+    /// Poll peers for a document from now on
+    pub fn track_document(&self, doc_id: Uuid) {
+        self.documents.insert(doc_id);
+    }
+
+    /// Stop polling peers for a document
+    pub fn untrack_document(&self, doc_id: &Uuid) {
+        self.documents.remove(doc_id);
+    }
+
+    /// Ask every connected peer for what it has of each tracked document, returning the
+    /// number of requests sent
+    pub async fn poll(&self) -> Result<usize> {
+        let peers = self.service.connected_peers().await;
+        let documents: Vec<Uuid> = self.documents.iter().map(|doc_id| *doc_id).collect();
+
+        let mut sent = 0;
+        for document_id in documents {
+            let version = match self.crdt_engine.read().await.encoded_version(&document_id).await {
+                Ok(version) => version,
+                Err(e) => {
+                    tracing::warn!("Not polling for document {}: {}", document_id, e);
+                    continue;
+                }
+            };
+
+            for peer in &peers {
+                let request = NetworkMessage::SyncRequest {
+                    document_id,
+                    user_id: self.service.local_peer_id.to_string(),
+                    version: Some(version.clone()),
+                };
+                let request_id = format!("{}{}/{}", POLL_REQUEST_PREFIX, document_id, Uuid::new_v4());
+                self.service.send_request(*peer, request, request_id).await?;
+                sent += 1;
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Answer a peer's sync request, or merge a peer's sync response
+    ///
+    /// Events that have nothing to do with syncing are handed back.
+    pub async fn handle_event(&self, event: NetworkEvent) -> Option<NetworkEvent> {
+        match event {
+            NetworkEvent::RequestReceived {
+                source,
+                request: CollabRequest(NetworkMessage::SyncRequest { document_id, version, .. }),
+                channel,
+                ..
+            } => {
+                let response = sync_response(&*self.crdt_engine.read().await, document_id, version.as_deref()).await;
+                if let Err(e) = self.service.send_response(channel, response).await {
+                    tracing::warn!("Failed to answer sync request from peer {}: {}", source, e);
+                }
+                None
+            }
+            NetworkEvent::ResponseReceived {
+                source,
+                response: CollabResponse(NetworkMessage::SyncResponse { document_id, operations, is_full_sync, version, content_hash }),
+                ..
+            } => {
+                let peer_copy = version.as_deref().zip(content_hash.as_deref());
+                match apply_sync_response(&*self.crdt_engine.read().await, &document_id, &operations, peer_copy).await {
+                    Ok(comparison) if needs_full_resync(&document_id, &source, comparison, is_full_sync) => {
+                        let request = full_sync_request(document_id, self.service.local_peer_id.to_string());
+                        let request_id = format!("{}{}/{}", POLL_REQUEST_PREFIX, document_id, Uuid::new_v4());
+                        if let Err(e) = self.service.send_request(source, request, request_id).await {
+                            tracing::warn!("Failed to ask peer {} to resync document {}: {}", source, document_id, e);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Failed to merge document {} polled from peer {}: {}", document_id, source, e),
+                }
+                None
+            }
+            NetworkEvent::RequestFailed { request_id, peer, error } if is_poll_request(&request_id) => {
+                // The next poll asks again
+                tracing::debug!("Polling peer {} failed: {}", peer, error);
+                None
+            }
+            event => Some(event),
+        }
+    }
+
+    /// Poll peers every interval, leaving the sync messages that come of it to whatever
+    /// handles the service's events, e.g. the network engine's event loop
+    pub fn start_polling(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.poll().await {
+                    tracing::warn!("Failed to poll peers: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Poll peers every interval and handle sync messages among the service's events, passing
+    /// every other event on to the returned receiver
+    pub fn start(self: Arc<Self>, mut events: mpsc::Receiver<NetworkEvent>) -> mpsc::Receiver<NetworkEvent> {
+        let (sender, receiver) = mpsc::channel(100);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        if let Err(e) = self.poll().await {
+                            tracing::warn!("Failed to poll peers: {}", e);
+                        }
+                    }
+                    event = events.recv() => {
+                        let Some(event) = event else {
+                            break;
+                        };
+                        if let Some(event) = self.handle_event(event).await
+                            && sender.send(event).await.is_err()
+                        {
+                            tracing::debug!("Dropped a network event nobody is listening for");
+                        }
+                    }
+                }
+            }
+        });
+
+        receiver
+    }
+}
+
+/// Whether a request was sent while polling, so its failure can wait for the next poll
+pub fn is_poll_request(request_id: &str) -> bool {
+    request_id.starts_with(POLL_REQUEST_PREFIX)
+}
+
+/// The answer to a peer's `SyncRequest`: the operations of the document it lacks, or nothing
+/// if this node doesn't hold the document, along with the hash of this node's copy
+pub async fn sync_response(engine: &CrdtEngine, document_id: Uuid, version: Option<&[u8]>) -> NetworkMessage {
+    let (operations, is_full_sync) = match engine.export_since(&document_id, version).await {
+        Ok(export) => export,
+        Err(e) => {
+            tracing::debug!("Nothing to sync of document {}: {}", document_id, e);
+            (Vec::new(), false)
+        }
+    };
+
+    NetworkMessage::SyncResponse {
+        document_id,
+        operations,
+        is_full_sync,
+        version: engine.encoded_version(&document_id).await.ok(),
+        content_hash: engine.content_hash(&document_id).await.ok(),
+    }
+}
+
+/// Merge the operations a peer sent in a `SyncResponse`, if it sent any, then compare the
+/// result with the peer's copy, if the peer sent its version and content hash
+pub async fn apply_sync_response(
+    engine: &CrdtEngine,
+    document_id: &Uuid,
+    operations: &[u8],
+    peer_copy: Option<(&[u8], &str)>,
+) -> Result<HashComparison> {
+    if !operations.is_empty() {
+        engine.sync_document(document_id, operations).await?;
+    }
+
+    // The merge waits for a frozen document to thaw, so the copies would look diverged
+    if engine.is_frozen(document_id) {
+        return Ok(HashComparison::NotComparable);
+    }
+
+    match peer_copy {
+        Some((version, content_hash)) => engine.compare_content_hash(document_id, version, content_hash).await,
+        None => Ok(HashComparison::NotComparable),
+    }
+}
+
+/// Whether to ask a peer for its whole OpLog, given how a document compares to the peer's
+/// copy, logging any divergence
+///
+/// A copy that still differs after a full resync is only reported, so two nodes can't keep
+/// resyncing with each other.
+pub fn needs_full_resync(document_id: &Uuid, peer: &PeerId, comparison: HashComparison, after_full_sync: bool) -> bool {
+    if comparison != HashComparison::Diverged {
+        return false;
+    }
+
+    if after_full_sync {
+        tracing::error!("Document {} still differs from peer {}'s copy after a full resync", document_id, peer);
+        return false;
+    }
+    tracing::warn!("Document {} diverged from peer {}'s copy at the same version; resyncing", document_id, peer);
+    true
+}
+
+/// A `SyncRequest` for a peer's whole OpLog
+pub fn full_sync_request(document_id: Uuid, user_id: String) -> NetworkMessage {
+    NetworkMessage::SyncRequest {
+        document_id,
+        user_id,
+        version: None,
+    }
+}
@@ -1,18 +1,56 @@
 use futures::prelude::*;
 use libp2p::{request_response::{Codec}};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io;
 use std::future::Future;
 use std::pin::Pin;
 use uuid::Uuid;
 
-/// Protocol for P2P LaTeX collaboration
-#[derive(Debug, Clone)]
-pub struct CollabProtocol;
+use super::transfer::ChunkHeader;
+
+/// Largest length prefix accepted from a peer, so a bogus one can't exhaust memory
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Versions of the collaboration protocol, oldest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProtocolVersion {
+    /// One JSON message per stream, ended by closing the stream
+    V1,
+    /// JSON messages prefixed with their length as a big-endian `u32`
+    V2,
+}
+
+impl ProtocolVersion {
+    /// Every version this build can speak
+    pub const ALL: [ProtocolVersion; 2] = [ProtocolVersion::V1, ProtocolVersion::V2];
+}
+
+/// Protocol for P2P LaTeX collaboration, at one particular version
+///
+/// Peers advertise every version they speak, and the highest one both support is negotiated
+/// for each stream. Peers with no version in common can't open streams to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollabProtocol(pub ProtocolVersion);
+
+impl CollabProtocol {
+    /// The protocols to advertise for the given versions, highest first, so the highest
+    /// common version is the one negotiated
+    pub fn advertised(versions: &[ProtocolVersion]) -> Vec<CollabProtocol> {
+        let mut versions = versions.to_vec();
+        versions.sort_unstable_by(|a, b| b.cmp(a));
+        versions.dedup();
+        versions.into_iter().map(CollabProtocol).collect()
+    }
+}
 
 impl AsRef<[u8]> for CollabProtocol {
     fn as_ref(&self) -> &[u8] {
-        b"/p2p-latex-collab/1.0.0"
+        match self.0 {
+            ProtocolVersion::V1 => b"/p2p-latex-collab/1.0.0",
+            ProtocolVersion::V2 => b"/p2p-latex-collab/2.0.0",
+        }
     }
 }
 
@@ -52,6 +90,33 @@ pub enum NetworkMessage {
         document_id: Uuid,
         operations: Vec<u8>,      // Encoded operations
         is_full_sync: bool,
+        /// Version of the responder's copy, encoded like a request's
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<Vec<u8>>,
+        /// Hash of the responder's content, to check the merged result against
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content_hash: Option<String>,
+    },
+
+    /// Hash of a document's content at a version, sent periodically so peers notice copies
+    /// that diverged
+    ContentHash {
+        document_id: Uuid,
+        version: Vec<u8>,
+        content_hash: String,
+    },
+
+    /// Slice of an oplog too large for a single sync or join response
+    OplogChunk {
+        document_id: Uuid,
+        header: ChunkHeader,
+        data: Vec<u8>,
+    },
+
+    /// Acknowledgement that every byte of a transfer before `offset` has been received
+    ChunkAck {
+        transfer_id: Uuid,
+        offset: usize,
     },
 
     /// User presence information
@@ -68,6 +133,17 @@ pub enum NetworkMessage {
         document_id: Uuid,
         title: Option<String>,
         repository_url: Option<String>,
+        /// The document's custom metadata, in full
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        custom_metadata: Option<HashMap<String, String>>,
+    },
+
+    /// Chat message about a document
+    Chat {
+        document_id: Uuid,
+        user_id: String,
+        body: String,
+        timestamp: chrono::DateTime<chrono::Utc>,
     },
 
     /// User leaving the document
@@ -75,6 +151,16 @@ pub enum NetworkMessage {
         document_id: Uuid,
         user_id: String,
     },
+
+    /// A public document offered to any peer that wants to join it
+    AnnounceDocument {
+        document_id: Uuid,
+        title: String,
+        owner: String,
+        /// Key shared with copies of the document other peers created independently
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dedup_key: Option<String>,
+    },
 }
 
 /// Request type for the request-response protocol
@@ -97,7 +183,7 @@ impl Codec for CollabCodec {
     // Use the exact lifetime parameter names expected by the trait
     fn read_request<'life0, 'life1, 'life2, 'async_trait, T>(
         &'life0 mut self,
-        _: &'life1 Self::Protocol,
+        protocol: &'life1 Self::Protocol,
         io: &'life2 mut T
     ) -> Pin<Box<dyn Future<Output = io::Result<Self::Request>> + Send + 'async_trait>>
     where
@@ -107,18 +193,14 @@ impl Codec for CollabCodec {
         'life1: 'async_trait,
         'life2: 'async_trait,
     {
-        Box::pin(async move {
-            let mut buffer = Vec::new();
-            io.read_to_end(&mut buffer).await?;
-            serde_json::from_slice(&buffer)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
-        })
+        let version = protocol.0;
+        Box::pin(async move { read_message(version, io).await })
     }
 
     // Use the exact lifetime parameter names expected by the trait
     fn read_response<'life0, 'life1, 'life2, 'async_trait, T>(
         &'life0 mut self,
-        _: &'life1 Self::Protocol,
+        protocol: &'life1 Self::Protocol,
         io: &'life2 mut T
     ) -> Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send + 'async_trait>>
     where
@@ -128,18 +210,14 @@ impl Codec for CollabCodec {
         'life1: 'async_trait,
         'life2: 'async_trait,
     {
-        Box::pin(async move {
-            let mut buffer = Vec::new();
-            io.read_to_end(&mut buffer).await?;
-            serde_json::from_slice(&buffer)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
-        })
+        let version = protocol.0;
+        Box::pin(async move { read_message(version, io).await })
     }
 
     // Use the exact lifetime parameter names expected by the trait
     fn write_request<'life0, 'life1, 'life2, 'async_trait, T>(
         &'life0 mut self,
-        _: &'life1 Self::Protocol,
+        protocol: &'life1 Self::Protocol,
         io: &'life2 mut T,
         req: Self::Request
     ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'async_trait>>
@@ -150,17 +228,14 @@ impl Codec for CollabCodec {
         'life1: 'async_trait,
         'life2: 'async_trait,
     {
-        Box::pin(async move {
-            let bytes = serde_json::to_vec(&req)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
-            io.write_all(&bytes).await
-        })
+        let version = protocol.0;
+        Box::pin(async move { write_message(version, io, &req).await })
     }
 
     // Use the exact lifetime parameter names expected by the trait
     fn write_response<'life0, 'life1, 'life2, 'async_trait, T>(
         &'life0 mut self,
-        _: &'life1 Self::Protocol,
+        protocol: &'life1 Self::Protocol,
         io: &'life2 mut T,
         res: Self::Response
     ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'async_trait>>
@@ -171,10 +246,54 @@ impl Codec for CollabCodec {
         'life1: 'async_trait,
         'life2: 'async_trait,
     {
-        Box::pin(async move {
-            let bytes = serde_json::to_vec(&res)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
-            io.write_all(&bytes).await
-        })
+        let version = protocol.0;
+        Box::pin(async move { write_message(version, io, &res).await })
+    }
+}
+
+/// Read one message in the format of the negotiated protocol version
+async fn read_message<T, M>(version: ProtocolVersion, io: &mut T) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let buffer = match version {
+        ProtocolVersion::V1 => {
+            let mut buffer = Vec::new();
+            io.read_to_end(&mut buffer).await?;
+            buffer
+        }
+        ProtocolVersion::V2 => {
+            let mut length = [0u8; 4];
+            io.read_exact(&mut length).await?;
+            let length = u32::from_be_bytes(length) as usize;
+            if length > MAX_MESSAGE_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Message of {} bytes is too large", length)));
+            }
+
+            let mut buffer = vec![0u8; length];
+            io.read_exact(&mut buffer).await?;
+            buffer
+        }
+    };
+
+    serde_json::from_slice(&buffer)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Write one message in the format of the negotiated protocol version
+async fn write_message<T, M>(version: ProtocolVersion, io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let bytes = serde_json::to_vec(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    if version >= ProtocolVersion::V2 {
+        let length = u32::try_from(bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Message is too large"))?;
+        io.write_all(&length.to_be_bytes()).await?;
     }
+    io.write_all(&bytes).await
 }
@@ -0,0 +1,219 @@
+use anyhow::Result;
+use git2::Repository;
+use libp2p::{Multiaddr, PeerId};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::service::RealNetworkService;
+use crate::git::repository::RepositoryManager;
+use crate::utils::config::{BackoffConfig, GitConfig, NetworkConfig};
+
+/// File in the rendezvous repository listing peers, one `peer_id,address;address` per line
+pub const BOOTSTRAP_FILE: &str = "bootstrap.txt";
+
+/// How often this node's entry in the bootstrap file is refreshed
+pub const RENDEZVOUS_UPDATE_INTERVAL: Duration = Duration::from_secs(300);
+
+// The rendezvous repository is cloned next to the document repositories, under an ID no
+// document is ever given
+const RENDEZVOUS_REPO_ID: Uuid = Uuid::nil();
+
+/// A peer listed in the bootstrap file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootstrapEntry {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+}
+
+impl BootstrapEntry {
+    /// Parse a `peer_id,address;address` line, or `None` if it isn't one
+    pub fn parse(line: &str) -> Option<Self> {
+        let (peer_id, addresses) = line.trim().split_once(',')?;
+        let peer_id = peer_id.parse().ok()?;
+        let addresses = addresses
+            .split(';')
+            .filter_map(|address| address.trim().parse().ok())
+            .collect();
+
+        Some(Self { peer_id, addresses })
+    }
+
+    /// Format the entry as a line of the bootstrap file
+    pub fn to_line(&self) -> String {
+        let addresses: Vec<String> = self.addresses.iter().map(|address| address.to_string()).collect();
+        format!("{},{}", self.peer_id, addresses.join(";"))
+    }
+}
+
+/// Peer rendezvous through a shared Git repository
+///
+/// Nodes pointed at the same repository dial the peers its bootstrap file lists, and add
+/// their own addresses to it, so the repository stands in for a bootstrap server.
+#[derive(Clone)]
+pub struct GitRendezvous {
+    repo_manager: RepositoryManager,
+    repo_url: String,
+    external_addresses: Vec<Multiaddr>,
+    /// Delays between attempts to read the bootstrap file while it can't be fetched
+    retry_backoff: BackoffConfig,
+}
+
+// Written out so the Git configuration, and its token, stay out of debug output
+impl std::fmt::Debug for GitRendezvous {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GitRendezvous")
+            .field("repo_url", &self.repo_url)
+            .field("external_addresses", &self.external_addresses)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GitRendezvous {
+    pub fn new(repo_manager: RepositoryManager, repo_url: String) -> Self {
+        Self {
+            repo_manager,
+            repo_url,
+            external_addresses: Vec::new(),
+            retry_backoff: BackoffConfig::default(),
+        }
+    }
+
+    /// Rendezvous through the configured bootstrap repository, if there is one
+    pub fn from_config(network: &NetworkConfig, git: &GitConfig) -> Option<Self> {
+        let repo_url = network.bootstrap_repo_url.clone()?;
+        let mut rendezvous = Self::new(RepositoryManager::new(git.clone()), repo_url);
+        rendezvous.external_addresses = network
+            .external_addresses
+            .iter()
+            .filter_map(|address| address.parse().ok())
+            .collect();
+        rendezvous.retry_backoff = network.redial_backoff.clone();
+
+        Some(rendezvous)
+    }
+
+    /// Clone or open the repository, returning the remote's latest commit as well
+    fn open(&self) -> Result<(Repository, Option<git2::Oid>)> {
+        let repo = self.repo_manager.clone_or_open(&self.repo_url, &RENDEZVOUS_REPO_ID)?;
+        let remote = self.repo_manager.fetch(&repo)?;
+        Ok((repo, remote))
+    }
+
+    /// The peers listed in the remote's bootstrap file, skipping lines that don't parse
+    pub fn read_peers(&self) -> Result<Vec<BootstrapEntry>> {
+        let (repo, remote) = self.open()?;
+        let Some(remote) = remote else {
+            return Ok(Vec::new());
+        };
+
+        let content = self.repo_manager.read_file_at(&repo, remote, BOOTSTRAP_FILE)?;
+        Ok(content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| {
+                let entry = BootstrapEntry::parse(line);
+                if entry.is_none() {
+                    tracing::warn!("Ignoring malformed bootstrap entry {:?}", line);
+                }
+                entry
+            })
+            .collect())
+    }
+
+    /// Dial every address in the bootstrap file other than our own, returning the number of
+    /// dials started
+    pub async fn dial_peers(&self, service: &RealNetworkService) -> Result<usize> {
+        let rendezvous = self.clone();
+        let entries = tokio::task::spawn_blocking(move || rendezvous.read_peers()).await??;
+
+        let mut dialed = 0;
+        for entry in entries {
+            if entry.peer_id == service.local_peer_id {
+                continue;
+            }
+            for address in entry.addresses {
+                match service.dial(address.clone()).await {
+                    Ok(()) => dialed += 1,
+                    Err(e) => tracing::warn!("Failed to dial bootstrap peer {} at {}: {}", entry.peer_id, address, e),
+                }
+            }
+        }
+
+        Ok(dialed)
+    }
+
+    /// Replace this node's entry in the bootstrap file with its current addresses and push it
+    pub fn publish(&self, peer_id: PeerId, addresses: Vec<Multiaddr>) -> Result<()> {
+        let (repo, remote) = self.open()?;
+        let current = match remote {
+            Some(remote) => self.repo_manager.read_file_at(&repo, remote, BOOTSTRAP_FILE)?,
+            None => String::new(),
+        };
+
+        let mut lines: Vec<String> = current
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter(|line| BootstrapEntry::parse(line).is_none_or(|entry| entry.peer_id != peer_id))
+            .map(str::to_string)
+            .collect();
+        lines.push(BootstrapEntry { peer_id, addresses }.to_line());
+        let content = lines.join("\n");
+
+        let message = format!("Update bootstrap entry of {}", peer_id);
+        match remote {
+            Some(remote) => {
+                self.repo_manager.merge_remote(&repo, remote, BOOTSTRAP_FILE, &content, &message)?;
+                self.repo_manager.push(&repo)
+            }
+            None => self.repo_manager.save_document(&repo, &content, BOOTSTRAP_FILE, &message).map(|_| ()),
+        }
+    }
+
+    /// Addresses other nodes can reach this one at: the configured external addresses, or
+    /// else whatever the service listens on
+    async fn local_addresses(&self, service: &RealNetworkService) -> Vec<Multiaddr> {
+        if self.external_addresses.is_empty() {
+            service.listen_addresses().await
+        } else {
+            self.external_addresses.clone()
+        }
+    }
+
+    /// Dial the peers in the bootstrap file, retrying until it can be read, then keep this
+    /// node's entry in it up to date
+    pub fn start(self, service: Arc<RealNetworkService>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut backoff = self.retry_backoff.backoff();
+            loop {
+                match self.dial_peers(&service).await {
+                    Ok(dialed) => {
+                        tracing::info!("Dialed {} peers from {}", dialed, self.repo_url);
+                        break;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to read bootstrap peers from {}: {}", self.repo_url, e);
+                        tokio::time::sleep(backoff.next_delay()).await;
+                    }
+                }
+            }
+
+            let mut interval = tokio::time::interval(RENDEZVOUS_UPDATE_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                let addresses = self.local_addresses(&service).await;
+                if addresses.is_empty() {
+                    continue;
+                }
+                let rendezvous = self.clone();
+                let peer_id = service.local_peer_id;
+                match tokio::task::spawn_blocking(move || rendezvous.publish(peer_id, addresses)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => tracing::warn!("Failed to update bootstrap entry in {}: {}", self.repo_url, e),
+                    Err(e) => tracing::warn!("Bootstrap entry update in {} panicked: {}", self.repo_url, e),
+                }
+            }
+        })
+    }
+}
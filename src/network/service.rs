@@ -1,19 +1,22 @@
 use anyhow::Result;
 use futures::StreamExt;
 use libp2p::{
-    gossipsub::{self, self as gossipsub_mod, MessageAuthenticity},
+    gossipsub::{self, self as gossipsub_mod, MessageAuthenticity, PublishError},
     identity, noise, yamux,
     request_response::{self, self as request_response_mod, ProtocolSupport},
     swarm::{self, SwarmEvent, keep_alive},
     tcp, Multiaddr, PeerId, Transport,
 };
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
 
+use super::access::PeerAccess;
 use super::protocol::{CollabCodec, CollabProtocol, CollabRequest, CollabResponse, NetworkMessage};
-use crate::utils::config::NetworkConfig;
+use crate::utils::backoff::ExponentialBackoff;
+use crate::utils::config::{BackoffConfig, NetworkConfig};
 use crate::utils::errors::AppError;
 
 /// Network events that can be sent to the application
@@ -44,21 +47,89 @@ pub enum NetworkEvent {
         source: PeerId,
         response: CollabResponse,
     },
+    /// A request failed or timed out before the peer responded
+    RequestFailed {
+        request_id: String,
+        peer: PeerId,
+        error: String,
+    },
+}
+
+/// An outbound request that is still waiting for a response
+#[derive(Debug, Clone)]
+struct PendingRequest {
+    /// Caller-supplied request ID
+    id: String,
+    /// Peer the request was sent to
+    peer: PeerId,
 }
 
+type PendingRequests = Arc<Mutex<HashMap<request_response_mod::RequestId, PendingRequest>>>;
+
+/// Gossipsub health of a subscribed topic
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicMetrics {
+    /// Topic name
+    pub topic: String,
+    /// Peers in our mesh for the topic; with none, nothing we publish propagates
+    pub mesh_peers: usize,
+    /// Peers known to subscribe to the topic, whether in the mesh or not
+    pub topic_peers: usize,
+    /// Messages published
+    pub published: u64,
+    /// Messages received
+    pub received: u64,
+    /// Publishes rejected as duplicates of a message recently published
+    pub duplicates: u64,
+    /// Messages received but not passed on, as they don't say who published them
+    pub ignored: u64,
+    /// Publishes that failed otherwise, e.g. for lack of peers to send to
+    pub publish_failures: u64,
+}
+
+/// Message counts of a topic, kept from its first subscription on
+#[derive(Debug, Clone, Copy, Default)]
+struct TopicCounters {
+    published: u64,
+    received: u64,
+    duplicates: u64,
+    ignored: u64,
+    publish_failures: u64,
+}
+
+/// Error a request fails with when the peer shares no protocol version with us
+pub const INCOMPATIBLE_VERSION: &str = "incompatible protocol version";
+
+/// How long the event loop waits for a swarm event before releasing the swarm lock
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often idle connections are checked for
+const IDLE_SWEEP_INTERVAL: Duration = Duration::from_millis(500);
+
 /// Network service for P2P communication
 pub struct RealNetworkService {
     /// libp2p Swarm
     swarm: Arc<Mutex<swarm::Swarm<MyBehaviour>>>,
     /// Local peer ID
     pub local_peer_id: PeerId,
-    /// Subscribed topics
-    subscribed_topics: Arc<Mutex<HashSet<String>>>,
-    /// Sender for network events
-    #[allow(dead_code)]
-    event_sender: mpsc::Sender<NetworkEvent>,
-    /// Mapping of request IDs to string IDs for tracking responses
-    request_ids: Arc<Mutex<HashMap<request_response_mod::RequestId, String>>>,
+    /// Subscribed topics, with the number of subscribers holding each one
+    subscribed_topics: Arc<Mutex<HashMap<String, usize>>>,
+    /// Message counts per topic name
+    topic_counters: Arc<Mutex<HashMap<String, TopicCounters>>>,
+    /// Sender for network events, set once the event loop is running
+    event_sender: Arc<Mutex<Option<mpsc::Sender<NetworkEvent>>>>,
+    /// Outstanding requests, keyed by libp2p request ID
+    request_ids: PendingRequests,
+    /// How long to wait for a response before failing a request
+    request_timeout: Duration,
+    /// How long a peer sharing no documents may stay connected, if limited
+    idle_timeout: Option<Duration>,
+    /// Which peers may stay connected
+    access: PeerAccess,
+    /// Bootstrap nodes, dialed again whenever we aren't connected to them
+    bootstrap_nodes: Vec<(PeerId, Multiaddr)>,
+    /// Delays between attempts to dial a bootstrap node
+    redial_backoff: BackoffConfig,
 }
 
 impl std::fmt::Debug for RealNetworkService {
@@ -104,6 +175,8 @@ impl From<void::Void> for MyBehaviourEvent {
 impl RealNetworkService {
     /// Create a new network service with the given configuration
     pub async fn new(config: NetworkConfig) -> Result<Self> {
+        let access = PeerAccess::from_config(&config);
+
         // Create a keypair for the local node
         let local_key = if let Some(seed) = config.peer_id_seed {
             // Generate deterministic key from seed
@@ -117,16 +190,19 @@ impl RealNetworkService {
         };
 
         let local_peer_id = PeerId::from(local_key.public());
-
-        // Create event channel
-        let (event_sender, _event_receiver) = mpsc::channel(100);
+        let request_timeout = Duration::from_secs(config.request_timeout_secs);
 
         // Create request-response protocol
-        let protocols = vec![(CollabProtocol, ProtocolSupport::Full)];
+        let protocols: Vec<_> = CollabProtocol::advertised(&config.protocol_versions)
+            .into_iter()
+            .map(|protocol| (protocol, ProtocolSupport::Full))
+            .collect();
+        let mut request_response_config = request_response::Config::default();
+        request_response_config.set_request_timeout(request_timeout);
         let request_response = request_response_mod::Behaviour::new(
             CollabCodec,
             protocols.into_iter(),
-            request_response::Config::default()
+            request_response_config
         );
 
         // Create gossipsub protocol
@@ -170,33 +246,64 @@ impl RealNetworkService {
         }
 
         // Connect to bootstrap nodes
-        for node in &config.bootstrap_nodes {
-            if let Ok((peer_id, addr)) = parse_peer_and_addr(node) {
-                if let Err(e) = swarm.dial(addr) {
-                    tracing::warn!("Failed to dial bootstrap node {}: {}", peer_id, e);
-                }
+        let bootstrap_nodes: Vec<_> = config.bootstrap_nodes
+            .iter()
+            .filter_map(|node| parse_peer_and_addr(node).ok())
+            .collect();
+        for (peer_id, addr) in &bootstrap_nodes {
+            if let Err(e) = swarm.dial(addr.clone()) {
+                tracing::warn!("Failed to dial bootstrap node {}: {}", peer_id, e);
             }
         }
 
         Ok(Self {
             swarm: Arc::new(Mutex::new(swarm)),
             local_peer_id,
-            subscribed_topics: Arc::new(Mutex::new(HashSet::new())),
-            event_sender,
+            subscribed_topics: Arc::new(Mutex::new(HashMap::new())),
+            topic_counters: Arc::new(Mutex::new(HashMap::new())),
+            event_sender: Arc::new(Mutex::new(None)),
             request_ids: Arc::new(Mutex::new(HashMap::new())),
+            request_timeout,
+            idle_timeout: config.connection_idle_timeout_secs.map(Duration::from_secs),
+            access,
+            bootstrap_nodes,
+            redial_backoff: config.redial_backoff,
         })
     }
 
     /// Start the network service event loop
     pub async fn start_event_loop(self: Arc<Self>) -> Result<mpsc::Receiver<NetworkEvent>> {
         let (event_sender, event_receiver) = mpsc::channel(100);
+        *self.event_sender.lock().await = Some(event_sender.clone());
         let service_clone = self.clone();
 
+        for (peer_id, addr) in self.bootstrap_nodes.clone() {
+            tokio::spawn(Arc::clone(&self).redial_bootstrap_node(peer_id, addr, self.redial_backoff.backoff()));
+        }
+
         tokio::spawn(async move {
+            let mut idle_since = HashMap::new();
+            let mut seen_peers = HashSet::new();
+            let mut last_sweep = Instant::now();
+
             loop {
+                // Wait for the next event, but release the swarm regularly so requests,
+                // publishes and dials from other tasks aren't starved
                 let event = {
                     let mut swarm = service_clone.swarm.lock().await;
-                    swarm.select_next_some().await
+                    tokio::select! {
+                        event = swarm.select_next_some() => Some(event),
+                        _ = tokio::time::sleep(EVENT_POLL_INTERVAL) => None,
+                    }
+                };
+
+                if last_sweep.elapsed() >= IDLE_SWEEP_INTERVAL {
+                    service_clone.close_idle_connections(&mut idle_since).await;
+                    last_sweep = Instant::now();
+                }
+
+                let Some(event) = event else {
+                    continue;
                 };
 
                 match event {
@@ -207,15 +314,22 @@ impl RealNetworkService {
                                 message_id: _,
                                 message,
                             } => {
-                                if let Some(source_peer) = message.source {
-                                    let topic_str = message.topic.to_string();
-                                    if let Err(e) = event_sender.send(NetworkEvent::MessageReceived {
+                                let topic_str = service_clone.topic_name(&message.topic).await;
+                                service_clone.count(&topic_str, |counters| {
+                                    counters.received += 1;
+                                    if message.source.is_none() {
+                                        counters.ignored += 1;
+                                    }
+                                }).await;
+
+                                if let Some(source_peer) = message.source
+                                    && let Err(e) = event_sender.send(NetworkEvent::MessageReceived {
                                         source: source_peer,
                                         topic: topic_str,
                                         data: message.data,
-                                    }).await {
-                                        tracing::error!("Failed to send gossipsub message event: {}", e);
-                                    }
+                                    }).await
+                                {
+                                    tracing::error!("Failed to send gossipsub message event: {}", e);
                                 }
                             },
                             _ => {}
@@ -248,8 +362,11 @@ impl RealNetworkService {
                                 }
                             } => {
                                 let string_id = {
-                                    let request_ids = service_clone.request_ids.lock().await;
-                                    request_ids.get(&request_id).cloned().unwrap_or_else(|| request_id.to_string())
+                                    let mut request_ids = service_clone.request_ids.lock().await;
+                                    request_ids
+                                        .remove(&request_id)
+                                        .map(|pending| pending.id)
+                                        .unwrap_or_else(|| request_id.to_string())
                                 };
 
                                 if let Err(e) = event_sender.send(NetworkEvent::ResponseReceived {
@@ -260,14 +377,56 @@ impl RealNetworkService {
                                     tracing::error!("Failed to send response event: {}", e);
                                 }
                             },
+                            request_response_mod::Event::OutboundFailure {
+                                peer,
+                                request_id,
+                                error: request_response_mod::OutboundFailure::UnsupportedProtocols,
+                            } => {
+                                // Never try to parse messages from a peer we share no version with
+                                tracing::warn!("Peer {} speaks no compatible protocol version", peer);
+                                fail_request(
+                                    &service_clone.request_ids,
+                                    &service_clone.event_sender,
+                                    request_id,
+                                    INCOMPATIBLE_VERSION.to_string(),
+                                ).await;
+                            },
+                            request_response_mod::Event::InboundFailure {
+                                peer,
+                                error: request_response_mod::InboundFailure::UnsupportedProtocols,
+                                ..
+                            } => {
+                                tracing::warn!("Rejected request from peer {} with no compatible protocol version", peer);
+                            },
+                            request_response_mod::Event::OutboundFailure { request_id, error, .. } => {
+                                fail_request(
+                                    &service_clone.request_ids,
+                                    &service_clone.event_sender,
+                                    request_id,
+                                    error.to_string(),
+                                ).await;
+                            },
                             _ => {}
                         }
                     },
-                    SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                    SwarmEvent::ConnectionEstablished { peer_id, num_established, .. } => {
+                        // Peers that aren't admitted are dropped before anything else sees them
+                        if !service_clone.access.permits(&peer_id) {
+                            tracing::warn!("Rejected connection from peer {}, which is not allowed", peer_id);
+                            let _ = service_clone.swarm.lock().await.disconnect_peer_id(peer_id);
+                            continue;
+                        }
+                        // A peer coming back may find no mesh for our topics, so announce them afresh.
+                        // New peers are sent our topics anyway, and rejoining them would get the mesh
+                        // they are being grafted into pruned again.
+                        if num_established.get() == 1 && !seen_peers.insert(peer_id) {
+                            service_clone.resubscribe_topics().await;
+                        }
                         if let Err(e) = event_sender.send(NetworkEvent::PeerConnected(peer_id)).await {
                             tracing::error!("Failed to send peer connected event: {}", e);
                         }
                     },
+                    SwarmEvent::ConnectionClosed { peer_id, .. } if !service_clone.access.permits(&peer_id) => {},
                     SwarmEvent::ConnectionClosed { peer_id, .. } => {
                         if let Err(e) = event_sender.send(NetworkEvent::PeerDisconnected(peer_id)).await {
                             tracing::error!("Failed to send peer disconnected event: {}", e);
@@ -287,18 +446,67 @@ impl RealNetworkService {
     /// Publish a message to a topic
     pub async fn publish_to_topic(&self, topic_str: String, data: Vec<u8>) -> Result<()> {
         // Create a topic hash from the string
-        let topic = gossipsub_mod::Sha256Topic::new(topic_str);
-        let mut swarm = self.swarm.lock().await;
+        let topic = gossipsub_mod::Sha256Topic::new(topic_str.as_str());
+        let result = self.swarm.lock().await.behaviour_mut().gossipsub.publish(topic, data);
+
+        self.count(&topic_str, |counters| match &result {
+            Ok(_) => counters.published += 1,
+            Err(PublishError::Duplicate) => counters.duplicates += 1,
+            Err(_) => counters.publish_failures += 1,
+        }).await;
 
-        if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic, data) {
+        if let Err(e) = result {
             return Err(anyhow::anyhow!(AppError::NetworkError(format!("Failed to publish to topic: {}", e))));
         }
 
         Ok(())
     }
 
+    /// Update a topic's message counts
+    async fn count(&self, topic_str: &str, update: impl FnOnce(&mut TopicCounters)) {
+        let mut counters = self.topic_counters.lock().await;
+        update(counters.entry(topic_str.to_string()).or_default());
+    }
+
+    /// Mesh health and message counts of every subscribed topic, sorted by topic name
+    pub async fn gossipsub_metrics(&self) -> Vec<TopicMetrics> {
+        let mut topics: Vec<String> = self.subscribed_topics.lock().await.keys().cloned().collect();
+        topics.sort();
+
+        let counters = self.topic_counters.lock().await.clone();
+        let swarm = self.swarm.lock().await;
+        let gossipsub = &swarm.behaviour().gossipsub;
+
+        topics
+            .into_iter()
+            .map(|topic| {
+                let hash = gossipsub_mod::Sha256Topic::new(topic.as_str()).hash();
+                let counts = counters.get(&topic).copied().unwrap_or_default();
+                TopicMetrics {
+                    mesh_peers: gossipsub.mesh_peers(&hash).count(),
+                    topic_peers: gossipsub.all_peers().filter(|(_, topics)| topics.contains(&&hash)).count(),
+                    published: counts.published,
+                    received: counts.received,
+                    duplicates: counts.duplicates,
+                    ignored: counts.ignored,
+                    publish_failures: counts.publish_failures,
+                    topic,
+                }
+            })
+            .collect()
+    }
+
     /// Subscribe to a topic
+    ///
+    /// Subscriptions are reference counted: the gossipsub topic is only joined by the first
+    /// subscriber, and every call must be balanced by a call to `unsubscribe_from_topic`.
     pub async fn subscribe_to_topic(&self, topic_str: String) -> Result<()> {
+        let mut topics = self.subscribed_topics.lock().await;
+        if let Some(count) = topics.get_mut(&topic_str) {
+            *count += 1;
+            return Ok(());
+        }
+
         let topic = gossipsub_mod::Sha256Topic::new(topic_str.clone());
         let mut swarm = self.swarm.lock().await;
 
@@ -306,15 +514,24 @@ impl RealNetworkService {
             return Err(anyhow::anyhow!(AppError::NetworkError(format!("Failed to subscribe to topic: {}", e))));
         }
 
-        // Add to subscribed topics
-        let mut topics = self.subscribed_topics.lock().await;
-        topics.insert(topic_str);
+        topics.insert(topic_str, 1);
 
         Ok(())
     }
 
     /// Unsubscribe from a topic
+    ///
+    /// The gossipsub topic is only left once its last subscriber has unsubscribed.
     pub async fn unsubscribe_from_topic(&self, topic_str: String) -> Result<()> {
+        let mut topics = self.subscribed_topics.lock().await;
+        let Some(count) = topics.get_mut(&topic_str) else {
+            return Err(anyhow::anyhow!(AppError::NetworkError(format!("Not subscribed to topic: {}", topic_str))));
+        };
+        if *count > 1 {
+            *count -= 1;
+            return Ok(());
+        }
+
         let topic = gossipsub_mod::Sha256Topic::new(topic_str.clone());
         let mut swarm = self.swarm.lock().await;
 
@@ -322,13 +539,45 @@ impl RealNetworkService {
             return Err(anyhow::anyhow!(AppError::NetworkError(format!("Failed to unsubscribe from topic: {}", e))));
         }
 
-        // Remove from subscribed topics
-        let mut topics = self.subscribed_topics.lock().await;
         topics.remove(&topic_str);
 
         Ok(())
     }
 
+    /// Leave and rejoin every subscribed gossipsub topic, so peers rebuild their mesh for it
+    ///
+    /// Subscriber counts are left as they are.
+    pub async fn resubscribe_topics(&self) {
+        let topics = self.subscribed_topics.lock().await;
+        let mut swarm = self.swarm.lock().await;
+
+        for topic_str in topics.keys() {
+            let topic = gossipsub_mod::Sha256Topic::new(topic_str.as_str());
+            let gossipsub = &mut swarm.behaviour_mut().gossipsub;
+            let _ = gossipsub.unsubscribe(&topic);
+            if let Err(e) = gossipsub.subscribe(&topic) {
+                tracing::warn!("Failed to resubscribe to topic {}: {}", topic_str, e);
+            }
+        }
+    }
+
+    /// Name of a subscribed topic, given the hash gossipsub identifies it by
+    ///
+    /// Falls back to the hash itself for topics this node is not subscribed to.
+    async fn topic_name(&self, hash: &gossipsub_mod::TopicHash) -> String {
+        let topics = self.subscribed_topics.lock().await;
+        topics
+            .keys()
+            .find(|name| gossipsub_mod::Sha256Topic::new(name.as_str()).hash() == *hash)
+            .cloned()
+            .unwrap_or_else(|| hash.to_string())
+    }
+
+    /// Whether the gossipsub topic is currently subscribed
+    pub async fn is_subscribed(&self, topic_str: &str) -> bool {
+        self.subscribed_topics.lock().await.contains_key(topic_str)
+    }
+
     /// Send a request to a peer
     pub async fn send_request(
         &self,
@@ -343,13 +592,121 @@ impl RealNetworkService {
             CollabRequest(request),
         );
 
-        // Store the request ID mapping
-        let mut request_ids = self.request_ids.lock().await;
-        request_ids.insert(outbound_id, request_id);
+        // Track the request until it is answered, fails or times out
+        self.request_ids.lock().await.insert(outbound_id, PendingRequest {
+            id: request_id,
+            peer: peer_id,
+        });
+
+        let request_ids = Arc::clone(&self.request_ids);
+        let event_sender = Arc::clone(&self.event_sender);
+        let timeout = self.request_timeout;
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            fail_request(&request_ids, &event_sender, outbound_id, "request timed out".to_string()).await;
+        });
 
         Ok(())
     }
 
+    /// Number of requests still waiting for a response
+    pub async fn pending_request_count(&self) -> usize {
+        self.request_ids.lock().await.len()
+    }
+
+    /// Dial a peer at the given address
+    pub async fn dial(&self, addr: Multiaddr) -> Result<()> {
+        let mut swarm = self.swarm.lock().await;
+        swarm
+            .dial(addr)
+            .map_err(|e| anyhow::anyhow!(AppError::NetworkError(format!("Failed to dial: {}", e))))
+    }
+
+    /// Dial a bootstrap node again whenever we aren't connected to it, backing off while the
+    /// dials keep failing
+    async fn redial_bootstrap_node(self: Arc<Self>, peer_id: PeerId, addr: Multiaddr, mut backoff: ExponentialBackoff) {
+        loop {
+            tokio::time::sleep(backoff.next_delay()).await;
+
+            if self.swarm.lock().await.is_connected(&peer_id) {
+                backoff.reset();
+                continue;
+            }
+
+            tracing::debug!("Dialing bootstrap node {} again", peer_id);
+            if let Err(e) = self.dial(addr.clone()).await {
+                tracing::warn!("Failed to dial bootstrap node {}: {}", peer_id, e);
+            }
+        }
+    }
+
+    /// Addresses the swarm is currently listening on
+    pub async fn listen_addresses(&self) -> Vec<Multiaddr> {
+        let swarm = self.swarm.lock().await;
+        swarm.listeners().cloned().collect()
+    }
+
+    /// Close every connection to a peer
+    pub async fn disconnect(&self, peer_id: PeerId) -> Result<()> {
+        let mut swarm = self.swarm.lock().await;
+        swarm
+            .disconnect_peer_id(peer_id)
+            .map_err(|_| anyhow::anyhow!(AppError::NetworkError(format!("Not connected to peer {}", peer_id))))
+    }
+
+    /// Peers with at least one open connection
+    pub async fn connected_peers(&self) -> Vec<PeerId> {
+        let swarm = self.swarm.lock().await;
+        swarm.connected_peers().cloned().collect()
+    }
+
+    /// Disconnect peers that have shared no subscribed topic with us for longer than the idle timeout.
+    ///
+    /// Peers with outstanding requests are never considered idle. Disconnected peers can still
+    /// be dialed again later, e.g. to catch up on a document.
+    async fn close_idle_connections(&self, idle_since: &mut HashMap<PeerId, Instant>) {
+        let Some(timeout) = self.idle_timeout else {
+            return;
+        };
+
+        // Collect these before taking the swarm lock, which other callers take first
+        let subscribed: HashSet<gossipsub_mod::TopicHash> = self
+            .subscribed_topics
+            .lock()
+            .await
+            .keys()
+            .map(|topic| gossipsub_mod::Sha256Topic::new(topic.clone()).hash())
+            .collect();
+        let busy: HashSet<PeerId> = self.request_ids.lock().await.values().map(|p| p.peer).collect();
+
+        let mut swarm = self.swarm.lock().await;
+        let connected: Vec<PeerId> = swarm.connected_peers().cloned().collect();
+        let sharing: HashSet<PeerId> = swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .filter(|(_, topics)| topics.iter().any(|topic| subscribed.contains(*topic)))
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        idle_since.retain(|peer, _| connected.contains(peer));
+
+        let now = Instant::now();
+        for peer in connected {
+            if sharing.contains(&peer) || busy.contains(&peer) {
+                idle_since.remove(&peer);
+                continue;
+            }
+
+            let since = *idle_since.entry(peer).or_insert(now);
+            if now.duration_since(since) >= timeout {
+                tracing::info!("Closing idle connection to {}", peer);
+                let _ = swarm.disconnect_peer_id(peer);
+                idle_since.remove(&peer);
+            }
+        }
+    }
+
     /// Send a response to a request
     pub async fn send_response(
         &self,
@@ -369,6 +726,33 @@ impl RealNetworkService {
     }
 }
 
+/// Stop tracking a request and report its failure, unless it was already answered or failed
+async fn fail_request(
+    request_ids: &PendingRequests,
+    event_sender: &Mutex<Option<mpsc::Sender<NetworkEvent>>>,
+    request_id: request_response_mod::RequestId,
+    error: String,
+) {
+    let Some(pending) = request_ids.lock().await.remove(&request_id) else {
+        return;
+    };
+
+    tracing::warn!("Request {} to {} failed: {}", pending.id, pending.peer, error);
+
+    // Without a running event loop there is nobody to notify
+    let Some(sender) = event_sender.lock().await.clone() else {
+        return;
+    };
+
+    if let Err(e) = sender.send(NetworkEvent::RequestFailed {
+        request_id: pending.id,
+        peer: pending.peer,
+        error,
+    }).await {
+        tracing::error!("Failed to send request failure event: {}", e);
+    }
+}
+
 /// Parse a peer ID and multiaddress from a string like "/ip4/127.0.0.1/tcp/4001/p2p/QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N"
 fn parse_peer_and_addr(addr_str: &str) -> Result<(PeerId, Multiaddr)> {
     let mut addr = addr_str.parse::<Multiaddr>()?;
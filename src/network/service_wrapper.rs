@@ -14,7 +14,6 @@ pub enum NetworkServiceWrapper {
     Mock(super::engine::NetworkService),
 
     /// Real implementation for production
-    #[allow(dead_code)]
     Real(Arc<super::service::RealNetworkService>, mpsc::Receiver<super::service::NetworkEvent>),
 }
 
@@ -56,6 +55,8 @@ impl NetworkServiceWrapper {
                                 super::engine::NetworkEvent::RequestReceived { request_id, source, request, channel },
                             super::service::NetworkEvent::ResponseReceived { request_id, source, response } =>
                                 super::engine::NetworkEvent::ResponseReceived { request_id, source, response },
+                            super::service::NetworkEvent::RequestFailed { request_id, peer, error } =>
+                                super::engine::NetworkEvent::RequestFailed { request_id, peer, error },
                         };
 
                         if let Err(e) = tx.send(converted_event).await {
@@ -94,6 +95,22 @@ impl NetworkServiceWrapper {
         }
     }
 
+    /// Rejoin every subscribed topic
+    pub async fn resubscribe_topics(&mut self) {
+        match self {
+            NetworkServiceWrapper::Mock(service) => service.resubscribe_topics().await,
+            NetworkServiceWrapper::Real(service, _) => service.resubscribe_topics().await,
+        }
+    }
+
+    /// Gossipsub mesh health and message counts of each subscribed topic
+    pub async fn gossipsub_metrics(&self) -> Vec<super::service::TopicMetrics> {
+        match self {
+            NetworkServiceWrapper::Mock(service) => service.gossipsub_metrics().await,
+            NetworkServiceWrapper::Real(service, _) => service.gossipsub_metrics().await,
+        }
+    }
+
     /// Send a request to a peer
     pub async fn send_request(
         &mut self,
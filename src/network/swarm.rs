@@ -156,7 +156,9 @@ impl NetworkService {
         let request_protocol_config = request_response::Config::default();
         let request_response = RequestResponseBehaviour::new(
             CollabCodec,
-            [(CollabProtocol, ProtocolSupport::Full)],
+            CollabProtocol::advertised(&config.protocol_versions)
+                .into_iter()
+                .map(|protocol| (protocol, ProtocolSupport::Full)),
             request_protocol_config,
         );
 
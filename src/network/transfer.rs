@@ -0,0 +1,252 @@
+use anyhow::Result;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::utils::errors::AppError;
+
+/// Largest slice of an oplog sent in one chunk
+pub const TRANSFER_CHUNK_SIZE: usize = 256 * 1024;
+
+/// How long an unfinished transfer is kept without any progress, waiting to be resumed
+pub const TRANSFER_TTL: Duration = Duration::from_secs(600);
+
+/// Largest oplog accepted from a peer unless configured otherwise, the default document size limit
+pub const DEFAULT_MAX_TRANSFER_BYTES: usize = 50 * 1024 * 1024;
+
+/// Where a chunk belongs in the oplog being transferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkHeader {
+    pub transfer_id: Uuid,
+    /// Byte offset of the chunk in the oplog
+    pub offset: usize,
+    /// Length of the whole oplog
+    pub total: usize,
+}
+
+/// An oplog being sent to a peer
+#[derive(Debug)]
+struct OutgoingTransfer {
+    document_id: Uuid,
+    data: Vec<u8>,
+    /// Everything before this offset has been acknowledged by the receiver
+    acked: usize,
+    last_activity: Instant,
+}
+
+/// Oplogs being sent in chunks, kept until the receiver has acknowledged all of them so an
+/// interrupted transfer resumes from the last acknowledged offset
+#[derive(Debug, Default)]
+pub struct OutgoingTransfers {
+    transfers: DashMap<Uuid, OutgoingTransfer>,
+}
+
+impl OutgoingTransfers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start sending a document's oplog, returning the ID its chunks carry
+    pub fn start(&self, document_id: Uuid, data: Vec<u8>) -> Uuid {
+        let transfer_id = Uuid::new_v4();
+        self.transfers.insert(transfer_id, OutgoingTransfer {
+            document_id,
+            data,
+            acked: 0,
+            last_activity: Instant::now(),
+        });
+        transfer_id
+    }
+
+    /// The first chunk the receiver hasn't acknowledged, or `None` once the transfer is
+    /// complete or unknown
+    pub fn next_chunk(&self, transfer_id: &Uuid) -> Option<(ChunkHeader, Vec<u8>)> {
+        let transfer = self.transfers.get(transfer_id)?;
+        if transfer.acked >= transfer.data.len() {
+            return None;
+        }
+
+        let end = (transfer.acked + TRANSFER_CHUNK_SIZE).min(transfer.data.len());
+        let header = ChunkHeader {
+            transfer_id: *transfer_id,
+            offset: transfer.acked,
+            total: transfer.data.len(),
+        };
+        Some((header, transfer.data[transfer.acked..end].to_vec()))
+    }
+
+    /// Document whose oplog a transfer carries, or `None` once it is complete or unknown
+    pub fn document_id(&self, transfer_id: &Uuid) -> Option<Uuid> {
+        self.transfers.get(transfer_id).map(|transfer| transfer.document_id)
+    }
+
+    /// Offset the receiver has acknowledged everything before, or `None` once the transfer is
+    /// complete or unknown
+    pub fn acked(&self, transfer_id: &Uuid) -> Option<usize> {
+        self.transfers.get(transfer_id).map(|transfer| transfer.acked)
+    }
+
+    /// Record the receiver's acknowledgement of everything before `offset`, returning whether
+    /// the transfer is now complete
+    ///
+    /// A complete transfer is forgotten. Stale acknowledgements never move the offset back.
+    pub fn acknowledge(&self, transfer_id: &Uuid, offset: usize) -> Result<bool> {
+        let complete = {
+            let mut transfer = self.transfers.get_mut(transfer_id).ok_or_else(|| {
+                anyhow::anyhow!(AppError::NetworkError(format!("Unknown transfer {}", transfer_id)))
+            })?;
+            if offset > transfer.data.len() {
+                return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                    "Acknowledged offset {} is past the end of transfer {}",
+                    offset, transfer_id
+                ))));
+            }
+
+            transfer.acked = transfer.acked.max(offset);
+            transfer.last_activity = Instant::now();
+            transfer.acked == transfer.data.len()
+        };
+
+        if complete {
+            self.transfers.remove(transfer_id);
+        }
+        Ok(complete)
+    }
+
+    /// Forget transfers that made no progress for longer than the TTL, as of `now`, returning
+    /// how many were dropped
+    pub fn expire(&self, now: Instant) -> usize {
+        let before = self.transfers.len();
+        self.transfers
+            .retain(|_, transfer| now.saturating_duration_since(transfer.last_activity) < TRANSFER_TTL);
+        before - self.transfers.len()
+    }
+
+    /// Number of transfers still in progress
+    pub fn len(&self) -> usize {
+        self.transfers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transfers.is_empty()
+    }
+}
+
+/// What a receiver makes of one chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkReceipt {
+    /// Offset to acknowledge: everything before it has been received
+    pub acked: usize,
+    /// The whole oplog, once the last chunk is in
+    pub complete: Option<Vec<u8>>,
+}
+
+/// An oplog being received
+#[derive(Debug)]
+struct IncomingTransfer {
+    data: Vec<u8>,
+    total: usize,
+    last_activity: Instant,
+}
+
+/// Oplogs being received in chunks, kept across reconnections so the sender only has to
+/// resend what wasn't acknowledged
+#[derive(Debug)]
+pub struct IncomingTransfers {
+    transfers: DashMap<Uuid, IncomingTransfer>,
+    /// Largest oplog accepted, so a peer can't have us reserve whatever it likes
+    max_total: usize,
+}
+
+impl Default for IncomingTransfers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IncomingTransfers {
+    pub fn new() -> Self {
+        Self {
+            transfers: DashMap::new(),
+            max_total: DEFAULT_MAX_TRANSFER_BYTES,
+        }
+    }
+
+    /// Reject transfers of oplogs longer than `max_total` bytes
+    pub fn with_max_total(mut self, max_total: usize) -> Self {
+        self.max_total = max_total;
+        self
+    }
+
+    /// Add a chunk to its transfer
+    ///
+    /// Chunks already received are acknowledged again without being stored twice; a chunk
+    /// that leaves a gap or lies outside the transfer is rejected, so the sender falls back
+    /// to the last acknowledged offset.
+    pub fn receive(&self, header: ChunkHeader, chunk: &[u8]) -> Result<ChunkReceipt> {
+        if header.total > self.max_total {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Transfer {} of {} bytes is over the {} byte limit",
+                header.transfer_id, header.total, self.max_total
+            ))));
+        }
+        if header.offset.checked_add(chunk.len()).is_none_or(|end| end > header.total) {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Chunk at offset {} overruns transfer {} of {} bytes",
+                header.offset, header.transfer_id, header.total
+            ))));
+        }
+
+        let mut transfer = self.transfers.entry(header.transfer_id).or_insert_with(|| IncomingTransfer {
+            // Grown as chunks arrive rather than reserved up front for a transfer that may never finish
+            data: Vec::with_capacity(chunk.len()),
+            total: header.total,
+            last_activity: Instant::now(),
+        });
+        if transfer.total != header.total {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Transfer {} changed length from {} to {}",
+                header.transfer_id, transfer.total, header.total
+            ))));
+        }
+
+        let received = transfer.data.len();
+        if header.offset > received {
+            return Err(anyhow::anyhow!(AppError::ProtocolError(format!(
+                "Chunk at offset {} leaves a gap after {} bytes of transfer {}",
+                header.offset, received, header.transfer_id
+            ))));
+        }
+
+        // Only the part of the chunk we don't have yet is new
+        let skip = received - header.offset;
+        if skip < chunk.len() {
+            transfer.data.extend_from_slice(&chunk[skip..]);
+        }
+        transfer.last_activity = Instant::now();
+
+        let acked = transfer.data.len();
+        if acked < transfer.total {
+            return Ok(ChunkReceipt { acked, complete: None });
+        }
+
+        drop(transfer);
+        let complete = self.transfers.remove(&header.transfer_id).map(|(_, transfer)| transfer.data);
+        Ok(ChunkReceipt { acked, complete })
+    }
+
+    /// Offset an interrupted transfer should resume from, or `None` if it is unknown
+    pub fn resume_offset(&self, transfer_id: &Uuid) -> Option<usize> {
+        self.transfers.get(transfer_id).map(|transfer| transfer.data.len())
+    }
+
+    /// Forget transfers that made no progress for longer than the TTL, as of `now`, returning
+    /// how many were dropped
+    pub fn expire(&self, now: Instant) -> usize {
+        let before = self.transfers.len();
+        self.transfers
+            .retain(|_, transfer| now.saturating_duration_since(transfer.last_activity) < TRANSFER_TTL);
+        before - self.transfers.len()
+    }
+}
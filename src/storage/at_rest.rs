@@ -0,0 +1,354 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::Result;
+use argon2::Argon2;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::crdt::agent_map::AgentMap;
+use crate::crdt::attribution::Attribution;
+use crate::crdt::document::Document;
+use crate::utils::atomic_file;
+use crate::utils::config::StorageConfig;
+use crate::utils::errors::AppError;
+
+/// Marks a file written by `AtRestCodec`; files without it are read as plain OpLogs
+const MAGIC: &[u8; 4] = b"TXSW";
+
+/// Version of the header that follows the magic bytes
+///
+/// Version 1 files derived their key from a plain SHA-256 of the passphrase; they are still
+/// read, but only ever written as version 2, which stores the salt of an Argon2 key.
+const FORMAT_VERSION: u8 = 2;
+const LEGACY_FORMAT_VERSION: u8 = 1;
+
+const FLAG_COMPRESSED: u8 = 0b01;
+const FLAG_ENCRYPTED: u8 = 0b10;
+
+const HEADER_LEN: usize = MAGIC.len() + 2;
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+
+/// Transforms applied to document files before they are written to disk
+///
+/// Data is compressed first and then encrypted. Every file records which transforms were
+/// applied in its header, so it can be read back whatever the current settings are, as long
+/// as the passphrase is available. Encrypted files also store the random salt their key was
+/// derived with.
+#[derive(Clone)]
+pub struct AtRestCodec {
+    compress: bool,
+    passphrase: Option<Arc<str>>,
+    // Salt of the key this codec encrypts with, drawn when the codec is created
+    salt: [u8; SALT_LEN],
+    // Keys derived from the passphrase so far, by salt, as deriving one is slow on purpose
+    keys: Arc<Mutex<HashMap<[u8; SALT_LEN], Key<Aes256Gcm>>>>,
+}
+
+impl AtRestCodec {
+    /// Create a codec, deriving the encryption key from a passphrase and a fresh salt
+    pub fn new(compress: bool, passphrase: Option<&str>) -> Self {
+        let codec = Self {
+            compress,
+            passphrase: passphrase.map(Arc::from),
+            salt: rand::random(),
+            keys: Arc::new(Mutex::new(HashMap::new())),
+        };
+        // Paid once up front rather than on the first save
+        codec.key(&codec.salt);
+        codec
+    }
+
+    /// The key derived from the passphrase with `salt` by Argon2id, or `None` without a
+    /// passphrase
+    fn key(&self, salt: &[u8; SALT_LEN]) -> Option<Key<Aes256Gcm>> {
+        let passphrase = self.passphrase.as_ref()?;
+        let mut keys = self.keys.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let key = keys.entry(*salt).or_insert_with(|| {
+            let mut key = [0u8; 32];
+            Argon2::default()
+                .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                .expect("the salt and key lengths are fixed and valid for Argon2");
+            Key::<Aes256Gcm>::from(key)
+        });
+        Some(*key)
+    }
+
+    /// The key version 1 files were encrypted with
+    fn legacy_key(&self) -> Option<Key<Aes256Gcm>> {
+        let passphrase = self.passphrase.as_ref()?;
+        Some(Key::<Aes256Gcm>::from(<[u8; 32]>::from(Sha256::digest(passphrase.as_bytes()))))
+    }
+
+    pub fn from_config(config: &StorageConfig) -> Self {
+        Self::new(config.compress_at_rest, config.resolved_encryption_key().as_deref())
+    }
+
+    /// Apply the configured transforms to data about to be written
+    pub fn seal(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut flags = 0;
+        let mut body = data.to_vec();
+
+        if self.compress {
+            body = zstd::encode_all(body.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            flags |= FLAG_COMPRESSED;
+        }
+
+        if let Some(key) = self.key(&self.salt) {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let ciphertext = Aes256Gcm::new(&key)
+                .encrypt(&nonce, body.as_slice())
+                .map_err(|_| anyhow::anyhow!(AppError::StorageError("Encryption failed".to_string())))?;
+            body = self.salt.to_vec();
+            body.extend_from_slice(&nonce);
+            body.extend(ciphertext);
+            flags |= FLAG_ENCRYPTED;
+        }
+
+        let mut sealed = Vec::with_capacity(HEADER_LEN + body.len());
+        sealed.extend_from_slice(MAGIC);
+        sealed.push(FORMAT_VERSION);
+        sealed.push(flags);
+        sealed.extend(body);
+
+        Ok(sealed)
+    }
+
+    /// Reverse whatever transforms were applied to data read back from disk
+    pub fn open(&self, data: &[u8]) -> Result<Vec<u8>> {
+        // Files written before at-rest transforms existed are plain OpLogs
+        if !data.starts_with(MAGIC) {
+            return Ok(data.to_vec());
+        }
+
+        let (version, flags) = match data.get(MAGIC.len()..HEADER_LEN) {
+            Some(&[version, flags]) => (version, flags),
+            _ => return Err(storage_error("File header is truncated")),
+        };
+        if version != FORMAT_VERSION && version != LEGACY_FORMAT_VERSION {
+            return Err(storage_error(&format!("Unsupported at-rest format version {}", version)));
+        }
+
+        let mut body = data[HEADER_LEN..].to_vec();
+
+        if flags & FLAG_ENCRYPTED != 0 {
+            let (key, encrypted) = if version == LEGACY_FORMAT_VERSION {
+                (self.legacy_key(), body.as_slice())
+            } else {
+                if body.len() < SALT_LEN {
+                    return Err(storage_error("Encrypted file is truncated"));
+                }
+                let (salt, encrypted) = body.split_at(SALT_LEN);
+                let salt: [u8; SALT_LEN] = salt.try_into().expect("split at the salt length");
+                (self.key(&salt), encrypted)
+            };
+            let key = key.ok_or_else(|| storage_error("File is encrypted but no encryption key is configured"))?;
+            if encrypted.len() < NONCE_LEN {
+                return Err(storage_error("Encrypted file is truncated"));
+            }
+            let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+            body = Aes256Gcm::new(&key)
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| storage_error("Decryption failed: wrong encryption key or corrupted file"))?;
+        }
+
+        if flags & FLAG_COMPRESSED != 0 {
+            body = zstd::decode_all(body.as_slice())
+                .map_err(|e| storage_error(&format!("Decompression failed: {}", e)))?;
+        }
+
+        Ok(body)
+    }
+}
+
+impl std::fmt::Debug for AtRestCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the key
+        f.debug_struct("AtRestCodec")
+            .field("compress", &self.compress)
+            .field("encrypted", &self.passphrase.is_some())
+            .finish()
+    }
+}
+
+/// Persists document OpLogs as `<document id>.oplog` files in a directory, each with the
+/// agent mapping it was exported with in `<document id>.agents`, the moves made to the
+/// document in `<document id>.attribution`, the document's metadata in
+/// `<document id>.document` and, while it is in the trash, when it was deleted in
+/// `<document id>.trash`
+#[derive(Debug, Clone)]
+pub struct OplogStore {
+    dir: PathBuf,
+    codec: AtRestCodec,
+}
+
+impl OplogStore {
+    pub fn new(dir: PathBuf, codec: AtRestCodec) -> Self {
+        Self { dir, codec }
+    }
+
+    pub fn from_config(config: &StorageConfig) -> Self {
+        Self::new(config.documents_path.clone(), AtRestCodec::from_config(config))
+    }
+
+    /// Path of the file a document's OpLog is stored in
+    pub fn path(&self, doc_id: &Uuid) -> PathBuf {
+        self.dir.join(format!("{}.oplog", doc_id))
+    }
+
+    /// Write a document's encoded OpLog, replacing any previous copy atomically
+    pub fn save(&self, doc_id: &Uuid, encoded_oplog: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        atomic_file::write_atomic(&self.path(doc_id), &self.codec.seal(encoded_oplog)?)?;
+        Ok(())
+    }
+
+    /// Read back a document's encoded OpLog
+    pub fn load(&self, doc_id: &Uuid) -> Result<Vec<u8>> {
+        let data = fs::read(self.path(doc_id))?;
+        self.codec.open(&data)
+    }
+
+    /// Path of the file a document's agent mapping is stored in
+    pub fn agent_map_path(&self, doc_id: &Uuid) -> PathBuf {
+        self.dir.join(format!("{}.agents", doc_id))
+    }
+
+    /// Write the agent mapping a document's OpLog was exported with
+    pub fn save_agent_map(&self, doc_id: &Uuid, agent_map: &AgentMap) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let encoded = serde_json::to_vec(agent_map)?;
+        atomic_file::write_atomic(&self.agent_map_path(doc_id), &self.codec.seal(&encoded)?)?;
+        Ok(())
+    }
+
+    /// Read back a document's agent mapping, which is empty for OpLogs saved without one
+    pub fn load_agent_map(&self, doc_id: &Uuid) -> Result<AgentMap> {
+        let data = match fs::read(self.agent_map_path(doc_id)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(AgentMap::default()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(serde_json::from_slice(&self.codec.open(&data)?)?)
+    }
+
+    /// Path of the file the moves made to a document are stored in
+    pub fn attribution_path(&self, doc_id: &Uuid) -> PathBuf {
+        self.dir.join(format!("{}.attribution", doc_id))
+    }
+
+    /// Write the moves made to a document, which credit moved text to its authors
+    pub fn save_attribution(&self, doc_id: &Uuid, attribution: &Attribution) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let encoded = serde_json::to_vec(attribution)?;
+        atomic_file::write_atomic(&self.attribution_path(doc_id), &self.codec.seal(&encoded)?)?;
+        Ok(())
+    }
+
+    /// Read back the moves made to a document, which are none for OpLogs saved without them
+    pub fn load_attribution(&self, doc_id: &Uuid) -> Result<Attribution> {
+        let data = match fs::read(self.attribution_path(doc_id)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Attribution::default()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(serde_json::from_slice(&self.codec.open(&data)?)?)
+    }
+
+    /// Path of the file a document's metadata is stored in
+    pub fn metadata_path(&self, doc_id: &Uuid) -> PathBuf {
+        self.dir.join(format!("{}.document", doc_id))
+    }
+
+    /// Write a document's metadata: its title, owner, roles and so on
+    pub fn save_metadata(&self, document: &Document) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let encoded = serde_json::to_vec(document)?;
+        atomic_file::write_atomic(&self.metadata_path(&document.id), &self.codec.seal(&encoded)?)?;
+        Ok(())
+    }
+
+    /// Read back a document's metadata, if it was saved
+    pub fn load_metadata(&self, doc_id: &Uuid) -> Result<Option<Document>> {
+        let data = match fs::read(self.metadata_path(doc_id)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(serde_json::from_slice(&self.codec.open(&data)?)?))
+    }
+
+    /// Path of the file recording when a document was moved to the trash
+    pub fn trash_path(&self, doc_id: &Uuid) -> PathBuf {
+        self.dir.join(format!("{}.trash", doc_id))
+    }
+
+    /// Record that a document is in the trash, and since when
+    pub fn save_trashed(&self, doc_id: &Uuid, deleted_at: &chrono::DateTime<chrono::Utc>) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let encoded = serde_json::to_vec(deleted_at)?;
+        atomic_file::write_atomic(&self.trash_path(doc_id), &self.codec.seal(&encoded)?)?;
+        Ok(())
+    }
+
+    /// When a document was moved to the trash, if it is there
+    pub fn load_trashed(&self, doc_id: &Uuid) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+        let data = match fs::read(self.trash_path(doc_id)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(serde_json::from_slice(&self.codec.open(&data)?)?))
+    }
+
+    /// Record that a document is out of the trash
+    pub fn remove_trashed(&self, doc_id: &Uuid) -> Result<()> {
+        match fs::remove_file(self.trash_path(doc_id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// IDs of the documents an OpLog is stored for
+    pub fn saved_documents(&self) -> Result<Vec<Uuid>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut doc_ids = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            if let Some(doc_id) = name.to_str().and_then(|name| name.strip_suffix(".oplog")).and_then(|id| Uuid::parse_str(id).ok()) {
+                doc_ids.push(doc_id);
+            }
+        }
+        Ok(doc_ids)
+    }
+
+    /// Delete everything stored for a document
+    pub fn remove(&self, doc_id: &Uuid) -> Result<()> {
+        for path in [
+            self.path(doc_id),
+            self.agent_map_path(doc_id),
+            self.attribution_path(doc_id),
+            self.metadata_path(doc_id),
+            self.trash_path(doc_id),
+        ] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn storage_error(message: &str) -> anyhow::Error {
+    anyhow::anyhow!(AppError::StorageError(message.to_string()))
+}
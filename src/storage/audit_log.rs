@@ -0,0 +1,241 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::crdt::events::{DocumentEvent, MetadataChange};
+use crate::crdt::history::OperationKind;
+use crate::utils::config::StorageConfig;
+
+/// How often buffered audit records are written out
+pub const AUDIT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many records can wait for the writer before recording one waits too
+pub const AUDIT_QUEUE_CAPACITY: usize = 1024;
+
+/// One line of the audit log: who did what to which document, and when
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// User who acted, when known
+    pub user: Option<String>,
+    /// Document acted on, if any
+    pub document_id: Option<Uuid>,
+    pub action: String,
+    pub details: serde_json::Value,
+}
+
+impl AuditRecord {
+    pub fn new(user: Option<String>, document_id: Option<Uuid>, action: &str, details: serde_json::Value) -> Self {
+        Self {
+            timestamp: chrono::Utc::now(),
+            user,
+            document_id,
+            action: action.to_string(),
+            details,
+        }
+    }
+
+    /// The records an engine event leaves in the audit log, if any
+    pub fn from_event(event: &DocumentEvent) -> Vec<Self> {
+        match event {
+            DocumentEvent::OperationApplied { document_id, records } => records
+                .iter()
+                .map(|record| {
+                    let action = match record.kind {
+                        OperationKind::Insert => "insert",
+                        OperationKind::Delete => "delete",
+                    };
+                    Self::new(Some(record.agent.clone()), Some(*document_id), action, serde_json::json!({
+                        "version": record.version,
+                        "position": record.position,
+                        "len": record.len,
+                    }))
+                })
+                .collect(),
+            DocumentEvent::MetadataChanged { document_id, change } => Self::from_metadata_change(document_id, change),
+            DocumentEvent::DocumentDeleted { document_id } => {
+                vec![Self::new(None, Some(*document_id), "delete_document", serde_json::Value::Null)]
+            }
+            DocumentEvent::DocumentIdChanged { old_id, new_id } => {
+                vec![Self::new(None, Some(*new_id), "change_document_id", serde_json::json!({ "old_id": old_id }))]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// One record per changed field, credited to whoever made the change
+    fn from_metadata_change(document_id: &Uuid, change: &MetadataChange) -> Vec<Self> {
+        let fields = [
+            ("rename", change.title.as_ref().map(|title| serde_json::json!({ "title": title }))),
+            ("transfer_ownership", change.owner.as_ref().map(|owner| serde_json::json!({ "owner": owner }))),
+            ("set_tags", change.tags.as_ref().map(|tags| serde_json::json!({ "tags": tags }))),
+            ("set_collaborators", change.collaborators.as_ref().map(|users| serde_json::json!({ "collaborators": users }))),
+            ("set_repository", change.repository_url.as_ref().map(|url| serde_json::json!({ "repository_url": url }))),
+            ("set_roles", change.roles.as_ref().map(|roles| serde_json::json!({ "roles": roles }))),
+            ("set_custom_metadata", change.custom_metadata.as_ref().map(|metadata| serde_json::json!({ "custom_metadata": metadata }))),
+        ];
+
+        fields
+            .into_iter()
+            .filter_map(|(action, details)| Some(Self::new(change.user.clone(), Some(*document_id), action, details?)))
+            .collect()
+    }
+}
+
+enum Command {
+    Record(AuditRecord),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Append-only JSON-lines record of what users did, written to disk in the background
+///
+/// Records are buffered and written out every `AUDIT_FLUSH_INTERVAL`. Once the file would
+/// grow past its size limit it is renamed to the first free `<name>.<n>` and a new one is
+/// started, so nothing is ever overwritten.
+#[derive(Clone)]
+pub struct AuditLog {
+    commands: mpsc::Sender<Command>,
+}
+
+impl AuditLog {
+    /// Open the log at `path`, recording every event the engine emits on `events`
+    pub async fn open(path: PathBuf, max_file_bytes: u64, events: broadcast::Receiver<DocumentEvent>) -> Result<Self> {
+        let writer = AuditWriter::open(path, max_file_bytes).await?;
+        let (commands, receiver) = mpsc::channel(AUDIT_QUEUE_CAPACITY);
+        tokio::spawn(writer.run(receiver, events));
+
+        Ok(Self { commands })
+    }
+
+    /// Open the configured log, if one is configured
+    pub async fn from_config(config: &StorageConfig, events: broadcast::Receiver<DocumentEvent>) -> Result<Option<Self>> {
+        match &config.audit_log_path {
+            Some(path) => Ok(Some(Self::open(path.clone(), config.audit_log_max_bytes, events).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Append a record, waiting for room if the writer is behind rather than dropping it
+    pub async fn record(&self, record: AuditRecord) {
+        let _ = self.commands.send(Command::Record(record)).await;
+    }
+
+    /// Wait until every record appended so far, and every event already emitted, is on disk
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.commands.send(Command::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+struct AuditWriter {
+    path: PathBuf,
+    max_file_bytes: u64,
+    file: BufWriter<File>,
+    len: u64,
+}
+
+impl AuditWriter {
+    async fn open(path: PathBuf, max_file_bytes: u64) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path).await?;
+        let len = file.metadata().await?.len();
+
+        Ok(Self { path, max_file_bytes, file: BufWriter::new(file), len })
+    }
+
+    async fn run(mut self, mut commands: mpsc::Receiver<Command>, events: broadcast::Receiver<DocumentEvent>) {
+        let mut events = Some(events);
+        let mut flush_interval = tokio::time::interval(AUDIT_FLUSH_INTERVAL);
+
+        loop {
+            // Events are taken first, so a flush also covers the events emitted before it
+            tokio::select! {
+                biased;
+
+                event = async { events.as_mut().unwrap().recv().await }, if events.is_some() => match event {
+                    Ok(event) => {
+                        for record in AuditRecord::from_event(&event) {
+                            self.append(&record).await;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("Audit log missed {} engine events", missed);
+                        let record = AuditRecord::new(None, None, "events_missed", serde_json::json!({ "count": missed }));
+                        self.append(&record).await;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => events = None,
+                },
+                command = commands.recv() => match command {
+                    Some(Command::Record(record)) => self.append(&record).await,
+                    Some(Command::Flush(done)) => {
+                        self.flush().await;
+                        let _ = done.send(());
+                    }
+                    None => break,
+                },
+                _ = flush_interval.tick() => self.flush().await,
+            }
+        }
+
+        self.flush().await;
+    }
+
+    async fn append(&mut self, record: &AuditRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("Failed to serialize audit record: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        let full = self.len > 0 && self.len + line.len() as u64 > self.max_file_bytes;
+        if full && let Err(e) = self.rotate().await {
+            tracing::warn!("Failed to rotate audit log {}: {}", self.path.display(), e);
+        }
+
+        match self.file.write_all(&line).await {
+            Ok(()) => self.len += line.len() as u64,
+            Err(e) => tracing::warn!("Failed to write to audit log {}: {}", self.path.display(), e),
+        }
+    }
+
+    async fn flush(&mut self) {
+        if let Err(e) = self.file.flush().await {
+            tracing::warn!("Failed to flush audit log {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Move the current file aside and start a new one
+    async fn rotate(&mut self) -> Result<()> {
+        self.file.flush().await?;
+        tokio::fs::rename(&self.path, rotated_path(&self.path).await?).await?;
+
+        let file = OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        self.file = BufWriter::new(file);
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// First `<path>.<n>` that doesn't exist yet
+async fn rotated_path(path: &Path) -> Result<PathBuf> {
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    for n in 1.. {
+        let candidate = path.with_file_name(format!("{}.{}", file_name, n));
+        if !tokio::fs::try_exists(&candidate).await? {
+            return Ok(candidate);
+        }
+    }
+    unreachable!("ran out of rotated file names")
+}
@@ -5,9 +5,12 @@ use tokio::sync::RwLock;
 use tokio::time::{interval, Instant};
 use uuid::Uuid;
 
+use super::at_rest::OplogStore;
+use super::wal::WriteAheadLog;
 use crate::crdt::engine::CrdtEngine;
 use crate::crdt::document_branch_manager::DocumentBranchManager;
 use crate::git::manager::GitManager;
+use crate::utils::errors::AppError;
 
 /// Service responsible for persisting documents to both local storage and remote Git repositories
 pub struct DocumentPersistenceService {
@@ -18,6 +21,10 @@ pub struct DocumentPersistenceService {
     auto_save_interval: u64,
     /// Last time documents were saved
     last_save: RwLock<std::collections::HashMap<Uuid, Instant>>,
+    /// Where OpLogs are written to disk, if anywhere
+    oplog_store: Option<OplogStore>,
+    /// Log of the operations applied since each OpLog was written, if kept
+    write_ahead_log: Option<WriteAheadLog>,
 }
 
 impl DocumentPersistenceService {
@@ -34,9 +41,30 @@ impl DocumentPersistenceService {
             branch_manager,
             auto_save_interval,
             last_save: RwLock::new(std::collections::HashMap::new()),
+            oplog_store: None,
+            write_ahead_log: None,
         }
     }
 
+    /// Create documents that are asked for but missing, instead of reporting them
+    pub fn with_auto_create_missing_documents(mut self, enabled: bool) -> Self {
+        self.branch_manager = Arc::new(DocumentBranchManager::new(Arc::clone(&self.crdt_engine)).with_auto_create(enabled));
+        self
+    }
+
+    /// Also write each saved document's OpLog to disk
+    pub fn with_oplog_store(mut self, oplog_store: OplogStore) -> Self {
+        self.oplog_store = Some(oplog_store);
+        self
+    }
+
+    /// Replay the operations in `write_ahead_log` when loading documents, and drop those
+    /// their saved OpLog reflects when saving them
+    pub fn with_write_ahead_log(mut self, write_ahead_log: Option<WriteAheadLog>) -> Self {
+        self.write_ahead_log = write_ahead_log;
+        self
+    }
+
     /// Start the auto-save service
     pub async fn start(self: Arc<Self>) {
         // Run auto-save every 30 seconds
@@ -55,37 +83,75 @@ impl DocumentPersistenceService {
         self.branch_manager.clone()
     }
 
+    /// Whether a document is loaded, leaving it missing if it isn't
+    pub async fn document_exists(&self, document_id: &Uuid) -> bool {
+        self.crdt_engine.read().await.document_exists(document_id)
+    }
+
     /// Manually save a specific document
     pub async fn save_document(&self, document_id: &Uuid) -> Result<()> {
-        // Get the document content
-        let content = {
+        self.crdt_engine.read().await.get_document(document_id).await?;
+
+        // Save locally first
+        self.save_locally(document_id).await?;
+
+        // Then sync to Git, which does nothing for local-only documents. It waits its turn
+        // with other Git operations on the document and runs on a blocking thread, so neither
+        // the manager nor an async worker is tied up meanwhile.
+        GitManager::sync_now(Arc::clone(&self.git_manager), *document_id).await?;
+
+        // Update the last save time
+        let mut last_save = self.last_save.write().await;
+        last_save.insert(*document_id, Instant::now());
+        Ok(())
+    }
+
+    /// Write a document's metadata, agent mapping and OpLog to the OpLog store, if there is
+    /// one, and drop the logged operations they reflect
+    async fn save_locally(&self, document_id: &Uuid) -> Result<()> {
+        let Some(store) = &self.oplog_store else {
+            return Ok(());
+        };
+
+        // The version is read first: the OpLog may get further ahead of it, but never behind
+        let (version, encoded, agent_map, attribution, metadata) = {
             let engine = self.crdt_engine.read().await;
-            engine.get_document_content(document_id).await?
+            let version = engine.get_versioned_snapshot(document_id).await?.1;
+            let encoded = engine.export_document(document_id).await?;
+            let metadata = engine.get_document(document_id).await?.read().await.clone();
+            let attribution = engine.export_attribution(document_id);
+            (version, encoded, engine.export_agent_map(document_id).await?, attribution, metadata)
         };
+        // The OpLog is written last, so one on disk never lacks the files that go with it
+        store.save_metadata(&metadata)?;
+        store.save_agent_map(document_id, &agent_map)?;
+        store.save_attribution(document_id, &attribution)?;
+        store.save(document_id, &encoded)?;
 
-        // Save locally first (this happens automatically in the CRDT engine)
+        if let Some(wal) = &self.write_ahead_log {
+            wal.checkpoint(document_id, version)?;
+        }
+        Ok(())
+    }
 
-        // Then attempt to save to Git if available
-        let mut git = self.git_manager.write().await;
-        match git.sync_document_blocking(document_id, content) {
-            Ok(_) => {
-                // Update the last save time
-                let mut last_save = self.last_save.write().await;
-                last_save.insert(*document_id, Instant::now());
-                Ok(())
-            }
-            Err(e) => {
-                // Check if the error is due to missing Git repository - this is fine for local-only docs
-                if e.to_string().contains("No repository found") {
-                    // Local-only document, just update the save time
-                    let mut last_save = self.last_save.write().await;
-                    last_save.insert(*document_id, Instant::now());
-                    Ok(())
-                } else {
-                    Err(e)
-                }
+    /// Save every document, whether or not it is due, e.g. before shutting down
+    ///
+    /// Returns how many documents were saved; failures are logged and skipped.
+    pub async fn save_all_documents(&self) -> Result<usize> {
+        let documents = {
+            let engine = self.crdt_engine.read().await;
+            engine.get_all_documents().await?
+        };
+
+        let mut save_count = 0;
+        for doc_id in documents {
+            match self.save_document(&doc_id).await {
+                Ok(()) => save_count += 1,
+                Err(e) => tracing::warn!("Failed to save document {}: {}", doc_id, e),
             }
         }
+
+        Ok(save_count)
     }
 
     /// Auto-save all documents that need saving
@@ -125,6 +191,67 @@ impl DocumentPersistenceService {
         Ok(())
     }
 
+    /// Restore every document written to disk that isn't loaded yet, e.g. on startup
+    ///
+    /// Returns how many documents were restored; failures are logged and skipped.
+    pub async fn load_saved_documents(&self) -> Result<usize> {
+        let Some(store) = &self.oplog_store else {
+            return Ok(0);
+        };
+
+        let mut load_count = 0;
+        for doc_id in store.saved_documents()? {
+            if self.crdt_engine.read().await.document_exists(&doc_id) {
+                continue;
+            }
+            match self.load_document(&doc_id).await {
+                Ok(()) => load_count += 1,
+                Err(e) => tracing::warn!("Failed to load document {}: {}", doc_id, e),
+            }
+        }
+
+        Ok(load_count)
+    }
+
+    /// Restore a document, under its own ID, from what was previously written to disk for
+    /// it, along with the operations logged for it since
+    pub async fn load_document(&self, document_id: &Uuid) -> Result<()> {
+        let store = self
+            .oplog_store
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!(AppError::StorageError("No OpLog store is configured".to_string())))?;
+        let metadata = store.load_metadata(document_id)?.ok_or_else(|| {
+            anyhow::anyhow!(AppError::StorageError(format!("No metadata is saved for document {}", document_id)))
+        })?;
+        let encoded = store.load(document_id)?;
+        let agent_map = store.load_agent_map(document_id)?;
+        let attribution = store.load_attribution(document_id)?;
+        let trashed = store.load_trashed(document_id)?;
+
+        // Every user keeps the agent ID they had, so attribution survives the restart
+        let replayed = {
+            let engine = self.crdt_engine.read().await;
+            engine.import_saved_document(metadata, &encoded, &agent_map).await?;
+            engine.import_attribution(document_id, attribution);
+            if let Some(deleted_at) = trashed {
+                engine.import_trashed(document_id, deleted_at);
+            }
+
+            match &self.write_ahead_log {
+                Some(wal) => engine.replay_logged_operations(document_id, &wal.entries(document_id)?).await?,
+                None => 0,
+            }
+        };
+
+        // Replaying logged the operations again, so they are saved and the log starts over
+        if replayed > 0 {
+            tracing::info!("Recovered {} operations on document {} from its write-ahead log", replayed, document_id);
+            self.save_locally(document_id).await?;
+        }
+
+        Ok(())
+    }
+
     /// Create a new document and ensure it persists
     pub async fn create_document(&self, title: &str, owner: &str) -> Result<Uuid> {
         // Create the document in the CRDT engine
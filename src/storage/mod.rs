@@ -1 +1,4 @@
+pub mod at_rest;
 pub mod document_persistence_service;
+pub mod audit_log;
+pub mod wal;
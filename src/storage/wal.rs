@@ -0,0 +1,173 @@
+use anyhow::Result;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use super::at_rest::AtRestCodec;
+use crate::utils::atomic_file;
+use crate::utils::config::StorageConfig;
+
+/// Bytes in front of each entry: its length, then the document version it applies to
+const FRAME_HEADER_LEN: usize = 4 + 8;
+
+/// An operation recorded in a write-ahead log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalEntry {
+    /// Number of operations in the document's OpLog when this one was applied
+    pub base_version: usize,
+    /// The encoded operation
+    pub operation: Vec<u8>,
+}
+
+/// Append-only `<document id>.wal` files of the operations applied to documents since their
+/// OpLog was last written to disk
+///
+/// Each operation is appended, and flushed to disk, before it is applied, so a crash loses
+/// nothing that was acknowledged; one that then fails to apply is discarded again. Once the OpLog has been saved the entries it reflects are
+/// dropped with `checkpoint`. Entries are sealed with the same at-rest codec as OpLogs.
+#[derive(Debug, Clone)]
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    codec: AtRestCodec,
+    /// Serializes appends with checkpoints, which replace the file
+    lock: Arc<Mutex<()>>,
+}
+
+impl WriteAheadLog {
+    pub fn new(dir: PathBuf, codec: AtRestCodec) -> Self {
+        Self {
+            dir,
+            codec,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// The write-ahead log, if the configuration enables it
+    pub fn from_config(config: &StorageConfig) -> Option<Self> {
+        config
+            .write_ahead_log
+            .then(|| Self::new(config.documents_path.clone(), AtRestCodec::from_config(config)))
+    }
+
+    /// Path of the file a document's operations are logged to
+    pub fn path(&self, doc_id: &Uuid) -> PathBuf {
+        self.dir.join(format!("{}.wal", doc_id))
+    }
+
+    /// Append an operation applied to a document at `base_version`, flushing it to disk
+    pub fn append(&self, doc_id: &Uuid, base_version: usize, operation: &[u8]) -> Result<()> {
+        let frame = self.frame(base_version, operation)?;
+
+        let _guard = self.lock();
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(doc_id);
+        let created = !path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(&frame)?;
+        file.sync_data()?;
+
+        // A new log is only found after a crash if its directory entry reached the disk too
+        if created {
+            atomic_file::sync_dir(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    /// `append` on a blocking thread, so writing and flushing the entry doesn't hold up the
+    /// async worker it is awaited on
+    pub async fn append_async(&self, doc_id: &Uuid, base_version: usize, operation: Vec<u8>) -> Result<()> {
+        let wal = self.clone();
+        let doc_id = *doc_id;
+        tokio::task::spawn_blocking(move || wal.append(&doc_id, base_version, &operation)).await?
+    }
+
+    /// The operations logged for a document, oldest first
+    ///
+    /// An entry cut short by a crash while it was being appended is ignored, as its operation
+    /// was never applied.
+    pub fn entries(&self, doc_id: &Uuid) -> Result<Vec<WalEntry>> {
+        let _guard = self.lock();
+        self.read_entries(doc_id)
+    }
+
+    /// Drop the entries a document's saved OpLog reflects, those applied before it reached
+    /// `version` operations
+    pub fn checkpoint(&self, doc_id: &Uuid, version: usize) -> Result<()> {
+        self.retain(doc_id, |entry| entry.base_version >= version)
+    }
+
+    /// Drop the entries logged at or after `base_version`, e.g. for an operation that was
+    /// logged and then failed to apply
+    pub fn discard_from(&self, doc_id: &Uuid, base_version: usize) -> Result<()> {
+        self.retain(doc_id, |entry| entry.base_version < base_version)
+    }
+
+    /// Rewrite a document's log with only the entries `keep` accepts
+    fn retain(&self, doc_id: &Uuid, keep: impl Fn(&WalEntry) -> bool) -> Result<()> {
+        let _guard = self.lock();
+        let remaining: Vec<WalEntry> = self.read_entries(doc_id)?.into_iter().filter(|entry| keep(entry)).collect();
+
+        if remaining.is_empty() {
+            return match fs::remove_file(self.path(doc_id)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+
+        let mut data = Vec::new();
+        for entry in &remaining {
+            data.extend(self.frame(entry.base_version, &entry.operation)?);
+        }
+        atomic_file::write_atomic(&self.path(doc_id), &data)?;
+        Ok(())
+    }
+
+    fn read_entries(&self, doc_id: &Uuid) -> Result<Vec<WalEntry>> {
+        let data = match fs::read(self.path(doc_id)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = Vec::new();
+        let mut rest = data.as_slice();
+        while let Some((len, base_version)) = read_header(rest) {
+            let Some(sealed) = rest.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len) else {
+                break;
+            };
+            entries.push(WalEntry {
+                base_version,
+                operation: self.codec.open(sealed)?,
+            });
+            rest = &rest[FRAME_HEADER_LEN + len..];
+        }
+        if !rest.is_empty() {
+            tracing::warn!("Ignored a partially written entry at the end of the write-ahead log of document {}", doc_id);
+        }
+
+        Ok(entries)
+    }
+
+    fn frame(&self, base_version: usize, operation: &[u8]) -> Result<Vec<u8>> {
+        let sealed = self.codec.seal(operation)?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + sealed.len());
+        frame.extend_from_slice(&(sealed.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&(base_version as u64).to_le_bytes());
+        frame.extend(sealed);
+        Ok(frame)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Length and base version of the entry at the start of `data`, if its header is complete
+fn read_header(data: &[u8]) -> Option<(usize, usize)> {
+    let header = data.get(..FRAME_HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[..4].try_into().ok()?) as usize;
+    let base_version = u64::from_le_bytes(header[4..].try_into().ok()?) as usize;
+    Some((len, base_version))
+}
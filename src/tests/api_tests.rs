@@ -1,6 +1,21 @@
 use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::RwLock;
+
+use crate::api::http::{BatchDocumentEntry, BatchDocumentsResponse, CreateDocumentResponse, CustomMetadataEntry, DocumentInfo, HttpApi, LintResponse, NetworkInfoResponse, OperationBatchResponse, PatchesResponse, CONTENT_STREAM_CHUNK_SIZE, MAX_BATCH_SIZE};
+use crate::crdt::comments::Comment;
+use crate::crdt::document::Role;
+use crate::crdt::engine::CrdtEngine;
 use crate::crdt::operations::DocumentOperation;
+use crate::crdt::stats::DocumentStats;
+use crate::network::engine::NetworkEngine;
+use crate::storage::at_rest::AtRestCodec;
+use crate::storage::wal::WriteAheadLog;
 use crate::utils::config::Config;
+use crate::utils::errors::AppError;
+use crate::utils::latex::references::{RefIssue, RefIssueKind};
 
 #[tokio::test]
 async fn test_crdt_operations() -> Result<()> {
@@ -20,14 +35,14 @@ async fn test_crdt_operations() -> Result<()> {
 
     let encoded = engine.apply_local_operation(&doc_id, insert_op).await?;
 
-    // Apply the same operation as if it came from the network
+    // Apply the same operation as if the network echoed it back
     engine.apply_remote_operation(&doc_id, &encoded).await?;
 
     // Get the document content
     let content = engine.get_document_content(&doc_id).await?;
 
-    // Verify that the text was inserted correctly (twice)
-    assert_eq!(content, "Hello, world!Hello, world!");
+    // Verify that the text was inserted correctly, and only once
+    assert_eq!(content, "Hello, world!");
 
     Ok(())
 }
@@ -85,3 +100,766 @@ async fn test_document_lifecycle() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_operation_stream_emits_new_operations() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        engine.create_document("Streamed".to_string(), "alice".to_string()).await?
+    };
+
+    let (addr, server) = warp::serve(HttpApi::operation_stream_route(Arc::clone(&engine)))
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    // Connect like `curl -N` would, and wait for the response headers
+    let mut stream = tokio::net::TcpStream::connect(addr).await?;
+    stream.write_all(format!(
+        "GET /api/documents/{}/operations/stream HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\n\r\n",
+        doc_id
+    ).as_bytes()).await?;
+
+    let mut received = String::new();
+    let mut buf = [0u8; 1024];
+    while !received.contains("\r\n\r\n") {
+        let n = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf)).await??;
+        received.push_str(&String::from_utf8_lossy(&buf[..n]));
+    }
+    assert!(received.contains("text/event-stream"));
+
+    // Apply an operation after the client connected
+    {
+        let engine = engine.read().await;
+        engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: "bob".to_string(),
+            position: 0,
+            content: "\\section{Intro}".to_string(),
+        }).await?;
+    }
+
+    while !received.contains("\n\n") || !received.contains("data:") {
+        let n = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf)).await??;
+        received.push_str(&String::from_utf8_lossy(&buf[..n]));
+    }
+
+    let data = received
+        .lines()
+        .find_map(|line| line.strip_prefix("data:"))
+        .expect("No SSE data line");
+    let record: serde_json::Value = serde_json::from_str(data.trim())?;
+    assert_eq!(record["agent"], "bob");
+    assert_eq!(record["kind"], "Insert");
+    assert_eq!(record["content"], "\\section{Intro}");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fetch_line_range_of_content() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Lines".to_string(), "alice".to_string()).await?;
+        engine.update_document_content(&doc_id, "one\ntwo\nthree\nfour\nfive".to_string()).await?;
+        doc_id
+    };
+    let route = HttpApi::content_route(Arc::clone(&engine));
+
+    let response = warp::test::request()
+        .path(&format!("/api/documents/{}/content?lines=2-3", doc_id))
+        .reply(&route)
+        .await;
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.headers()["content-range"], "lines 2-3/5");
+    assert_eq!(response.body().as_ref(), b"two\nthree\n");
+
+    let response = warp::test::request()
+        .path(&format!("/api/documents/{}/content?bytes=4-6", doc_id))
+        .reply(&route)
+        .await;
+    assert_eq!(response.headers()["content-range"], "bytes 4-6/23");
+    assert_eq!(response.body().as_ref(), b"two");
+
+    let response = warp::test::request()
+        .path(&format!("/api/documents/{}/content?lines=4-6", doc_id))
+        .reply(&route)
+        .await;
+    assert_eq!(response.status(), 416);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_batch_fetch_reports_errors_per_document() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let (first, second) = {
+        let engine = engine.read().await;
+        (
+            engine.create_document("First".to_string(), "alice".to_string()).await?,
+            engine.create_document("Second".to_string(), "bob".to_string()).await?,
+        )
+    };
+    let route = HttpApi::batch_documents_route(Arc::clone(&engine));
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/documents/batch")
+        .json(&serde_json::json!({ "ids": [first.to_string(), second.to_string(), "not-a-uuid"] }))
+        .reply(&route)
+        .await;
+    let response: BatchDocumentsResponse = serde_json::from_slice(response.body())?;
+
+    assert_eq!(response.documents.len(), 3);
+    match &response.documents[&first.to_string()] {
+        BatchDocumentEntry::Document(info) => assert_eq!(info.title, "First"),
+        other => panic!("Unexpected entry: {:?}", other),
+    }
+    match &response.documents[&second.to_string()] {
+        BatchDocumentEntry::Document(info) => assert_eq!(info.owner, "bob"),
+        other => panic!("Unexpected entry: {:?}", other),
+    }
+    assert!(matches!(response.documents["not-a-uuid"], BatchDocumentEntry::Error(_)));
+
+    // Oversized batches are refused outright
+    let ids: Vec<String> = (0..=MAX_BATCH_SIZE).map(|_| first.to_string()).collect();
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/documents/batch")
+        .json(&serde_json::json!({ "ids": ids }))
+        .reply(&route)
+        .await;
+    let response: serde_json::Value = serde_json::from_slice(response.body())?;
+    assert!(response["error"].is_string());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lint_flags_deprecated_command_by_line() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Lint".to_string(), "alice".to_string()).await?;
+        engine.update_document_content(&doc_id, [
+            "\\documentclass{article}",
+            "\\begin{document}",
+            "Plain {\\bfseries bold} and \\\\bf is a line break. % {\\bf commented out}",
+            "Some {\\bf old-style bold}.",
+            "\\end{document}",
+        ].join("\n")).await?;
+        doc_id
+    };
+    let route = HttpApi::lint_route(Arc::clone(&engine));
+
+    let response = warp::test::request()
+        .method("POST")
+        .path(&format!("/api/documents/{}/lint", doc_id))
+        .reply(&route)
+        .await;
+    let response: LintResponse = serde_json::from_slice(response.body())?;
+
+    assert_eq!(response.warnings.len(), 1, "{:?}", response.warnings);
+    let warning = &response.warnings[0];
+    assert_eq!(warning.rule_id, "deprecated-bf");
+    assert_eq!((warning.line, warning.column), (4, 7));
+    assert!(response.references.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lint_reports_dangling_reference_and_unused_label() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("References".to_string(), "alice".to_string()).await?;
+        engine.update_document_content(&doc_id, [
+            "\\section{Intro}\\label{sec:intro}",
+            "See Section~\\ref{sec:intro} and Figure~\\ref{fig:x}.",
+            "\\begin{equation}\\label{eq:unused} x = 1 \\end{equation}",
+            "% \\ref{fig:commented} is ignored",
+        ].join("\n")).await?;
+        doc_id
+    };
+    let route = HttpApi::lint_route(Arc::clone(&engine));
+
+    let response = warp::test::request()
+        .method("POST")
+        .path(&format!("/api/documents/{}/lint", doc_id))
+        .reply(&route)
+        .await;
+    let response: LintResponse = serde_json::from_slice(response.body())?;
+
+    assert_eq!(response.references, vec![
+        RefIssue { kind: RefIssueKind::UndefinedReference, key: "fig:x".to_string(), line: 2, column: 40 },
+        RefIssue { kind: RefIssueKind::UnusedLabel, key: "eq:unused".to_string(), line: 3, column: 17 },
+    ]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_network_info_reports_peer_id_and_subscriptions() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let (first, second) = {
+        let engine = engine.read().await;
+        let first = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+        let second = engine.create_document("Notes".to_string(), "alice".to_string()).await?;
+        (first, second)
+    };
+
+    let config = Config::default();
+    let mut network = NetworkEngine::new(&config.network, Arc::clone(&engine)).await?;
+    network.start().await?;
+    network.subscribe_to_document(first).await?;
+    network.subscribe_to_document(second).await?;
+    let network = Arc::new(RwLock::new(network));
+    let route = HttpApi::network_info_route(Arc::clone(&network));
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/api/network/info")
+        .reply(&route)
+        .await;
+    let info: NetworkInfoResponse = serde_json::from_slice(response.body())?;
+
+    assert!(info.peer_id.parse::<libp2p::PeerId>().is_ok(), "{}", info.peer_id);
+    assert_eq!(info.peer_id, network.read().await.get_local_peer_id().await?);
+    assert_eq!(info.listen_addresses, config.network.listen_addresses);
+    let mut expected = vec![first, second];
+    expected.sort();
+    assert_eq!(info.subscribed_documents, expected);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_documents_created_with_a_nonce_carry_a_dedup_key() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let config = Config::default();
+    let mut network = NetworkEngine::new(&config.network, Arc::clone(&engine)).await?;
+    network.start().await?;
+    let route = HttpApi::create_document_route(Arc::clone(&engine), Arc::new(RwLock::new(network)));
+
+    let mut keys = Vec::new();
+    for nonce in [Some("invite-42"), None] {
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/documents")
+            .json(&serde_json::json!({ "title": "Paper", "owner": "alice", "dedup_nonce": nonce }))
+            .reply(&route)
+            .await;
+        let created: CreateDocumentResponse = serde_json::from_slice(response.body())?;
+        let document = engine.read().await.get_document(&created.document_id).await?;
+        keys.push(document.read().await.dedup_key.clone());
+    }
+
+    assert_eq!(keys[0], Some(crate::crdt::document::dedup_key("Paper", "alice", "invite-42")));
+    assert_eq!(keys[1], None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewers_cannot_update_documents_or_resolve_comments() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let (doc_id, comment) = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Paper".to_string(), "alice".to_string()).await?;
+        engine.set_role(&doc_id, "bob".to_string(), Role::Viewer, None).await?;
+        engine.set_role(&doc_id, "carol".to_string(), Role::Editor, None).await?;
+        engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: "alice".to_string(),
+            position: 0,
+            content: "Hello world".to_string(),
+        }).await?;
+        let comment = engine.add_comment(&doc_id, "alice".to_string(), 0..5, "Too plain".to_string()).await?;
+        (doc_id, comment)
+    };
+
+    let config = Config::default();
+    let mut network = NetworkEngine::new(&config.network, Arc::clone(&engine)).await?;
+    network.start().await?;
+    let update_route = HttpApi::update_document_route(Arc::clone(&engine), Arc::new(RwLock::new(network)));
+    let comments_route = HttpApi::comments_route(Arc::clone(&engine));
+
+    let rename = |user_id: &str| {
+        warp::test::request()
+            .method("PATCH")
+            .path(&format!("/api/documents/{}", doc_id))
+            .json(&serde_json::json!({ "user_id": user_id, "title": format!("Renamed by {}", user_id) }))
+    };
+    let resolve = |user_id: &str| {
+        warp::test::request()
+            .method("PATCH")
+            .path(&format!("/api/documents/{}/comments/{}/resolve", doc_id, comment.id))
+            .json(&serde_json::json!({ "user_id": user_id }))
+    };
+
+    let response = rename("bob").reply(&update_route).await;
+    assert!(serde_json::from_slice::<DocumentInfo>(response.body()).is_err());
+    let response = resolve("bob").reply(&comments_route).await;
+    assert!(serde_json::from_slice::<Comment>(response.body()).is_err());
+    {
+        let engine = engine.read().await;
+        assert_eq!(engine.get_document(&doc_id).await?.read().await.title, "Paper");
+        assert!(!engine.get_comments(&doc_id).await?[0].resolved);
+    }
+
+    // Editors may do both
+    let response = rename("carol").reply(&update_route).await;
+    let info: DocumentInfo = serde_json::from_slice(response.body())?;
+    assert_eq!(info.title, "Renamed by carol");
+    let response = resolve("carol").reply(&comments_route).await;
+    let resolved: Comment = serde_json::from_slice(response.body())?;
+    assert!(resolved.resolved);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_custom_metadata_is_set_read_back_and_synced() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = engine.read().await.create_document("Paper".to_string(), "alice".to_string()).await?;
+    engine.read().await.set_role(&doc_id, "bob".to_string(), Role::Viewer, None).await?;
+
+    let config = Config::default();
+    let mut network = NetworkEngine::new(&config.network, Arc::clone(&engine)).await?;
+    network.start().await?;
+    let route = HttpApi::custom_metadata_route(Arc::clone(&engine), Arc::new(RwLock::new(network)));
+
+    for (key, value) in [("journal", "Physical Review D"), ("doi", "10.1103/PhysRevD.1.1")] {
+        let response = warp::test::request()
+            .method("PUT")
+            .path(&format!("/api/documents/{}/metadata/{}", doc_id, key))
+            .json(&serde_json::json!({ "user_id": "alice", "value": value }))
+            .reply(&route)
+            .await;
+        let info: DocumentInfo = serde_json::from_slice(response.body())?;
+        assert_eq!(info.custom_metadata.get(key).map(String::as_str), Some(value));
+    }
+
+    let response = warp::test::request()
+        .method("GET")
+        .path(&format!("/api/documents/{}/metadata/journal", doc_id))
+        .reply(&route)
+        .await;
+    let entry: CustomMetadataEntry = serde_json::from_slice(response.body())?;
+    assert_eq!(entry.value, "Physical Review D");
+
+    // Viewers may not set metadata
+    let response = warp::test::request()
+        .method("PUT")
+        .path(&format!("/api/documents/{}/metadata/journal", doc_id))
+        .json(&serde_json::json!({ "user_id": "bob", "value": "Nature" }))
+        .reply(&route)
+        .await;
+    assert!(serde_json::from_slice::<DocumentInfo>(response.body()).is_err());
+
+    // A second instance holding the same document picks both entries up from the update
+    let update = {
+        let engine = engine.read().await;
+        let document = engine.get_document(&doc_id).await?;
+        let doc = document.read().await;
+        NetworkEngine::metadata_update(&doc)?
+    };
+    let replica = CrdtEngine::new()?;
+    let local_id = replica.create_document("Paper".to_string(), "alice".to_string()).await?;
+    replica.adopt_document_id(&local_id, doc_id).await?;
+    NetworkEngine::apply_metadata_update(&replica, &doc_id, &update).await?;
+
+    let synced = replica.get_document(&doc_id).await?.read().await.custom_metadata.clone();
+    assert_eq!(synced.len(), 2);
+    assert_eq!(synced["journal"], "Physical Review D");
+    assert_eq!(synced["doi"], "10.1103/PhysRevD.1.1");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_patches_list_inserts_in_order_with_authors() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = engine.read().await.create_document("Paper".to_string(), "alice".to_string()).await?;
+    for (user, position, content) in [("alice", 0, "One. "), ("bob", 5, "Two. "), ("carol", 10, "Three.")] {
+        engine.read().await.apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: user.to_string(),
+            position,
+            content: content.to_string(),
+        }).await?;
+    }
+    let route = HttpApi::patches_route(Arc::clone(&engine));
+
+    let response = warp::test::request()
+        .method("GET")
+        .path(&format!("/api/documents/{}/patches", doc_id))
+        .reply(&route)
+        .await;
+    let patches = serde_json::from_slice::<PatchesResponse>(response.body())?.patches;
+
+    let authors: Vec<&str> = patches.iter().map(|patch| patch.author.as_str()).collect();
+    assert_eq!(authors, ["alice", "bob", "carol"]);
+    let contents: Vec<Option<&str>> = patches.iter().map(|patch| patch.content.as_deref()).collect();
+    assert_eq!(contents, [Some("One. "), Some("Two. "), Some("Three.")]);
+    assert_eq!(patches.iter().map(|patch| patch.version).collect::<Vec<_>>(), [0, 5, 10]);
+    assert_eq!(patches.iter().map(|patch| patch.id.as_str()).collect::<Vec<_>>(), ["alice:4", "bob:4", "carol:5"]);
+    assert!(patches.iter().all(|patch| patch.timestamp.is_some()));
+    assert!(patches.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+
+    // Bounded by frontiers, which name the same versions on every replica
+    let response = warp::test::request()
+        .method("GET")
+        .path(&format!("/api/documents/{}/patches?since=alice:4&until=bob:4", doc_id))
+        .reply(&route)
+        .await;
+    let patches = serde_json::from_slice::<PatchesResponse>(response.body())?.patches;
+    assert_eq!(patches.len(), 1);
+    assert_eq!(patches[0].author, "bob");
+
+    let response = warp::test::request()
+        .method("GET")
+        .path(&format!("/api/documents/{}/patches?since=5", doc_id))
+        .reply(&route)
+        .await;
+    assert!(serde_json::from_slice::<PatchesResponse>(response.body()).is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_operation_batch_applies_in_order_or_not_at_all() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = engine.read().await.create_document("Paper".to_string(), "alice".to_string()).await?;
+    let mut network = NetworkEngine::new(&Config::default().network, Arc::clone(&engine)).await?;
+    network.start().await?;
+    let route = HttpApi::operation_batch_route(Arc::clone(&engine), Arc::new(RwLock::new(network)));
+
+    let response = warp::test::request()
+        .method("POST")
+        .path(&format!("/api/documents/{}/operations", doc_id))
+        .json(&serde_json::json!([
+            { "kind": "insert", "user_id": "alice", "position": 0, "content": "Hello world" },
+            { "kind": "replace", "user_id": "assistant", "range": { "start": 0, "end": 5 }, "content": "Howdy" },
+            { "kind": "insert", "user_id": "assistant", "position": 11, "content": "!" },
+        ]))
+        .reply(&route)
+        .await;
+    let batch: OperationBatchResponse = serde_json::from_slice(response.body())?;
+    assert_eq!(batch.applied, 3);
+    assert_eq!(engine.read().await.get_document_content(&doc_id).await?, "Howdy world!");
+    assert_eq!(batch.version, engine.read().await.get_versioned_snapshot(&doc_id).await?.1);
+
+    // The second operation is out of bounds once the first has been applied, so nothing is
+    // applied
+    let response = warp::test::request()
+        .method("POST")
+        .path(&format!("/api/documents/{}/operations", doc_id))
+        .json(&serde_json::json!([
+            { "kind": "delete", "user_id": "alice", "range": { "start": 0, "end": 6 } },
+            { "kind": "insert", "user_id": "alice", "position": 40, "content": "?" },
+        ]))
+        .reply(&route)
+        .await;
+    assert!(serde_json::from_slice::<OperationBatchResponse>(response.body()).is_err());
+    let error = String::from_utf8_lossy(response.body());
+    assert!(error.contains("operation 1 of the batch") && error.contains("6 characters long"), "{}", error);
+    assert_eq!(engine.read().await.get_document_content(&doc_id).await?, "Howdy world!");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_streamed_content_matches_buffered_content() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    // Multibyte characters land on chunk boundaries, which must not split them
+    let content = "\\section{Résumé} naïve café – ∑ x_i\n".repeat(40_000);
+    assert!(content.len() > 10 * CONTENT_STREAM_CHUNK_SIZE);
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Large".to_string(), "alice".to_string()).await?;
+        engine.update_document_content(&doc_id, content.clone()).await?;
+        doc_id
+    };
+
+    let streamed = warp::test::request()
+        .path(&format!("/api/documents/{}/content/stream", doc_id))
+        .reply(&HttpApi::content_stream_route(Arc::clone(&engine)))
+        .await;
+    let buffered = warp::test::request()
+        .path(&format!("/api/documents/{}/content", doc_id))
+        .reply(&HttpApi::content_route(Arc::clone(&engine)))
+        .await;
+
+    assert_eq!(streamed.status(), 200);
+    assert_eq!(streamed.headers()["content-length"], content.len().to_string().as_str());
+    assert_eq!(streamed.headers()["content-type"], "text/plain; charset=utf-8");
+    assert_eq!(streamed.body().len(), content.len());
+    assert!(streamed.body() == buffered.body(), "Streamed content differs from buffered content");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stats_count_each_users_characters() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = engine.read().await.create_document("Paper".to_string(), "alice".to_string()).await?;
+    let insert = |user: &str, position, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: user.to_string(),
+        position,
+        content: content.to_string(),
+    };
+    {
+        let engine = engine.read().await;
+        engine.apply_local_operation(&doc_id, insert("alice", 0, "Introduction. ")).await?;
+        engine.apply_local_operation(&doc_id, insert("bob", 14, "Results. ")).await?;
+        engine.apply_local_operation(&doc_id, insert("alice", 23, "Fin.")).await?;
+        engine.apply_local_operation(&doc_id, DocumentOperation::Delete {
+            document_id: doc_id,
+            user_id: "bob".to_string(),
+            range: 0..3,
+        }).await?;
+    }
+
+    let response = warp::test::request()
+        .path(&format!("/api/documents/{}/stats", doc_id))
+        .reply(&HttpApi::stats_route(Arc::clone(&engine)))
+        .await;
+    let stats: DocumentStats = serde_json::from_slice(response.body())?;
+
+    assert_eq!(stats.total_operations, 4);
+    assert_eq!(stats.length, 24);
+    let users: Vec<(&str, usize, usize, usize, usize)> = stats
+        .users
+        .iter()
+        .map(|user| (user.user_id.as_str(), user.operations, user.inserted, user.deleted, user.characters))
+        .collect();
+    // Bob deleted three of alice's characters
+    assert_eq!(users, [("alice", 2, 18, 0, 15), ("bob", 2, 9, 3, 9)]);
+    assert!(stats.users.iter().all(|user| user.last_edit.is_some()));
+
+    Ok(())
+}
+
+/// A `multipart/form-data` body holding a file and text fields, with its boundary
+fn multipart_body(file: &[u8], fields: &[(&str, &str)]) -> (String, Vec<u8>) {
+    let boundary = "texswarm-test-boundary";
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend_from_slice(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            boundary, name, value
+        ).as_bytes());
+    }
+    body.extend_from_slice(format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"thesis.tex\"\r\nContent-Type: application/x-tex\r\n\r\n",
+        boundary
+    ).as_bytes());
+    body.extend_from_slice(file);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
+
+#[tokio::test]
+async fn test_uploaded_tex_file_becomes_a_document() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let route = HttpApi::upload_route(Arc::clone(&engine), 256);
+    let upload = |file: Vec<u8>, fields: Vec<(&'static str, &'static str)>| {
+        let route = route.clone();
+        async move {
+            let (content_type, body) = multipart_body(&file, &fields);
+            let response = warp::test::request()
+                .method("POST")
+                .path("/api/documents/upload")
+                .header("content-type", content_type)
+                .body(body)
+                .reply(&route)
+                .await;
+            serde_json::from_slice::<serde_json::Value>(response.body()).unwrap()
+        }
+    };
+
+    let tex = "\\documentclass{article}\n\\begin{document}\nHello\n\\end{document}\n";
+    let response = upload(tex.as_bytes().to_vec(), vec![("title", "Thesis"), ("owner", "alice")]).await;
+    let created: CreateDocumentResponse = serde_json::from_value(response)?;
+    {
+        let engine = engine.read().await;
+        assert_eq!(engine.get_document_content(&created.document_id).await?, tex);
+        let document = engine.get_document(&created.document_id).await?;
+        let document = document.read().await;
+        assert_eq!(document.title, "Thesis");
+        assert_eq!(document.owner, "alice");
+    }
+
+    // Without a title, the file's name is used
+    let response = upload(b"\\section{Intro}\n".to_vec(), vec![("owner", "alice")]).await;
+    let created: CreateDocumentResponse = serde_json::from_value(response)?;
+    let document = engine.read().await.get_document(&created.document_id).await?;
+    assert_eq!(document.read().await.title, "thesis");
+
+    // Binary files, and files over the limit, are turned away without creating anything
+    let documents = engine.read().await.get_all_documents().await?.len();
+    for file in [vec![0x89, b'P', b'N', b'G', 0, 0, 0, 0x0d], vec![0xff, 0xfe, 0xfd], vec![b'%'; 512]] {
+        let response = upload(file, vec![("owner", "alice")]).await;
+        assert!(response["error"].as_str().is_some_and(|error| !error.is_empty()), "{}", response);
+    }
+    assert_eq!(engine.read().await.get_all_documents().await?.len(), documents);
+
+    // As are files whose content can't be applied once the document has been created, here
+    // because the write-ahead log can't be written where a file is in the way
+    let blocked = std::env::temp_dir().join(format!("texswarm-api-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&blocked, b"")?;
+    let wal = WriteAheadLog::new(blocked.clone(), AtRestCodec::new(false, None));
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?.with_write_ahead_log(Some(wal))));
+    let route = HttpApi::upload_route(Arc::clone(&engine), 256);
+    let (content_type, body) = multipart_body(b"\\section{Intro}\n", &[("owner", "alice")]);
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/documents/upload")
+        .header("content-type", content_type)
+        .body(body)
+        .reply(&route)
+        .await;
+    let response = serde_json::from_slice::<serde_json::Value>(response.body())?;
+    assert!(response["error"].as_str().is_some_and(|error| !error.is_empty()), "{}", response);
+    assert!(engine.read().await.get_all_documents().await?.is_empty());
+
+    let _ = std::fs::remove_file(&blocked);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_document_is_found_by_its_slug_or_its_id() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let (paper, other) = {
+        let engine = engine.read().await;
+        let paper = engine.create_document("My Paper".to_string(), "alice".to_string()).await?;
+        let other = engine.create_document("Other".to_string(), "bob".to_string()).await?;
+        engine.set_document_slug(&paper, Some("my-paper".to_string())).await?;
+        (paper, other)
+    };
+    let route = HttpApi::get_document_route(Arc::clone(&engine));
+    let get = |id: String| {
+        let route = route.clone();
+        async move {
+            let response = warp::test::request().path(&format!("/api/documents/{}", id)).reply(&route).await;
+            serde_json::from_slice::<serde_json::Value>(response.body()).unwrap()
+        }
+    };
+
+    let by_slug: DocumentInfo = serde_json::from_value(get("my-paper".to_string()).await)?;
+    let by_id: DocumentInfo = serde_json::from_value(get(paper.to_string()).await)?;
+    assert_eq!(by_slug.id, paper);
+    assert_eq!(by_id.id, paper);
+    assert_eq!(by_id.slug.as_deref(), Some("my-paper"));
+
+    // An unknown slug names no document
+    assert!(get("no-such-paper".to_string()).await["error"].is_string());
+
+    // Slugs are unique and limited to URL-friendly characters
+    {
+        let engine = engine.read().await;
+        let error = engine.set_document_slug(&other, Some("my-paper".to_string())).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<AppError>(), Some(AppError::SlugInUse(_, id)) if *id == paper));
+        for invalid in ["", "My Paper", "-paper", "paper/1", &paper.to_string()] {
+            assert!(engine.set_document_slug(&other, Some(invalid.to_string())).await.is_err(), "{:?}", invalid);
+        }
+
+        // Renaming frees the old slug for others
+        engine.set_document_slug(&paper, Some("thesis".to_string())).await?;
+        engine.set_document_slug(&other, Some("my-paper".to_string())).await?;
+        assert_eq!(engine.resolve_document_id("my-paper")?, other);
+        assert_eq!(engine.resolve_document_id("thesis")?, paper);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_only_the_owner_may_publish_a_document() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+        engine.set_role(&doc_id, "bob".to_string(), Role::Editor, None).await?;
+        doc_id
+    };
+    let mut config = Config::default();
+    config.git.repositories_path = std::env::temp_dir().join(format!("texswarm-api-test-{}", uuid::Uuid::new_v4()));
+    let git = Arc::new(RwLock::new(crate::git::manager::GitManager::new(&config, Arc::clone(&engine))?));
+    let route = HttpApi::publish_route(Arc::clone(&engine), git);
+    let publish = |user_id: &str| {
+        let route = route.clone();
+        let body = serde_json::json!({ "user_id": user_id, "github_repo": "alice/thesis" });
+        async move {
+            let response = warp::test::request()
+                .method("POST")
+                .path(&format!("/api/documents/{}/publish", doc_id))
+                .json(&body)
+                .reply(&route)
+                .await;
+            serde_json::from_slice::<serde_json::Value>(response.body()).unwrap()["error"].as_str().unwrap_or_default().to_string()
+        }
+    };
+
+    // Editors and strangers are turned away before the server's GitHub token is touched
+    assert!(publish("bob").await.starts_with("Forbidden"));
+    assert!(publish("mallory").await.starts_with("Forbidden"));
+
+    // The owner gets as far as needing a token
+    assert!(publish("alice").await.contains("github_token"));
+
+    let _ = std::fs::remove_dir_all(&config.git.repositories_path);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_maintenance_needs_an_admin_and_an_unshared_document() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let (local, shared) = {
+        let engine = engine.read().await;
+        let local = engine.create_document("Local".to_string(), "alice".to_string()).await?;
+        let shared = engine.create_document("Shared".to_string(), "alice".to_string()).await?;
+        (local, shared)
+    };
+    let mut config = Config::default();
+    config.git.repositories_path = std::env::temp_dir().join(format!("texswarm-api-test-{}", uuid::Uuid::new_v4()));
+    let git = Arc::new(RwLock::new(crate::git::manager::GitManager::new(&config, Arc::clone(&engine))?));
+    let mut network = NetworkEngine::new(&config.network, Arc::clone(&engine)).await?;
+    network.start().await?;
+    network.subscribe_to_document(shared).await?;
+    let network = Arc::new(RwLock::new(network));
+    let route = HttpApi::maintenance_route(Arc::clone(&engine), network, git, Some("secret".to_string()));
+    let maintain = |doc_id: uuid::Uuid, authorization: &str| {
+        let route = route.clone();
+        let authorization = authorization.to_string();
+        async move {
+            let response = warp::test::request()
+                .method("POST")
+                .path(&format!("/api/documents/{}/maintenance", doc_id))
+                .header("authorization", authorization)
+                .reply(&route)
+                .await;
+            serde_json::from_slice::<serde_json::Value>(response.body()).unwrap()
+        }
+    };
+
+    // Rewriting history is for admins only
+    assert!(maintain(local, "Bearer wrong").await["error"].as_str().unwrap().starts_with("Unauthorized"));
+
+    // Peers could still hold the history of a shared document, so it's left alone
+    let refused = maintain(shared, "Bearer secret").await;
+    assert!(refused["error"].as_str().unwrap().starts_with("Operation rejected"), "{}", refused);
+
+    // A document kept to this node has its history checkpointed; it has no repository to repack
+    let maintained = maintain(local, "Bearer secret").await;
+    assert!(maintained["oplog_bytes_reclaimed"].is_u64(), "{}", maintained);
+    assert!(maintained["repository_bytes_reclaimed"].is_null(), "{}", maintained);
+
+    let _ = std::fs::remove_dir_all(&config.git.repositories_path);
+    Ok(())
+}
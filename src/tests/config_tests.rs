@@ -0,0 +1,174 @@
+use anyhow::Result;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::crdt::engine::CrdtEngine;
+use crate::git::manager::GitManager;
+use crate::utils::atomic_file;
+use crate::utils::backoff::ExponentialBackoff;
+use crate::utils::config::Config;
+use crate::utils::errors::AppError;
+use crate::utils::signals::{ShutdownListener, ShutdownSignal};
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("texswarm-config-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn test_config(dir: &std::path::Path) -> Config {
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+    config.storage.documents_path = dir.join("documents");
+    config
+}
+
+#[test]
+fn test_interrupted_save_leaves_original_config() -> Result<()> {
+    let dir = temp_dir();
+    let config_path = dir.join("config.json");
+
+    let config = test_config(&dir);
+    config.save_to(&config_path)?;
+    let original = std::fs::read_to_string(&config_path)?;
+
+    // Simulate a save that dies halfway through writing
+    let result = atomic_file::write_atomic_with(&config_path, |file| {
+        file.write_all(b"{\"server\": {")?;
+        Err(std::io::Error::other("disk full"))
+    });
+    assert!(result.is_err());
+
+    // The original config is intact and no temporary file is left behind
+    assert_eq!(std::fs::read_to_string(&config_path)?, original);
+    assert!(!atomic_file::temp_path_for(&config_path).exists());
+    let loaded = Config::load_from(&config_path)?;
+    assert_eq!(loaded.server.api_port, config.server.api_port);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_validate_rejects_bad_config() {
+    let dir = temp_dir();
+
+    let mut config = test_config(&dir);
+    assert!(config.validate().is_ok());
+
+    config.server.ws_port = config.server.api_port;
+    assert!(config.validate().is_err());
+
+    let mut config = test_config(&dir);
+    config.network.listen_addresses.clear();
+    let error = config.validate().unwrap_err();
+    assert!(error.to_string().contains("listen_addresses"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_validate_leaves_the_filesystem_alone() {
+    let dir = temp_dir();
+    let config = test_config(&dir);
+
+    // Validating doesn't create the storage directories; startup does
+    assert!(config.validate().is_ok());
+    assert!(!config.git.repositories_path.exists());
+    assert!(!config.storage.documents_path.exists());
+
+    config.check_storage_paths().unwrap();
+    assert!(config.git.repositories_path.is_dir());
+    assert!(config.storage.documents_path.is_dir());
+
+    // A file where a directory should be is caught without writing anything
+    let mut config = test_config(&dir);
+    config.storage.documents_path = dir.join("file");
+    std::fs::write(&config.storage.documents_path, b"").unwrap();
+    let error = config.validate().unwrap_err();
+    assert!(error.to_string().contains("storage.documents_path"), "{}", error);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_unwritable_repositories_path_is_named_at_startup() {
+    let dir = temp_dir();
+
+    // A file where the directory should be can't be turned into one, even by root
+    let blocked = dir.join("blocked");
+    std::fs::write(&blocked, b"").unwrap();
+    let mut config = test_config(&dir);
+    config.git.repositories_path = blocked.join("repositories");
+
+    let engine = std::sync::Arc::new(tokio::sync::RwLock::new(CrdtEngine::new().unwrap()));
+    let error = GitManager::new(&config, engine).err().expect("startup should fail");
+    assert!(matches!(error.downcast_ref::<AppError>(), Some(AppError::IoError(_))));
+    let message = error.to_string();
+    assert!(message.contains("git.repositories_path"), "{}", message);
+    assert!(message.contains(&blocked.display().to_string()), "{}", message);
+
+    // The documents path gets the same check
+    let mut config = test_config(&dir);
+    config.storage.documents_path = blocked.join("documents");
+    let message = config.check_storage_paths().unwrap_err().to_string();
+    assert!(message.contains("storage.documents_path"), "{}", message);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_shutdown_listener_resolves_on_sigterm() -> Result<()> {
+    let mut listener = ShutdownListener::new()?;
+
+    // Deliver a real SIGTERM to this process; the installed handler keeps it alive
+    let status = std::process::Command::new("kill")
+        .args(["-TERM", &std::process::id().to_string()])
+        .status()?;
+    assert!(status.success());
+
+    let signal = tokio::time::timeout(std::time::Duration::from_secs(5), listener.recv()).await?;
+    assert_eq!(signal, ShutdownSignal::Terminate);
+
+    Ok(())
+}
+
+#[test]
+fn test_backoff_respects_cap_and_jitter_bounds() {
+    let mut backoff = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_millis(1000));
+    let delays: Vec<u64> = (0..6).map(|_| backoff.next_delay().as_millis() as u64).collect();
+    assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+
+    // Jitter only ever shortens a delay, by at most the configured fraction
+    let mut jittered = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_millis(1000))
+        .with_multiplier(3.0)
+        .with_jitter(0.5);
+    for expected in [100u64, 300, 900, 1000, 1000, 1000, 1000, 1000] {
+        let delay = jittered.next_delay();
+        assert!(delay <= Duration::from_millis(expected), "{:?} exceeds {}ms", delay, expected);
+        assert!(delay >= Duration::from_millis(expected / 2), "{:?} is below {}ms", delay, expected / 2);
+    }
+
+    // The configured backoff is rejected when its cap is below its initial delay
+    let dir = temp_dir();
+    let mut config = test_config(&dir);
+    config.network.redial_backoff.max_delay_ms = config.network.redial_backoff.initial_delay_ms - 1;
+    assert!(config.validate().is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_interval_sync_backs_up_the_quiet_period_sync_unless_disabled() {
+    // Both run by default: the quiet-period sync after edits, the interval sync regardless
+    let mut config = Config::default();
+    assert_eq!(config.git.sync_interval(), Some(Duration::from_secs(300)));
+    assert!(config.git.sync_quiet_period().is_some());
+
+    config.git.sync_interval_secs = 0;
+    assert_eq!(config.git.sync_interval(), None);
+}
@@ -0,0 +1,1263 @@
+use anyhow::Result;
+use diamond_types::list::OpLog;
+
+use crate::api::protocol::{DocumentInfoMessage, UserPresence};
+use crate::crdt::activity::ActivityKind;
+use crate::crdt::agent_map::AgentMap;
+use crate::crdt::document::DocumentEncoding;
+use crate::crdt::document_branch_manager::DocumentBranchManager;
+use crate::crdt::engine::{CrdtEngine, MAX_UNDO_STEPS};
+use crate::crdt::events::DocumentEvent;
+use crate::crdt::freeze::{FreezeGuard, MAX_QUEUED_WHILE_FROZEN};
+use crate::crdt::history::OperationKind;
+use crate::crdt::intercept::{InterceptDecision, OperationInterceptor};
+use crate::crdt::integrity::IntegrityIssue;
+use crate::crdt::latex_ops;
+use crate::crdt::presence::{user_color, ConflictHint, PresenceDecay};
+use crate::crdt::operations::{DocumentOperation, OperationEncoder, OPERATION_FORMAT_VERSION};
+use crate::utils::errors::AppError;
+
+#[tokio::test]
+async fn test_agent_map_survives_export_and_import() -> Result<()> {
+    // Build an OpLog where "system" is registered but never edits, so a plain
+    // decode would shift alice and bob down by one
+    let mut oplog = OpLog::new();
+    oplog.get_or_create_agent_id("system");
+    let alice = oplog.get_or_create_agent_id("alice");
+    oplog.add_insert(alice, 0, "Hello ");
+    let bob = oplog.get_or_create_agent_id("bob");
+    oplog.add_insert(bob, 6, "world");
+    // Carol joins after the last edit, so she appears nowhere in the history
+    let carol = oplog.get_or_create_agent_id("carol");
+
+    let encoded = oplog.encode(diamond_types::list::encoding::EncodeOptions::default());
+    let agent_map = AgentMap::from_oplog(&oplog);
+    assert_eq!(agent_map.agent_id("alice"), Some(alice));
+    assert_eq!(agent_map.agent_id("bob"), Some(bob));
+    assert_eq!(agent_map.agent_id("carol"), Some(carol));
+    assert_eq!(agent_map.len(), 4);
+
+    let engine = CrdtEngine::new()?;
+
+    // Importing with the mapping restores the original agent IDs
+    let doc_id = engine.import_document_with_agents(
+        "Imported".to_string(),
+        "alice".to_string(),
+        &encoded,
+        &agent_map,
+    ).await?;
+
+    let restored = engine.export_agent_map(&doc_id).await?;
+    assert_eq!(restored, agent_map);
+    assert_eq!(engine.get_document_content(&doc_id).await?, "Hello world");
+
+    // Attribution is preserved for the original users
+    let authors = engine.get_operation_authors(&doc_id).await?;
+    assert_eq!(authors, vec![
+        ("alice".to_string(), 0..6),
+        ("bob".to_string(), 0..5),
+    ]);
+
+    // Without the mapping the IDs are reassigned
+    let plain_id = engine.import_document("Plain".to_string(), "alice".to_string(), &encoded).await?;
+    let plain = engine.export_agent_map(&plain_id).await?;
+    assert_ne!(plain.agent_id("alice"), Some(alice));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_comment_anchor_shifts_with_text() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Comments".to_string(), "alice".to_string()).await?;
+
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "Hello world".to_string(),
+    }).await?;
+
+    // Comment on "world"
+    let comment = engine.add_comment(&doc_id, "bob".to_string(), 6..11, "Too generic".to_string()).await?;
+
+    // Insert text before the commented range
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "Say: ".to_string(),
+    }).await?;
+
+    let comments = engine.get_comments(&doc_id).await?;
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].id, comment.id);
+    assert_eq!(comments[0].range, 11..16);
+
+    let content = engine.get_document_content(&doc_id).await?;
+    let anchored: String = content.chars().skip(11).take(5).collect();
+    assert_eq!(anchored, "world");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_is_a_dry_run() -> Result<()> {
+    // A known history: alice types, bob types right after her, alice deletes a word
+    let mut oplog = OpLog::new();
+    let alice = oplog.get_or_create_agent_id("alice");
+    let bob = oplog.get_or_create_agent_id("bob");
+    oplog.add_insert(alice, 0, "Hello ");
+    oplog.add_insert(bob, 6, "big world");
+    oplog.add_delete_without_content(alice, 6..10);
+    let encoded = oplog.encode(diamond_types::list::encoding::EncodeOptions::default());
+
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Live".to_string(), "alice".to_string()).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "Untouched".to_string(),
+    }).await?;
+
+    assert_eq!(engine.replay_oplog(&doc_id, &encoded).await?, "Hello world");
+    assert_eq!(engine.get_document_content(&doc_id).await?, "Untouched");
+
+    // The decoded history attributes each operation to its author
+    let imported = engine.import_document("Imported".to_string(), "alice".to_string(), &encoded).await?;
+    let records = engine.get_operation_log(&imported).await?;
+    let summary: Vec<_> = records
+        .iter()
+        .map(|r| (r.agent.as_str(), r.kind, r.position, r.len, r.content.as_deref()))
+        .collect();
+    assert_eq!(summary, vec![
+        ("alice", OperationKind::Insert, 0, 6, Some("Hello ")),
+        ("bob", OperationKind::Insert, 6, 9, Some("big world")),
+        ("alice", OperationKind::Delete, 6, 4, None),
+    ]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_normally_built_document_converges() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Converged".to_string(), "alice".to_string()).await?;
+
+    for (user_id, position, content) in [("alice", 0, "Hello"), ("bob", 5, " world"), ("alice", 0, "> ")] {
+        engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: user_id.to_string(),
+            position,
+            content: content.to_string(),
+        }).await?;
+    }
+    engine.apply_local_operation(&doc_id, DocumentOperation::Delete {
+        document_id: doc_id,
+        user_id: "bob".to_string(),
+        range: 0..2,
+    }).await?;
+
+    assert!(engine.verify_convergence(&doc_id).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_presence_colors_are_deterministic() -> Result<()> {
+    assert_eq!(user_color("alice"), user_color("alice"));
+    assert_ne!(user_color("alice"), user_color("bob"));
+
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Colors".to_string(), "alice".to_string()).await?;
+
+    let presence = |user_id: &str, color: &str| UserPresence {
+        user_id: user_id.to_string(),
+        display_name: user_id.to_string(),
+        cursor_position: Some(0),
+        selection: None,
+        is_active: true,
+        last_activity: chrono::Utc::now().to_rfc3339(),
+        color: color.to_string(),
+    };
+
+    // Missing colors are assigned, while a client's own choice is kept
+    let alice = engine.update_user_presence(doc_id, presence("alice", "")).await?;
+    assert_eq!(alice.color, user_color("alice"));
+    let bob = engine.update_user_presence(doc_id, presence("bob", "#ff8800")).await?;
+    assert_eq!(bob.color, "#ff8800");
+
+    let colors: Vec<_> = engine.get_document_presences(&doc_id).await?
+        .into_iter()
+        .map(|p| p.color)
+        .collect();
+    assert_eq!(colors, vec![user_color("alice"), "#ff8800".to_string()]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_latex_wrap_helpers() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("LaTeX".to_string(), "alice".to_string()).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "say foo".to_string(),
+    }).await?;
+
+    for op in latex_ops::wrap_in_command(doc_id, "alice", 4..7, "textbf")? {
+        engine.apply_local_operation(&doc_id, op).await?;
+    }
+    assert_eq!(engine.get_document_content(&doc_id).await?, "say \\textbf{foo}");
+
+    for op in latex_ops::insert_environment(doc_id, "alice", 0, "figure")? {
+        engine.apply_local_operation(&doc_id, op).await?;
+    }
+    assert_eq!(
+        engine.get_document_content(&doc_id).await?,
+        "\\begin{figure}\n\n\\end{figure}say \\textbf{foo}"
+    );
+
+    assert!(latex_ops::insert_environment(doc_id, "alice", 0, "bad}name").is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fork_has_independent_history() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let original = engine.create_document("Paper".to_string(), "alice".to_string()).await?;
+    engine.apply_local_operation(&original, DocumentOperation::Insert {
+        document_id: original,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "Shared draft".to_string(),
+    }).await?;
+
+    let fork = engine.fork_document(&original, "Paper (experiment)".to_string(), "bob".to_string()).await?;
+    assert_eq!(engine.get_document_content(&fork).await?, "Shared draft");
+    assert_eq!(engine.get_document(&fork).await?.read().await.forked_from, Some(original));
+
+    engine.apply_local_operation(&fork, DocumentOperation::Insert {
+        document_id: fork,
+        user_id: "bob".to_string(),
+        position: 12,
+        content: " with bold ideas".to_string(),
+    }).await?;
+    engine.apply_local_operation(&original, DocumentOperation::Delete {
+        document_id: original,
+        user_id: "alice".to_string(),
+        range: 0..7,
+    }).await?;
+
+    assert_eq!(engine.get_document_content(&original).await?, "draft");
+    assert_eq!(engine.get_document_content(&fork).await?, "Shared draft with bold ideas");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transfer_ownership_demotes_previous_owner() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    engine.add_collaborator(&doc_id, "bob".to_string(), None).await?;
+
+    engine.transfer_ownership(&doc_id, "bob".to_string(), None).await?;
+
+    let document = engine.get_document(&doc_id).await?;
+    let info = DocumentInfoMessage::from(&*document.read().await);
+    assert_eq!(info.owner, "bob");
+    assert_eq!(info.collaborators, vec!["alice".to_string()]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_latin1_bytes_round_trip() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Résumé".to_string(), "alice".to_string()).await?;
+    engine.set_document_encoding(&doc_id, DocumentEncoding::Latin1).await?;
+
+    // "Café \'e" in Latin-1, where 0xE9 alone is not valid UTF-8
+    let latin1 = b"Caf\xe9 \\'e".to_vec();
+    engine.insert_bytes(&doc_id, "alice".to_string(), 0, &latin1).await?;
+
+    assert_eq!(engine.get_document_content(&doc_id).await?, "Café \\'e");
+    assert_eq!(engine.get_document_bytes(&doc_id).await?, latin1);
+
+    // Text that Latin-1 cannot represent is rejected on the way out
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "bob".to_string(),
+        position: 0,
+        content: "€".to_string(),
+    }).await?;
+    assert!(engine.get_document_bytes(&doc_id).await.is_err());
+
+    // Invalid UTF-8 is replaced rather than rejected
+    assert_eq!(DocumentEncoding::Utf8.decode(b"Caf\xe9"), "Caf\u{fffd}");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_typing_is_broadcast_and_undone_as_one_operation() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Typing".to_string(), "alice".to_string()).await?;
+
+    for (position, c) in "hello".chars().enumerate() {
+        let ready = engine.apply_typed_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: "alice".to_string(),
+            position,
+            content: c.to_string(),
+        }).await?;
+        assert!(ready.is_empty());
+    }
+    assert_eq!(engine.get_document_content(&doc_id).await?, "hello");
+
+    // Once alice pauses, her typing is broadcast as a single insert
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+    let ready = engine.flush_coalesced_operations()?;
+    assert_eq!(ready.len(), 1);
+    match OperationEncoder::new().decode_operation(&ready[0].1)? {
+        DocumentOperation::Insert { position, content, .. } => {
+            assert_eq!(position, 0);
+            assert_eq!(content, "hello");
+        }
+        other => panic!("Expected an insert, got {:?}", other),
+    }
+
+    // And undone in one step
+    assert_eq!(engine.undo(&doc_id, "alice").await?.len(), 1);
+    assert_eq!(engine.get_document_content(&doc_id).await?, "");
+    assert!(engine.undo(&doc_id, "alice").await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_undo_keeps_only_the_latest_steps() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Undo cap".to_string(), "alice".to_string()).await?;
+
+    for position in 0..MAX_UNDO_STEPS + 5 {
+        engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: "alice".to_string(),
+            position,
+            content: "x ".to_string(),
+        }).await?;
+    }
+
+    for _ in 0..MAX_UNDO_STEPS {
+        assert_eq!(engine.undo(&doc_id, "alice").await?.len(), 1);
+    }
+
+    // The first five inserts can no longer be undone
+    assert!(engine.undo(&doc_id, "alice").await?.is_empty());
+    assert_eq!(engine.get_document_content(&doc_id).await?.chars().count(), 10);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_garbage_payloads_leave_document_unchanged() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Garbage".to_string(), "alice".to_string()).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "intact".to_string(),
+    }).await?;
+    let exported = engine.export_document(&doc_id).await?;
+
+    assert!(engine.apply_remote_operation(&doc_id, b"\x00\xffnot an operation").await.is_err());
+
+    // Well-formed, but pointing past the end of the document
+    let out_of_bounds = OperationEncoder::new().encode_operation(&DocumentOperation::Delete {
+        document_id: doc_id,
+        user_id: "mallory".to_string(),
+        range: 3..50,
+    })?;
+    assert!(engine.apply_remote_operation(&doc_id, &out_of_bounds).await.is_err());
+
+    // An OpLog that is cut off partway through
+    assert!(engine.sync_document(&doc_id, &exported[..exported.len() / 2]).await.is_err());
+
+    assert_eq!(engine.get_document_content(&doc_id).await?, "intact");
+    assert_eq!(engine.export_document(&doc_id).await?, exported);
+    assert_eq!(engine.rejected_payload_count(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_own_operations_echoed_back_are_skipped() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Echo".to_string(), "alice".to_string()).await?;
+    let insert = |content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: content.to_string(),
+    };
+
+    let encoded = engine.apply_local_operation(&doc_id, insert("once")).await?;
+
+    // Gossipsub delivers our own broadcast back to us
+    engine.apply_remote_operation(&doc_id, &encoded).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "once");
+    assert_eq!(engine.skipped_echo_count(), 1);
+
+    // Operations from other nodes are applied as usual
+    let remote = OperationEncoder::with_origin("another-node".to_string()).encode_operation(&insert("just "))?;
+    engine.apply_remote_operation(&doc_id, &remote).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "just once");
+    assert_eq!(engine.skipped_echo_count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_content_cache_is_invalidated_by_edits() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Cached".to_string(), "alice".to_string()).await?;
+    let insert = |content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: content.to_string(),
+    };
+
+    // Repeated reads between edits render the branch once and share the snapshot
+    engine.apply_local_operation(&doc_id, insert("world")).await?;
+    let first = engine.get_document_snapshot(&doc_id).await?;
+    let renders = engine.content_render_count();
+    for _ in 0..100 {
+        assert_eq!(engine.get_document_content(&doc_id).await?, "world");
+    }
+    assert!(std::sync::Arc::ptr_eq(&first, &engine.get_document_snapshot(&doc_id).await?));
+    assert_eq!(engine.content_render_count(), renders);
+
+    // Every way of editing invalidates the cache
+    engine.apply_local_operation(&doc_id, insert("hello ")).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "hello world");
+
+    let remote = OperationEncoder::new().encode_operation(&insert("> "))?;
+    engine.apply_remote_operation(&doc_id, &remote).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "> hello world");
+
+    engine.update_document_content(&doc_id, "replaced".to_string()).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "replaced");
+
+    let other = engine.import_document("Other".to_string(), "bob".to_string(), &engine.export_document(&doc_id).await?).await?;
+    engine.apply_local_operation(&other, DocumentOperation::Insert {
+        document_id: other,
+        user_id: "bob".to_string(),
+        position: 8,
+        content: "!".to_string(),
+    }).await?;
+    engine.sync_document(&doc_id, &engine.export_document(&other).await?).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "replaced!");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_move_reorders_sections_and_syncs() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Sections".to_string(), "alice".to_string()).await?;
+    let insert = |user_id: &str, position: usize, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: user_id.to_string(),
+        position,
+        content: content.to_string(),
+    };
+    engine.apply_local_operation(&doc_id, insert("alice", 0, "\\section{A}\n")).await?;
+    engine.apply_local_operation(&doc_id, insert("bob", 12, "\\section{B}\n")).await?;
+
+    // A second instance that starts out in sync
+    let replica = CrdtEngine::new()?;
+    let replica_id = replica.import_document_with_agents(
+        "Sections".to_string(),
+        "alice".to_string(),
+        &engine.export_document(&doc_id).await?,
+        &engine.export_agent_map(&doc_id).await?,
+    ).await?;
+
+    engine.apply_local_operation(&doc_id, DocumentOperation::Move {
+        document_id: doc_id,
+        user_id: "carol".to_string(),
+        source: 12..24,
+        dest: 0,
+    }).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "\\section{B}\n\\section{A}\n");
+
+    // Carol's delete and insert are her own operations, so no other agent's sequence
+    // numbers are taken, but the moved text is still credited to bob
+    let authors = engine.get_operation_authors(&doc_id).await?;
+    assert!(authors.iter().all(|(user, seq)| user != "bob" || seq.end <= 12));
+    let stats = engine.document_stats(&doc_id).await?;
+    let characters = |user: &str| stats.users.iter().find(|stats| stats.user_id == user).map_or(0, |stats| stats.characters);
+    assert_eq!(characters("bob"), 12);
+    assert_eq!(characters("carol"), 0);
+
+    replica.sync_document(&replica_id, &engine.export_document(&doc_id).await?).await?;
+    assert_eq!(replica.get_document_content(&replica_id).await?, "\\section{B}\n\\section{A}\n");
+
+    // The move is undone in one step
+    assert_eq!(engine.undo(&doc_id, "carol").await?.len(), 1);
+    assert_eq!(engine.get_document_content(&doc_id).await?, "\\section{A}\n\\section{B}\n");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_edit_inside_another_selection_sets_conflict_hint() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Hot spot".to_string(), "alice".to_string()).await?;
+    let insert = |position: usize, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position,
+        content: content.to_string(),
+    };
+    engine.apply_local_operation(&doc_id, insert(0, &"x".repeat(100))).await?;
+
+    // Bob has 10..20 selected
+    engine.update_user_presence(doc_id, UserPresence {
+        user_id: "bob".to_string(),
+        display_name: "Bob".to_string(),
+        cursor_position: Some(20),
+        selection: Some(10..20),
+        is_active: true,
+        last_activity: chrono::Utc::now().to_rfc3339(),
+        color: String::new(),
+    }).await?;
+
+    let mut events = engine.subscribe_events();
+    let mut next_hint = async || loop {
+        match events.recv().await {
+            Ok(DocumentEvent::OperationApplied { records, .. }) => return records[0].conflict_hint.clone(),
+            Ok(DocumentEvent::ActivityRecorded { .. }) => continue,
+            other => panic!("Expected an applied operation, got {:?}", other),
+        }
+    };
+
+    engine.apply_local_operation(&doc_id, insert(15, "y")).await?;
+    assert_eq!(next_hint().await, Some(ConflictHint { user_ids: vec!["bob".to_string()] }));
+
+    engine.apply_local_operation(&doc_id, insert(80, "z")).await?;
+    assert_eq!(next_hint().await, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replacing_content_only_changes_what_differs() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Diffed".to_string(), "alice".to_string()).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "hello world".to_string(),
+    }).await?;
+    let comment = engine.add_comment(&doc_id, "bob".to_string(), 6..11, "Which world?".to_string()).await?;
+    let before = engine.get_operation_log(&doc_id).await?.len();
+
+    engine.update_document_content(&doc_id, "hello brave world".to_string()).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "hello brave world");
+
+    // A single insert, leaving alice's text and the comment on it alone
+    let log = engine.get_operation_log(&doc_id).await?;
+    assert_eq!(log.len(), before + 1);
+    assert_eq!(log[before].kind, OperationKind::Insert);
+    assert_eq!(log[before].len, "brave ".len());
+    let comments = engine.get_comments(&doc_id).await?;
+    assert_eq!(comments.iter().find(|c| c.id == comment.id).unwrap().range, 12..17);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_silent_users_decay_to_inactive_then_removed() -> Result<()> {
+    use std::time::{Duration, Instant};
+
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Presence".to_string(), "alice".to_string()).await?;
+
+    engine.update_user_presence(doc_id, UserPresence {
+        user_id: "bob".to_string(),
+        display_name: "Bob".to_string(),
+        cursor_position: Some(0),
+        selection: None,
+        is_active: true,
+        last_activity: chrono::Utc::now().to_rfc3339(),
+        color: String::new(),
+    }).await?;
+    let start = Instant::now();
+    let mut events = engine.subscribe_events();
+
+    let decay = PresenceDecay {
+        inactive_after: Duration::from_secs(30),
+        remove_after: Duration::from_secs(120),
+    };
+
+    // Still within the silence window
+    assert_eq!(engine.sweep_presences(start + Duration::from_secs(10), decay), 0);
+    assert!(engine.get_document_presences(&doc_id).await?[0].is_active);
+
+    // Marked inactive once, however often the sweep runs
+    assert_eq!(engine.sweep_presences(start + Duration::from_secs(31), decay), 1);
+    assert_eq!(engine.sweep_presences(start + Duration::from_secs(60), decay), 0);
+    assert!(!engine.get_document_presences(&doc_id).await?[0].is_active);
+    match events.try_recv()? {
+        DocumentEvent::PresenceChanged { document_id, presence } => {
+            assert_eq!(document_id, doc_id);
+            assert_eq!(presence.user_id, "bob");
+            assert!(!presence.is_active);
+        }
+        other => panic!("Unexpected event: {:?}", other),
+    }
+
+    // Then removed altogether
+    assert_eq!(engine.sweep_presences(start + Duration::from_secs(121), decay), 1);
+    assert!(engine.get_document_presences(&doc_id).await?.is_empty());
+    match events.try_recv()? {
+        DocumentEvent::PresenceRemoved { document_id, user_id } => {
+            assert_eq!(document_id, doc_id);
+            assert_eq!(user_id, "bob");
+        }
+        other => panic!("Unexpected event: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_active_collaborator_count_decays_with_presence() -> Result<()> {
+    use std::time::{Duration, Instant};
+
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Count".to_string(), "alice".to_string()).await?;
+    assert_eq!(engine.active_collaborator_count(&doc_id), 0);
+
+    for user_id in ["alice", "bob"] {
+        engine.update_user_presence(doc_id, UserPresence {
+            user_id: user_id.to_string(),
+            display_name: user_id.to_string(),
+            cursor_position: Some(0),
+            selection: None,
+            is_active: true,
+            last_activity: chrono::Utc::now().to_rfc3339(),
+            color: String::new(),
+        }).await?;
+    }
+    let start = Instant::now();
+    assert_eq!(engine.active_collaborator_count(&doc_id), 2);
+
+    let decay = PresenceDecay {
+        inactive_after: Duration::from_secs(30),
+        remove_after: Duration::from_secs(120),
+    };
+    engine.sweep_presences(start + Duration::from_secs(31), decay);
+    assert_eq!(engine.active_collaborator_count(&doc_id), 0);
+
+    // Hearing from a user again brings them back
+    let mut events = engine.subscribe_events();
+    engine.touch_presence(&doc_id, "bob");
+    assert_eq!(engine.active_collaborator_count(&doc_id), 1);
+    match events.try_recv()? {
+        DocumentEvent::PresenceChanged { document_id, presence } => {
+            assert_eq!(document_id, doc_id);
+            assert_eq!(presence.user_id, "bob");
+            assert!(presence.is_active);
+        }
+        other => panic!("Unexpected event: {:?}", other),
+    }
+
+    // Only once
+    engine.touch_presence(&doc_id, "bob");
+    assert!(events.try_recv().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_v1_operations_decode_with_synthesized_id() -> Result<()> {
+    let operation = DocumentOperation::Insert {
+        document_id: uuid::Uuid::new_v4(),
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "legacy".to_string(),
+    };
+
+    // Both tagged version 1 and the bare JSON from before versioning carry no operation ID
+    let tagged = OperationEncoder::with_origin("old-node".to_string()).with_format_version(1)?.encode_operation(&operation)?;
+    assert_eq!(tagged[0], 1);
+    assert!(!String::from_utf8_lossy(&tagged).contains("op_id"));
+
+    let decoder = OperationEncoder::new();
+    for encoded in [tagged.clone(), tagged[1..].to_vec()] {
+        let decoded = decoder.decode(&encoded)?;
+        match decoded.operation {
+            DocumentOperation::Insert { content, .. } => assert_eq!(content, "legacy"),
+            other => panic!("Unexpected operation: {:?}", other),
+        }
+        assert_eq!(decoded.origin_node_id.as_deref(), Some("old-node"));
+        assert!(!decoded.op_id.is_nil());
+
+        // Every node synthesizes the same ID for the same payload
+        assert_eq!(decoder.decode(&encoded)?.op_id, decoded.op_id);
+    }
+
+    // Current operations carry their own ID
+    let current = decoder.encode_operation(&operation)?;
+    assert_eq!(current[0], OPERATION_FORMAT_VERSION);
+    assert_ne!(decoder.decode(&current)?.op_id, decoder.decode(&tagged)?.op_id);
+
+    // Versions from the future are refused
+    let mut future = current.clone();
+    future[0] = OPERATION_FORMAT_VERSION + 1;
+    let error = decoder.decode(&future).unwrap_err().to_string();
+    assert!(error.contains("newer than the latest supported version"), "{}", error);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_edit_and_join_are_recorded_in_order() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Activity".to_string(), "alice".to_string()).await?;
+
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "hello".to_string(),
+    }).await?;
+    engine.update_user_presence(doc_id, UserPresence {
+        user_id: "bob".to_string(),
+        display_name: "Bob".to_string(),
+        cursor_position: Some(0),
+        selection: None,
+        is_active: true,
+        last_activity: chrono::Utc::now().to_rfc3339(),
+        color: String::new(),
+    }).await?;
+
+    let activity = engine.get_activity(&doc_id, 10).await?;
+    assert_eq!(activity.len(), 2);
+    assert_eq!(activity[0].kind, ActivityKind::Edited { user_id: "alice".to_string() });
+    assert_eq!(activity[1].kind, ActivityKind::Joined { user_id: "bob".to_string() });
+    assert!(activity[0].sequence < activity[1].sequence);
+
+    // The limit keeps the most recent entries
+    let latest = engine.get_activity(&doc_id, 1).await?;
+    assert_eq!(latest, activity[1..]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_panicking_oplog_merge_is_rejected_cleanly() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Guarded".to_string(), "carol".to_string()).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "carol".to_string(),
+        position: 0,
+        content: "intro".to_string(),
+    }).await?;
+
+    let mut remote = OpLog::new();
+    let alice = remote.get_or_create_agent_id("alice");
+    let bob = remote.get_or_create_agent_id("bob");
+    remote.add_insert(alice, 0, "hello world");
+    remote.add_insert(bob, 5, " there");
+    remote.add_delete_without_content(alice, 0..2);
+    let mut corrupted = remote.encode(diamond_types::list::encoding::EncodeOptions::default());
+
+    // Garble the op type and position chunk, so the ops point past the end of the document.
+    // diamond-types panics on this before it gets to check the payload's checksum
+    assert_eq!(corrupted[61], 22, "Unexpected encoding layout");
+    corrupted[63] = 0xff;
+
+    let error = engine.sync_document(&doc_id, &corrupted).await.unwrap_err();
+    assert!(error.to_string().contains("oplog merge failed"), "Unexpected error: {}", error);
+    assert_eq!(engine.rejected_payload_count(), 1);
+
+    // The document is left as it was, and still accepts edits
+    assert_eq!(engine.get_document_content(&doc_id).await?, "intro");
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "carol".to_string(),
+        position: 5,
+        content: "!".to_string(),
+    }).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "intro!");
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct BannedWord(&'static str);
+
+impl OperationInterceptor for BannedWord {
+    fn before_apply(&self, operation: &DocumentOperation) -> InterceptDecision {
+        match operation {
+            DocumentOperation::Insert { content, .. } if content.contains(self.0) => {
+                InterceptDecision::Reject(format!("Inserts may not contain {:?}", self.0))
+            }
+            _ => InterceptDecision::Allow,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_interceptor_rejects_inserts_with_banned_word() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    engine.register_interceptor(std::sync::Arc::new(BannedWord("darn")));
+    let doc_id = engine.create_document("Filtered".to_string(), "alice".to_string()).await?;
+    let insert = |content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: content.to_string(),
+    };
+
+    engine.apply_local_operation(&doc_id, insert("hello")).await?;
+    let error = engine.apply_local_operation(&doc_id, insert("darn it ")).await.unwrap_err();
+    assert!(matches!(error.downcast_ref::<AppError>(), Some(AppError::OperationRejected(_))));
+    assert_eq!(engine.get_document_content(&doc_id).await?, "hello");
+
+    // Operations from peers are filtered too
+    let encoded = OperationEncoder::with_origin("peer".to_string()).encode_operation(&insert("darn "))?;
+    assert!(engine.apply_remote_operation(&doc_id, &encoded).await.is_err());
+    assert_eq!(engine.get_document_content(&doc_id).await?, "hello");
+    assert_eq!(engine.rejected_payload_count(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_document_limit_rejects_one_past_it_until_a_slot_frees() -> Result<()> {
+    let engine = CrdtEngine::new()?.with_max_documents(2);
+    let first = engine.create_document("First".to_string(), "alice".to_string()).await?;
+    let encoded = engine.export_document(&first).await?;
+    engine.create_document("Second".to_string(), "alice".to_string()).await?;
+
+    let error = engine.create_document("Third".to_string(), "alice".to_string()).await.unwrap_err();
+    assert!(error.to_string().contains("document capacity reached"), "Unexpected error: {}", error);
+    assert!(engine.import_document("Imported".to_string(), "alice".to_string(), &encoded).await.is_err());
+    assert_eq!(engine.get_all_documents().await?.len(), 2);
+
+    // Trashed documents still take up room until they are removed for good
+    engine.delete_document(&first).await?;
+    assert!(engine.create_document("Third".to_string(), "alice".to_string()).await.is_err());
+    engine.purge_document(&first).await?;
+    engine.create_document("Third".to_string(), "alice".to_string()).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deleted_document_is_hidden_until_restored() -> Result<()> {
+    let engine = CrdtEngine::new()?.with_trash_retention(std::time::Duration::from_secs(60));
+    let doc_id = engine.create_document("Draft".to_string(), "alice".to_string()).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "keep me".to_string(),
+    }).await?;
+
+    engine.delete_document(&doc_id).await?;
+    assert!(engine.get_all_documents().await?.is_empty());
+    assert!(engine.list_documents().await?.is_empty());
+    assert!(engine.get_document(&doc_id).await.is_err());
+    let trashed = engine.list_trashed_documents().await?;
+    assert_eq!(trashed.len(), 1);
+    assert_eq!(trashed[0].0.read().await.id, doc_id);
+
+    // Still within the retention period, so nothing is reaped and it comes back intact
+    assert_eq!(engine.reap_trash(chrono::Utc::now() + chrono::Duration::seconds(30)).await?, 0);
+    engine.restore_document(&doc_id).await?;
+    assert_eq!(engine.get_all_documents().await?, vec![doc_id]);
+    assert_eq!(engine.get_document_content(&doc_id).await?, "keep me");
+
+    // Once the period is over, trashed documents are gone for good
+    engine.delete_document(&doc_id).await?;
+    assert_eq!(engine.reap_trash(chrono::Utc::now() + chrono::Duration::seconds(61)).await?, 1);
+    assert!(engine.restore_document(&doc_id).await.is_err());
+    assert!(engine.list_trashed_documents().await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_insert_over_the_operation_size_limit_is_rejected() -> Result<()> {
+    let engine = CrdtEngine::new()?.with_max_operation_bytes(16);
+    let doc_id = engine.create_document("Limits".to_string(), "alice".to_string()).await?;
+    let insert = |content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: content.to_string(),
+    };
+
+    let error = engine.apply_local_operation(&doc_id, insert(&"x".repeat(17))).await.unwrap_err();
+    assert!(matches!(error.downcast_ref::<AppError>(), Some(AppError::OperationRejected(_))));
+    assert!(error.to_string().contains("smaller chunks"), "Unexpected error: {}", error);
+    assert_eq!(engine.get_document_content(&doc_id).await?, "");
+
+    // Right at the limit is fine
+    engine.apply_local_operation(&doc_id, insert(&"x".repeat(16))).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?.len(), 16);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_checkpoint_shrinks_exported_oplog_after_long_history() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Checkpoint".to_string(), "alice".to_string()).await?;
+
+    // Type and erase a scratch line many times, leaving a short document behind
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "\\section{Intro}\n".to_string(),
+    }).await?;
+    for round in 0..200 {
+        let user_id = if round % 2 == 0 { "alice" } else { "bob" };
+        let position = engine.get_document_content(&doc_id).await?.chars().count();
+        engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: user_id.to_string(),
+            position,
+            content: format!("draft sentence number {}", round),
+        }).await?;
+        engine.apply_local_operation(&doc_id, DocumentOperation::Delete {
+            document_id: doc_id,
+            user_id: user_id.to_string(),
+            range: position..position + format!("draft sentence number {}", round).chars().count(),
+        }).await?;
+    }
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "bob".to_string(),
+        position: 16,
+        content: "Kept.".to_string(),
+    }).await?;
+
+    let content = engine.get_document_content(&doc_id).await?;
+    let before = engine.export_document(&doc_id).await?.len();
+
+    let reclaimed = engine.checkpoint_document(&doc_id).await?;
+    let after = engine.export_document(&doc_id).await?.len();
+
+    assert!(after < before, "checkpoint grew the oplog from {} to {} bytes", before, after);
+    assert_eq!(reclaimed, (before - after) as u64);
+    assert_eq!(engine.get_document_content(&doc_id).await?, content);
+
+    // The text is still attributed to whoever wrote it
+    let users: Vec<String> = engine.get_operation_authors(&doc_id).await?.into_iter().map(|(user, _)| user).collect();
+    assert_eq!(users, ["alice", "bob"]);
+
+    // Editing carries on from the compacted history
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "% header\n".to_string(),
+    }).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, format!("% header\n{}", content));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_synced_document_is_not_checkpointed_so_peer_logs_still_merge() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Shared".to_string(), "alice".to_string()).await?;
+    let insert = |user_id: &str, position: usize, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: user_id.to_string(),
+        position,
+        content: content.to_string(),
+    };
+    engine.apply_local_operation(&doc_id, insert("alice", 0, "draft")).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Delete {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        range: 0..5,
+    }).await?;
+    engine.apply_local_operation(&doc_id, insert("alice", 0, "final")).await?;
+
+    // A peer takes a copy of the history and keeps editing it
+    let peer = CrdtEngine::new()?;
+    let peer_id = peer.create_document("Shared".to_string(), "alice".to_string()).await?;
+    let (history, _) = engine.export_since(&doc_id, None).await?;
+    peer.sync_document(&peer_id, &history).await?;
+    let position = peer.get_document_content(&peer_id).await?.chars().count();
+    peer.apply_local_operation(&peer_id, DocumentOperation::Insert {
+        document_id: peer_id,
+        user_id: "alice".to_string(),
+        position,
+        content: " text".to_string(),
+    }).await?;
+
+    // Compacting would hand alice's sequence numbers out again, for text the peer knows
+    // under them as something else
+    let error = engine.checkpoint_document(&doc_id).await.unwrap_err();
+    assert!(matches!(error.downcast_ref::<AppError>(), Some(AppError::OperationRejected(_))));
+    assert!(engine.get_document(&doc_id).await?.read().await.history_shared);
+
+    // So the peer's log, built on the pre-checkpoint history, still merges cleanly
+    engine.sync_document(&doc_id, &peer.export_document(&peer_id).await?).await?;
+    assert_eq!(engine.get_document_content(&doc_id).await?, "final text");
+    assert_eq!(engine.get_document_content(&doc_id).await?, peer.get_document_content(&peer_id).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_repair_integrity_fixes_half_finished_documents() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let mut ids = Vec::new();
+    for title in ["No branch", "No oplog", "Orphaned oplog", "Orphaned branch", "Healthy"] {
+        let doc_id = engine.create_document(title.to_string(), "alice".to_string()).await?;
+        engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: "alice".to_string(),
+            position: 0,
+            content: title.to_string(),
+        }).await?;
+        ids.push(doc_id);
+    }
+    assert!(engine.verify_integrity().is_empty());
+
+    let mut injected = vec![
+        IntegrityIssue::MissingBranch(ids[0]),
+        IntegrityIssue::MissingOplog(ids[1]),
+        IntegrityIssue::OrphanedOplog(ids[2]),
+        IntegrityIssue::OrphanedBranch(ids[3]),
+    ];
+    for issue in &injected {
+        engine.break_integrity(issue);
+    }
+    injected.sort_by_key(|issue| issue.document_id());
+    assert_eq!(engine.verify_integrity(), injected);
+    assert!(engine.get_document_content(&ids[0]).await.is_err());
+
+    assert_eq!(engine.repair_integrity().await?, injected);
+    assert!(engine.verify_integrity().is_empty());
+
+    // The missing branch and OpLog are rebuilt from what was left, the orphans are gone
+    assert_eq!(engine.get_document_content(&ids[0]).await?, "No branch");
+    assert_eq!(engine.get_document_content(&ids[1]).await?, "No oplog");
+    assert_eq!(engine.get_all_documents().await?.len(), 3);
+    assert_eq!(engine.get_document_content(&ids[4]).await?, "Healthy");
+
+    // Repaired documents can be edited again
+    engine.apply_local_operation(&ids[1], DocumentOperation::Insert {
+        document_id: ids[1],
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "Fixed: ".to_string(),
+    }).await?;
+    assert_eq!(engine.get_document_content(&ids[1]).await?, "Fixed: No oplog");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frozen_document_rejects_edits_and_queues_remote_ones() -> Result<()> {
+    let engine = std::sync::Arc::new(tokio::sync::RwLock::new(CrdtEngine::new()?));
+    let doc_id = engine.read().await.create_document("Frozen".to_string(), "alice".to_string()).await?;
+    let insert = |user_id: &str, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: user_id.to_string(),
+        position: 0,
+        content: content.to_string(),
+    };
+    let remote = OperationEncoder::new().encode_operation(&insert("bob", "remote "))?;
+    let peer_oplog = {
+        let peer = CrdtEngine::new()?;
+        let peer_id = peer.create_document("Frozen".to_string(), "carol".to_string()).await?;
+        peer.apply_local_operation(&peer_id, DocumentOperation::Insert {
+            document_id: peer_id,
+            user_id: "carol".to_string(),
+            position: 0,
+            content: "synced ".to_string(),
+        }).await?;
+        peer.export_document(&peer_id).await?
+    };
+
+    let guard = FreezeGuard::new(std::sync::Arc::clone(&engine), doc_id).await?;
+    {
+        let engine = engine.read().await;
+        let rejected = engine.apply_local_operation(&doc_id, insert("alice", "local")).await.unwrap_err();
+        assert!(matches!(rejected.downcast_ref::<AppError>(), Some(AppError::CrdtError(message)) if message == "document frozen"));
+        assert!(engine.apply_typed_operation(&doc_id, insert("alice", "typed")).await.is_err());
+
+        // Remote operations wait for the thaw, and so do OpLogs synced from peers
+        engine.apply_remote_operation(&doc_id, &remote).await?;
+        assert_eq!(engine.get_document_content(&doc_id).await?, "");
+        engine.sync_document(&doc_id, &peer_oplog).await?;
+        assert_eq!(engine.get_document_content(&doc_id).await?, "");
+
+        // Nested freezes hold until the last is lifted
+        engine.freeze_document(&doc_id)?;
+        engine.unfreeze_document(&doc_id).await?;
+        assert!(engine.is_frozen(&doc_id));
+    }
+    guard.release().await;
+
+    {
+        let engine = engine.read().await;
+        assert!(!engine.is_frozen(&doc_id));
+        assert_eq!(engine.get_document_content(&doc_id).await?, "remote synced ");
+        engine.apply_local_operation(&doc_id, insert("alice", "local ")).await?;
+        assert_eq!(engine.get_document_content(&doc_id).await?, "local remote synced ");
+    }
+
+    // A guard dropped without being released, as on an error, still thaws the document
+    let result: Result<()> = async {
+        let _guard = FreezeGuard::new(std::sync::Arc::clone(&engine), doc_id).await?;
+        Err(AppError::GitError("push rejected".to_string()).into())
+    }.await;
+    assert!(result.is_err());
+    tokio::task::yield_now().await;
+    assert!(!engine.read().await.is_frozen(&doc_id));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_overflowing_the_frozen_queue_asks_for_a_resync() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Frozen".to_string(), "alice".to_string()).await?;
+    let mut events = engine.subscribe_events();
+    let encoder = OperationEncoder::new();
+
+    engine.freeze_document(&doc_id)?;
+    for _ in 0..=MAX_QUEUED_WHILE_FROZEN {
+        let remote = encoder.encode_operation(&DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: "bob".to_string(),
+            position: 0,
+            content: "x".to_string(),
+        })?;
+        engine.apply_remote_operation(&doc_id, &remote).await?;
+    }
+    engine.unfreeze_document(&doc_id).await?;
+
+    // Nothing from the dropped queue is applied; peers are asked for the document instead
+    assert_eq!(engine.get_document_content(&doc_id).await?, "");
+    let mut resync_requested = false;
+    while let Ok(event) = events.try_recv() {
+        resync_requested |= matches!(event, DocumentEvent::ResyncNeeded { document_id } if document_id == doc_id);
+    }
+    assert!(resync_requested);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_preview_merge_shows_merged_content_without_changing_document() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Paper".to_string(), "alice".to_string()).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "Hello".to_string(),
+    }).await?;
+
+    let replica = CrdtEngine::new()?;
+    let replica_id = replica.import_document("Paper".to_string(), "alice".to_string(), &engine.export_document(&doc_id).await?).await?;
+    replica.apply_local_operation(&replica_id, DocumentOperation::Insert {
+        document_id: replica_id,
+        user_id: "bob".to_string(),
+        position: 5,
+        content: " world".to_string(),
+    }).await?;
+    let remote = replica.export_document(&replica_id).await?;
+
+    let before = engine.export_document(&doc_id).await?;
+    assert_eq!(engine.preview_merge(&doc_id, &remote).await?, "Hello world");
+    assert_eq!(engine.get_document_content(&doc_id).await?, "Hello");
+    assert_eq!(engine.export_document(&doc_id).await?, before);
+
+    // A payload that doesn't decode fails the preview, and still changes nothing
+    assert!(engine.preview_merge(&doc_id, &remote[..remote.len() / 2]).await.is_err());
+    assert_eq!(engine.get_document_content(&doc_id).await?, "Hello");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_large_insert_is_logged_as_slow() -> Result<()> {
+    let engine = CrdtEngine::new()?.with_slow_op_threshold(Some(std::time::Duration::from_millis(1)));
+    let doc_id = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+
+    // Large enough to take well over a millisecond to apply and encode
+    let content = "\\section{Results} lorem ipsum dolor sit amet\n".repeat(100_000);
+    let bytes = content.len();
+    engine
+        .apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: "alice".to_string(),
+            position: 0,
+            content,
+        })
+        .await?;
+
+    let histograms = engine.operation_timings().histograms();
+    let local = histograms
+        .iter()
+        .find(|histogram| histogram.operation == "apply_local_operation")
+        .expect("local operations are timed");
+    assert_eq!(local.count, 1);
+    assert_eq!(local.slow, 1);
+    assert!(local.max_ms > 1.0);
+    assert_eq!(local.buckets.iter().map(|bucket| bucket.count).sum::<u64>(), 1);
+
+    // The warning itself goes through the same check
+    assert!(engine.operation_timings().record("sync_document", &doc_id, bytes, std::time::Duration::from_millis(2)));
+    assert!(!engine.operation_timings().record("sync_document", &doc_id, 1, std::time::Duration::from_micros(10)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_branch_manager_creates_missing_documents_only_when_enabled() -> Result<()> {
+    let engine = std::sync::Arc::new(tokio::sync::RwLock::new(CrdtEngine::new()?));
+    let missing = uuid::Uuid::new_v4();
+    assert!(!crate::utils::config::Config::default().storage.auto_create_missing_documents);
+
+    // By default a mistyped ID stays an error, and nothing is created for it
+    let manager = DocumentBranchManager::new(std::sync::Arc::clone(&engine));
+    let error = manager.ensure_document_exists(&missing, "Typo").await.unwrap_err();
+    assert!(error.to_string().contains(&format!("Document not found: {}", missing)));
+    assert!(engine.read().await.get_all_documents().await?.is_empty());
+
+    // Opted in, the document is created under the ID that was asked for
+    let manager = DocumentBranchManager::new(std::sync::Arc::clone(&engine)).with_auto_create(true);
+    assert!(!manager.ensure_document_exists(&missing, "Healed").await?);
+    assert!(manager.ensure_document_exists(&missing, "Healed").await?);
+    let document = engine.read().await.get_document(&missing).await?;
+    assert_eq!(document.read().await.title, "Healed");
+    assert_eq!(document.read().await.owner, "system");
+
+    Ok(())
+}
@@ -0,0 +1,498 @@
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+use warp::Filter;
+
+use crate::api::http::{GitStatusResponse, HttpApi};
+use crate::crdt::engine::CrdtEngine;
+use crate::crdt::operations::DocumentOperation;
+use crate::git::manager::GitManager;
+use crate::git::repository::RepositoryManager;
+use crate::git::sync::{sanitize_filename, GitSync, MAX_FILENAME_LENGTH};
+use crate::storage::document_persistence_service::DocumentPersistenceService;
+use crate::utils::config::Config;
+use crate::utils::errors::AppError;
+
+#[tokio::test]
+async fn test_publish_creates_pushes_and_links_repository() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-git-test-{}", uuid::Uuid::new_v4()));
+    let remote_path = dir.join("remote.git");
+    git2::Repository::init_bare(&remote_path)?;
+
+    // Stand in for the GitHub API, handing out the local bare repository as the clone URL
+    let (requests_tx, mut requests_rx) = mpsc::unbounded_channel();
+    let clone_url = remote_path.to_string_lossy().to_string();
+    let create_repo = warp::path!("user" / "repos")
+        .and(warp::post())
+        .and(warp::header::<String>("authorization"))
+        .and(warp::body::json())
+        .map(move |authorization: String, body: serde_json::Value| {
+            requests_tx.send((authorization, body)).unwrap();
+            warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "html_url": "https://github.com/alice/thesis",
+                    "clone_url": clone_url,
+                })),
+                warp::http::StatusCode::CREATED,
+            )
+        });
+    let (api_addr, api) = warp::serve(create_repo).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(api);
+
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+    config.git.github_token = Some("secret".to_string());
+    config.git.github_username = Some("alice".to_string());
+    config.git.github_api_url = format!("http://{}", api_addr);
+
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+        engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: "alice".to_string(),
+            position: 0,
+            content: "\\documentclass{article}".to_string(),
+        }).await?;
+        doc_id
+    };
+
+    let mut manager = GitManager::new(&config, Arc::clone(&engine))?;
+    let html_url = manager.publish_document(&doc_id, "alice/thesis", true).await?;
+    assert_eq!(html_url, "https://github.com/alice/thesis");
+
+    let (authorization, body) = requests_rx.recv().await.unwrap();
+    assert_eq!(authorization, "Bearer secret");
+    assert_eq!(body, serde_json::json!({ "name": "thesis", "private": true }));
+
+    // The content was committed and pushed to the new remote
+    let remote = git2::Repository::open_bare(&remote_path)?;
+    let commit = remote.find_reference("refs/heads/master")?.peel_to_commit()?;
+    let blob = commit.tree()?.get_path(std::path::Path::new("Thesis.tex"))?.to_object(&remote)?;
+    assert_eq!(blob.as_blob().unwrap().content(), b"\\documentclass{article}");
+
+    // And the document now points at the repository
+    let engine = engine.read().await;
+    let document = engine.get_document(&doc_id).await?;
+    assert_eq!(document.read().await.repository_url.as_deref(), Some(remote_path.to_string_lossy().as_ref()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_commit_pushed_to_remote_is_merged_into_crdt() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-git-test-{}", uuid::Uuid::new_v4()));
+    let remote_path = dir.join("remote.git");
+    git2::Repository::init_bare(&remote_path)?;
+
+    // Someone working on the repository directly, outside the app
+    let external = git2::Repository::clone(&remote_path.to_string_lossy(), dir.join("external"))?;
+    commit_and_push(&external, "Thesis.tex", "\\section{Intro}\n\\section{Method}\n")?;
+
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+        engine.update_document_content(&doc_id, "\\section{Intro}\n\\section{Method}\n".to_string()).await?;
+        engine.set_repository_url(&doc_id, remote_path.to_string_lossy().to_string(), None).await?;
+        engine.flush_coalesced_operations()?;
+        doc_id
+    };
+
+    let sync = GitSync::new(
+        RepositoryManager::new(config.git.clone()),
+        Arc::clone(&engine),
+        std::time::Duration::from_secs(0),
+    );
+    sync.sync_document(&doc_id).await?;
+
+    // Both sides edit the document before the next sync
+    let external = git2::Repository::clone(&remote_path.to_string_lossy(), dir.join("external-2"))?;
+    commit_and_push(&external, "Thesis.tex", "\\section{Intro}\n\\section{Method}\n\\section{Results}\n")?;
+    engine.read().await.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "% draft\n".to_string(),
+    }).await?;
+
+    sync.sync_document(&doc_id).await?;
+
+    let merged = "% draft\n\\section{Intro}\n\\section{Method}\n\\section{Results}\n";
+    let engine = engine.read().await;
+    assert_eq!(engine.get_document_content(&doc_id).await?, merged);
+
+    // The remote's changes are attributed to the remote they came from
+    let authors = engine.get_operation_authors(&doc_id).await?;
+    assert!(authors.iter().any(|(agent, _)| agent == "git:origin"));
+
+    // The remote's changes are broadcast to peers like any other edit
+    assert!(engine.flush_coalesced_operations()?.iter().any(|(id, _)| *id == doc_id));
+
+    // And the merge was pushed back, so the remote has both sides too
+    let remote = git2::Repository::open_bare(&remote_path)?;
+    let commit = remote.find_reference("refs/heads/master")?.peel_to_commit()?;
+    let blob = commit.tree()?.get_path(std::path::Path::new("Thesis.tex"))?.to_object(&remote)?;
+    assert_eq!(blob.as_blob().unwrap().content(), merged.as_bytes());
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    Ok(())
+}
+
+/// Commit a file on the current branch and push it to origin
+#[test]
+fn test_gc_packs_history_and_keeps_it_readable() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-git-test-{}", uuid::Uuid::new_v4()));
+    let remote_path = dir.join("remote.git");
+    git2::Repository::init_bare(&remote_path)?;
+    let repo = git2::Repository::clone(&remote_path.to_string_lossy(), dir.join("local"))?;
+
+    // A long file revised many times, which loose objects store in full on every commit
+    let mut content: String = (0..100).map(|_| format!("% {}\n", uuid::Uuid::new_v4())).collect();
+    for section in 0..20 {
+        content.push_str(&format!("\\section{{Part {}}}\n", section));
+        commit_and_push(&repo, "Thesis.tex", &content)?;
+    }
+
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+    let manager = RepositoryManager::new(config.git.clone());
+
+    assert!(manager.gc(&repo)? > 0);
+    let head = manager.head_commit(&repo)?.unwrap();
+    assert_eq!(manager.read_file_at(&repo, head, "Thesis.tex")?, content);
+
+    // Everything is packed now, so a second run has nothing left to do
+    assert_eq!(manager.gc(&repo)?, 0);
+
+    Ok(())
+}
+
+fn commit_and_push(repo: &git2::Repository, filename: &str, content: &str) -> Result<()> {
+    std::fs::write(repo.workdir().unwrap().join(filename), content)?;
+    let mut index = repo.index()?;
+    index.add_path(std::path::Path::new(filename))?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+
+    let signature = git2::Signature::now("bob", "bob@example.com")?;
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    repo.commit(Some("HEAD"), &signature, &signature, "Edit outside the app", &tree, &parents)?;
+
+    repo.find_remote("origin")?.push(&["refs/heads/master:refs/heads/master"], None)?;
+    Ok(())
+}
+
+#[test]
+fn test_sanitized_filename_stays_inside_repository() {
+    let repo = std::path::Path::new("/srv/repositories/thesis");
+
+    let filename = sanitize_filename("../../etc/passwd");
+    assert_eq!(filename, "etc_passwd.tex");
+    assert_eq!(repo.join(&filename).parent(), Some(repo));
+
+    assert_eq!(sanitize_filename("My  \"Thesis\"\tDraft"), "My_Thesis_Draft.tex");
+    assert_eq!(sanitize_filename("Übung: Kapitel 1.tex"), "Übung_Kapitel_1.tex");
+    assert_eq!(sanitize_filename("/.."), "document.tex");
+
+    let long = sanitize_filename(&"é".repeat(500));
+    assert_eq!(long.chars().count(), MAX_FILENAME_LENGTH);
+    assert!(long.ends_with(".tex"));
+}
+
+#[tokio::test]
+async fn test_concurrent_syncs_of_one_document_are_serialized() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-git-test-{}", uuid::Uuid::new_v4()));
+    let remote_path = dir.join("remote.git");
+    git2::Repository::init_bare(&remote_path)?;
+
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+    config.git.max_concurrent_operations = 2;
+
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+        engine.update_document_content(&doc_id, "\\section{Intro}\n".to_string()).await?;
+        engine.set_repository_url(&doc_id, remote_path.to_string_lossy().to_string(), None).await?;
+        doc_id
+    };
+    let manager = Arc::new(RwLock::new(GitManager::new(&config, Arc::clone(&engine))?));
+
+    // Whoever holds a document's guard has its repository to itself
+    let active = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let holders: Vec<_> = (0..2)
+        .map(|_| {
+            let (manager, active, engine) = (Arc::clone(&manager), Arc::clone(&active), Arc::clone(&engine));
+            tokio::spawn(async move {
+                let manager = manager.read().await;
+                let _guard = manager.lock_document(&doc_id).await.unwrap();
+                // Edits only pause while remote content is merged, not for the whole operation
+                assert!(!engine.read().await.is_frozen(&doc_id));
+                assert_eq!(active.fetch_add(1, std::sync::atomic::Ordering::SeqCst), 0);
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                active.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            })
+        })
+        .collect();
+    for holder in holders {
+        holder.await?;
+    }
+
+    // Two syncs requested at once both succeed, one after the other
+    let first = async { manager.read().await.sync_to_repository(&doc_id).await };
+    let second = async {
+        engine.read().await.update_document_content(&doc_id, "\\section{Intro}\n\\section{Method}\n".to_string()).await?;
+        manager.read().await.sync_to_repository(&doc_id).await
+    };
+    let (first, second) = tokio::join!(first, second);
+    first?;
+    second?;
+
+    // The remote's history is a single line ending in the latest content
+    let remote = git2::Repository::open_bare(&remote_path)?;
+    let head = remote.find_reference("refs/heads/master")?.peel_to_commit()?;
+    let mut commit = Some(head.clone());
+    while let Some(current) = commit {
+        assert!(current.parent_count() <= 1);
+        commit = current.parents().next();
+    }
+    let blob = head.tree()?.get_path(std::path::Path::new("Thesis.tex"))?.to_object(&remote)?;
+    assert_eq!(blob.as_blob().unwrap().content(), b"\\section{Intro}\n\\section{Method}\n");
+
+    // Saving goes through the same locks, and none is kept once nothing waits for it
+    engine.read().await.update_document_content(&doc_id, "\\section{Results}\n".to_string()).await?;
+    let persistence = DocumentPersistenceService::new(Arc::clone(&engine), Arc::clone(&manager), 300);
+    persistence.save_document(&doc_id).await?;
+    let head = remote.find_reference("refs/heads/master")?.peel_to_commit()?;
+    let blob = head.tree()?.get_path(std::path::Path::new("Thesis.tex"))?.to_object(&remote)?;
+    assert_eq!(blob.as_blob().unwrap().content(), b"\\section{Results}\n");
+    assert_eq!(manager.read().await.locked_documents(), 0);
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_burst_of_edits_is_synced_once_after_quiet_period() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-git-test-{}", uuid::Uuid::new_v4()));
+    let remote_path = dir.join("remote.git");
+    git2::Repository::init_bare(&remote_path)?;
+
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Notes".to_string(), "alice".to_string()).await?;
+        engine.set_repository_url(&doc_id, remote_path.to_string_lossy().to_string(), None).await?;
+        doc_id
+    };
+    let manager = Arc::new(RwLock::new(GitManager::new(&config, Arc::clone(&engine))?));
+    let events = engine.read().await.subscribe_events();
+
+    // Edits come faster than the quiet period, then stop
+    let quiet_period = std::time::Duration::from_millis(300);
+    let edits = async {
+        let mut content = String::new();
+        for line in 0..5 {
+            content.push_str(&format!("line {}\n", line));
+            engine.read().await.update_document_content(&doc_id, content.clone()).await?;
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        tokio::time::sleep(quiet_period * 3).await;
+        Ok::<_, anyhow::Error>(content)
+    };
+    // The loop runs as an ordinary task, taking a blocking thread only to sync
+    let sync = tokio::spawn(GitManager::run_quiet_sync(Arc::clone(&manager), events, quiet_period));
+    let content = edits.await?;
+    assert!(!sync.is_finished(), "Quiet sync stopped");
+    sync.abort();
+
+    // The document was committed once, holding the whole burst; the peer list is committed
+    // separately
+    let remote = git2::Repository::open_bare(&remote_path)?;
+    let mut history = remote.revwalk()?;
+    history.push_ref("refs/heads/master")?;
+    let mut document_commits = Vec::new();
+    for oid in history {
+        let commit = remote.find_commit(oid?)?;
+        if commit.message() == Some("Update document Notes") {
+            document_commits.push(commit);
+        }
+    }
+    assert_eq!(document_commits.len(), 1);
+    let blob = document_commits[0].tree()?.get_path(std::path::Path::new("Notes.tex"))?.to_object(&remote)?;
+    assert_eq!(blob.as_blob().unwrap().content(), content.as_bytes());
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_repository_linked_to_another_document_is_rejected() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-git-test-{}", uuid::Uuid::new_v4()));
+    let remote_path = dir.join("remote.git");
+    git2::Repository::init_bare(&remote_path)?;
+    let remote_url = remote_path.to_string_lossy().to_string();
+
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let (thesis, paper) = {
+        let engine = engine.read().await;
+        (
+            engine.create_document("Thesis".to_string(), "alice".to_string()).await?,
+            engine.create_document("Paper".to_string(), "alice".to_string()).await?,
+        )
+    };
+    let mut manager = GitManager::new(&config, Arc::clone(&engine))?;
+    manager.clone_repository(&thesis, &remote_url).await?;
+
+    // The same repository, however its URL is spelled, can't be linked to a second document
+    for url in [remote_url.clone(), format!("{}/", remote_url)] {
+        let error = manager.clone_repository(&paper, &url).await.unwrap_err();
+        assert!(
+            matches!(error.downcast_ref::<AppError>(), Some(AppError::RepositoryInUse(_, id)) if *id == thesis),
+            "{}", error
+        );
+    }
+    let error = engine.read().await.set_repository_url(&paper, remote_url.clone(), None).await.unwrap_err();
+    assert!(matches!(error.downcast_ref::<AppError>(), Some(AppError::RepositoryInUse(..))));
+    let document = engine.read().await.get_document(&paper).await?;
+    assert_eq!(document.read().await.repository_url, None);
+    assert!(!config.git.repositories_path.join(paper.to_string()).exists());
+
+    // Linking a document to its own repository again is fine
+    manager.clone_repository(&thesis, &remote_url).await?;
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_commit_credits_each_user_who_edited_since_the_last_commit() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-git-test-{}", uuid::Uuid::new_v4()));
+    let remote_path = dir.join("remote.git");
+    git2::Repository::init_bare(&remote_path)?;
+
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = {
+        let engine = engine.read().await;
+        let doc_id = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+        engine.set_repository_url(&doc_id, remote_path.to_string_lossy().to_string(), None).await?;
+        doc_id
+    };
+    let insert = |user: &str, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: user.to_string(),
+        position: 0,
+        content: content.to_string(),
+    };
+
+    let sync = GitSync::new(
+        RepositoryManager::new(config.git.clone()),
+        Arc::clone(&engine),
+        std::time::Duration::from_secs(0),
+    );
+    let remote = git2::Repository::open_bare(&remote_path)?;
+    // The document's latest commit; the bootstrap file is committed separately after it
+    let head_message = || -> Result<String> {
+        let mut commit = remote.find_reference("refs/heads/master")?.peel_to_commit()?;
+        while !commit.summary().unwrap_or_default().starts_with("Update document") {
+            commit = commit.parent(0)?;
+        }
+        Ok(commit.message().unwrap_or_default().to_string())
+    };
+    let trailers = |message: &str| -> Vec<String> {
+        message.lines().filter(|line| line.starts_with("Co-authored-by:")).map(str::to_string).collect()
+    };
+
+    engine.read().await.apply_local_operation(&doc_id, insert("alice", "\\section{Intro}\n")).await?;
+    engine.read().await.apply_local_operation(&doc_id, insert("bob", "% draft\n")).await?;
+    engine.read().await.apply_local_operation(&doc_id, insert("alice", "% todo\n")).await?;
+    sync.sync_document(&doc_id).await?;
+
+    let message = head_message()?;
+    assert!(message.starts_with("Update document Thesis\n\n"), "{}", message);
+    assert_eq!(trailers(&message), [
+        "Co-authored-by: alice <alice@users.noreply.texswarm>",
+        "Co-authored-by: bob <bob@users.noreply.texswarm>",
+    ]);
+
+    // Only those who edited since the last commit are credited on the next one
+    engine.read().await.apply_local_operation(&doc_id, insert("bob", "% final\n")).await?;
+    sync.sync_document(&doc_id).await?;
+    assert_eq!(trailers(&head_message()?), ["Co-authored-by: bob <bob@users.noreply.texswarm>"]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_remote_check_reports_unreachable_and_healthy_remotes() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-git-test-{}", uuid::Uuid::new_v4()));
+    let remote_path = dir.join("remote.git");
+    git2::Repository::init_bare(&remote_path)?;
+
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let (healthy, broken) = {
+        let engine = engine.read().await;
+        let healthy = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+        let broken = engine.create_document("Paper".to_string(), "alice".to_string()).await?;
+        engine.set_repository_url(&healthy, remote_path.to_string_lossy().to_string(), None).await?;
+        engine.set_repository_url(&broken, dir.join("deleted.git").to_string_lossy().to_string(), None).await?;
+        (healthy, broken)
+    };
+    let manager = Arc::new(RwLock::new(GitManager::new(&config, Arc::clone(&engine))?));
+    let route = HttpApi::git_status_route(Arc::clone(&engine), Arc::clone(&manager));
+    let git_status = |doc_id: uuid::Uuid| {
+        let route = route.clone();
+        async move {
+            let response = warp::test::request()
+                .path(&format!("/api/documents/{}/git-status", doc_id))
+                .reply(&route)
+                .await;
+            serde_json::from_slice::<GitStatusResponse>(response.body()).unwrap()
+        }
+    };
+
+    // Nothing is known before the first check
+    assert_eq!(git_status(healthy).await.reachable, None);
+
+    assert_eq!(manager.read().await.check_remotes().await?, 1);
+
+    let status = git_status(healthy).await;
+    assert_eq!(status.reachable, Some(true));
+    assert_eq!(status.last_success, status.last_checked);
+    assert_eq!(status.last_error, None);
+
+    let status = git_status(broken).await;
+    assert_eq!(status.reachable, Some(false));
+    assert!(status.last_checked.is_some());
+    assert_eq!(status.last_success, None);
+    assert!(status.last_error.is_some_and(|error| !error.is_empty()));
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    Ok(())
+}
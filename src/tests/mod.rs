@@ -1 +1,7 @@
 pub mod api_tests;
+pub mod websocket_tests;
+pub mod crdt_tests;
+pub mod config_tests;
+pub mod network_tests;
+pub mod git_tests;
+pub mod storage_tests;
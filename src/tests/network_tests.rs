@@ -0,0 +1,1076 @@
+use anyhow::Result;
+use libp2p::PeerId;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::api::protocol::ApiMessage;
+use crate::api::websocket::WebSocketServer;
+use crate::crdt::document::{Document, DocumentVisibility};
+use crate::crdt::engine::CrdtEngine;
+use crate::crdt::events::DocumentEvent;
+use crate::crdt::integrity::HashComparison;
+use crate::crdt::operations::DocumentOperation;
+use crate::network::directory::{DocumentDirectory, ANNOUNCE_TOPIC};
+use crate::network::engine::{DocumentTopic, NetworkEngine};
+use crate::network::pause::{PropagationPause, MAX_HELD_OPERATIONS};
+use crate::network::polling::{self, PollingSync};
+use crate::network::protocol::{NetworkMessage, ProtocolVersion};
+use crate::network::rendezvous::{BootstrapEntry, GitRendezvous, BOOTSTRAP_FILE};
+use crate::network::service::{NetworkEvent, RealNetworkService, INCOMPATIBLE_VERSION};
+use crate::network::transfer::{ChunkHeader, IncomingTransfers, OutgoingTransfers, TRANSFER_CHUNK_SIZE};
+use crate::utils::config::{Config, SyncMode};
+
+#[tokio::test]
+async fn test_unanswered_request_times_out() -> Result<()> {
+    let mut config = Config::default().network;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.request_timeout_secs = 1;
+
+    let service = RealNetworkService::new(config).await?;
+
+    // Nobody is listening as this peer, so the request is never answered
+    service.send_request(
+        PeerId::random(),
+        NetworkMessage::SyncRequest {
+            document_id: Uuid::new_v4(),
+            user_id: "alice".to_string(),
+            version: None,
+        },
+        "sync-1".to_string(),
+    ).await?;
+    assert_eq!(service.pending_request_count().await, 1);
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(service.pending_request_count().await, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_idle_peer_is_disconnected() -> Result<()> {
+    let mut config = Config::default().network;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.enable_mdns = false;
+
+    let mut idle_config = config.clone();
+    idle_config.connection_idle_timeout_secs = Some(1);
+
+    let local = Arc::new(RealNetworkService::new(idle_config).await?);
+    let remote = Arc::new(RealNetworkService::new(config).await?);
+    let _local_events = Arc::clone(&local).start_event_loop().await?;
+    let _remote_events = Arc::clone(&remote).start_event_loop().await?;
+
+    let remote_addr = wait_for(|| async { remote.listen_addresses().await.into_iter().next() }).await
+        .expect("Remote never started listening");
+    local.dial(remote_addr).await?;
+
+    // The peers connect, and since they share no document the connection is closed again
+    wait_for(|| async { local.connected_peers().await.contains(&remote.local_peer_id).then_some(()) }).await
+        .expect("Peers never connected");
+    wait_for(|| async { (!local.connected_peers().await.contains(&remote.local_peer_id)).then_some(()) }).await
+        .expect("Idle peer was not disconnected");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_denied_peer_is_disconnected_without_being_registered() -> Result<()> {
+    let mut config = Config::default().network;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.enable_mdns = false;
+
+    let remote = Arc::new(RealNetworkService::new(config.clone()).await?);
+    let mut guarded_config = config;
+    guarded_config.denied_peers = vec![remote.local_peer_id.to_string()];
+    let local = Arc::new(RealNetworkService::new(guarded_config).await?);
+    let mut local_events = Arc::clone(&local).start_event_loop().await?;
+    let mut remote_events = Arc::clone(&remote).start_event_loop().await?;
+
+    let local_addr = wait_for(|| async { local.listen_addresses().await.into_iter().next() }).await
+        .expect("Local never started listening");
+    remote.dial(local_addr).await?;
+
+    // The denied peer gets as far as connecting, and is dropped straight away
+    let closed = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut connected = false;
+        while let Some(event) = remote_events.recv().await {
+            match event {
+                NetworkEvent::PeerConnected(peer) if peer == local.local_peer_id => connected = true,
+                NetworkEvent::PeerDisconnected(peer) if peer == local.local_peer_id => return connected,
+                _ => {}
+            }
+        }
+        false
+    }).await;
+    assert_eq!(closed.ok(), Some(true), "Denied peer was not connected and then disconnected");
+    assert!(!local.connected_peers().await.contains(&remote.local_peer_id));
+
+    // Nothing upstream of the service ever hears about it
+    while let Ok(event) = local_events.try_recv() {
+        assert!(!matches!(event, NetworkEvent::PeerConnected(peer) if peer == remote.local_peer_id));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_interrupted_transfer_resumes_from_acknowledged_offset() -> Result<()> {
+    let oplog: Vec<u8> = (0..4 * TRANSFER_CHUNK_SIZE).map(|i| (i % 251) as u8).collect();
+    let half = oplog.len() / 2;
+    let sender = OutgoingTransfers::new();
+    let receiver = IncomingTransfers::new();
+    let transfer_id = sender.start(Uuid::new_v4(), oplog.clone());
+
+    // The first half arrives and is acknowledged
+    while let Some((header, chunk)) = sender.next_chunk(&transfer_id) {
+        if header.offset == half {
+            break;
+        }
+        let receipt = receiver.receive(header, &chunk)?;
+        assert!(receipt.complete.is_none());
+        sender.acknowledge(&transfer_id, receipt.acked)?;
+    }
+
+    // The connection drops with the next chunk in flight; after reconnecting the sender
+    // picks up where the receiver left off
+    assert_eq!(receiver.resume_offset(&transfer_id), Some(half));
+    let mut resent = 0;
+    let mut complete = None;
+    while let Some((header, chunk)) = sender.next_chunk(&transfer_id) {
+        assert!(header.offset >= half, "Chunk at {} was already acknowledged", header.offset);
+        resent += chunk.len();
+        let receipt = receiver.receive(header, &chunk)?;
+        sender.acknowledge(&transfer_id, receipt.acked)?;
+        complete = receipt.complete;
+    }
+
+    assert_eq!(resent, oplog.len() - half);
+    assert_eq!(complete, Some(oplog));
+    assert!(sender.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_operation_made_while_disconnected_arrives_after_reconnect() -> Result<()> {
+    let mut config = Config::default().network;
+    config.enable_mdns = false;
+    config.real_network = true;
+    config.redial_backoff.initial_delay_ms = 100;
+    let alice_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let mut seed = [0u8; 32];
+    seed[..5].copy_from_slice(b"alice");
+    let alice_peer = PeerId::from(libp2p::identity::Keypair::ed25519_from_bytes(seed)?.public());
+
+    let alice = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = alice.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    let bob = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let bob_id = bob.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    bob.read().await.adopt_document_id(&bob_id, doc_id).await?;
+
+    // Bob follows the document and keeps dialing Alice while she is away
+    let mut bob_config = config.clone();
+    bob_config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    bob_config.bootstrap_nodes = vec![format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", alice_port, alice_peer)];
+    let mut bob_network = NetworkEngine::new(&bob_config, Arc::clone(&bob)).await?;
+    bob_network.start().await?;
+    bob_network.subscribe_to_document(doc_id).await?;
+
+    // Alice writes with nobody to send it to
+    alice.read().await.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "written offline".to_string(),
+    }).await?;
+
+    // Once she is back, Bob catches up on the document by himself
+    config.listen_addresses = vec![format!("/ip4/127.0.0.1/tcp/{}", alice_port)];
+    config.peer_id_seed = Some("alice".to_string());
+    let mut alice_network = NetworkEngine::new(&config, Arc::clone(&alice)).await?;
+    alice_network.start().await?;
+    assert_eq!(alice_network.get_local_peer_id().await?, alice_peer.to_string());
+
+    let synced = wait_for(|| async {
+        let content = bob.read().await.get_document_content(&doc_id).await.ok()?;
+        (content == "written offline").then_some(())
+    }).await;
+    assert!(synced.is_some(), "Catch-up never arrived");
+    assert_eq!(bob_network.get_subscribed_documents().await?, vec![doc_id]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_topic_subscriptions_are_reference_counted() -> Result<()> {
+    let mut config = Config::default().network;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.enable_mdns = false;
+
+    let service = RealNetworkService::new(config).await?;
+    let topic = DocumentTopic::Operations(Uuid::new_v4()).to_topic_string();
+
+    service.subscribe_to_topic(topic.clone()).await?;
+    service.subscribe_to_topic(topic.clone()).await?;
+
+    // One subscriber leaving must not drop the topic for the other
+    service.unsubscribe_from_topic(topic.clone()).await?;
+    assert!(service.is_subscribed(&topic).await);
+
+    service.unsubscribe_from_topic(topic.clone()).await?;
+    assert!(!service.is_subscribed(&topic).await);
+    assert!(service.unsubscribe_from_topic(topic).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_public_document_is_discovered_by_other_node() -> Result<()> {
+    let mut config = Config::default().network;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.enable_mdns = false;
+
+    let announcer = Arc::new(RealNetworkService::new(config.clone()).await?);
+    let listener = Arc::new(RealNetworkService::new(config).await?);
+    let _announcer_events = Arc::clone(&announcer).start_event_loop().await?;
+    let mut listener_events = Arc::clone(&listener).start_event_loop().await?;
+    announcer.subscribe_to_topic(ANNOUNCE_TOPIC.to_string()).await?;
+    listener.subscribe_to_topic(ANNOUNCE_TOPIC.to_string()).await?;
+
+    let listener_addr = wait_for(|| async { listener.listen_addresses().await.into_iter().next() }).await
+        .expect("Listener never started listening");
+    announcer.dial(listener_addr).await?;
+
+    // Private documents are never announced
+    let mut doc = Document::new(Uuid::new_v4(), "Thesis".to_string(), "alice".to_string());
+    assert!(DocumentDirectory::announcement(&doc).is_none());
+
+    doc.set_visibility(DocumentVisibility::Public);
+    let announcement = DocumentDirectory::announcement(&doc).expect("Public document is announced");
+
+    // Publishing fails until the listener's subscription has reached the announcer
+    let directory = DocumentDirectory::new();
+    let received = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let _ = announcer.publish_to_topic(ANNOUNCE_TOPIC.to_string(), announcement.clone()).await;
+            let event = tokio::time::timeout(Duration::from_millis(200), listener_events.recv()).await;
+            if let Ok(Some(NetworkEvent::MessageReceived { source, topic, data })) = event {
+                assert_eq!(topic, ANNOUNCE_TOPIC);
+                return directory.record(source, &data);
+            }
+        }
+    }).await;
+    received.expect("Announcement never arrived")?;
+
+    let discovered = directory.list();
+    assert_eq!(discovered.len(), 1);
+    assert_eq!(discovered[0].document_id, doc.id);
+    assert_eq!(discovered[0].title, "Thesis");
+    assert_eq!(discovered[0].owner, "alice");
+    assert_eq!(discovered[0].announced_by, announcer.local_peer_id.to_string());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_simultaneous_creations_converge_on_one_document_id() -> Result<()> {
+    let peers = [PeerId::random(), PeerId::random()];
+    let engines = [CrdtEngine::new()?, CrdtEngine::new()?];
+    let directories = [DocumentDirectory::new(), DocumentDirectory::new()];
+
+    // Everyone clicks New on the same shared paper
+    let mut announcements = Vec::new();
+    for (engine, directory) in engines.iter().zip(&directories) {
+        let doc_id = engine.create_document_with_dedup("Paper".to_string(), "alice".to_string(), "invite-42").await?;
+        let doc = engine.get_document(&doc_id).await?;
+        let mut doc = doc.write().await;
+        doc.set_visibility(DocumentVisibility::Public);
+        directory.add_pending_creation(doc.dedup_key.clone().expect("Created with a dedup key"), doc_id);
+        announcements.push((doc_id, DocumentDirectory::announcement(&doc).expect("Public document is announced")));
+    }
+    assert_ne!(announcements[0].0, announcements[1].0);
+
+    // Each peer hears the other's announcement
+    for (local, remote) in [(0, 1), (1, 0)] {
+        let dedup_match = directories[local].record(peers[remote], &announcements[remote].1)?
+            .expect("Matching creation was not recognized");
+        assert_eq!(dedup_match.local_document, announcements[local].0);
+        if let Some(canonical) = dedup_match.canonical_id(&peers[local]) {
+            engines[local].adopt_document_id(&dedup_match.local_document, canonical).await?;
+        }
+    }
+
+    let winner = if peers[0] < peers[1] { announcements[0].0 } else { announcements[1].0 };
+    for engine in &engines {
+        assert_eq!(engine.get_all_documents().await?, vec![winner]);
+        assert_eq!(engine.get_document(&winner).await?.read().await.id, winner);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_duplicate_creation_moves_subscription_to_the_merged_id() -> Result<()> {
+    let mut config = Config::default().network;
+    config.enable_mdns = false;
+    config.real_network = true;
+    let alice_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    let alice = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let bob = Arc::new(RwLock::new(CrdtEngine::new()?));
+    config.listen_addresses = vec![format!("/ip4/127.0.0.1/tcp/{}", alice_port)];
+    let mut alice_network = NetworkEngine::new(&config, Arc::clone(&alice)).await?;
+    alice_network.start().await?;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.bootstrap_nodes = vec![format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", alice_port, alice_network.get_local_peer_id().await?)];
+    let mut bob_network = NetworkEngine::new(&config, Arc::clone(&bob)).await?;
+    bob_network.start().await?;
+    wait_for(|| async { (!alice_network.get_connected_peers().await.ok()?.is_empty()).then_some(()) }).await
+        .expect("Bob never connected to Alice");
+
+    // Both click New on the same shared paper, and follow their copy
+    let mut created = Vec::new();
+    let mut events = Vec::new();
+    for (engine, network) in [(&alice, &mut alice_network), (&bob, &mut bob_network)] {
+        let doc_id = engine.read().await.create_document_with_dedup("Paper".to_string(), "alice".to_string(), "invite-42").await?;
+        engine.read().await.set_document_visibility(&doc_id, DocumentVisibility::Public).await?;
+        network.subscribe_to_document(doc_id).await?;
+        events.push(engine.read().await.subscribe_events());
+        created.push(doc_id);
+    }
+
+    // Announcements only get through once the peers have heard of each other's subscriptions
+    let alice_network = Arc::new(RwLock::new(alice_network));
+    let bob_network = Arc::new(RwLock::new(bob_network));
+    let converged = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            for (engine, network) in [(&alice, &alice_network), (&bob, &bob_network)] {
+                let documents = engine.read().await.get_all_documents().await.unwrap_or_default();
+                for doc_id in documents {
+                    let doc = engine.read().await.get_document(&doc_id).await.ok()?.read().await.clone();
+                    let _ = network.write().await.announce_document(&doc).await;
+                }
+            }
+            let alice_docs = alice.read().await.get_all_documents().await.ok()?;
+            let bob_docs = bob.read().await.get_all_documents().await.ok()?;
+            if alice_docs.len() == 1 && alice_docs == bob_docs {
+                return Some(alice_docs[0]);
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }).await;
+    let winner = converged.ok().flatten().expect("Copies never merged");
+
+    // Whoever moved follows the document under its new ID, and its clients are told so
+    for (index, network) in [&alice_network, &bob_network].into_iter().enumerate() {
+        wait_for(|| async { (network.read().await.get_subscribed_documents().await.ok()? == vec![winner]).then_some(()) }).await
+            .expect("Subscription never moved");
+        if created[index] == winner {
+            continue;
+        }
+        let changed = std::iter::from_fn(|| events[index].try_recv().ok())
+            .find_map(|event| match event {
+                DocumentEvent::DocumentIdChanged { old_id, new_id } => Some((old_id, new_id)),
+                DocumentEvent::DocumentDeleted { document_id } => panic!("Document {} reported deleted", document_id),
+                _ => None,
+            });
+        assert_eq!(changed, Some((created[index], winner)));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_active_sessions_count_subscribed_peers() -> Result<()> {
+    let crdt_engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let (public_id, private_id) = {
+        let engine = crdt_engine.read().await;
+        let public_id = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+        engine.set_document_visibility(&public_id, DocumentVisibility::Public).await?;
+        let private_id = engine.create_document("Diary".to_string(), "alice".to_string()).await?;
+        (public_id, private_id)
+    };
+
+    let mut network = NetworkEngine::new(&Config::default().network, Arc::clone(&crdt_engine)).await?;
+    network.start().await?;
+
+    // This node and one remote peer are editing each document
+    for doc_id in [public_id, private_id] {
+        network.subscribe_to_document(doc_id).await?;
+        network.add_document_subscriber(doc_id, PeerId::random().to_string());
+    }
+
+    // Private documents are never listed
+    let sessions = network.get_active_sessions().await?;
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].document_id, public_id);
+    assert_eq!(sessions[0].title, "Thesis");
+    assert_eq!(sessions[0].collaborators, 2);
+
+    // Leaving takes this node out of the count
+    network.unsubscribe_from_document(public_id).await?;
+    let sessions = network.get_active_sessions().await?;
+    assert_eq!(sessions[0].collaborators, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_incompatible_protocol_versions_are_detected() -> Result<()> {
+    let mut config = Config::default().network;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.enable_mdns = false;
+
+    let mut newer_config = config.clone();
+    newer_config.protocol_versions = vec![ProtocolVersion::V2];
+    let mut older_config = config;
+    older_config.protocol_versions = vec![ProtocolVersion::V1];
+
+    let newer = Arc::new(RealNetworkService::new(newer_config).await?);
+    let older = Arc::new(RealNetworkService::new(older_config).await?);
+    let mut newer_events = Arc::clone(&newer).start_event_loop().await?;
+    let mut older_events = Arc::clone(&older).start_event_loop().await?;
+
+    let older_addr = wait_for(|| async { older.listen_addresses().await.into_iter().next() }).await
+        .expect("Older node never started listening");
+    newer.dial(older_addr).await?;
+    wait_for(|| async { newer.connected_peers().await.contains(&older.local_peer_id).then_some(()) }).await
+        .expect("Peers never connected");
+
+    newer.send_request(
+        older.local_peer_id,
+        NetworkMessage::SyncRequest {
+            document_id: Uuid::new_v4(),
+            user_id: "alice".to_string(),
+            version: None,
+        },
+        "sync-1".to_string(),
+    ).await?;
+
+    let failure = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match newer_events.recv().await {
+                Some(NetworkEvent::RequestFailed { request_id, error, .. }) => return Some((request_id, error)),
+                Some(_) => continue,
+                None => return None,
+            }
+        }
+    }).await?.expect("Event loop stopped");
+    assert_eq!(failure, ("sync-1".to_string(), INCOMPATIBLE_VERSION.to_string()));
+
+    // The older node never got to parse the request
+    while let Ok(event) = older_events.try_recv() {
+        assert!(!matches!(event, NetworkEvent::RequestReceived { .. }));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peers_in_bootstrap_repository_are_dialed() -> Result<()> {
+    let mut config = Config::default();
+    config.network.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.network.enable_mdns = false;
+    let service = RealNetworkService::new(config.network.clone()).await?;
+
+    // A shared repository listing two other peers, and this node from an earlier run
+    let dir = std::env::temp_dir().join(format!("texswarm-rendezvous-test-{}", Uuid::new_v4()));
+    let shared_path = dir.join("shared");
+    let shared = git2::Repository::init(&shared_path)?;
+    let bootstrap = [
+        format!("{},/ip4/127.0.0.1/tcp/1", PeerId::random()),
+        format!("{},/ip4/127.0.0.1/tcp/2", PeerId::random()),
+        format!("{},/ip4/127.0.0.1/tcp/3", service.local_peer_id),
+    ];
+    std::fs::write(shared_path.join(BOOTSTRAP_FILE), bootstrap.join("\n"))?;
+    let mut index = shared.index()?;
+    index.add_path(std::path::Path::new(BOOTSTRAP_FILE))?;
+    let tree = shared.find_tree(index.write_tree()?)?;
+    let signature = git2::Signature::now("bob", "bob@example.com")?;
+    shared.commit(Some("HEAD"), &signature, &signature, "Add peers", &tree, &[])?;
+
+    config.network.bootstrap_repo_url = Some(shared_path.to_string_lossy().to_string());
+    config.git.repositories_path = dir.join("repositories");
+    let rendezvous = GitRendezvous::from_config(&config.network, &config.git).expect("Rendezvous configured");
+
+    assert_eq!(rendezvous.read_peers()?.len(), 3);
+    assert_eq!(rendezvous.dial_peers(&service).await?, 2);
+
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_network_engine_lists_itself_in_bootstrap_repository() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-rendezvous-test-{}", Uuid::new_v4()));
+    let shared_path = dir.join("shared.git");
+    git2::Repository::init_bare(&shared_path)?;
+
+    let mut config = Config::default();
+    config.network.real_network = true;
+    config.network.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.network.external_addresses = vec!["/ip4/127.0.0.1/tcp/4001".to_string()];
+    config.network.enable_mdns = false;
+    config.network.bootstrap_repo_url = Some(shared_path.to_string_lossy().to_string());
+    config.git.repositories_path = dir.join("repositories");
+
+    let crdt_engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let mut engine = NetworkEngine::new(&config.network, crdt_engine)
+        .await?
+        .with_rendezvous(GitRendezvous::from_config(&config.network, &config.git));
+    engine.start().await?;
+    let peer_id = engine.get_local_peer_id().await?;
+
+    // Once started, the engine adds its entry to the shared repository
+    let entry = wait_for(|| async {
+        let shared = git2::Repository::open_bare(&shared_path).ok()?;
+        let commit = shared.find_reference("refs/heads/master").ok()?.peel_to_commit().ok()?;
+        let blob = commit.tree().ok()?.get_path(std::path::Path::new(BOOTSTRAP_FILE)).ok()?.to_object(&shared).ok()?;
+        let content = String::from_utf8(blob.as_blob()?.content().to_vec()).ok()?;
+        content.lines().find_map(BootstrapEntry::parse)
+    }).await.expect("Bootstrap entry never pushed");
+    assert_eq!(entry.peer_id.to_string(), peer_id);
+    assert_eq!(entry.addresses, vec!["/ip4/127.0.0.1/tcp/4001".parse::<libp2p::Multiaddr>()?]);
+
+    engine.stop().await?;
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peers_sharing_a_topic_report_each_other_in_their_mesh() -> Result<()> {
+    let mut config = Config::default().network;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.enable_mdns = false;
+    let topic = DocumentTopic::Operations(Uuid::new_v4()).to_topic_string();
+
+    let local = Arc::new(RealNetworkService::new(config.clone()).await?);
+    let remote = Arc::new(RealNetworkService::new(config).await?);
+    let _local_events = Arc::clone(&local).start_event_loop().await?;
+    let mut remote_events = Arc::clone(&remote).start_event_loop().await?;
+    local.subscribe_to_topic(topic.clone()).await?;
+    remote.subscribe_to_topic(topic.clone()).await?;
+
+    let remote_addr = wait_for(|| async { remote.listen_addresses().await.into_iter().next() }).await
+        .expect("Remote never started listening");
+    local.dial(remote_addr).await?;
+
+    // Each side grafts the other into its mesh on hearing it subscribes to the topic
+    for service in [&local, &remote] {
+        let metrics = wait_for(|| async {
+            let metrics = service.gossipsub_metrics().await;
+            metrics.iter().all(|topic| topic.mesh_peers > 0).then_some(metrics)
+        }).await.expect("Mesh never formed");
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].topic, topic);
+        assert_eq!(metrics[0].mesh_peers, 1);
+        assert_eq!(metrics[0].topic_peers, 1);
+    }
+
+    // Messages are counted on both sides
+    local.publish_to_topic(topic.clone(), b"hello".to_vec()).await?;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(event) = remote_events.recv().await {
+            if matches!(event, NetworkEvent::MessageReceived { .. }) {
+                return;
+            }
+        }
+    }).await.expect("Message never arrived");
+
+    let sent = &local.gossipsub_metrics().await[0];
+    assert_eq!((sent.published, sent.publish_failures, sent.received), (1, 0, 0));
+    let received = &remote.gossipsub_metrics().await[0];
+    assert_eq!((received.published, received.received, received.ignored), (0, 1, 0));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_polling_nodes_converge_without_gossip() -> Result<()> {
+    let mut config = Config::default().network;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.enable_mdns = false;
+    config.sync_mode = SyncMode::Polling;
+    config.poll_interval_secs = 1;
+
+    let alice = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let bob = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = alice.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    let bob_id = bob.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    bob.read().await.adopt_document_id(&bob_id, doc_id).await?;
+    for (engine, content) in [(&alice, "alice's intro. "), (&bob, "bob's results. ")] {
+        engine.read().await.apply_local_operation(&doc_id, DocumentOperation::Insert {
+            document_id: doc_id,
+            user_id: content[..3].to_string(),
+            position: 0,
+            content: content.to_string(),
+        }).await?;
+    }
+
+    let local = Arc::new(RealNetworkService::new(config.clone()).await?);
+    let remote = Arc::new(RealNetworkService::new(config.clone()).await?);
+    let mut nodes = Vec::new();
+    for (service, engine) in [(&local, &alice), (&remote, &bob)] {
+        let events = Arc::clone(service).start_event_loop().await?;
+        let polling = PollingSync::from_config(&config, Arc::clone(service), Arc::clone(engine))
+            .expect("Polling mode polls");
+        polling.track_document(doc_id);
+        nodes.push(Arc::new(polling).start(events));
+    }
+
+    let remote_addr = wait_for(|| async { remote.listen_addresses().await.into_iter().next() }).await
+        .expect("Remote never started listening");
+    local.dial(remote_addr).await?;
+
+    // Neither node joins a gossipsub topic, yet each picks up the other's edit within a poll
+    let converged = wait_for(|| async {
+        let ours = alice.read().await.get_document_content(&doc_id).await.ok()?;
+        let theirs = bob.read().await.get_document_content(&doc_id).await.ok()?;
+        (ours == theirs && ours.contains("alice's") && ours.contains("bob's")).then_some(ours)
+    }).await;
+    assert!(converged.is_some(), "Nodes never converged");
+    assert!(local.gossipsub_metrics().await.is_empty());
+    assert!(remote.gossipsub_metrics().await.is_empty());
+
+    // Once up to date, a poll carries no operations
+    let version = bob.read().await.encoded_version(&doc_id).await?;
+    let (missing, full) = alice.read().await.export_since(&doc_id, Some(&version)).await?;
+    assert!(missing.is_empty() && !full);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_network_engine_polls_peers_in_polling_mode() -> Result<()> {
+    let mut config = Config::default().network;
+    config.enable_mdns = false;
+    config.real_network = true;
+    config.sync_mode = SyncMode::Polling;
+    config.poll_interval_secs = 1;
+    let alice_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    let alice = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let bob = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = alice.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    let bob_id = bob.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    bob.read().await.adopt_document_id(&bob_id, doc_id).await?;
+    let insert = |position: usize, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position,
+        content: content.to_string(),
+    };
+    alice.read().await.apply_local_operation(&doc_id, insert(0, "Synced. ")).await?;
+
+    config.listen_addresses = vec![format!("/ip4/127.0.0.1/tcp/{}", alice_port)];
+    let mut alice_network = NetworkEngine::new(&config, Arc::clone(&alice)).await?;
+    alice_network.start().await?;
+    alice_network.subscribe_to_document(doc_id).await?;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.bootstrap_nodes = vec![format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", alice_port, alice_network.get_local_peer_id().await?)];
+    let mut bob_network = NetworkEngine::new(&config, Arc::clone(&bob)).await?;
+    bob_network.start().await?;
+    bob_network.subscribe_to_document(doc_id).await?;
+    // Bob catches up with Alice once connected
+    let synced = wait_for(|| async {
+        (bob.read().await.get_document_content(&doc_id).await.ok()? == "Synced. ").then_some(())
+    }).await;
+    assert!(synced.is_some(), "Bob never synced with Alice");
+
+    // Alice's next edit is never published, and no peer connects after it, so only a poll
+    // brings it to Bob
+    alice.read().await.apply_local_operation(&doc_id, insert(8, "Polled. ")).await?;
+    let polled = wait_for(|| async {
+        (bob.read().await.get_document_content(&doc_id).await.ok()? == "Synced. Polled. ").then_some(())
+    }).await;
+    assert!(polled.is_some(), "Bob never polled Alice's edit");
+
+    alice_network.stop().await?;
+    bob_network.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_network_engine_runs_on_either_service() -> Result<()> {
+    let mut config = Config::default().network;
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.enable_mdns = false;
+    let doc_id = Uuid::new_v4();
+
+    for real_network in [false, true] {
+        config.real_network = real_network;
+        let mut network = NetworkEngine::new(&config, Arc::new(RwLock::new(CrdtEngine::new()?))).await?;
+        network.start().await?;
+        network.subscribe_to_document(doc_id).await?;
+
+        assert!(network.get_local_peer_id().await?.parse::<PeerId>().is_ok());
+        let debug = format!("{:?}", network);
+        assert_eq!(debug.contains("RealNetworkService"), real_network, "{}", debug);
+
+        // Only the real service joins gossipsub topics
+        let topics: Vec<String> = network.get_gossipsub_metrics().await?.into_iter().map(|metrics| metrics.topic).collect();
+        if real_network {
+            assert!(topics.contains(&ANNOUNCE_TOPIC.to_string()));
+            assert!(topics.contains(&DocumentTopic::Operations(doc_id).to_topic_string()));
+            assert!(topics.contains(&DocumentTopic::Metadata(doc_id).to_topic_string()));
+        } else {
+            assert!(topics.is_empty());
+        }
+
+        network.stop().await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_operations_held_while_paused_apply_on_resume() -> Result<()> {
+    let mut config = Config::default().network;
+    config.enable_mdns = false;
+    config.real_network = true;
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    let alice = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let bob = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = alice.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    let bob_id = bob.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    bob.read().await.adopt_document_id(&bob_id, doc_id).await?;
+
+    config.listen_addresses = vec![format!("/ip4/127.0.0.1/tcp/{}", port)];
+    let mut bob_network = NetworkEngine::new(&config, Arc::clone(&bob)).await?;
+    bob_network.start().await?;
+
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.bootstrap_nodes = vec![format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", port, bob_network.get_local_peer_id().await?)];
+    let mut alice_network = NetworkEngine::new(&config, Arc::clone(&alice)).await?;
+    alice_network.start().await?;
+
+    // Subscribe once connected, as a new connection makes subscribed nodes rejoin their
+    // documents' topics, which keeps the mesh from forming for a while
+    wait_for(|| async { (!bob_network.get_connected_peers().await.ok()?.is_empty()).then_some(()) }).await
+        .expect("Peers never connected");
+    alice_network.subscribe_to_document(doc_id).await?;
+    bob_network.subscribe_to_document(doc_id).await?;
+    let topic = DocumentTopic::Operations(doc_id).to_topic_string();
+    for network in [&alice_network, &bob_network] {
+        wait_for(|| async {
+            let metrics = network.get_gossipsub_metrics().await.ok()?;
+            metrics.iter().any(|metrics| metrics.topic == topic && metrics.mesh_peers > 0).then_some(())
+        }).await.expect("Mesh never formed");
+    }
+
+    let insert = |user_id: &str, position, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: user_id.to_string(),
+        position,
+        content: content.to_string(),
+    };
+
+    // Bob holds back what arrives while paused, with the connection still up
+    bob_network.pause();
+    let encoded = alice.read().await.apply_local_operation(&doc_id, insert("alice", 0, "Intro. ")).await?;
+    alice_network.broadcast_operation(&doc_id, encoded).await?;
+    assert!(wait_for(|| async { (bob_network.held_operations().await.0 == 1).then_some(()) }).await.is_some());
+    assert_eq!(bob.read().await.get_document_content(&doc_id).await?, "");
+    assert!(!bob_network.get_connected_peers().await?.is_empty());
+
+    // What a sync brings back is left for resume too
+    assert_eq!(bob_network.request_sync_from_peers(doc_id).await?, 1);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(bob.read().await.get_document_content(&doc_id).await?, "");
+
+    assert_eq!(bob_network.resume().await?, (1, 0));
+    assert_eq!(bob.read().await.get_document_content(&doc_id).await?, "Intro. ");
+    // Let the sync asked for on resume finish, so it can't carry Alice's next edit
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Alice holds back what she makes while paused
+    alice_network.pause();
+    let encoded = alice.read().await.apply_local_operation(&doc_id, insert("alice", 7, "Results.")).await?;
+    alice_network.broadcast_operation(&doc_id, encoded).await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(bob.read().await.get_document_content(&doc_id).await?, "Intro. ");
+
+    assert_eq!(alice_network.resume().await?, (0, 1));
+    let synced = wait_for(|| async {
+        let content = bob.read().await.get_document_content(&doc_id).await.ok()?;
+        (content == "Intro. Results.").then_some(())
+    }).await;
+    assert!(synced.is_some(), "Operation held while paused never arrived");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_held_operations_are_capped_and_kept_when_resuming_fails() -> Result<()> {
+    let doc_id = Uuid::new_v4();
+
+    // Past the cap, operations are dropped and their document noted to be synced whole
+    let pause = PropagationPause::new();
+    pause.pause();
+    for _ in 0..=MAX_HELD_OPERATIONS {
+        assert!(pause.hold_outbound(doc_id, b"operation").await);
+    }
+    assert_eq!(pause.held().await, (0, MAX_HELD_OPERATIONS));
+    let (_, outbound) = pause.resume().await;
+    assert!(outbound.dropped.contains(&doc_id));
+
+    // Publishing fails once the service is gone, so the operation stays held
+    let mut network = NetworkEngine::new(&Config::default().network, Arc::new(RwLock::new(CrdtEngine::new()?))).await?;
+    network.start().await?;
+    network.pause();
+    network.broadcast_operation(&doc_id, b"operation".to_vec()).await?;
+    network.stop().await?;
+    assert!(network.resume().await.is_err());
+    assert!(network.is_paused());
+    assert_eq!(network.held_operations().await, (0, 1));
+
+    network.start().await?;
+    assert_eq!(network.resume().await?, (0, 1));
+    assert!(!network.is_paused());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_chat_message_reaches_subscribed_peer() -> Result<()> {
+    let mut config = Config::default().network;
+    config.enable_mdns = false;
+    config.real_network = true;
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    let alice = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let bob = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = alice.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    let bob_id = bob.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    bob.read().await.adopt_document_id(&bob_id, doc_id).await?;
+
+    config.listen_addresses = vec![format!("/ip4/127.0.0.1/tcp/{}", port)];
+    let mut bob_network = NetworkEngine::new(&config, Arc::clone(&bob)).await?;
+    bob_network.start().await?;
+
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.bootstrap_nodes = vec![format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", port, bob_network.get_local_peer_id().await?)];
+    let mut alice_network = NetworkEngine::new(&config, Arc::clone(&alice)).await?;
+    alice_network.start().await?;
+
+    wait_for(|| async { (!bob_network.get_connected_peers().await.ok()?.is_empty()).then_some(()) }).await
+        .expect("Peers never connected");
+    alice_network.subscribe_to_document(doc_id).await?;
+    bob_network.subscribe_to_document(doc_id).await?;
+    let topic = DocumentTopic::Chat(doc_id).to_topic_string();
+    for network in [&alice_network, &bob_network] {
+        wait_for(|| async {
+            let metrics = network.get_gossipsub_metrics().await.ok()?;
+            metrics.iter().any(|metrics| metrics.topic == topic && metrics.mesh_peers > 0).then_some(())
+        }).await.expect("Mesh never formed");
+    }
+
+    // Alice chats from her editor, which hands the message to her node's peers
+    let server = WebSocketServer::new(Arc::clone(&alice)).with_network_engine(Arc::new(RwLock::new(alice_network)));
+    server.handle_message("session-1", ApiMessage::Authentication { user_id: "alice".to_string(), token: None }).await?;
+    let sent = server.handle_message("session-1", ApiMessage::ChatMessage {
+        document_id: doc_id,
+        body: "Shall we split the related work section?".to_string(),
+        user_id: None,
+        timestamp: None,
+    }).await?;
+    assert!(sent.is_none(), "Unexpected response: {:?}", sent);
+
+    let history = wait_for(|| async {
+        let history = bob.read().await.get_chat_history(&doc_id, 10).await.ok()?;
+        (!history.is_empty()).then_some(history)
+    }).await.expect("Chat message never arrived");
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].user_id, "alice");
+    assert_eq!(history[0].body, "Shall we split the related work section?");
+    assert_eq!(history, alice.read().await.get_chat_history(&doc_id, 10).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_converged_copies_share_a_content_hash_and_divergence_is_detected() -> Result<()> {
+    let alice = CrdtEngine::new()?;
+    let bob = CrdtEngine::new()?;
+    let doc_id = alice.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    let bob_id = bob.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    bob.adopt_document_id(&bob_id, doc_id).await?;
+    let peer = PeerId::random();
+
+    let insert = |user_id: &str, position, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: user_id.to_string(),
+        position,
+        content: content.to_string(),
+    };
+    alice.apply_local_operation(&doc_id, insert("alice", 0, "Intro. ")).await?;
+    alice.apply_local_operation(&doc_id, insert("alice", 7, "Results.")).await?;
+
+    // Bob catches up, and both copies hash the same
+    let bob_version = bob.encoded_version(&doc_id).await?;
+    let NetworkMessage::SyncResponse { operations, is_full_sync, version: Some(version), content_hash: Some(content_hash), .. } =
+        polling::sync_response(&alice, doc_id, Some(&bob_version)).await
+    else {
+        panic!("Sync response carries no content hash");
+    };
+    let comparison = polling::apply_sync_response(&bob, &doc_id, &operations, Some((&version, &content_hash))).await?;
+    assert_eq!(comparison, HashComparison::Matches);
+    assert!(!polling::needs_full_resync(&doc_id, &peer, comparison, is_full_sync));
+    assert_eq!(bob.content_hash(&doc_id).await?, alice.content_hash(&doc_id).await?);
+    assert_eq!(bob.get_document_content(&doc_id).await?, "Intro. Results.");
+
+    // A copy at the same version with different content has diverged, and is resynced once
+    let comparison = bob.compare_content_hash(&doc_id, &version, &"0".repeat(content_hash.len())).await?;
+    assert_eq!(comparison, HashComparison::Diverged);
+    assert!(polling::needs_full_resync(&doc_id, &peer, comparison, false));
+    assert!(!polling::needs_full_resync(&doc_id, &peer, comparison, true));
+
+    // Copies at different versions can't be told apart by their hashes
+    bob.apply_local_operation(&doc_id, insert("bob", 0, "Abstract. ")).await?;
+    assert_eq!(bob.compare_content_hash(&doc_id, &version, &content_hash).await?, HashComparison::NotComparable);
+    assert_ne!(bob.content_hash(&doc_id).await?, alice.content_hash(&doc_id).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_sync_request_is_retried_with_another_peer() -> Result<()> {
+    let mut config = Config::default().network;
+    config.enable_mdns = false;
+    config.real_network = true;
+    config.request_timeout_secs = 2;
+    let bob_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let alice_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    let alice = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let carol = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = carol.read().await.create_document("Thesis".to_string(), "carol".to_string()).await?;
+    carol.read().await.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "carol".to_string(),
+        position: 0,
+        content: "carol's draft".to_string(),
+    }).await?;
+    let alice_id = alice.read().await.create_document("Thesis".to_string(), "carol".to_string()).await?;
+    alice.read().await.adopt_document_id(&alice_id, doc_id).await?;
+
+    // Bob takes requests but never answers them
+    config.listen_addresses = vec![format!("/ip4/127.0.0.1/tcp/{}", bob_port)];
+    let bob = Arc::new(RealNetworkService::new(config.clone()).await?);
+    let mut bob_events = Arc::clone(&bob).start_event_loop().await?;
+    tokio::spawn(async move {
+        let mut unanswered = Vec::new();
+        while let Some(event) = bob_events.recv().await {
+            unanswered.push(event);
+        }
+    });
+
+    config.listen_addresses = vec![format!("/ip4/127.0.0.1/tcp/{}", alice_port)];
+    config.bootstrap_nodes = vec![format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", bob_port, bob.local_peer_id)];
+    let mut alice_network = NetworkEngine::new(&config, Arc::clone(&alice)).await?;
+    alice_network.start().await?;
+    wait_for(|| async { (!alice_network.get_connected_peers().await.ok()?.is_empty()).then_some(()) }).await
+        .expect("Alice never connected to Bob");
+    assert_eq!(alice_network.request_sync_from_peers(doc_id).await?, 1);
+
+    // Carol turns up while Alice is still waiting on Bob
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.bootstrap_nodes = vec![format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", alice_port, alice_network.get_local_peer_id().await?)];
+    let mut carol_network = NetworkEngine::new(&config, Arc::clone(&carol)).await?;
+    carol_network.start().await?;
+    wait_for(|| async { (alice_network.get_connected_peers().await.ok()?.len() == 2).then_some(()) }).await
+        .expect("Carol never connected to Alice");
+
+    // Once Bob's request times out, Carol is asked instead
+    let synced = wait_for(|| async {
+        let content = alice.read().await.get_document_content(&doc_id).await.ok()?;
+        (content == "carol's draft").then_some(())
+    }).await;
+    assert!(synced.is_some(), "Sync was never retried with Carol");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_large_sync_response_arrives_in_chunks() -> Result<()> {
+    let mut config = Config::default().network;
+    config.enable_mdns = false;
+    config.real_network = true;
+    let carol_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    // Carol's document is several chunks long, even compressed
+    let carol = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let doc_id = carol.read().await.create_document("Thesis".to_string(), "carol".to_string()).await?;
+    let mut seed = 1u32;
+    let content: String = (0..3 * TRANSFER_CHUNK_SIZE)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            char::from(b'a' + (seed >> 24) as u8 % 26)
+        })
+        .collect();
+    carol.read().await.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "carol".to_string(),
+        position: 0,
+        content: content.clone(),
+    }).await?;
+    let exported = carol.read().await.export_since(&doc_id, None).await?.0;
+    assert!(exported.len() > 2 * TRANSFER_CHUNK_SIZE, "{} bytes fit in a response", exported.len());
+    let alice = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let alice_id = alice.read().await.create_document("Thesis".to_string(), "carol".to_string()).await?;
+    alice.read().await.adopt_document_id(&alice_id, doc_id).await?;
+
+    config.listen_addresses = vec![format!("/ip4/127.0.0.1/tcp/{}", carol_port)];
+    let mut carol_network = NetworkEngine::new(&config, Arc::clone(&carol)).await?;
+    carol_network.start().await?;
+
+    config.listen_addresses = vec!["/ip4/127.0.0.1/tcp/0".to_string()];
+    config.bootstrap_nodes = vec![format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", carol_port, carol_network.get_local_peer_id().await?)];
+    let mut alice_network = NetworkEngine::new(&config, Arc::clone(&alice)).await?;
+    alice_network.start().await?;
+    wait_for(|| async { (!alice_network.get_connected_peers().await.ok()?.is_empty()).then_some(()) }).await
+        .expect("Alice never connected to Carol");
+    assert_eq!(alice_network.request_sync_from_peers(doc_id).await?, 1);
+
+    let synced = wait_for(|| async {
+        let synced = alice.read().await.get_document_content(&doc_id).await.ok()?;
+        (synced == content).then_some(())
+    }).await;
+    assert!(synced.is_some(), "Chunked oplog never arrived");
+
+    Ok(())
+}
+
+#[test]
+fn test_oversized_and_out_of_range_chunks_are_rejected() {
+    let receiver = IncomingTransfers::new().with_max_total(1024);
+    let header = |offset, total| ChunkHeader { transfer_id: Uuid::nil(), offset, total };
+
+    // Longer than the limit, however little is sent
+    assert!(receiver.receive(header(0, 2048), b"x").is_err());
+    // Past the end of the transfer, or of the address space
+    assert!(receiver.receive(header(1020, 1024), &[0; 8]).is_err());
+    assert!(receiver.receive(header(usize::MAX, 1024), b"x").is_err());
+    assert_eq!(receiver.resume_offset(&Uuid::nil()), None);
+
+    let receipt = receiver.receive(header(0, 4), b"abcd").expect("Chunk within the limit");
+    assert_eq!(receipt.complete, Some(b"abcd".to_vec()));
+}
+
+/// Poll a condition for up to five seconds
+async fn wait_for<T, F, Fut>(mut check: F) -> Option<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Option<T>>,
+{
+    for _ in 0..100 {
+        if let Some(value) = check().await {
+            return Some(value);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    None
+}
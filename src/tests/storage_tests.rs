@@ -0,0 +1,481 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::api::document_persistence_api::{CheckDocumentResponse, DocumentPersistenceApi};
+use crate::crdt::agent_map::AgentMap;
+use crate::crdt::engine::CrdtEngine;
+use crate::crdt::events::DocumentEvent;
+use crate::crdt::operations::DocumentOperation;
+use crate::git::manager::GitManager;
+use crate::storage::at_rest::{AtRestCodec, OplogStore};
+use crate::storage::audit_log::{AuditLog, AuditRecord};
+use crate::storage::document_persistence_service::DocumentPersistenceService;
+use crate::storage::wal::WriteAheadLog;
+use crate::utils::config::Config;
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("texswarm-storage-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn test_encrypted_oplog_needs_the_right_key() -> Result<()> {
+    let engine = CrdtEngine::new()?;
+    let doc_id = engine.create_document("Secret".to_string(), "alice".to_string()).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "\\begin{document}top secret\\end{document}".to_string(),
+    }).await?;
+    let encoded = engine.export_document(&doc_id).await?;
+
+    let dir = temp_dir();
+    let store = OplogStore::new(dir.clone(), AtRestCodec::new(true, Some("correct horse")));
+    store.save(&doc_id, &encoded)?;
+
+    // Nothing readable ends up on disk
+    let on_disk = std::fs::read(store.path(&doc_id))?;
+    assert!(!on_disk.windows(10).any(|w| w == b"top secret"));
+
+    // The right key restores the document
+    let loaded = store.load(&doc_id)?;
+    let restored = engine.import_document("Secret".to_string(), "alice".to_string(), &loaded).await?;
+    assert_eq!(engine.get_document_content(&restored).await?, "\\begin{document}top secret\\end{document}");
+
+    // Another codec with the same passphrase draws its own salt, but reads the file with the
+    // salt stored in it
+    let same_key = OplogStore::new(dir.clone(), AtRestCodec::new(true, Some("correct horse")));
+    assert_eq!(same_key.load(&doc_id)?, encoded);
+    same_key.save(&doc_id, &encoded)?;
+    assert_ne!(std::fs::read(store.path(&doc_id))?[6..22], on_disk[6..22]);
+    assert_eq!(store.load(&doc_id)?, encoded);
+
+    // The wrong key, or none at all, fails without returning garbage
+    let wrong_key = OplogStore::new(dir.clone(), AtRestCodec::new(true, Some("battery staple")));
+    assert!(wrong_key.load(&doc_id).is_err());
+    let no_key = OplogStore::new(dir.clone(), AtRestCodec::new(false, None));
+    assert!(no_key.load(&doc_id).is_err());
+
+    // Plain OpLogs written before at-rest transforms are still readable
+    std::fs::write(no_key.path(&doc_id), &encoded)?;
+    assert_eq!(store.load(&doc_id)?, encoded);
+
+    // So are files encrypted under the unsalted key of the first format version
+    use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+    use sha2::Digest;
+    let legacy_key = aes_gcm::Key::<aes_gcm::Aes256Gcm>::from(<[u8; 32]>::from(sha2::Sha256::digest(b"correct horse")));
+    let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+    let mut legacy = b"TXSW\x01\x02".to_vec();
+    legacy.extend_from_slice(&nonce);
+    legacy.extend(aes_gcm::Aes256Gcm::new(&legacy_key).encrypt(&nonce, encoded.as_slice()).expect("encrypts"));
+    std::fs::write(store.path(&doc_id), &legacy)?;
+    assert_eq!(store.load(&doc_id)?, encoded);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_operation_and_rename_are_audited() -> Result<()> {
+    let dir = temp_dir();
+    let path = dir.join("audit").join("audit.jsonl");
+
+    let engine = CrdtEngine::new()?;
+    let audit_log = AuditLog::open(path.clone(), 1024 * 1024, engine.subscribe_events()).await?;
+
+    let doc_id = engine.create_document("Draft".to_string(), "alice".to_string()).await?;
+    engine.apply_local_operation(&doc_id, DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "Hello".to_string(),
+    }).await?;
+    engine.rename_document(&doc_id, "Thesis".to_string(), Some("alice")).await?;
+    audit_log.flush().await;
+
+    let records: Vec<AuditRecord> = std::fs::read_to_string(&path)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(records.len(), 2, "{:?}", records);
+
+    assert_eq!(records[0].action, "insert");
+    assert_eq!(records[0].user.as_deref(), Some("alice"));
+    assert_eq!(records[0].document_id, Some(doc_id));
+    assert_eq!(records[0].details["position"], 0);
+    assert_eq!(records[0].details["len"], 5);
+
+    assert_eq!(records[1].action, "rename");
+    assert_eq!(records[1].user.as_deref(), Some("alice"));
+    assert_eq!(records[1].document_id, Some(doc_id));
+    assert_eq!(records[1].details["title"], "Thesis");
+    assert!(records[0].timestamp <= records[1].timestamp);
+
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_audit_log_rotates_without_losing_records() -> Result<()> {
+    let dir = temp_dir();
+    let path = dir.join("audit.jsonl");
+
+    let engine = CrdtEngine::new()?;
+    let audit_log = AuditLog::open(path.clone(), 200, engine.subscribe_events()).await?;
+    for n in 0..5 {
+        audit_log.record(AuditRecord::new(Some(format!("user-{}", n)), None, "authenticate", serde_json::Value::Null)).await;
+    }
+    audit_log.flush().await;
+
+    // Each record is over half the limit, so every file holds one
+    let mut users = Vec::new();
+    for file in [path.clone(), dir.join("audit.jsonl.1"), dir.join("audit.jsonl.4")] {
+        let content = std::fs::read_to_string(&file)?;
+        assert_eq!(content.lines().count(), 1, "{}", file.display());
+        let record: AuditRecord = serde_json::from_str(content.trim())?;
+        users.push(record.user.unwrap());
+    }
+    assert_eq!(users, ["user-4", "user-0", "user-3"]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_operation_logged_before_a_crash_is_recovered_on_restart() -> Result<()> {
+    let dir = temp_dir();
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+    let codec = AtRestCodec::new(false, None);
+    let wal = WriteAheadLog::new(dir.clone(), codec.clone());
+
+    let start = |wal: WriteAheadLog| -> Result<(Arc<RwLock<CrdtEngine>>, DocumentPersistenceService)> {
+        let engine = Arc::new(RwLock::new(CrdtEngine::new()?.with_write_ahead_log(Some(wal.clone()))));
+        let git = Arc::new(RwLock::new(GitManager::new(&config, Arc::clone(&engine))?));
+        let persistence = DocumentPersistenceService::new(Arc::clone(&engine), git, 300)
+            .with_oplog_store(OplogStore::new(dir.clone(), codec.clone()))
+            .with_write_ahead_log(Some(wal));
+        Ok((engine, persistence))
+    };
+
+    let (engine, persistence) = start(wal.clone())?;
+    let doc_id = persistence.create_document("Thesis", "alice").await?;
+    let insert = |position: usize, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position,
+        content: content.to_string(),
+    };
+    engine.read().await.apply_local_operation(&doc_id, insert(0, "saved")).await?;
+
+    // Saving checkpoints the log
+    persistence.save_document(&doc_id).await?;
+    assert!(wal.entries(&doc_id)?.is_empty());
+
+    // The process dies after this operation is logged, and before the OpLog is saved again
+    engine.read().await.apply_local_operation(&doc_id, insert(5, " and logged")).await?;
+    assert_eq!(wal.entries(&doc_id)?.len(), 1);
+    drop((engine, persistence));
+
+    let (engine, persistence) = start(wal.clone())?;
+    let logged = wal.entries(&doc_id)?;
+    assert_eq!(persistence.load_saved_documents().await?, 1);
+    {
+        let engine = engine.read().await;
+        assert_eq!(engine.get_document_content(&doc_id).await?, "saved and logged");
+        assert_eq!(engine.get_document(&doc_id).await?.read().await.title, "Thesis");
+    }
+
+    // The recovered operation is saved with the OpLog, and replaying it again changes nothing
+    assert!(wal.entries(&doc_id)?.is_empty());
+    assert_eq!(engine.read().await.replay_logged_operations(&doc_id, &logged).await?, 0);
+
+    // An entry past the document's version, with the ones before it lost, isn't applied to
+    // content it wasn't made against; peers are asked for the document instead
+    let mut events = engine.read().await.subscribe_events();
+    let mut gapped = logged[0].clone();
+    gapped.base_version = engine.read().await.get_versioned_snapshot(&doc_id).await?.1 + 10;
+    assert_eq!(engine.read().await.replay_logged_operations(&doc_id, &[gapped]).await?, 0);
+    assert_eq!(engine.read().await.get_document_content(&doc_id).await?, "saved and logged");
+    let mut resync_requested = false;
+    while let Ok(event) = events.try_recv() {
+        resync_requested |= matches!(event, DocumentEvent::ResyncNeeded { document_id } if document_id == doc_id);
+    }
+    assert!(resync_requested);
+
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_attribution_survives_a_restart() -> Result<()> {
+    let dir = temp_dir();
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+    let store = OplogStore::new(dir.clone(), AtRestCodec::new(false, None));
+
+    let start = || -> Result<(Arc<RwLock<CrdtEngine>>, DocumentPersistenceService)> {
+        let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+        let git = Arc::new(RwLock::new(GitManager::new(&config, Arc::clone(&engine))?));
+        let persistence = DocumentPersistenceService::new(Arc::clone(&engine), git, 300).with_oplog_store(store.clone());
+        Ok((engine, persistence))
+    };
+
+    // "system" is registered but never edits, so a plain decode would shift alice and bob down
+    let mut oplog = diamond_types::list::OpLog::new();
+    oplog.get_or_create_agent_id("system");
+    let alice = oplog.get_or_create_agent_id("alice");
+    oplog.add_insert(alice, 0, "Hello ");
+    let bob = oplog.get_or_create_agent_id("bob");
+    oplog.add_insert(bob, 6, "world");
+    let encoded = oplog.encode(diamond_types::list::encoding::EncodeOptions::default());
+
+    let (engine, persistence) = start()?;
+    let doc_id = engine
+        .read()
+        .await
+        .import_document_with_agents("Thesis".to_string(), "alice".to_string(), &encoded, &AgentMap::from_oplog(&oplog))
+        .await?;
+    // Moved text stays bob's, not carol's
+    engine.read().await.apply_local_operation(&doc_id, DocumentOperation::Move {
+        document_id: doc_id,
+        user_id: "carol".to_string(),
+        source: 6..11,
+        dest: 0,
+    }).await?;
+    let agent_map = engine.read().await.export_agent_map(&doc_id).await?;
+    let authors = engine.read().await.get_operation_authors(&doc_id).await?;
+    let stats = engine.read().await.document_stats(&doc_id).await?;
+    persistence.save_document(&doc_id).await?;
+    drop((engine, persistence));
+
+    let (engine, persistence) = start()?;
+    persistence.load_document(&doc_id).await?;
+    assert_eq!(engine.read().await.export_agent_map(&doc_id).await?, agent_map);
+    assert_eq!(engine.read().await.get_operation_authors(&doc_id).await?, authors);
+    let restored = engine.read().await.document_stats(&doc_id).await?;
+    assert_eq!(
+        restored.users.iter().map(|user| (&user.user_id, user.characters)).collect::<Vec<_>>(),
+        stats.users.iter().map(|user| (&user.user_id, user.characters)).collect::<Vec<_>>()
+    );
+    assert!(restored.users.iter().any(|user| user.user_id == "bob" && user.characters == 5));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_saves_replace_files_whole_and_leave_no_temporary_files() -> Result<()> {
+    let dir = temp_dir();
+    let store = OplogStore::new(dir.clone(), AtRestCodec::new(false, None));
+    let doc_id = uuid::Uuid::new_v4();
+    let old = vec![b'a'; 256 * 1024];
+    let new = vec![b'b'; 512 * 1024];
+    store.save(&doc_id, &old)?;
+
+    // A reader racing the writer sees one whole version or the other, never a torn file
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let reader = {
+        let (store, stop, old, new) = (store.clone(), Arc::clone(&stop), old.clone(), new.clone());
+        std::thread::spawn(move || {
+            let mut reads = 0;
+            while !stop.load(std::sync::atomic::Ordering::SeqCst) {
+                let read = store.load(&doc_id).unwrap();
+                assert!(read == old || read == new, "read a torn file of {} bytes", read.len());
+                reads += 1;
+            }
+            reads
+        })
+    };
+    for round in 0..50 {
+        store.save(&doc_id, if round % 2 == 0 { &new } else { &old })?;
+    }
+    stop.store(true, std::sync::atomic::Ordering::SeqCst);
+    assert!(reader.join().unwrap() > 0);
+
+    // The write-ahead log is rewritten the same way when checkpointed, or when the entry of
+    // an operation that failed to apply is discarded
+    let wal = WriteAheadLog::new(dir.clone(), AtRestCodec::new(false, None));
+    for version in 0..4 {
+        wal.append(&doc_id, version, b"operation")?;
+    }
+    wal.checkpoint(&doc_id, 2)?;
+    assert_eq!(wal.entries(&doc_id)?.len(), 2);
+    wal.discard_from(&doc_id, 3)?;
+    assert_eq!(wal.entries(&doc_id)?.iter().map(|entry| entry.base_version).collect::<Vec<_>>(), [2]);
+
+    let leftovers: Vec<_> = std::fs::read_dir(&dir)?
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".tmp"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_checking_a_missing_document_does_not_create_it() -> Result<()> {
+    let dir = temp_dir();
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let git = Arc::new(RwLock::new(GitManager::new(&config, Arc::clone(&engine))?));
+    let persistence = Arc::new(DocumentPersistenceService::new(Arc::clone(&engine), git, 300).with_auto_create_missing_documents(true));
+    let routes = DocumentPersistenceApi::routes(Arc::clone(&persistence));
+    let check = |id: uuid::Uuid| warp::test::request().method("GET").path(&format!("/api/documents/{}/check", id));
+
+    let missing = uuid::Uuid::new_v4();
+    let response = check(missing).reply(&routes).await;
+    assert_eq!(response.status(), 200);
+    let checked: CheckDocumentResponse = serde_json::from_slice(response.body())?;
+    assert!(!checked.exists);
+    assert!(!engine.read().await.document_exists(&missing));
+    assert!(engine.read().await.list_documents().await?.is_empty());
+
+    // Creating it takes asking for it explicitly
+    let response = warp::test::request()
+        .method("POST")
+        .path(&format!("/api/documents/{}/ensure", missing))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 200);
+    let checked: CheckDocumentResponse = serde_json::from_slice(check(missing).reply(&routes).await.body())?;
+    assert!(checked.exists);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_checkpoint_saves_the_compacted_history_and_empties_the_log() -> Result<()> {
+    let dir = temp_dir();
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+    let codec = AtRestCodec::new(false, None);
+    let wal = WriteAheadLog::new(dir.clone(), codec.clone());
+    let store = OplogStore::new(dir.clone(), codec.clone());
+
+    let start = || -> Result<(Arc<RwLock<CrdtEngine>>, DocumentPersistenceService)> {
+        let engine = Arc::new(RwLock::new(
+            CrdtEngine::new()?
+                .with_write_ahead_log(Some(wal.clone()))
+                .with_oplog_store(Some(store.clone())),
+        ));
+        let git = Arc::new(RwLock::new(GitManager::new(&config, Arc::clone(&engine))?));
+        let persistence = DocumentPersistenceService::new(Arc::clone(&engine), git, 300)
+            .with_oplog_store(store.clone())
+            .with_write_ahead_log(Some(wal.clone()));
+        Ok((engine, persistence))
+    };
+
+    let (engine, persistence) = start()?;
+    let doc_id = persistence.create_document("Thesis", "alice").await?;
+    let insert = |position: usize, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position,
+        content: content.to_string(),
+    };
+    for (position, content) in [(0, "one"), (3, " two"), (7, " three")] {
+        engine.read().await.apply_local_operation(&doc_id, insert(position, content)).await?;
+    }
+    assert_eq!(wal.entries(&doc_id)?.len(), 3);
+
+    // The entries were numbered by the history the checkpoint replaced, so they go with it
+    engine.read().await.checkpoint_document(&doc_id).await?;
+    assert!(wal.entries(&doc_id)?.is_empty());
+    assert_eq!(store.load(&doc_id)?, engine.read().await.export_document(&doc_id).await?);
+
+    // Operations logged after the checkpoint line up with the saved history after a crash
+    engine.read().await.apply_local_operation(&doc_id, insert(13, " four")).await?;
+    drop((engine, persistence));
+
+    let (engine, persistence) = start()?;
+    persistence.load_document(&doc_id).await?;
+    assert_eq!(engine.read().await.get_document_content(&doc_id).await?, "one two three four");
+
+    // A purged document stays gone after the next restart
+    engine.read().await.purge_document(&doc_id).await?;
+    drop((engine, persistence));
+    let (engine, persistence) = start()?;
+    assert_eq!(persistence.load_saved_documents().await?, 0);
+    assert!(!engine.read().await.document_exists(&doc_id));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_batch_that_cannot_be_logged_leaves_the_document_untouched() -> Result<()> {
+    let dir = temp_dir();
+    let wal = WriteAheadLog::new(dir.clone(), AtRestCodec::new(false, None));
+    let engine = CrdtEngine::new()?.with_write_ahead_log(Some(wal.clone()));
+    let doc_id = engine.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    let insert = |position: usize, content: &str| DocumentOperation::Insert {
+        document_id: doc_id,
+        user_id: "alice".to_string(),
+        position,
+        content: content.to_string(),
+    };
+    engine.apply_local_operation(&doc_id, insert(0, "kept")).await?;
+    let (content, version) = engine.get_versioned_snapshot(&doc_id).await?;
+    let records = engine.get_operation_log(&doc_id).await?.len();
+
+    // Every write to the log now fails
+    std::fs::remove_file(wal.path(&doc_id))?;
+    std::fs::create_dir(wal.path(&doc_id))?;
+    let batch = vec![insert(4, " one"), insert(8, " two")];
+    assert!(engine.apply_operation_batch(&doc_id, batch).await.is_err());
+
+    assert_eq!(engine.get_versioned_snapshot(&doc_id).await?, (content, version));
+    assert_eq!(engine.get_operation_log(&doc_id).await?.len(), records);
+
+    // Only the operation that was applied can be undone
+    std::fs::remove_dir(wal.path(&doc_id))?;
+    assert_eq!(engine.undo(&doc_id, "alice").await?.len(), 1);
+    assert_eq!(engine.get_document_content(&doc_id).await?, "");
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_trashed_documents_stay_trashed_after_a_restart() -> Result<()> {
+    let dir = temp_dir();
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+    let store = OplogStore::new(dir.clone(), AtRestCodec::new(false, None));
+
+    let start = || -> Result<(Arc<RwLock<CrdtEngine>>, DocumentPersistenceService)> {
+        let engine = Arc::new(RwLock::new(CrdtEngine::new()?.with_oplog_store(Some(store.clone()))));
+        let git = Arc::new(RwLock::new(GitManager::new(&config, Arc::clone(&engine))?));
+        let persistence = DocumentPersistenceService::new(Arc::clone(&engine), git, 300).with_oplog_store(store.clone());
+        Ok((engine, persistence))
+    };
+
+    let (engine, persistence) = start()?;
+    let doc_id = engine.read().await.create_document("Thesis".to_string(), "alice".to_string()).await?;
+    persistence.save_document(&doc_id).await?;
+    engine.read().await.delete_document(&doc_id).await?;
+    let deleted_at = engine.read().await.list_trashed_documents().await?[0].1;
+    drop((engine, persistence));
+
+    let (engine, persistence) = start()?;
+    persistence.load_document(&doc_id).await?;
+    assert!(engine.read().await.get_document(&doc_id).await.is_err());
+    assert_eq!(engine.read().await.list_trashed_documents().await?[0].1, deleted_at);
+
+    // Restoring clears the saved state too
+    engine.read().await.restore_document(&doc_id).await?;
+    drop((engine, persistence));
+    let (engine, persistence) = start()?;
+    persistence.load_document(&doc_id).await?;
+    assert!(engine.read().await.get_document(&doc_id).await.is_ok());
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,1068 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
+
+use crate::api::identity::{IdentityProvider, JwtProvider, SharedSecretProvider};
+use crate::api::protocol::{ApiMessage, Operation, UserPresence};
+use crate::api::websocket::WebSocketServer;
+use crate::client::TexSwarmClient;
+use crate::crdt::document::Role;
+use crate::crdt::engine::CrdtEngine;
+use crate::crdt::history::OperationKind;
+use crate::crdt::operations::DocumentOperation;
+use crate::git::manager::GitManager;
+use crate::utils::config::{Config, RateLimitConfig};
+use crate::utils::errors::AppError;
+
+#[tokio::test]
+async fn test_get_document_info_keeps_active_document() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+
+    // Authenticate and create a document, which becomes the active one
+    server.handle_message("session-1", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+
+    let active_id = match server.handle_message("session-1", ApiMessage::CreateDocument {
+        title: "Active".to_string(),
+        repository_url: None,
+    }).await? {
+        Some(ApiMessage::DocumentUpdate { document_id, .. }) => document_id,
+        other => panic!("Unexpected response: {:?}", other),
+    };
+
+    // Create a second document directly in the engine
+    let other_id = {
+        let engine = engine.read().await;
+        engine.create_document("Other".to_string(), "bob".to_string()).await?
+    };
+
+    // Fetch its metadata
+    let response = server.handle_message("session-1", ApiMessage::GetDocumentInfo {
+        document_id: other_id,
+    }).await?;
+
+    match response {
+        Some(ApiMessage::DocumentInfo { document }) => {
+            assert_eq!(document.id, other_id);
+            assert_eq!(document.title, "Other");
+            assert_eq!(document.owner, "bob");
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    // The active document must not have changed
+    assert_eq!(server.get_active_document("session-1").await?, Some(active_id));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resolved_comment_is_broadcast() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+    let _forwarder = server.start_event_forwarding().await;
+
+    server.handle_message("session-1", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+
+    let document_id = match server.handle_message("session-1", ApiMessage::CreateDocument {
+        title: "Reviewed".to_string(),
+        repository_url: None,
+    }).await? {
+        Some(ApiMessage::DocumentUpdate { document_id, .. }) => document_id,
+        other => panic!("Unexpected response: {:?}", other),
+    };
+
+    let (sender, mut receiver) = mpsc::channel(8);
+    server.set_sender("session-1", sender).await?;
+
+    // Add and resolve a comment through the engine, as the HTTP API does
+    let comment = {
+        let engine = engine.read().await;
+        let comment = engine.add_comment(&document_id, "bob".to_string(), 0..0, "Needs an intro".to_string()).await?;
+        engine.resolve_comment(&document_id, &comment.id).await?
+    };
+    assert!(comment.resolved);
+
+    // The session sees both the new comment and its resolution, between activity updates
+    for expected_resolved in [false, true] {
+        let (id, update) = loop {
+            let message = tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await?
+                .expect("Channel closed");
+            match serde_json::from_str::<ApiMessage>(message.to_str().unwrap())? {
+                ApiMessage::CommentUpdate { document_id, comment } => break (document_id, comment),
+                ApiMessage::ActivityUpdate { .. } => continue,
+                other => panic!("Unexpected message: {:?}", other),
+            }
+        };
+        assert_eq!(id, document_id);
+        assert_eq!(update.id, comment.id);
+        assert_eq!(update.resolved, expected_resolved);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rename_broadcasts_metadata_change_without_content() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+    let _forwarder = server.start_event_forwarding().await;
+
+    server.handle_message("session-1", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+
+    let document_id = match server.handle_message("session-1", ApiMessage::CreateDocument {
+        title: "Draft".to_string(),
+        repository_url: None,
+    }).await? {
+        Some(ApiMessage::DocumentUpdate { document_id, .. }) => document_id,
+        other => panic!("Unexpected response: {:?}", other),
+    };
+
+    let (sender, mut receiver) = mpsc::channel(8);
+    server.set_sender("session-1", sender).await?;
+
+    {
+        let engine = engine.read().await;
+        engine.rename_document(&document_id, "Final".to_string(), None).await?;
+    }
+
+    let message = tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await?
+        .expect("Channel closed");
+    let json: serde_json::Value = serde_json::from_str(message.to_str().unwrap())?;
+    assert_eq!(json["type"], "MetadataChanged");
+    assert!(json["payload"].get("content").is_none());
+    assert!(json["payload"].get("tags").is_none());
+
+    match serde_json::from_value::<ApiMessage>(json)? {
+        ApiMessage::MetadataChanged { document_id: id, title, owner, tags, collaborators, repository_url, roles, custom_metadata } => {
+            assert_eq!(id, document_id);
+            assert_eq!(title.as_deref(), Some("Final"));
+            assert!(owner.is_none() && tags.is_none() && collaborators.is_none() && repository_url.is_none());
+            assert!(roles.is_none() && custom_metadata.is_none());
+        }
+        other => panic!("Unexpected message: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unauthenticated_session_cannot_open_document() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+    let _forwarder = server.start_event_forwarding().await;
+
+    let document_id = {
+        let engine = engine.read().await;
+        engine.create_document("Private".to_string(), "alice".to_string()).await?
+    };
+
+    // A freshly connected socket that never authenticates
+    let (sender, mut receiver) = mpsc::channel(8);
+    server.register_connection("anonymous-session", sender).await?;
+
+    let response = server.handle_message("anonymous-session", ApiMessage::OpenDocument {
+        document_id,
+    }).await?;
+    match response {
+        Some(ApiMessage::Error { code, .. }) => assert_eq!(code, "unauthenticated"),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    assert_eq!(server.get_active_document("anonymous-session").await?, None);
+
+    // Changes to the document are not delivered to the anonymous session
+    {
+        let engine = engine.read().await;
+        engine.rename_document(&document_id, "Still private".to_string(), None).await?;
+    }
+    server.broadcast_document_update(document_id, "secret".to_string()).await?;
+
+    let received = tokio::time::timeout(Duration::from_millis(200), receiver.recv()).await;
+    assert!(received.is_err(), "Anonymous session received {:?}", received);
+
+    Ok(())
+}
+
+#[test]
+fn test_strict_protocol_rejects_unknown_fields() {
+    let typo = r#"{"type":"CreateDocument","payload":{"titel":"Thesis","title":"Thesis","repository_url":null}}"#;
+
+    assert!(ApiMessage::parse(typo, false).is_ok());
+    let error = ApiMessage::parse(typo, true).unwrap_err();
+    assert_eq!(error, "unknown field `payload.titel`");
+
+    // Nested fields are checked too, while unset optional fields are fine
+    let nested = r#"{"type":"PresenceUpdate","payload":{"document_id":"6f1c1d2e-7a4b-4c3d-9e8f-0a1b2c3d4e5f","presence":{"user_id":"alice","display_name":"Alice","cursor_position":null,"selection":null,"is_active":true,"last_activity":"now","colour":"red"}}}"#;
+    assert!(ApiMessage::parse(nested, false).is_ok());
+    assert_eq!(ApiMessage::parse(nested, true).unwrap_err(), "unknown field `payload.presence.colour`");
+
+    let valid = r#"{"type":"CreateDocument","payload":{"title":"Thesis","repository_url":null}}"#;
+    assert!(ApiMessage::parse(valid, true).is_ok());
+}
+
+#[tokio::test]
+async fn test_mention_notifies_every_session_of_the_user() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+    let _forwarder = server.start_event_forwarding().await;
+
+    // Bob is signed in twice, say on a laptop and a phone
+    let mut receivers = Vec::new();
+    for session_id in ["bob-laptop", "bob-phone"] {
+        server.handle_message(session_id, ApiMessage::Authentication {
+            user_id: "bob".to_string(),
+            token: None,
+        }).await?;
+        let (sender, receiver) = mpsc::channel(8);
+        server.set_sender(session_id, sender).await?;
+        receivers.push(receiver);
+    }
+
+    let document_id = {
+        let engine = engine.read().await;
+        let document_id = engine.create_document("Draft".to_string(), "alice".to_string()).await?;
+        engine.add_comment(&document_id, "alice".to_string(), 0..0, "@bob, can you check this?".to_string()).await?;
+        document_id
+    };
+
+    for receiver in &mut receivers {
+        let message = tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await?
+            .expect("Channel closed");
+        match serde_json::from_str::<ApiMessage>(message.to_str().unwrap())? {
+            ApiMessage::Notification { from, body } => {
+                assert_eq!(from, "alice");
+                assert_eq!(body, "@bob, can you check this?");
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    // Sending to a user directly reaches both sessions too
+    let sent = server.send_to_user("bob", &ApiMessage::Notification {
+        from: "system".to_string(),
+        body: format!("You were added to {}", document_id),
+    }).await?;
+    assert_eq!(sent, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_queue_depth_sets_backpressure_point() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+
+    for depth in [32, 64] {
+        let server = WebSocketServer::new(Arc::clone(&engine)).with_session_queue_depth(depth);
+        let (sender, _receiver) = server.session_channel();
+        server.register_connection("session-1", sender).await?;
+
+        // Nobody drains the queue, so heartbeats pile up until it is full
+        for _ in 0..depth {
+            tokio::time::timeout(Duration::from_millis(100), server.send_heartbeat()).await??;
+        }
+        assert!(tokio::time::timeout(Duration::from_millis(100), server.send_heartbeat()).await.is_err());
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deleted_document_is_closed_for_sessions() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+    let _forwarder = server.start_event_forwarding().await;
+
+    server.handle_message("session-1", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+
+    let document_id = match server.handle_message("session-1", ApiMessage::CreateDocument {
+        title: "Doomed".to_string(),
+        repository_url: None,
+    }).await? {
+        Some(ApiMessage::DocumentUpdate { document_id, .. }) => document_id,
+        other => panic!("Unexpected response: {:?}", other),
+    };
+
+    let (sender, mut receiver) = mpsc::channel(8);
+    server.set_sender("session-1", sender).await?;
+
+    engine.read().await.delete_document(&document_id).await?;
+
+    let message = tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await?
+        .expect("Channel closed");
+    match serde_json::from_str::<ApiMessage>(message.to_str().unwrap())? {
+        ApiMessage::DocumentDeleted { document_id: id } => assert_eq!(id, document_id),
+        other => panic!("Unexpected message: {:?}", other),
+    }
+    assert_eq!(server.get_active_document("session-1").await?, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewer_cannot_edit() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+
+    let document_id = {
+        let engine = engine.read().await;
+        let document_id = engine.create_document("Paper".to_string(), "alice".to_string()).await?;
+        engine.set_role(&document_id, "bob".to_string(), Role::Viewer, None).await?;
+        engine.set_role(&document_id, "carol".to_string(), Role::Editor, None).await?;
+        document_id
+    };
+
+    for (session_id, user_id) in [("bob-session", "bob"), ("carol-session", "carol")] {
+        server.handle_message(session_id, ApiMessage::Authentication {
+            user_id: user_id.to_string(),
+            token: None,
+        }).await?;
+    }
+
+    let insert = |content: &str| ApiMessage::DocumentOperation {
+        operation: Operation::Insert { document_id, position: 0, content: content.to_string() },
+        operation_id: None,
+    };
+
+    match server.handle_message("bob-session", insert("viewer")).await? {
+        Some(ApiMessage::OperationResult { result }) => {
+            assert!(!result.success);
+            assert_eq!(result.code.as_deref(), Some("forbidden"));
+            assert!(result.error.unwrap().starts_with("Forbidden"));
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    match server.handle_message("carol-session", insert("editor")).await? {
+        Some(ApiMessage::OperationResult { result }) => assert!(result.success),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    let engine = engine.read().await;
+    assert_eq!(engine.get_document_content(&document_id).await?, "editor");
+
+    // Users without a role have no access once roles are assigned
+    assert!(engine.authorize(&document_id, "mallory", Role::Viewer).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_round_trip_against_server() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+
+    // Grab a free port for the server
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let mut config = Config::default();
+    config.server.ws_host = "127.0.0.1".to_string();
+    config.server.ws_port = port;
+    server.start(&config).await?;
+
+    let client = TexSwarmClient::connect(&format!("ws://127.0.0.1:{}/ws", port)).await?;
+    client.authenticate("alice", None).await?;
+
+    let document_id = client.create_document("Round trip").await?;
+    client.insert(document_id, 0, "Hello world").await?;
+    client.insert(document_id, 5, ",").await?;
+    client.delete(document_id, 6..7).await?;
+    assert_eq!(client.open_document(document_id).await?, "Hello,world");
+
+    // Errors come back on the operation that caused them
+    assert!(client.insert(document_id, 100, "!").await.is_err());
+
+    // Updates pushed by the server reach the callbacks
+    let (updates, mut received) = mpsc::unbounded_channel();
+    client.on_update(move |document_id, content| {
+        let _ = updates.send((document_id, content.to_string()));
+    });
+    server.broadcast_document_update(document_id, "pushed".to_string()).await?;
+    let update = tokio::time::timeout(Duration::from_secs(1), received.recv()).await?
+        .expect("Channel closed");
+    assert_eq!(update, (document_id, "pushed".to_string()));
+
+    client.close();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_listens_on_every_configured_address() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+
+    // Grab two distinct free ports, releasing them just before the server binds them
+    let mut config = Config::default();
+    {
+        let listeners = [std::net::TcpListener::bind("127.0.0.1:0")?, std::net::TcpListener::bind("127.0.0.1:0")?];
+        for listener in &listeners {
+            config.server.ws_addresses.push(listener.local_addr()?);
+        }
+    }
+    server.start(&config).await?;
+
+    for addr in &config.server.ws_addresses {
+        let client = TexSwarmClient::connect(&format!("ws://{}/ws", addr)).await?;
+        client.authenticate("alice", None).await?;
+        client.create_document("Reachable").await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resume_one_operation_behind_sends_that_operation() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+
+    server.handle_message("session-1", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+    let document_id = {
+        let engine = engine.read().await;
+        let document_id = engine.create_document("Resumed".to_string(), "alice".to_string()).await?;
+        engine.apply_local_operation(&document_id, DocumentOperation::Insert {
+            document_id,
+            user_id: "alice".to_string(),
+            position: 0,
+            content: "Hello".to_string(),
+        }).await?;
+        document_id
+    };
+
+    let version = match server.handle_message("session-1", ApiMessage::OpenDocument { document_id }).await? {
+        Some(ApiMessage::DocumentUpdate { content, version, .. }) => {
+            assert_eq!(content, "Hello");
+            version
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    };
+
+    // Bob edits while alice is disconnected
+    {
+        let engine = engine.read().await;
+        engine.apply_local_operation(&document_id, DocumentOperation::Insert {
+            document_id,
+            user_id: "bob".to_string(),
+            position: 5,
+            content: ", world".to_string(),
+        }).await?;
+    }
+
+    match server.handle_message("session-1", ApiMessage::Resume { document_id, version: version.clone() }).await? {
+        Some(ApiMessage::DocumentPatch { base_version, version: patched, operations, .. }) => {
+            assert_eq!(base_version, version);
+            assert_eq!(patched, "12");
+            assert_eq!(operations.len(), 1);
+            assert_eq!(operations[0].agent, "bob");
+            assert_eq!(operations[0].kind, OperationKind::Insert);
+            assert_eq!(operations[0].position, 5);
+            assert_eq!(operations[0].content.as_deref(), Some(", world"));
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    // A version the server doesn't know falls back to the full content
+    match server.handle_message("session-1", ApiMessage::Resume { document_id, version: "99".to_string() }).await? {
+        Some(ApiMessage::DocumentUpdate { content, version, .. }) => {
+            assert_eq!(content, "Hello, world");
+            assert_eq!(version, "12");
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_heartbeat_can_be_disabled_or_scheduled() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+    server.handle_message("session-1", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+    let (sender, mut receiver) = mpsc::channel(8);
+    server.set_sender("session-1", sender).await?;
+
+    // Disabled: no task, and nothing arrives
+    let mut config = Config::default();
+    config.server.heartbeat_interval_secs = None;
+    assert!(server.spawn_heartbeat(config.server.heartbeat_interval()).is_none());
+    assert!(tokio::time::timeout(Duration::from_millis(1500), receiver.recv()).await.is_err());
+
+    // Every second: one heartbeat per tick
+    config.server.heartbeat_interval_secs = Some(1);
+    let task = server.spawn_heartbeat(config.server.heartbeat_interval()).expect("Heartbeat enabled");
+    let start = std::time::Instant::now();
+    for tick in 1..=2 {
+        let message = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await?
+            .expect("Channel closed");
+        assert!(matches!(
+            serde_json::from_str::<ApiMessage>(message.to_str().unwrap())?,
+            ApiMessage::Heartbeat { .. }
+        ));
+        assert!(start.elapsed() >= Duration::from_secs(tick));
+    }
+    task.abort();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_every_operation_gets_a_result_with_its_id() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?.with_max_operation_bytes(16)));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+    server.handle_message("session-1", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+    let document_id = engine.read().await.create_document("Results".to_string(), "alice".to_string()).await?;
+
+    let valid = ApiMessage::DocumentOperation {
+        operation: Operation::Insert { document_id, position: 0, content: "Hello".to_string() },
+        operation_id: Some("op-1".to_string()),
+    };
+    match server.handle_message("session-1", valid).await? {
+        Some(ApiMessage::OperationResult { result }) => {
+            assert_eq!(result.operation_id, "op-1");
+            assert!(result.success);
+            assert_eq!(result.error, None);
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    // Inserting more than the engine allows in one operation fails, and says so
+    let invalid = ApiMessage::DocumentOperation {
+        operation: Operation::Insert { document_id, position: 5, content: "x".repeat(64) },
+        operation_id: Some("op-2".to_string()),
+    };
+    match server.handle_message("session-1", invalid).await? {
+        Some(ApiMessage::OperationResult { result }) => {
+            assert_eq!(result.operation_id, "op-2");
+            assert!(!result.success);
+            assert!(!result.error.unwrap().is_empty());
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    assert_eq!(engine.read().await.get_document_content(&document_id).await?, "Hello");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spectator_watches_without_editing_or_presence() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+
+    let document_id = {
+        let engine = engine.read().await;
+        engine.create_document("Talk".to_string(), "alice".to_string()).await?
+    };
+
+    server.handle_message("watcher-session", ApiMessage::Authentication {
+        user_id: "bob".to_string(),
+        token: None,
+    }).await?;
+    match server.handle_message("watcher-session", ApiMessage::Spectate { document_id }).await? {
+        Some(ApiMessage::DocumentUpdate { document_id: id, .. }) => assert_eq!(id, document_id),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    let (sender, mut receiver) = mpsc::channel(8);
+    server.set_sender("watcher-session", sender).await?;
+
+    // Updates reach the spectator like any editor
+    server.broadcast_document_update(document_id, "live".to_string()).await?;
+    let message = tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await?
+        .expect("Channel closed");
+    match serde_json::from_str::<ApiMessage>(message.to_str().unwrap())? {
+        ApiMessage::DocumentUpdate { content, .. } => assert_eq!(content, "live"),
+        other => panic!("Unexpected message: {:?}", other),
+    }
+
+    // Its edits are rejected
+    match server.handle_message("watcher-session", ApiMessage::DocumentOperation {
+        operation: Operation::Insert { document_id, position: 0, content: "spectator".to_string() },
+        operation_id: None,
+    }).await? {
+        Some(ApiMessage::OperationResult { result }) => {
+            assert!(!result.success);
+            assert_eq!(result.code.as_deref(), Some("forbidden"));
+            assert!(result.error.unwrap().starts_with("Forbidden"));
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    // And it stays out of the presence list
+    let presence = UserPresence {
+        user_id: "bob".to_string(),
+        display_name: "Bob".to_string(),
+        cursor_position: Some(0),
+        selection: None,
+        is_active: true,
+        last_activity: "now".to_string(),
+        color: String::new(),
+    };
+    match server.handle_message("watcher-session", ApiMessage::PresenceUpdate { document_id, presence }).await? {
+        Some(ApiMessage::Error { code, .. }) => assert_eq!(code, "forbidden"),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    let engine = engine.read().await;
+    assert_eq!(engine.get_document_content(&document_id).await?, "");
+    assert_eq!(engine.active_collaborator_count(&document_id), 0);
+    assert!(engine.get_document_presences(&document_id).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_documents_without_access_cannot_be_opened_or_watched() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+
+    let document_id = {
+        let engine = engine.read().await;
+        let document_id = engine.create_document("Private".to_string(), "alice".to_string()).await?;
+        engine.set_role(&document_id, "carol".to_string(), Role::Viewer, None).await?;
+        document_id
+    };
+
+    for (session_id, user_id) in [("bob-session", "bob"), ("carol-session", "carol")] {
+        server.handle_message(session_id, ApiMessage::Authentication {
+            user_id: user_id.to_string(),
+            token: None,
+        }).await?;
+    }
+
+    // Bob has no role, so neither opening nor spectating lets him in
+    for message in [ApiMessage::OpenDocument { document_id }, ApiMessage::Spectate { document_id }] {
+        let error = server.handle_message("bob-session", message).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<AppError>(), Some(AppError::Forbidden(_))));
+        assert_eq!(server.get_active_document("bob-session").await?, None);
+    }
+
+    // A viewer may watch, and is a spectator as soon as the document is active
+    server.handle_message("carol-session", ApiMessage::Spectate { document_id }).await?;
+    assert_eq!(server.get_active_document("carol-session").await?, Some(document_id));
+    match server.handle_message("carol-session", ApiMessage::DocumentOperation {
+        operation: Operation::Insert { document_id, position: 0, content: "carol".to_string() },
+        operation_id: None,
+    }).await? {
+        Some(ApiMessage::OperationResult { result }) => assert_eq!(result.code.as_deref(), Some("forbidden")),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_jwt_identity_provider_authenticates_sessions() -> Result<()> {
+    let key = "test-signing-key";
+    let claims = serde_json::json!({
+        "sub": "alice@idp",
+        "name": "Alice",
+        "email": "alice@example.com",
+        "exp": chrono::Utc::now().timestamp() + 3600,
+    });
+    let sign = |claims: &serde_json::Value, key: &str| jsonwebtoken::encode(
+        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256),
+        claims,
+        &jsonwebtoken::EncodingKey::from_secret(key.as_bytes()),
+    );
+    let token = sign(&claims, key)?;
+
+    let provider = Arc::new(JwtProvider::new("HS256", key)?);
+    let identity = provider.resolve(&token).await?;
+    assert_eq!(identity.user_id, "alice@idp");
+    assert_eq!(identity.display_name.as_deref(), Some("Alice"));
+    assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+
+    // Swapping in other claims breaks the signature
+    let forged = sign(&serde_json::json!({ "sub": "mallory", "exp": claims["exp"] }), "other-key")?;
+    let mut parts: Vec<&str> = token.split('.').collect();
+    parts[1] = forged.split('.').nth(1).unwrap();
+    let tampered = parts.join(".");
+    assert!(provider.resolve(&tampered).await.is_err());
+
+    // Sessions become whoever their token says, whatever user ID they claim
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine)).with_identity_provider(provider);
+    let authenticate = |token: Option<String>| ApiMessage::Authentication { user_id: "bob".to_string(), token };
+
+    match server.handle_message("session-1", authenticate(Some(tampered))).await? {
+        Some(ApiMessage::Error { code, .. }) => assert_eq!(code, "auth_failed"),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    match server.handle_message("session-1", authenticate(None)).await? {
+        Some(ApiMessage::Error { code, .. }) => assert_eq!(code, "auth_failed"),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    match server.handle_message("session-1", ApiMessage::ListDocuments).await? {
+        Some(ApiMessage::Error { code, .. }) => assert_eq!(code, "unauthenticated"),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    match server.handle_message("session-1", authenticate(Some(token))).await? {
+        Some(ApiMessage::Error { code, .. }) => assert_eq!(code, "auth_success"),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    let document_id = match server.handle_message("session-1", ApiMessage::CreateDocument {
+        title: "Signed in".to_string(),
+        repository_url: None,
+    }).await? {
+        Some(ApiMessage::DocumentUpdate { document_id, .. }) => document_id,
+        other => panic!("Unexpected response: {:?}", other),
+    };
+    let document = engine.read().await.get_document(&document_id).await?;
+    assert_eq!(document.read().await.owner, "alice@idp");
+
+    // The shared-secret provider only accepts tokens signed with its secret
+    let shared = SharedSecretProvider::new(b"secret");
+    assert_eq!(shared.resolve(&shared.sign("carol")).await?.user_id, "carol");
+    assert!(shared.resolve(&SharedSecretProvider::new(b"other").sign("carol")).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_exceeding_operation_rate_pushes_throttle() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine))
+        .with_operation_rate_limit(Some(RateLimitConfig { per_second: 10.0, burst: 2 }));
+
+    server.handle_message("session-1", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+    let document_id = match server.handle_message("session-1", ApiMessage::CreateDocument {
+        title: "Fast".to_string(),
+        repository_url: None,
+    }).await? {
+        Some(ApiMessage::DocumentUpdate { document_id, .. }) => document_id,
+        other => panic!("Unexpected response: {:?}", other),
+    };
+    let (sender, mut receiver) = mpsc::channel(8);
+    server.set_sender("session-1", sender).await?;
+
+    let insert = || ApiMessage::DocumentOperation {
+        operation: Operation::Insert { document_id, position: 0, content: "x".to_string() },
+        operation_id: None,
+    };
+
+    // The burst goes through
+    for _ in 0..2 {
+        match server.handle_message("session-1", insert()).await? {
+            Some(ApiMessage::OperationResult { result }) => assert!(result.success),
+            other => panic!("Unexpected response: {:?}", other),
+        }
+    }
+    assert!(receiver.try_recv().is_err());
+
+    // The next one is rejected, and the session told to wait about a token's refill time
+    match server.handle_message("session-1", insert()).await? {
+        Some(ApiMessage::OperationResult { result }) => {
+            assert!(!result.success);
+            assert!(result.error.unwrap().starts_with("Rate limited"));
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    let message = receiver.try_recv().expect("Throttle pushed");
+    let retry_after_ms = match serde_json::from_str::<ApiMessage>(message.to_str().unwrap())? {
+        ApiMessage::Throttle { retry_after_ms } => retry_after_ms,
+        other => panic!("Unexpected message: {:?}", other),
+    };
+    assert!((1..=100).contains(&retry_after_ms), "retry after {} ms", retry_after_ms);
+
+    // Waiting that long is enough
+    tokio::time::sleep(Duration::from_millis(retry_after_ms)).await;
+    match server.handle_message("session-1", insert()).await? {
+        Some(ApiMessage::OperationResult { result }) => assert!(result.success),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    assert_eq!(engine.read().await.get_document_content(&document_id).await?, "xxx");
+
+    // Authenticating again, even from a new connection, doesn't refill the bucket
+    server.handle_message("session-1", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+    server.remove_session("session-1").await?;
+    server.handle_message("session-2", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+    match server.handle_message("session-2", insert()).await? {
+        Some(ApiMessage::OperationResult { result }) => assert_eq!(result.code.as_deref(), Some("rate_limited")),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list_my_documents_only_shows_users_own() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+
+    let (owned, shared, reviewed) = {
+        let engine = engine.read().await;
+        let owned = engine.create_document("Mine".to_string(), "alice".to_string()).await?;
+        let shared = engine.create_document("Shared".to_string(), "bob".to_string()).await?;
+        engine.add_collaborator(&shared, "alice".to_string(), None).await?;
+        let reviewed = engine.create_document("Reviewed".to_string(), "carol".to_string()).await?;
+        engine.set_role(&reviewed, "alice".to_string(), Role::Viewer, None).await?;
+        engine.create_document("Private".to_string(), "dave".to_string()).await?;
+        (owned, shared, reviewed)
+    };
+
+    server.handle_message("alice-session", ApiMessage::Authentication {
+        user_id: "alice".to_string(),
+        token: None,
+    }).await?;
+
+    let mut listed = match server.handle_message("alice-session", ApiMessage::ListMyDocuments).await? {
+        Some(ApiMessage::DocumentList { documents }) => documents.into_iter().map(|summary| summary.id).collect::<Vec<_>>(),
+        other => panic!("Unexpected response: {:?}", other),
+    };
+    listed.sort();
+    let mut expected = vec![owned, shared, reviewed];
+    expected.sort();
+    assert_eq!(listed, expected);
+
+    // Everyone else's documents are still in the full list
+    match server.handle_message("alice-session", ApiMessage::ListDocuments).await? {
+        Some(ApiMessage::DocumentList { documents }) => assert_eq!(documents.len(), 4),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rapid_cursor_updates_are_broadcast_once_with_the_latest() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine))
+        .with_presence_broadcast_interval(Some(Duration::from_millis(100)));
+    let document_id = engine.read().await.create_document("Talk".to_string(), "alice".to_string()).await?;
+
+    let mut receivers = Vec::new();
+    for (session_id, user_id) in [("alice-session", "alice"), ("bob-session", "bob")] {
+        server.handle_message(session_id, ApiMessage::Authentication {
+            user_id: user_id.to_string(),
+            token: None,
+        }).await?;
+        server.handle_message(session_id, ApiMessage::OpenDocument { document_id }).await?;
+        let (sender, receiver) = mpsc::channel(32);
+        server.set_sender(session_id, sender).await?;
+        receivers.push(receiver);
+    }
+    let mut bob = receivers.pop().unwrap();
+
+    for position in 0..10 {
+        server.handle_message("alice-session", ApiMessage::PresenceUpdate {
+            document_id,
+            presence: UserPresence {
+                user_id: "alice".to_string(),
+                display_name: "Alice".to_string(),
+                cursor_position: Some(position),
+                selection: None,
+                is_active: true,
+                last_activity: "now".to_string(),
+                color: String::new(),
+            },
+        }).await?;
+    }
+
+    // Nothing goes out until the window closes, then only the final position
+    assert!(bob.try_recv().is_err());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let mut cursors = Vec::new();
+    while let Ok(message) = bob.try_recv() {
+        if let ApiMessage::PresenceUpdate { presence, .. } = serde_json::from_str(message.to_str().unwrap())? {
+            cursors.push((presence.user_id, presence.cursor_position));
+        }
+    }
+    assert_eq!(cursors, [("alice".to_string(), Some(9))]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_edit_on_another_users_selection_sends_conflict_hint() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+    let _forwarder = server.start_event_forwarding().await;
+    let document_id = engine.read().await.create_document("Hot spot".to_string(), "alice".to_string()).await?;
+    engine.read().await.apply_local_operation(&document_id, DocumentOperation::Insert {
+        document_id,
+        user_id: "alice".to_string(),
+        position: 0,
+        content: "\\section{Intro}".to_string(),
+    }).await?;
+
+    let mut receivers = Vec::new();
+    for (session_id, user_id) in [("alice-session", "alice"), ("bob-session", "bob")] {
+        server.handle_message(session_id, ApiMessage::Authentication {
+            user_id: user_id.to_string(),
+            token: None,
+        }).await?;
+        server.handle_message(session_id, ApiMessage::OpenDocument { document_id }).await?;
+        let (sender, receiver) = mpsc::channel(32);
+        server.set_sender(session_id, sender).await?;
+        receivers.push(receiver);
+    }
+    let mut alice = receivers.remove(0);
+
+    // Alice has the heading selected when bob edits inside it
+    engine.read().await.update_user_presence(document_id, UserPresence {
+        user_id: "alice".to_string(),
+        display_name: "Alice".to_string(),
+        cursor_position: None,
+        selection: Some(9..14),
+        is_active: true,
+        last_activity: "now".to_string(),
+        color: String::new(),
+    }).await?;
+    engine.read().await.apply_local_operation(&document_id, DocumentOperation::Insert {
+        document_id,
+        user_id: "bob".to_string(),
+        position: 11,
+        content: "x".to_string(),
+    }).await?;
+
+    let (user_id, user_ids) = loop {
+        let message = tokio::time::timeout(Duration::from_secs(1), alice.recv()).await?
+            .expect("Channel closed");
+        if let ApiMessage::ConflictHint { user_id, user_ids, .. } = serde_json::from_str(message.to_str().unwrap())? {
+            break (user_id, user_ids);
+        }
+    };
+    assert_eq!(user_id, "bob");
+    assert_eq!(user_ids, ["alice"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_operation_resent_after_reconnect_is_applied_once() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let server = WebSocketServer::new(Arc::clone(&engine));
+    let document_id = engine.read().await.create_document("Resent".to_string(), "alice".to_string()).await?;
+    let insert = |content: &str, operation_id: &str| ApiMessage::DocumentOperation {
+        operation: Operation::Insert { document_id, position: 0, content: content.to_string() },
+        operation_id: Some(operation_id.to_string()),
+    };
+    let authenticate = |user_id: &str| ApiMessage::Authentication { user_id: user_id.to_string(), token: None };
+    let succeeded = |response: Option<ApiMessage>, expected_id: &str| match response {
+        Some(ApiMessage::OperationResult { result }) => {
+            assert_eq!(result.operation_id, expected_id);
+            result.success
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    };
+
+    server.handle_message("session-1", authenticate("alice")).await?;
+    assert!(succeeded(server.handle_message("session-1", insert("Hello", "op-1")).await?, "op-1"));
+
+    // The connection drops before the client hears back, so it sends the operation again
+    server.remove_session("session-1").await?;
+    server.handle_message("session-2", authenticate("alice")).await?;
+    assert!(succeeded(server.handle_message("session-2", insert("Hello", "op-1")).await?, "op-1"));
+    assert_eq!(engine.read().await.get_document_content(&document_id).await?, "Hello");
+
+    // Other operations, and other users' operations with the same ID, still apply
+    assert!(succeeded(server.handle_message("session-2", insert("> ", "op-2")).await?, "op-2"));
+    server.handle_message("session-3", authenticate("bob")).await?;
+    assert!(succeeded(server.handle_message("session-3", insert("# ", "op-1")).await?, "op-1"));
+    assert_eq!(engine.read().await.get_document_content(&document_id).await?, "# > Hello");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sync_now_commits_local_edits_to_the_repository() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("texswarm-ws-test-{}", uuid::Uuid::new_v4()));
+    let remote_path = dir.join("remote.git");
+    git2::Repository::init_bare(&remote_path)?;
+
+    let mut config = Config::default();
+    config.git.repositories_path = dir.join("repositories");
+
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let document_id = {
+        let engine = engine.read().await;
+        let document_id = engine.create_document("Synced".to_string(), "alice".to_string()).await?;
+        engine.set_repository_url(&document_id, format!("file://{}", remote_path.display()), None).await?;
+        document_id
+    };
+    let git_manager = Arc::new(RwLock::new(GitManager::new(&config, Arc::clone(&engine))?));
+    let server = WebSocketServer::new(Arc::clone(&engine)).with_git_manager(git_manager);
+    let sync_now = || ApiMessage::SyncNow { document_id };
+
+    server.handle_message("session-1", ApiMessage::Authentication { user_id: "alice".to_string(), token: None }).await?;
+    server.handle_message("session-1", ApiMessage::DocumentOperation {
+        operation: Operation::Insert { document_id, position: 0, content: "\\section{Intro}\n".to_string() },
+        operation_id: Some("op-1".to_string()),
+    }).await?;
+
+    // The edit is committed and pushed without waiting for a scheduled sync
+    let current = engine.read().await.get_versioned_snapshot(&document_id).await?.1.to_string();
+    match server.handle_message("session-1", sync_now()).await? {
+        Some(ApiMessage::SyncComplete { document_id: synced, version, git_committed }) => {
+            assert_eq!(synced, document_id);
+            assert_eq!(version, current);
+            assert!(git_committed);
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    let remote = git2::Repository::open_bare(&remote_path)?;
+    let head = remote.find_reference("refs/heads/master")?.peel_to_commit()?;
+    let blob = head.tree()?.get_path(std::path::Path::new("Synced.tex"))?.to_object(&remote)?;
+    assert_eq!(blob.as_blob().unwrap().content(), b"\\section{Intro}\n");
+
+    // With nothing new to commit, the sync still succeeds
+    match server.handle_message("session-1", sync_now()).await? {
+        Some(ApiMessage::SyncComplete { git_committed, .. }) => assert!(!git_committed),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_request_times_out_behind_a_long_held_engine_lock() -> Result<()> {
+    let engine = Arc::new(RwLock::new(CrdtEngine::new()?));
+    let document_id = engine.read().await.create_document("Stalled".to_string(), "alice".to_string()).await?;
+    let server = WebSocketServer::new(Arc::clone(&engine)).with_lock_wait_timeout(Some(Duration::from_millis(50)));
+    server.handle_message("session-1", ApiMessage::Authentication { user_id: "alice".to_string(), token: None }).await?;
+
+    // Something holds the engine for far longer than a request may wait
+    let held = engine.write().await;
+    let error = server.handle_message("session-1", ApiMessage::GetDocumentInfo { document_id }).await.unwrap_err();
+    assert!(matches!(error.downcast_ref::<AppError>(), Some(AppError::LockTimeout(what)) if what == "the CRDT engine"));
+
+    // Once it lets go, requests go through again
+    drop(held);
+    assert!(matches!(
+        server.handle_message("session-1", ApiMessage::GetDocumentInfo { document_id }).await?,
+        Some(ApiMessage::DocumentInfo { .. })
+    ));
+
+    Ok(())
+}
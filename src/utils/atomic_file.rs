@@ -0,0 +1,69 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Atomically replace the file at `path` with `contents`
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_atomic_with(path, |file| file.write_all(contents))
+}
+
+/// Atomically replace the file at `path` with whatever `write` produces.
+///
+/// The data is written to a temporary file next to the target, flushed to disk, and then
+/// renamed over the target, so a crash or failing writer never leaves a partially written
+/// file behind. The directory is flushed too, so once this returns the new file is what a
+/// crash leaves. On failure the temporary file is removed and the target is left untouched.
+pub fn write_atomic_with<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    let temp_path = temp_path_for(path);
+
+    let result = write_and_rename(&temp_path, path, write);
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+fn write_and_rename<F>(temp_path: &Path, path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    let mut file = File::create(temp_path)?;
+    write(&mut file)?;
+    file.sync_all()?;
+    fs::rename(temp_path, path)?;
+    sync_dir(parent_dir(path))
+}
+
+/// Flush a directory's entries to disk, so a file created in, renamed into or removed from
+/// it stays that way after a crash
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    // Only Unix lets directories be opened, and synced, like files
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Directory `path` is in
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Path of the temporary file used while writing `path`
+pub fn temp_path_for(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.tmp", file_name))
+}
@@ -0,0 +1,59 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Delays between retries that grow geometrically up to a cap
+///
+/// Each call to `next_delay` returns the current delay and multiplies it for the next one.
+/// With jitter, a delay is shortened by a random fraction of up to `jitter`, so peers that
+/// failed together don't all retry at the same moment; a delay never exceeds the cap.
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    jitter: f64,
+    current: Duration,
+}
+
+impl ExponentialBackoff {
+    /// Start at `initial` and double up to `max`, without jitter
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            multiplier: 2.0,
+            jitter: 0.0,
+            current: initial.min(max),
+        }
+    }
+
+    /// Grow the delay by `multiplier` after each attempt; values below 1 are treated as 1
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Shorten each delay by a random fraction of up to `jitter`, clamped to `0.0..=1.0`
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Delay to wait before the next attempt
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = self.current.mul_f64(self.multiplier).min(self.max);
+
+        if self.jitter > 0.0 {
+            let cut = rand::thread_rng().gen_range(0.0..=self.jitter);
+            delay.mul_f64(1.0 - cut)
+        } else {
+            delay
+        }
+    }
+
+    /// Start over from the initial delay, typically after a successful attempt
+    pub fn reset(&mut self) {
+        self.current = self.initial.min(self.max);
+    }
+}
@@ -1,7 +1,22 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::crdt::presence::PresenceDecay;
+use crate::network::protocol::ProtocolVersion;
+use crate::utils::atomic_file;
+use crate::utils::backoff::ExponentialBackoff;
+use crate::utils::rate_limit::TokenBucket;
+use crate::utils::errors::AppError;
+
+/// Environment variable overriding the location of the configuration file
+pub const CONFIG_PATH_ENV: &str = "TEXSWARM_CONFIG_PATH";
+
+/// Environment variable holding the at-rest encryption key, which overrides the config file
+pub const STORAGE_KEY_ENV: &str = "TEXSWARM_STORAGE_KEY";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -9,6 +24,8 @@ pub struct Config {
     pub network: NetworkConfig,
     pub git: GitConfig,
     pub storage: StorageConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,6 +34,141 @@ pub struct ServerConfig {
     pub api_port: u16,
     pub ws_host: String,
     pub ws_port: u16,
+    /// Bearer token for admin-only endpoints; they are disabled when unset
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Reject WebSocket messages with unknown fields instead of ignoring them
+    #[serde(default)]
+    pub strict_protocol: bool,
+    /// How many outgoing WebSocket messages each session queues before senders have to wait
+    #[serde(default = "default_session_queue_depth")]
+    pub session_queue_depth: usize,
+    /// Mark a collaborator inactive after this many seconds without a message from them
+    #[serde(default = "default_presence_inactive_after_secs")]
+    pub presence_inactive_after_secs: u64,
+    /// Remove a collaborator's presence after this many seconds without a message from them
+    #[serde(default = "default_presence_remove_after_secs")]
+    pub presence_remove_after_secs: u64,
+    /// Addresses to serve the HTTP API on, e.g. loopback and a LAN interface. When empty,
+    /// it is served on `api_host`:`api_port` alone.
+    #[serde(default)]
+    pub api_addresses: Vec<SocketAddr>,
+    /// Addresses to serve WebSockets on. When empty, they are served on `ws_host`:`ws_port` alone.
+    #[serde(default)]
+    pub ws_addresses: Vec<SocketAddr>,
+    /// Send every WebSocket client a heartbeat this often. No heartbeats are sent when unset.
+    #[serde(default = "default_heartbeat_interval_secs")]
+    pub heartbeat_interval_secs: Option<u64>,
+    /// How WebSocket clients' tokens are checked. When unset, clients are taken at their word.
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// How fast each user may send operations over WebSockets, across all their sessions;
+    /// unlimited when unset
+    #[serde(default)]
+    pub operation_rate_limit: Option<RateLimitConfig>,
+    /// Broadcast each user's presence at most this often, sending only the latest of the
+    /// updates made meanwhile; 0 broadcasts every update
+    #[serde(default = "default_presence_broadcast_interval_ms")]
+    pub presence_broadcast_interval_ms: u64,
+}
+
+/// A steady rate, with room for bursts above it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Sustained rate allowed
+    pub per_second: f64,
+    /// Most allowed in a burst
+    pub burst: u32,
+}
+
+impl RateLimitConfig {
+    /// A full token bucket enforcing this limit
+    pub fn bucket(&self) -> TokenBucket {
+        TokenBucket::new(self.burst, self.per_second)
+    }
+}
+
+/// Where the user behind an authentication token comes from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum AuthConfig {
+    /// Tokens are `<user_id>.<hex HMAC-SHA256 of the user ID>`, signed with `secret`
+    SharedSecret { secret: String },
+    /// Tokens are JWTs from an external identity provider, identifying the user by `sub`
+    Jwt {
+        /// Signing algorithm, e.g. `RS256`
+        algorithm: String,
+        /// Shared secret for the `HS*` algorithms, PEM-encoded public key for the others
+        key: String,
+        /// Required `iss` claim, if any
+        #[serde(default)]
+        issuer: Option<String>,
+        /// Required `aud` claim, if any
+        #[serde(default)]
+        audience: Option<String>,
+    },
+}
+
+fn default_session_queue_depth() -> usize {
+    crate::api::websocket::DEFAULT_SESSION_QUEUE_DEPTH
+}
+
+/// The configured addresses, or the single host and port when there are none
+fn listen_addrs(name: &str, addresses: &[SocketAddr], host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    if !addresses.is_empty() {
+        return Ok(addresses.to_vec());
+    }
+
+    let address = format!("{}:{}", host, port).parse().map_err(|e| {
+        AppError::ConfigError(format!("server.{}_host and server.{}_port are not a valid address: {}", name, name, e))
+    })?;
+    Ok(vec![address])
+}
+
+fn default_presence_inactive_after_secs() -> u64 {
+    60
+}
+
+fn default_presence_remove_after_secs() -> u64 {
+    300
+}
+
+fn default_heartbeat_interval_secs() -> Option<u64> {
+    Some(30)
+}
+
+fn default_presence_broadcast_interval_ms() -> u64 {
+    100
+}
+
+impl ServerConfig {
+    /// Addresses the HTTP API is served on
+    pub fn api_socket_addrs(&self) -> Result<Vec<SocketAddr>> {
+        listen_addrs("api", &self.api_addresses, &self.api_host, self.api_port)
+    }
+
+    /// Addresses WebSockets are served on
+    pub fn ws_socket_addrs(&self) -> Result<Vec<SocketAddr>> {
+        listen_addrs("ws", &self.ws_addresses, &self.ws_host, self.ws_port)
+    }
+
+    /// When the presence of silent collaborators decays
+    pub fn presence_decay(&self) -> PresenceDecay {
+        PresenceDecay {
+            inactive_after: Duration::from_secs(self.presence_inactive_after_secs),
+            remove_after: Duration::from_secs(self.presence_remove_after_secs),
+        }
+    }
+
+    /// How often each user's presence is broadcast at most, if updates are coalesced
+    pub fn presence_broadcast_interval(&self) -> Option<Duration> {
+        (self.presence_broadcast_interval_ms > 0).then(|| Duration::from_millis(self.presence_broadcast_interval_ms))
+    }
+
+    /// How often WebSocket clients are sent a heartbeat, if at all
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat_interval_secs.map(Duration::from_secs)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +179,131 @@ pub struct NetworkConfig {
     pub external_addresses: Vec<String>,
     pub enable_mdns: bool,
     pub enable_kad: bool,
+    /// How long to wait for a peer to answer a request before giving up
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// Close connections to peers that share no open document with us after this long.
+    /// Connections are kept alive indefinitely when unset.
+    #[serde(default)]
+    pub connection_idle_timeout_secs: Option<u64>,
+    /// Collaboration protocol versions to speak; the highest one a peer shares is used
+    #[serde(default = "default_protocol_versions")]
+    pub protocol_versions: Vec<ProtocolVersion>,
+    /// Git repository whose `bootstrap.txt` lists peers to dial on start, and which this
+    /// node keeps its own addresses in
+    #[serde(default)]
+    pub bootstrap_repo_url: Option<String>,
+    /// Peer IDs allowed to connect; any peer may connect when unset
+    #[serde(default)]
+    pub allowed_peers: Option<Vec<String>>,
+    /// Peer IDs that are disconnected as soon as they connect
+    #[serde(default)]
+    pub denied_peers: Vec<String>,
+    /// Most text a single operation may insert, in bytes; 0 means no limit
+    #[serde(default = "default_max_operation_bytes")]
+    pub max_operation_bytes: usize,
+    /// How long to wait between attempts to reach bootstrap peers we aren't connected to
+    #[serde(default)]
+    pub redial_backoff: BackoffConfig,
+    /// How operations reach peers
+    #[serde(default)]
+    pub sync_mode: SyncMode,
+    /// How often connected peers are polled for the documents we hold, when polling
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    /// How often a hash of each subscribed document's content is sent to peers, so copies
+    /// that silently diverged are noticed and resynced; 0 disables it
+    #[serde(default = "default_content_hash_interval_secs")]
+    pub content_hash_interval_secs: u64,
+    /// Run the network engine on the libp2p service rather than the in-process placeholder
+    #[serde(default)]
+    pub real_network: bool,
+}
+
+impl NetworkConfig {
+    /// How often content hashes are sent to peers, if they are
+    pub fn content_hash_interval(&self) -> Option<Duration> {
+        (self.content_hash_interval_secs > 0).then(|| Duration::from_secs(self.content_hash_interval_secs))
+    }
+}
+
+/// How document operations are propagated between peers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// Operations are published on each document's gossipsub topic
+    #[default]
+    Gossip,
+    /// Connected peers are polled with sync requests for what we lack. Slower, but works on
+    /// networks that block gossipsub.
+    Polling,
+    /// Operations are published and peers are polled as well
+    Both,
+}
+
+impl SyncMode {
+    /// Whether operations are published over gossipsub
+    pub fn gossips(self) -> bool {
+        matches!(self, SyncMode::Gossip | SyncMode::Both)
+    }
+
+    /// Whether peers are polled for operations
+    pub fn polls(self) -> bool {
+        matches!(self, SyncMode::Polling | SyncMode::Both)
+    }
+}
+
+/// Parameters of an `ExponentialBackoff`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackoffConfig {
+    /// Delay before the first retry
+    pub initial_delay_ms: u64,
+    /// Longest delay between two attempts
+    pub max_delay_ms: u64,
+    /// Factor the delay grows by after each attempt
+    pub multiplier: f64,
+    /// Largest fraction each delay is randomly shortened by, between 0 and 1
+    pub jitter: f64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 1000,
+            max_delay_ms: 60_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl BackoffConfig {
+    /// A backoff starting from the initial delay
+    pub fn backoff(&self) -> ExponentialBackoff {
+        ExponentialBackoff::new(Duration::from_millis(self.initial_delay_ms), Duration::from_millis(self.max_delay_ms))
+            .with_multiplier(self.multiplier)
+            .with_jitter(self.jitter)
+    }
+}
+
+fn default_max_operation_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_poll_interval_secs() -> u64 {
+    30
+}
+
+fn default_content_hash_interval_secs() -> u64 {
+    60
+}
+
+fn default_protocol_versions() -> Vec<ProtocolVersion> {
+    ProtocolVersion::ALL.to_vec()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,7 +312,56 @@ pub struct GitConfig {
     pub github_token: Option<String>,
     pub github_username: Option<String>,
     pub github_email: Option<String>,
+    /// Base URL of the GitHub REST API, overridable for GitHub Enterprise
+    #[serde(default = "default_github_api_url")]
+    pub github_api_url: String,
+    /// Sync every linked document at least this often, in seconds, edited or not, as a
+    /// backstop for the quiet-period sync; 0 disables it
     pub sync_interval_secs: u64,
+    /// Sync a document once it has been edited and then left alone this long, so a burst of
+    /// edits becomes one commit; 0 leaves only the interval sync
+    #[serde(default = "default_sync_quiet_period_secs")]
+    pub sync_quiet_period_secs: u64,
+    /// Most Git operations that may run at once, across all documents
+    #[serde(default = "default_max_concurrent_operations")]
+    pub max_concurrent_operations: usize,
+    /// How often, in seconds, each document's remote is checked for being reachable; 0 disables
+    /// the check
+    #[serde(default = "default_remote_check_interval_secs")]
+    pub remote_check_interval_secs: u64,
+}
+
+impl GitConfig {
+    /// How often every linked document is synced regardless of edits, if it is
+    pub fn sync_interval(&self) -> Option<Duration> {
+        (self.sync_interval_secs > 0).then(|| Duration::from_secs(self.sync_interval_secs))
+    }
+
+    /// How long an edited document must go unedited before it is synced, if it is synced then
+    pub fn sync_quiet_period(&self) -> Option<Duration> {
+        (self.sync_quiet_period_secs > 0).then(|| Duration::from_secs(self.sync_quiet_period_secs))
+    }
+
+    /// How often each document's remote is checked for being reachable, if it is checked
+    pub fn remote_check_interval(&self) -> Option<Duration> {
+        (self.remote_check_interval_secs > 0).then(|| Duration::from_secs(self.remote_check_interval_secs))
+    }
+}
+
+fn default_sync_quiet_period_secs() -> u64 {
+    10
+}
+
+fn default_max_concurrent_operations() -> usize {
+    4
+}
+
+fn default_remote_check_interval_secs() -> u64 {
+    300
+}
+
+fn default_github_api_url() -> String {
+    "https://api.github.com".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,6 +370,93 @@ pub struct StorageConfig {
     pub max_document_size_mb: u64,
     pub enable_autosave: bool,
     pub autosave_interval_seconds: u64,
+    /// Compress persisted OpLogs with zstd
+    #[serde(default)]
+    pub compress_at_rest: bool,
+    /// Passphrase to encrypt persisted OpLogs with (AES-GCM). Prefer setting it through
+    /// `TEXSWARM_STORAGE_KEY` over storing it in the config file.
+    #[serde(default)]
+    pub encryption_key: Option<String>,
+    /// Most documents the node holds at once; 0 means no limit
+    #[serde(default)]
+    pub max_documents: usize,
+    /// How long deleted documents stay in the trash, restorable, before they are removed for good
+    #[serde(default = "default_trash_retention_secs")]
+    pub trash_retention_secs: u64,
+    /// File to append a JSON-lines audit record of user actions to; nothing is audited when unset
+    #[serde(default)]
+    pub audit_log_path: Option<PathBuf>,
+    /// Size past which the audit log is moved aside and a new file started
+    #[serde(default = "default_audit_log_max_bytes")]
+    pub audit_log_max_bytes: u64,
+    /// Log each operation to a `.wal` file next to the document's OpLog before applying it,
+    /// so edits since the last save survive a crash
+    #[serde(default)]
+    pub write_ahead_log: bool,
+    /// Create a document, owned by `system`, when an operation or check names one this node
+    /// doesn't hold, instead of reporting it missing
+    #[serde(default)]
+    pub auto_create_missing_documents: bool,
+}
+
+/// Instrumentation for tracking down performance problems
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugConfig {
+    /// Warn about CRDT operations, merges and Git syncs that take longer than this; no
+    /// warnings are logged when unset
+    #[serde(default = "default_slow_op_threshold_ms")]
+    pub slow_op_threshold_ms: Option<u64>,
+    /// Fail with an error, logging which lock it was, after waiting this long for a document
+    /// or session lock; waits are unbounded when unset
+    #[serde(default = "default_lock_wait_timeout_ms")]
+    pub lock_wait_timeout_ms: Option<u64>,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            slow_op_threshold_ms: default_slow_op_threshold_ms(),
+            lock_wait_timeout_ms: default_lock_wait_timeout_ms(),
+        }
+    }
+}
+
+impl DebugConfig {
+    /// How long an operation may take before it is logged as slow, if slow ones are logged
+    pub fn slow_op_threshold(&self) -> Option<Duration> {
+        self.slow_op_threshold_ms.map(Duration::from_millis)
+    }
+
+    /// How long to wait for a lock before giving up, if waits are bounded
+    pub fn lock_wait_timeout(&self) -> Option<Duration> {
+        self.lock_wait_timeout_ms.map(Duration::from_millis)
+    }
+}
+
+fn default_slow_op_threshold_ms() -> Option<u64> {
+    Some(500)
+}
+
+fn default_lock_wait_timeout_ms() -> Option<u64> {
+    Some(30_000)
+}
+
+fn default_trash_retention_secs() -> u64 {
+    30 * 24 * 60 * 60
+}
+
+fn default_audit_log_max_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+impl StorageConfig {
+    /// The at-rest encryption key, taken from the environment if set there
+    pub fn resolved_encryption_key(&self) -> Option<String> {
+        std::env::var(STORAGE_KEY_ENV)
+            .ok()
+            .filter(|key| !key.is_empty())
+            .or_else(|| self.encryption_key.clone())
+    }
 }
 
 impl Default for Config {
@@ -54,6 +467,17 @@ impl Default for Config {
                 api_port: 8090,
                 ws_host: "0.0.0.0".to_string(),
                 ws_port: 8091,
+                admin_token: None,
+                strict_protocol: false,
+                session_queue_depth: default_session_queue_depth(),
+                presence_inactive_after_secs: default_presence_inactive_after_secs(),
+                presence_remove_after_secs: default_presence_remove_after_secs(),
+                api_addresses: vec![],
+                ws_addresses: vec![],
+                heartbeat_interval_secs: default_heartbeat_interval_secs(),
+                auth: None,
+                operation_rate_limit: None,
+                presence_broadcast_interval_ms: default_presence_broadcast_interval_ms(),
             },
             network: NetworkConfig {
                 peer_id_seed: None,
@@ -62,56 +486,227 @@ impl Default for Config {
                 external_addresses: vec![],
                 enable_mdns: true,
                 enable_kad: true,
+                request_timeout_secs: default_request_timeout_secs(),
+                connection_idle_timeout_secs: None,
+                protocol_versions: default_protocol_versions(),
+                bootstrap_repo_url: None,
+                allowed_peers: None,
+                denied_peers: vec![],
+                max_operation_bytes: default_max_operation_bytes(),
+                redial_backoff: BackoffConfig::default(),
+                sync_mode: SyncMode::default(),
+                poll_interval_secs: default_poll_interval_secs(),
+                content_hash_interval_secs: default_content_hash_interval_secs(),
+                real_network: false,
             },
             git: GitConfig {
                 repositories_path: PathBuf::from("./repositories"),
                 github_token: None,
                 github_username: None,
                 github_email: None,
+                github_api_url: default_github_api_url(),
                 sync_interval_secs: 300,
+                sync_quiet_period_secs: default_sync_quiet_period_secs(),
+                max_concurrent_operations: default_max_concurrent_operations(),
+                remote_check_interval_secs: default_remote_check_interval_secs(),
             },
             storage: StorageConfig {
                 documents_path: PathBuf::from("./documents"),
                 max_document_size_mb: 50,
                 enable_autosave: true,
                 autosave_interval_seconds: 60,
+                compress_at_rest: false,
+                encryption_key: None,
+                max_documents: 0,
+                trash_retention_secs: default_trash_retention_secs(),
+                audit_log_path: None,
+                audit_log_max_bytes: default_audit_log_max_bytes(),
+                write_ahead_log: false,
+                auto_create_missing_documents: false,
             },
+            debug: DebugConfig::default(),
         }
     }
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
-        let config_path = Self::config_path();
+        Self::load_from(&Self::config_path())
+    }
 
+    /// Load and validate the configuration from a specific file, creating it with
+    /// default values if it doesn't exist
+    pub fn load_from(config_path: &Path) -> Result<Self> {
         if !config_path.exists() {
             let default_config = Self::default();
-            default_config.save()?;
+            default_config.validate()?;
+            default_config.save_to(config_path)?;
             return Ok(default_config);
         }
 
-        let config_str = fs::read_to_string(&config_path)?;
+        let config_str = fs::read_to_string(config_path)?;
         let config: Config = serde_json::from_str(&config_str)?;
+        config.validate()?;
 
         Ok(config)
     }
 
     pub fn save(&self) -> Result<()> {
-        let config_path = Self::config_path();
+        self.save_to(&Self::config_path())
+    }
 
+    /// Save the configuration to a specific file.
+    ///
+    /// The file is replaced atomically so an interrupted save never leaves a truncated config.
+    pub fn save_to(&self, config_path: &Path) -> Result<()> {
         // Ensure the directory exists
         if let Some(parent) = config_path.parent() {
             fs::create_dir_all(parent)?;
         }
 
         let config_str = serde_json::to_string_pretty(self)?;
-        fs::write(&config_path, config_str)?;
+        atomic_file::write_atomic(config_path, config_str.as_bytes())?;
 
         Ok(())
     }
 
+    /// Check that the configuration is usable, returning a descriptive error if not
+    ///
+    /// Nothing is created or written; see `check_storage_paths` for that.
+    pub fn validate(&self) -> Result<()> {
+        if self.server.api_port == 0 {
+            return Err(AppError::ConfigError("server.api_port must be between 1 and 65535".to_string()).into());
+        }
+
+        // The document persistence API listens on api_port + 2
+        if self.server.api_port > u16::MAX - 2 {
+            return Err(AppError::ConfigError(format!(
+                "server.api_port must be at most {} to leave room for the document API port",
+                u16::MAX - 2
+            )).into());
+        }
+
+        if self.server.ws_port == 0 {
+            return Err(AppError::ConfigError("server.ws_port must be between 1 and 65535".to_string()).into());
+        }
+
+        if self.server.api_port == self.server.ws_port {
+            return Err(AppError::ConfigError(format!(
+                "server.api_port and server.ws_port must differ (both are {})",
+                self.server.api_port
+            )).into());
+        }
+
+        let api_addrs = self.server.api_socket_addrs()?;
+        if let Some(shared) = self.server.ws_socket_addrs()?.iter().find(|addr| api_addrs.contains(addr)) {
+            return Err(AppError::ConfigError(format!(
+                "{} is configured for both the HTTP API and WebSockets",
+                shared
+            )).into());
+        }
+
+        if self.server.session_queue_depth == 0 {
+            return Err(AppError::ConfigError("server.session_queue_depth must be greater than 0".to_string()).into());
+        }
+
+        if self.server.presence_inactive_after_secs == 0 {
+            return Err(AppError::ConfigError("server.presence_inactive_after_secs must be greater than 0".to_string()).into());
+        }
+
+        if self.server.presence_remove_after_secs <= self.server.presence_inactive_after_secs {
+            return Err(AppError::ConfigError(format!(
+                "server.presence_remove_after_secs must be greater than server.presence_inactive_after_secs ({})",
+                self.server.presence_inactive_after_secs
+            )).into());
+        }
+
+        if self.server.heartbeat_interval_secs == Some(0) {
+            return Err(AppError::ConfigError("server.heartbeat_interval_secs must be greater than 0".to_string()).into());
+        }
+
+        if self.network.request_timeout_secs == 0 {
+            return Err(AppError::ConfigError("network.request_timeout_secs must be greater than 0".to_string()).into());
+        }
+
+        if self.network.connection_idle_timeout_secs == Some(0) {
+            return Err(AppError::ConfigError("network.connection_idle_timeout_secs must be greater than 0".to_string()).into());
+        }
+
+        if self.network.poll_interval_secs == 0 {
+            return Err(AppError::ConfigError("network.poll_interval_secs must be greater than 0".to_string()).into());
+        }
+
+        if self.network.protocol_versions.is_empty() {
+            return Err(AppError::ConfigError("network.protocol_versions must not be empty".to_string()).into());
+        }
+
+        if self.network.listen_addresses.is_empty() {
+            return Err(AppError::ConfigError("network.listen_addresses must not be empty".to_string()).into());
+        }
+
+        let backoff = &self.network.redial_backoff;
+        if backoff.initial_delay_ms == 0 || backoff.max_delay_ms < backoff.initial_delay_ms {
+            return Err(AppError::ConfigError(
+                "network.redial_backoff delays must be greater than 0, with max_delay_ms at least initial_delay_ms".to_string()
+            ).into());
+        }
+
+        if !(0.0..=1.0).contains(&backoff.jitter) {
+            return Err(AppError::ConfigError("network.redial_backoff.jitter must be between 0 and 1".to_string()).into());
+        }
+
+        if let Some(limit) = &self.server.operation_rate_limit
+            && (limit.burst == 0 || !limit.per_second.is_finite() || limit.per_second <= 0.0)
+        {
+            return Err(AppError::ConfigError(
+                "server.operation_rate_limit needs a burst and rate greater than 0".to_string(),
+            ).into());
+        }
+
+        if let Some(auth) = &self.server.auth {
+            crate::api::identity::provider_from_config(auth)?;
+        }
+
+        if self.git.max_concurrent_operations == 0 {
+            return Err(AppError::ConfigError("git.max_concurrent_operations must be greater than 0".to_string()).into());
+        }
+
+        // Only look at the storage paths here; creating them and probing that they're writable
+        // waits for `check_storage_paths` at startup, so validating never touches the disk
+        for (name, path) in [
+            ("git.repositories_path", &self.git.repositories_path),
+            ("storage.documents_path", &self.storage.documents_path),
+        ] {
+            if path.as_os_str().is_empty() {
+                return Err(AppError::ConfigError(format!("{} must not be empty", name)).into());
+            }
+            if path.exists() && !path.is_dir() {
+                return Err(AppError::ConfigError(format!("{} ({}) is not a directory", name, path.display())).into());
+            }
+        }
+
+        if self.storage.encryption_key.as_deref() == Some("") {
+            return Err(AppError::ConfigError("storage.encryption_key must not be empty".to_string()).into());
+        }
+
+        Ok(())
+    }
+
+    /// Check that the directories the app writes to exist (creating them if needed) and are
+    /// writable, naming the path that isn't if one fails
+    pub fn check_storage_paths(&self) -> Result<()> {
+        ensure_writable_dir("git.repositories_path", &self.git.repositories_path)?;
+        ensure_writable_dir("storage.documents_path", &self.storage.documents_path)?;
+        Ok(())
+    }
+
     fn config_path() -> PathBuf {
-        // Always prefer local config.json first
+        // An explicit override always wins
+        if let Some(path) = std::env::var(CONFIG_PATH_ENV).ok().filter(|p| !p.is_empty()) {
+            return PathBuf::from(path);
+        }
+
+        // Prefer local config.json next
         let local_config = PathBuf::from("./config.json");
         if local_config.exists() {
             return local_config;
@@ -125,3 +720,23 @@ impl Config {
         }
     }
 }
+
+/// Check that a directory exists (creating it if needed) and can be written to
+///
+/// `name` is the config field the path comes from, so the error says which setting to fix.
+pub fn ensure_writable_dir(name: &str, path: &Path) -> Result<(), AppError> {
+    let describe = |problem: &str, e: std::io::Error| {
+        AppError::IoError(std::io::Error::new(
+            e.kind(),
+            format!("{} ({}) {}: {}", name, path.display(), problem, e),
+        ))
+    };
+
+    fs::create_dir_all(path).map_err(|e| describe("cannot be created", e))?;
+
+    let probe = path.join(".write-test");
+    fs::write(&probe, b"").map_err(|e| describe("is not writable", e))?;
+    let _ = fs::remove_file(&probe);
+
+    Ok(())
+}
@@ -20,21 +20,45 @@ pub enum AppError {
     #[error("Configuration error: {0}")]
     ConfigError(String),
 
+    #[error("Storage error: {0}")]
+    StorageError(String),
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 
     #[error("JSON error: {0}")]
     JsonError(#[from] serde_json::Error),
 
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Operation rejected: {0}")]
+    OperationRejected(String),
+
     #[error("Invalid UUID: {0}")]
     InvalidUuid(String),
 
     #[error("Repository not found for document: {0}")]
     RepositoryNotFound(uuid::Uuid),
 
+    #[error("Repository {0} is already linked to document {1}")]
+    RepositoryInUse(String, uuid::Uuid),
+
+    #[error("Slug {0} is already used by document {1}")]
+    SlugInUse(String, uuid::Uuid),
+
     #[error("Document not found: {0}")]
     DocumentNotFound(uuid::Uuid),
 
+    #[error("No document has the ID or slug: {0}")]
+    UnknownDocument(String),
+
+    #[error("Timed out waiting for the lock on {0}")]
+    LockTimeout(String),
+
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+/// What a lint rule looks for on each line, outside comments
+#[derive(Debug, Clone, Copy)]
+pub enum Pattern {
+    /// A command as a whole word, e.g. `\bf` but not `\bfseries`
+    Command(&'static str),
+    /// A command, or any command it prefixes, with a space before it rather than a tie
+    SpaceBefore(&'static str),
+    /// Literal text
+    Literal(&'static str),
+    /// A run of at least this many characters without a space, which can't be broken
+    /// across lines
+    UnbreakableRun(usize),
+}
+
+/// A lint rule: an ID, a pattern and what to tell the author
+#[derive(Debug, Clone, Copy)]
+pub struct LintRule {
+    pub id: &'static str,
+    pub pattern: Pattern,
+    pub message: &'static str,
+}
+
+/// The rules documents are checked against; adding a rule means adding an entry here
+pub const RULES: &[LintRule] = &[
+    LintRule {
+        id: "deprecated-bf",
+        pattern: Pattern::Command("\\bf"),
+        message: "`\\bf` is deprecated; use `\\textbf{...}` or `\\bfseries`",
+    },
+    LintRule {
+        id: "deprecated-it",
+        pattern: Pattern::Command("\\it"),
+        message: "`\\it` is deprecated; use `\\textit{...}` or `\\itshape`",
+    },
+    LintRule {
+        id: "deprecated-rm",
+        pattern: Pattern::Command("\\rm"),
+        message: "`\\rm` is deprecated; use `\\textrm{...}` or `\\rmfamily`",
+    },
+    LintRule {
+        id: "deprecated-tt",
+        pattern: Pattern::Command("\\tt"),
+        message: "`\\tt` is deprecated; use `\\texttt{...}` or `\\ttfamily`",
+    },
+    LintRule {
+        id: "deprecated-sc",
+        pattern: Pattern::Command("\\sc"),
+        message: "`\\sc` is deprecated; use `\\textsc{...}` or `\\scshape`",
+    },
+    LintRule {
+        id: "display-math-dollars",
+        pattern: Pattern::Literal("$$"),
+        message: "`$$` display math is plain TeX; use `\\[...\\]`",
+    },
+    LintRule {
+        id: "cite-without-tie",
+        pattern: Pattern::SpaceBefore("\\cite"),
+        message: "Use `~` before a citation so it isn't separated from the preceding word",
+    },
+    LintRule {
+        id: "ref-without-tie",
+        pattern: Pattern::SpaceBefore("\\ref"),
+        message: "Use `~` before a reference so it isn't separated from the preceding word",
+    },
+    LintRule {
+        id: "overfull-hbox",
+        pattern: Pattern::UnbreakableRun(60),
+        message: "Long unbreakable text is likely to cause an overfull hbox",
+    },
+];
+
+/// A rule violation, located by line and column, both counted from 1
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LintWarning {
+    pub rule_id: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// Check a document against the built-in rules
+pub fn lint(content: &str) -> Vec<LintWarning> {
+    lint_with(content, RULES)
+}
+
+/// Check a document against the given rules, returning warnings in document order
+pub fn lint_with(content: &str, rules: &[LintRule]) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line = strip_comment(line);
+        for rule in rules {
+            for offset in find(line, rule.pattern) {
+                warnings.push(LintWarning {
+                    rule_id: rule.id.to_string(),
+                    line: index + 1,
+                    column: line[..offset].chars().count() + 1,
+                    message: rule.message.to_string(),
+                });
+            }
+        }
+    }
+
+    warnings.sort_by_key(|w| (w.line, w.column));
+    warnings
+}
+
+/// Byte offsets in a line where a pattern matches
+fn find(line: &str, pattern: Pattern) -> Vec<usize> {
+    match pattern {
+        Pattern::Command(command) => commands(line, command)
+            .filter(|&offset| !line[offset + command.len()..].starts_with(|c: char| c.is_ascii_alphabetic()))
+            .collect(),
+        Pattern::SpaceBefore(command) => commands(line, command)
+            .filter(|&offset| line[..offset].ends_with([' ', '\t']))
+            .collect(),
+        Pattern::Literal(text) => line.match_indices(text).map(|(offset, _)| offset).collect(),
+        Pattern::UnbreakableRun(min_len) => {
+            let mut offsets = Vec::new();
+            let mut start = None;
+            let mut len = 0;
+            for (offset, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+                if c.is_whitespace() {
+                    if let (Some(start), true) = (start, len >= min_len) {
+                        offsets.push(start);
+                    }
+                    start = None;
+                    len = 0;
+                } else {
+                    start.get_or_insert(offset);
+                    len += 1;
+                }
+            }
+            offsets
+        }
+    }
+}
+
+/// Offsets where a command starts, skipping ones whose backslash is itself escaped
+fn commands<'a>(line: &'a str, command: &'a str) -> impl Iterator<Item = usize> + 'a {
+    line.match_indices(command)
+        .map(|(offset, _)| offset)
+        .filter(move |&offset| !escaped(line, offset))
+}
+
+/// Whether the character at `offset` follows an odd number of backslashes
+pub(super) fn escaped(line: &str, offset: usize) -> bool {
+    line[..offset].chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
+/// The part of a line before its comment, if any
+pub(super) fn strip_comment(line: &str) -> &str {
+    line.match_indices('%')
+        .find(|&(offset, _)| !escaped(line, offset))
+        .map_or(line, |(offset, _)| &line[..offset])
+}
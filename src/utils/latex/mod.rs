@@ -0,0 +1,4 @@
+pub mod lint;
+pub mod references;
+
+pub use references::{check_references, RefIssue};
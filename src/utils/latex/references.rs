@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use super::lint::{escaped, strip_comment};
+
+/// Commands that define a label
+const LABEL_COMMANDS: &[&str] = &["\\label"];
+
+/// Commands that refer to one or more comma-separated labels
+const REFERENCE_COMMANDS: &[&str] = &["\\ref", "\\eqref", "\\pageref", "\\autoref", "\\cref", "\\Cref"];
+
+/// What is wrong with a label or reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefIssueKind {
+    /// A reference to a label defined nowhere in the document
+    UndefinedReference,
+    /// A label nothing refers to
+    UnusedLabel,
+}
+
+/// A dangling reference or unused label, located by line and column, both counted from 1
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefIssue {
+    pub kind: RefIssueKind,
+    pub key: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Cross-check a document's `\label`s against its references, returning issues in
+/// document order
+pub fn check_references(content: &str) -> Vec<RefIssue> {
+    let labels = find_keys(content, LABEL_COMMANDS);
+    let references = find_keys(content, REFERENCE_COMMANDS);
+
+    let defined: HashSet<&str> = labels.iter().map(|(key, _, _)| key.as_str()).collect();
+    let used: HashSet<&str> = references.iter().map(|(key, _, _)| key.as_str()).collect();
+
+    let mut issues: Vec<RefIssue> = references
+        .iter()
+        .filter(|(key, _, _)| !defined.contains(key.as_str()))
+        .map(|(key, line, column)| RefIssue {
+            kind: RefIssueKind::UndefinedReference,
+            key: key.clone(),
+            line: *line,
+            column: *column,
+        })
+        .chain(labels.iter().filter(|(key, _, _)| !used.contains(key.as_str())).map(|(key, line, column)| RefIssue {
+            kind: RefIssueKind::UnusedLabel,
+            key: key.clone(),
+            line: *line,
+            column: *column,
+        }))
+        .collect();
+
+    issues.sort_by_key(|issue| (issue.line, issue.column));
+    issues
+}
+
+/// Every key passed to one of `commands`, with the line and column of its command
+fn find_keys(content: &str, commands: &[&str]) -> Vec<(String, usize, usize)> {
+    let mut keys = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line = strip_comment(line);
+        for command in commands {
+            let pattern = format!("{}{{", command);
+            for (offset, _) in line.match_indices(&pattern) {
+                if escaped(line, offset) {
+                    continue;
+                }
+                let argument = &line[offset + pattern.len()..];
+                let Some(end) = argument.find('}') else {
+                    continue;
+                };
+                let column = line[..offset].chars().count() + 1;
+                keys.extend(
+                    argument[..end]
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|key| !key.is_empty())
+                        .map(|key| (key.to_string(), index + 1, column)),
+                );
+            }
+        }
+    }
+
+    keys
+}
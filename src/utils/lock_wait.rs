@@ -0,0 +1,40 @@
+use std::time::Duration;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use super::errors::AppError;
+
+/// How long to wait for a lock before giving up, so a lock someone holds for too long shows
+/// up as an error instead of a stall
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LockWait {
+    timeout: Option<Duration>,
+}
+
+impl LockWait {
+    /// Wait at most `timeout` for each lock, or for as long as it takes when unset
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self { timeout }
+    }
+
+    /// Acquire `lock` for reading; `what` names the lock when waiting for it takes too long
+    pub async fn read<'a, T: ?Sized>(&self, lock: &'a RwLock<T>, what: impl FnOnce() -> String) -> Result<RwLockReadGuard<'a, T>, AppError> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, lock.read()).await.map_err(|_| Self::timed_out(timeout, "reading", what)),
+            None => Ok(lock.read().await),
+        }
+    }
+
+    /// Acquire `lock` for writing; `what` names the lock when waiting for it takes too long
+    pub async fn write<'a, T: ?Sized>(&self, lock: &'a RwLock<T>, what: impl FnOnce() -> String) -> Result<RwLockWriteGuard<'a, T>, AppError> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, lock.write()).await.map_err(|_| Self::timed_out(timeout, "writing", what)),
+            None => Ok(lock.write().await),
+        }
+    }
+
+    fn timed_out(timeout: Duration, access: &str, what: impl FnOnce() -> String) -> AppError {
+        let what = what();
+        tracing::warn!("Gave up waiting {:?} to lock {} for {}; whoever holds it is stalling", timeout, what, access);
+        AppError::LockTimeout(what)
+    }
+}
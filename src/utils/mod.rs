@@ -1,2 +1,9 @@
 pub mod config;
 pub mod errors;
+pub mod atomic_file;
+pub mod signals;
+pub mod latex;
+pub mod backoff;
+pub mod rate_limit;
+pub mod timing;
+pub mod lock_wait;
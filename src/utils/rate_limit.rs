@@ -0,0 +1,44 @@
+use std::time::{Duration, Instant};
+
+/// Token bucket allowing bursts of up to `capacity` actions, refilled at a steady rate
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket of `capacity` tokens, refilling `refill_per_sec` tokens a second
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            refill_per_sec,
+            tokens: capacity as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Whether the bucket will have refilled completely by `now`
+    pub fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens + elapsed * self.refill_per_sec >= self.capacity
+    }
+
+    /// Take a token as of `now`, or say how long until one will be available
+    pub fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else if self.refill_per_sec > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_sec))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+}
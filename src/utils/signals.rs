@@ -0,0 +1,78 @@
+use std::io;
+
+use crate::P2PLatexCollab;
+
+/// OS signal that asked the process to shut down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    /// SIGINT, e.g. Ctrl-C
+    Interrupt,
+    /// SIGTERM, e.g. from a service manager
+    Terminate,
+}
+
+/// Handlers for the signals that ask the process to shut down
+///
+/// The handlers are installed when the listener is created, so signals delivered between
+/// creating it and awaiting `recv` are not lost and don't kill the process.
+pub struct ShutdownListener {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl ShutdownListener {
+    #[cfg(unix)]
+    pub fn new() -> io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {})
+    }
+
+    /// Wait for the next shutdown signal
+    #[cfg(unix)]
+    pub async fn recv(&mut self) -> ShutdownSignal {
+        tokio::select! {
+            _ = self.interrupt.recv() => ShutdownSignal::Interrupt,
+            _ = self.terminate.recv() => ShutdownSignal::Terminate,
+        }
+    }
+
+    /// Wait for the next shutdown signal
+    #[cfg(not(unix))]
+    pub async fn recv(&mut self) -> ShutdownSignal {
+        // Only Ctrl-C can be listened for here
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => ShutdownSignal::Interrupt,
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+/// Complete once the process receives SIGINT or SIGTERM
+pub async fn wait_for_shutdown() -> io::Result<ShutdownSignal> {
+    Ok(ShutdownListener::new()?.recv().await)
+}
+
+/// Start the application, run it until a shutdown signal arrives, then stop it
+pub async fn run_until_shutdown(app: &P2PLatexCollab) -> anyhow::Result<()> {
+    // Listen before starting, so a signal during startup still stops the application cleanly
+    let mut shutdown = ShutdownListener::new()?;
+
+    app.start().await?;
+    tracing::info!("Application started, waiting for a shutdown signal");
+
+    let signal = shutdown.recv().await;
+    tracing::info!("Received {:?}, shutting down...", signal);
+
+    app.stop().await
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Upper bounds, in milliseconds, of the histogram buckets operation durations are counted in;
+/// longer operations fall in a last, unbounded bucket
+pub const TIMING_BUCKETS_MS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+/// Duration histograms of the engine's expensive operations, warning about the slow ones
+#[derive(Debug, Default)]
+pub struct OperationTimings {
+    slow_threshold: Option<Duration>,
+    histograms: Mutex<Vec<OperationHistogram>>,
+}
+
+/// How long one kind of operation has taken
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperationHistogram {
+    pub operation: String,
+    pub count: u64,
+    /// Times it took longer than the slow-operation threshold
+    pub slow: u64,
+    pub total_ms: f64,
+    pub max_ms: f64,
+    pub buckets: Vec<HistogramBucket>,
+}
+
+/// Number of operations that took at most `le_ms` milliseconds, and longer than the bucket
+/// before; `le_ms` is unset for the last bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+impl OperationHistogram {
+    fn new(operation: &str) -> Self {
+        let buckets = TIMING_BUCKETS_MS
+            .iter()
+            .map(|&le_ms| Some(le_ms))
+            .chain(std::iter::once(None))
+            .map(|le_ms| HistogramBucket { le_ms, count: 0 })
+            .collect();
+
+        Self {
+            operation: operation.to_string(),
+            count: 0,
+            slow: 0,
+            total_ms: 0.0,
+            max_ms: 0.0,
+            buckets,
+        }
+    }
+}
+
+impl OperationTimings {
+    /// Warn about operations taking longer than `slow_threshold`, if set
+    pub fn new(slow_threshold: Option<Duration>) -> Self {
+        Self {
+            slow_threshold,
+            histograms: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Count an operation on a document that took `elapsed`, handling `bytes` of input,
+    /// returning whether it was slow
+    pub fn record(&self, operation: &str, doc_id: &Uuid, bytes: usize, elapsed: Duration) -> bool {
+        let slow = self.slow_threshold.is_some_and(|threshold| elapsed > threshold);
+        if slow {
+            tracing::warn!(
+                "Slow {} on document {}: took {:?} for {} bytes",
+                operation, doc_id, elapsed, bytes
+            );
+        }
+
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        let mut histograms = self.histograms.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let index = match histograms.iter().position(|histogram| histogram.operation == operation) {
+            Some(index) => index,
+            None => {
+                histograms.push(OperationHistogram::new(operation));
+                histograms.len() - 1
+            }
+        };
+        let histogram = &mut histograms[index];
+        histogram.count += 1;
+        histogram.slow += u64::from(slow);
+        histogram.total_ms += ms;
+        histogram.max_ms = histogram.max_ms.max(ms);
+        if let Some(bucket) = histogram
+            .buckets
+            .iter_mut()
+            .find(|bucket| bucket.le_ms.is_none_or(|le_ms| ms <= le_ms as f64))
+        {
+            bucket.count += 1;
+        }
+
+        slow
+    }
+
+    /// Run `future`, counting how long it took as an operation on a document
+    pub async fn time<T>(&self, operation: &str, doc_id: &Uuid, bytes: usize, future: impl Future<Output = T>) -> T {
+        let started = Instant::now();
+        let output = future.await;
+        self.record(operation, doc_id, bytes, started.elapsed());
+        output
+    }
+
+    /// The histogram of each operation timed so far, in the order they were first seen
+    pub fn histograms(&self) -> Vec<OperationHistogram> {
+        self.histograms.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
@@ -640,9 +640,9 @@ async function fixDocumentBranch(documentId) {
     try {
         console.log(`Fixing document branch for ${documentId}`);
 
-        // Use the Document Persistence API endpoint to check/create the document
-        const response = await fetch(`${API_URL}/api/documents/${documentId}/check`, {
-            method: 'GET',
+        // Use the Document Persistence API endpoint to create the document if it's missing
+        const response = await fetch(`${API_URL}/api/documents/${documentId}/ensure`, {
+            method: 'POST',
             headers: {
                 'Accept': 'application/json',
                 'Content-Type': 'application/json',